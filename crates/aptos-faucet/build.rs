@@ -0,0 +1,70 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    env, fs,
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const GIT_DIR: &str = "../../.git";
+
+/// Save revision, build time, profile and enabled features to environment variables
+fn main() {
+    // Commits move the branch HEAD points to, checkouts move HEAD itself
+    let mut watched = vec!["HEAD".to_string(), "index".to_string()];
+    if let Ok(head) = fs::read_to_string(Path::new(GIT_DIR).join("HEAD")) {
+        if let Some(branch) = head.trim().strip_prefix("ref: ") {
+            watched.push(branch.to_string());
+            // Refs end up packed by git gc
+            watched.push("packed-refs".to_string());
+        }
+    }
+    for path in watched {
+        let path = Path::new(GIT_DIR).join(path);
+        if path.exists() {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    if env::var("GIT_REV").is_err() {
+        // Builds from a source archive have no repository to ask
+        let git_rev = Command::new("git")
+            .args(&["rev-parse", "--short", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .unwrap_or_else(|| "unknown".to_string());
+        println!("cargo:rustc-env=GIT_REV={}", git_rev.trim());
+    }
+
+    // Reproducible builds pin the timestamp
+    let build_timestamp = env::var("SOURCE_DATE_EPOCH").unwrap_or_else(|_| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            .to_string()
+    });
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", build_timestamp);
+
+    // `release` or `debug`, whatever the cargo profile was named
+    println!(
+        "cargo:rustc-env=BUILD_PROFILE={}",
+        env::var("PROFILE").unwrap_or_default()
+    );
+
+    // Optional dependencies are enabled through `dep:`, so that only the `[features]` show up
+    let mut features: Vec<_> = env::vars()
+        .filter_map(|(name, _)| {
+            let feature = name.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=BUILD_FEATURES={}", features.join(","));
+}
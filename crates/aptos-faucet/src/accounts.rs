@@ -0,0 +1,94 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! `POST /accounts` creates an account without minting anything to it, for flows that need the
+//! account on chain but no coins. Requests go through the same checks as `/mint`, and accounts
+//! that already exist are refused with a `409`.
+
+use crate::{
+    client_ip,
+    error::FaucetError,
+    mint::{self, MintParams, Response},
+    mint_queue,
+    partner::{self, SignedRequest},
+    request_log::{request_context, with_request_id},
+    Service,
+};
+use aptos_rest_client::CreatedAccount;
+use serde::Deserialize;
+use std::{convert::Infallible, net::IpAddr, sync::Arc, time::Instant};
+use warp::{Filter, Rejection, Reply};
+
+pub fn accounts_route(
+    service: Arc<Service>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let max_body_bytes = service.request_limits.max_body_bytes;
+    // POST /accounts?address=xxx
+    warp::path!("accounts")
+        .and(warp::post())
+        .and(client_ip(service.clone()))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::any().map(move || service.clone()))
+        .and(warp::query().map(move |params: AccountParams| params))
+        .and(partner::signed_request(max_body_bytes))
+        .and(mint_queue::deadline())
+        .and(request_context())
+        .and_then(
+            |client_ip, authorization, service, params, signed, deadline, context| {
+                with_request_id(
+                    context,
+                    handle(service, params, client_ip, authorization, signed, deadline),
+                )
+            },
+        )
+}
+
+/// Query parameters of `POST /accounts`, with the same meaning as for `/mint`
+#[derive(Debug, Default, Deserialize)]
+pub struct AccountParams {
+    pub auth_key: Option<String>,
+    pub address: Option<String>,
+    pub pub_key: Option<String>,
+    /// Wait for the creation to commit, and report its outcome
+    pub wait: Option<bool>,
+    pub nonce: Option<String>,
+    pub signature: Option<String>,
+}
+
+async fn handle(
+    service: Arc<Service>,
+    params: AccountParams,
+    client_ip: Option<IpAddr>,
+    authorization: Option<String>,
+    signed: Option<SignedRequest>,
+    deadline: Option<Instant>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let params = MintParams {
+        auth_key: params.auth_key,
+        address: params.address,
+        pub_key: params.pub_key,
+        wait: params.wait,
+        nonce: params.nonce,
+        signature: params.signature,
+        create_only: true,
+        deadline,
+        ..MintParams::default()
+    };
+
+    let response = match mint::mint(&service, params, client_ip, authorization, signed).await {
+        Ok((_, Response::Funded(response))) => response,
+        Ok((_, body)) => return Ok(Box::new(body.to_string())),
+        Err(err) => return Ok(mint::refused(&service, err)),
+    };
+
+    // Someone else created the account since it was looked up
+    let txn_hash = match response.txn_hashes.first() {
+        Some(txn_hash) => *txn_hash,
+        None => return Ok(mint::refused(&service, FaucetError::AccountExists)),
+    };
+    Ok(Box::new(warp::reply::json(&CreatedAccount {
+        address: response.destination,
+        txn_hash,
+        txn: response.txns.into_iter().next(),
+    })))
+}
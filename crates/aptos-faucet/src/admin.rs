@@ -0,0 +1,431 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Operator endpoints under `/admin`. They are only served when an admin token is configured,
+//! and every request must present it as `Authorization: Bearer <token>`.
+
+use crate::{
+    bulk::{self, Format},
+    counters, rejection,
+    request_log::{request_context, with_request_id},
+    FaucetError, Service,
+};
+use aptos_logger::{error, info, Filter, LevelFilter};
+use aptos_sdk::types::account_address::AccountAddress;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::{convert::Infallible, sync::Arc, time::Duration};
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+/// Coupons issued by a single `POST /admin/coupons`
+const MAX_COUPONS_PER_REQUEST: usize = 1_000;
+
+/// Largest list accepted by `POST /admin/bulk_fund`
+const MAX_BULK_FUND_BYTES: u64 = 2 * 1024 * 1024;
+
+/// Largest filter accepted by `PUT /admin/log_level`
+const MAX_LOG_FILTER_BYTES: u64 = 16 * 1024;
+
+pub fn admin_routes(
+    service: Arc<Service>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    // GET /admin/sybil
+    let sybil = warp::path!("admin" / "sybil")
+        .and(warp::get())
+        .and(authorized(service.clone()))
+        .and(request_context())
+        .and_then(|service, context| with_request_id(context, handle_sybil(service)));
+    // GET /admin/usage
+    let usage = warp::path!("admin" / "usage")
+        .and(warp::get())
+        .and(authorized(service.clone()))
+        .and(request_context())
+        .and_then(|service, context| with_request_id(context, handle_usage(service)));
+    // GET /admin/bans
+    let bans = warp::path!("admin" / "bans")
+        .and(warp::get())
+        .and(authorized(service.clone()))
+        .and(request_context())
+        .and_then(|service, context| with_request_id(context, handle_bans(service)));
+    // GET /admin/coupons
+    let list_coupons = warp::path!("admin" / "coupons")
+        .and(warp::get())
+        .and(authorized(service.clone()))
+        .and(request_context())
+        .and_then(|service, context| with_request_id(context, handle_list_coupons(service)));
+    // POST /admin/coupons?amount=1000000&count=10
+    let issue_coupons = warp::path!("admin" / "coupons")
+        .and(warp::post())
+        .and(authorized(service.clone()))
+        .and(warp::query().map(move |params: CouponParams| params))
+        .and(request_context())
+        .and_then(|service, params, context| {
+            with_request_id(context, handle_issue_coupons(service, params))
+        });
+    // POST /admin/bulk_fund, with a CSV or JSON list of receivers
+    let bulk_fund = warp::path!("admin" / "bulk_fund")
+        .and(warp::post())
+        .and(authorized(service.clone()))
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::content_length_limit(MAX_BULK_FUND_BYTES))
+        .and(warp::body::bytes())
+        .and(request_context())
+        .and_then(|service, content_type: Option<String>, body, context| {
+            with_request_id(context, handle_bulk_fund(service, content_type, body))
+        });
+    // GET /admin/queue
+    let queue = warp::path!("admin" / "queue")
+        .and(warp::get())
+        .and(authorized(service.clone()))
+        .and(request_context())
+        .and_then(|service, context| with_request_id(context, handle_queue(service)));
+    // GET /admin/inflight
+    let inflight = warp::path!("admin" / "inflight")
+        .and(warp::get())
+        .and(authorized(service.clone()))
+        .and(request_context())
+        .and_then(|service, context| with_request_id(context, handle_inflight(service)));
+    // POST /admin/redelegate
+    let redelegate = warp::path!("admin" / "redelegate")
+        .and(warp::post())
+        .and(authorized(service.clone()))
+        .and(request_context())
+        .and_then(|service, context| with_request_id(context, handle_redelegate(service)));
+    // PUT /admin/log_level, with a filter like `debug` or `info,aptos_faucet::mint=debug`
+    let log_level = warp::path!("admin" / "log_level")
+        .and(warp::put())
+        .and(authorized(service.clone()))
+        .and(warp::body::content_length_limit(MAX_LOG_FILTER_BYTES))
+        .and(warp::body::bytes())
+        .and(request_context())
+        .and_then(|service, body, context| {
+            with_request_id(context, handle_log_level(service, body))
+        });
+
+    let routes = sybil
+        .or(usage)
+        .or(bans)
+        .or(list_coupons)
+        .or(issue_coupons)
+        .or(bulk_fund)
+        .or(queue)
+        .or(inflight)
+        .or(redelegate)
+        .or(log_level);
+    // GET /debug/pprof/profile?seconds=30, where pprof tooling looks for it
+    #[cfg(feature = "cpu-profiling")]
+    let routes = routes.or(warp::path!("debug" / "pprof" / "profile")
+        .and(warp::get())
+        .and(authorized(service))
+        .and(warp::query::<crate::profiling::ProfileParams>())
+        .and(request_context())
+        .and_then(|_service, params, context| {
+            with_request_id(context, crate::profiling::handle_profile(params))
+        }));
+    #[cfg(not(feature = "cpu-profiling"))]
+    drop(service);
+    routes
+}
+
+/// Passes the service on to authorized requests, and rejects every other request with a 404 so
+/// that the admin API cannot be discovered.
+fn authorized(
+    service: Arc<Service>,
+) -> impl Filter<Extract = (Arc<Service>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization").and_then(
+        move |authorization: Option<String>| {
+            let service = service.clone();
+            async move {
+                let token = authorization
+                    .as_deref()
+                    .and_then(|header| header.strip_prefix("Bearer "));
+                match (&service.admin_token, token) {
+                    (Some(expected), Some(token)) if constant_time_eq(expected, token) => {
+                        Ok(service)
+                    }
+                    _ => Err(warp::reject::not_found()),
+                }
+            }
+        },
+    )
+}
+
+fn constant_time_eq(lhs: &str, rhs: &str) -> bool {
+    lhs.len() == rhs.len()
+        && lhs
+            .bytes()
+            .zip(rhs.bytes())
+            .fold(0, |acc, (l, r)| acc | (l ^ r))
+            == 0
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CouponParams {
+    /// Amount each coupon mints, in base units
+    pub amount: u64,
+    /// Coupons to issue, 1 by default
+    pub count: Option<usize>,
+}
+
+async fn handle_list_coupons(service: Arc<Service>) -> Result<Box<dyn warp::Reply>, Infallible> {
+    Ok(Box::new(warp::reply::json(&service.coupons.outstanding())))
+}
+
+async fn handle_issue_coupons(
+    service: Arc<Service>,
+    params: CouponParams,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let count = params.count.unwrap_or(1);
+    if params.amount == 0 || count == 0 || count > MAX_COUPONS_PER_REQUEST {
+        return Ok(rejection::reply(
+            StatusCode::BAD_REQUEST,
+            "bad_params",
+            format!(
+                "'amount' must be positive and 'count' between 1 and {}",
+                MAX_COUPONS_PER_REQUEST
+            ),
+        ));
+    }
+    Ok(Box::new(warp::reply::json(
+        &service.coupons.issue(params.amount, count),
+    )))
+}
+
+/// Funds every receiver of the list, replying with the report as a download in the list's
+/// format
+async fn handle_bulk_fund(
+    service: Arc<Service>,
+    content_type: Option<String>,
+    body: Bytes,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    if !service.is_leader() {
+        return Ok(rejection::faucet_error(&FaucetError::Standby));
+    }
+    let format = Format::from_content_type(content_type.as_deref());
+    let rows = match bulk::parse(format, &body) {
+        Ok(rows) => rows,
+        Err(err) => {
+            return Ok(rejection::reply(
+                StatusCode::BAD_REQUEST,
+                "invalid_body",
+                err,
+            ))
+        }
+    };
+
+    info!("[faucet]: bulk funding {} receivers", rows.len());
+    let report = bulk::fund_all(&service, rows).await;
+    info!(
+        "[faucet]: bulk funding done, {} funded, {} failed",
+        report.funded, report.failed
+    );
+
+    let body = match format {
+        Format::Csv => report.to_csv(),
+        Format::Json => serde_json::to_string(&report).expect("the report serializes"),
+    };
+    Ok(Box::new(warp::reply::with_header(
+        warp::reply::with_header(body, "content-type", format.content_type()),
+        "content-disposition",
+        format!(
+            "attachment; filename=\"bulk_fund_report.{}\"",
+            format.extension()
+        ),
+    )))
+}
+
+/// Returned by `GET /admin/inflight`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Inflight {
+    pub funder: AccountAddress,
+    pub onchain_sequence_number: u64,
+    /// The sequence number the next transaction is signed with
+    pub local_sequence_number: u64,
+    /// By sequence number
+    pub transactions: Vec<InflightTransaction>,
+}
+
+/// A transaction of the funder submitted but not committed yet
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InflightTransaction {
+    pub sequence_number: u64,
+    /// `0x` prefixed
+    pub hash: String,
+    /// The account created or sent coins, none for the funder's other transactions
+    pub receiver: Option<AccountAddress>,
+    /// Since the transaction was last submitted
+    pub age_secs: u64,
+    pub expiration_timestamp_secs: u64,
+}
+
+async fn handle_inflight(service: Arc<Service>) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let (funder, local_seq) = {
+        let funder = service.faucet_account.lock().await;
+        (funder.address(), funder.sequence_number())
+    };
+    let account = service.client.get_account(funder);
+    let onchain_seq = match counters::node_request("get_account", account).await {
+        Ok(account) => account.into_inner().sequence_number,
+        Err(err) => {
+            return Ok(rejection::reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "node_error",
+                format!("Unable to fetch the sequence number of {}: {}", funder, err),
+            ))
+        }
+    };
+
+    // Forgets the transactions committed since, like the gap and stuck transaction checks
+    let transactions = service
+        .outstanding
+        .submitted_before(funder, onchain_seq, Duration::ZERO)
+        .into_iter()
+        .map(|tracked| InflightTransaction {
+            sequence_number: tracked.txn.sequence_number(),
+            hash: tracked.txn.clone().committed_hash().to_hex_literal(),
+            receiver: tracked.receiver(),
+            age_secs: tracked.submitted_at.elapsed().as_secs(),
+            expiration_timestamp_secs: tracked.txn.expiration_timestamp_secs(),
+        })
+        .collect();
+    Ok(Box::new(warp::reply::json(&Inflight {
+        funder,
+        onchain_sequence_number: onchain_seq,
+        local_sequence_number: local_seq,
+        transactions,
+    })))
+}
+
+/// Returned by `POST /admin/redelegate`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Redelegated {
+    /// The account minting from now on
+    pub address: AccountAddress,
+}
+
+async fn handle_redelegate(service: Arc<Service>) -> Result<Box<dyn warp::Reply>, Infallible> {
+    if service.delegation.is_none() {
+        return Ok(rejection::reply(
+            StatusCode::CONFLICT,
+            "not_delegated",
+            "The faucet does not mint from a delegated account",
+        ));
+    }
+    match service.redelegate().await {
+        Ok(address) => Ok(Box::new(warp::reply::json(&Redelegated { address }))),
+        Err(err) => {
+            error!("[faucet]: redelegation failed: {}", err);
+            Ok(rejection::reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "node_error",
+                format!(
+                    "Redelegation failed, still minting from the previous account: {}",
+                    err
+                ),
+            ))
+        }
+    }
+}
+
+/// Returned by `PUT /admin/log_level`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LogLevel {
+    /// The filter logging from now on
+    pub filter: String,
+}
+
+async fn handle_log_level(
+    service: Arc<Service>,
+    body: Bytes,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let logger = match &service.logger {
+        Some(logger) => logger,
+        None => {
+            return Ok(rejection::reply(
+                StatusCode::NOT_FOUND,
+                "not_found",
+                "The faucet does not manage the process' logger",
+            ))
+        }
+    };
+    let filter = match log_filter(&body) {
+        Ok(filter) => filter,
+        Err(err) => return Ok(rejection::reply(StatusCode::BAD_REQUEST, "bad_params", err)),
+    };
+
+    logger.set_filter(Filter::builder().parse(filter).build());
+    info!("[faucet]: log filter set to {}", filter);
+    Ok(Box::new(warp::reply::json(&LogLevel {
+        filter: filter.to_string(),
+    })))
+}
+
+/// The filter in `body`, whose every directive must be a level, or a module and its level. The
+/// logger takes a lone word for a module logging everything, so a misspelled level would silence
+/// everything else
+fn log_filter(body: &[u8]) -> Result<&str, String> {
+    let filter = std::str::from_utf8(body)
+        .map_err(|_| "The filter is not UTF-8".to_string())?
+        .trim();
+    if filter.is_empty() {
+        return Err("The filter is empty".to_string());
+    }
+    for directive in filter.split(',').map(str::trim) {
+        let level = match directive.split_once('=') {
+            Some((module, level)) if !module.trim().is_empty() => level.trim(),
+            Some(_) => return Err(format!("'{}' names no module", directive)),
+            None => directive,
+        };
+        if level.parse::<LevelFilter>().is_err() {
+            return Err(format!(
+                "'{}' is not one of off, error, warn, info, debug or trace",
+                level
+            ));
+        }
+    }
+    Ok(filter)
+}
+
+async fn handle_usage(service: Arc<Service>) -> Result<Box<dyn warp::Reply>, Infallible> {
+    match &service.partners {
+        Some(partners) => Ok(Box::new(warp::reply::json(&partners.usage()))),
+        None => Ok(rejection::reply(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            "The faucet does not accept signed requests",
+        )),
+    }
+}
+
+async fn handle_queue(service: Arc<Service>) -> Result<Box<dyn warp::Reply>, Infallible> {
+    match &service.mint_queue {
+        Some(queue) => Ok(Box::new(warp::reply::json(&queue.stats()))),
+        None => Ok(rejection::reply(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            "The faucet does not bound concurrent mint requests",
+        )),
+    }
+}
+
+async fn handle_bans(service: Arc<Service>) -> Result<Box<dyn warp::Reply>, Infallible> {
+    match &service.bans {
+        Some(bans) => Ok(Box::new(warp::reply::json(&bans.bans()))),
+        None => Ok(rejection::reply(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            "Bans are not enabled",
+        )),
+    }
+}
+
+async fn handle_sybil(service: Arc<Service>) -> Result<Box<dyn warp::Reply>, Infallible> {
+    match &service.sybil {
+        Some(sybil) => Ok(Box::new(warp::reply::json(&sybil.findings()))),
+        None => Ok(rejection::reply(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            "Sybil detection is not enabled",
+        )),
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Requested amounts, either in base units (`amount=150000000`) or in whole coins with an `APT`
+//! suffix (`amount=1.5APT`). Whole coins are converted with the coin's on-chain decimals.
+
+use anyhow::format_err;
+use aptos_rest_client::Client;
+use aptos_sdk::types::account_config::CORE_CODE_ADDRESS;
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer,
+};
+use std::{convert::TryFrom, fmt, str::FromStr};
+
+/// Suffix marking an amount as whole coins
+const COIN_SUFFIX: &str = "APT";
+
+/// Resource holding the minted coin's decimals
+const COIN_INFO: &str = "0x1::Coin::CoinInfo<0x1::TestCoin::TestCoin>";
+
+/// Decimal places of the minted coin, as registered on chain
+pub async fn coin_decimals(client: &Client) -> anyhow::Result<u8> {
+    let resource = client
+        .get_account_resource(CORE_CODE_ADDRESS, COIN_INFO)
+        .await?
+        .into_inner()
+        .ok_or_else(|| format_err!("{} does not exist", COIN_INFO))?;
+    // Move u64s are returned as strings
+    let decimals = &resource.data["decimals"];
+    decimals
+        .as_u64()
+        .or_else(|| decimals.as_str()?.parse().ok())
+        .and_then(|decimals| u8::try_from(decimals).ok())
+        .ok_or_else(|| format_err!("Invalid decimals in {}: {}", COIN_INFO, decimals))
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Amount {
+    /// Base units of the coin
+    Base(u64),
+    /// Whole coins, as written by the requester
+    Coins { whole: u64, fraction: String },
+}
+
+impl Amount {
+    /// The amount in base units, for a coin with `decimals` decimal places
+    pub fn base_units(&self, decimals: u8) -> Result<u64, AmountError> {
+        let (whole, fraction) = match self {
+            Amount::Base(amount) => return Ok(*amount),
+            Amount::Coins { whole, fraction } => (*whole, fraction),
+        };
+        if fraction.len() > decimals as usize {
+            return Err(AmountError::TooPrecise { decimals });
+        }
+        let scale = 10u64
+            .checked_pow(decimals as u32)
+            .ok_or(AmountError::TooLarge)?;
+        let fraction = if fraction.is_empty() {
+            0
+        } else {
+            let padding = 10u64.pow((decimals as usize - fraction.len()) as u32);
+            fraction
+                .parse::<u64>()
+                .map_err(|_| AmountError::Invalid)?
+                .checked_mul(padding)
+                .ok_or(AmountError::TooLarge)?
+        };
+        whole
+            .checked_mul(scale)
+            .and_then(|whole| whole.checked_add(fraction))
+            .ok_or(AmountError::TooLarge)
+    }
+}
+
+impl From<u64> for Amount {
+    fn from(amount: u64) -> Self {
+        Amount::Base(amount)
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Amount::Base(amount) => write!(f, "{}", amount),
+            Amount::Coins { whole, fraction } if fraction.is_empty() => {
+                write!(f, "{}{}", whole, COIN_SUFFIX)
+            }
+            Amount::Coins { whole, fraction } => write!(f, "{}.{}{}", whole, fraction, COIN_SUFFIX),
+        }
+    }
+}
+
+impl FromStr for Amount {
+    type Err = AmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let coins = match s.len().checked_sub(COIN_SUFFIX.len()) {
+            Some(split)
+                if s.is_char_boundary(split) && s[split..].eq_ignore_ascii_case(COIN_SUFFIX) =>
+            {
+                s[..split].trim_end()
+            }
+            _ => {
+                return s
+                    .parse()
+                    .map(Amount::Base)
+                    .map_err(|_| AmountError::Invalid)
+            }
+        };
+
+        let (whole, fraction) = coins.split_once('.').unwrap_or((coins, ""));
+        let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if (whole.is_empty() && fraction.is_empty()) || !is_digits(whole) || !is_digits(fraction) {
+            return Err(AmountError::Invalid);
+        }
+        Ok(Amount::Coins {
+            whole: if whole.is_empty() {
+                0
+            } else {
+                whole.parse().map_err(|_| AmountError::TooLarge)?
+            },
+            fraction: fraction.trim_end_matches('0').to_string(),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AmountVisitor;
+
+        impl<'de> Visitor<'de> for AmountVisitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(
+                    f,
+                    "an amount in base units, or in coins like 1.5{}",
+                    COIN_SUFFIX
+                )
+            }
+
+            fn visit_u64<E: de::Error>(self, amount: u64) -> Result<Amount, E> {
+                Ok(Amount::Base(amount))
+            }
+
+            fn visit_str<E: de::Error>(self, amount: &str) -> Result<Amount, E> {
+                amount.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(AmountVisitor)
+    }
+}
+
+/// What is wrong with an amount, to be read after the name of the field holding it
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AmountError {
+    Invalid,
+    /// More decimal places than the coin has
+    TooPrecise {
+        decimals: u8,
+    },
+    /// Does not fit in a u64 of base units
+    TooLarge,
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AmountError::Invalid => {
+                write!(f, "must be base units, or coins like 1.5{}", COIN_SUFFIX)
+            }
+            AmountError::TooPrecise { decimals } => {
+                write!(f, "has more than the coin's {} decimal places", decimals)
+            }
+            AmountError::TooLarge => write!(f, "is too large"),
+        }
+    }
+}
+
+impl std::error::Error for AmountError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base_units(amount: &str) -> Result<u64, AmountError> {
+        amount.parse::<Amount>()?.base_units(8)
+    }
+
+    #[test]
+    fn amounts_convert() {
+        assert_eq!(base_units("150"), Ok(150));
+        assert_eq!(base_units("1.5APT"), Ok(150_000_000));
+        assert_eq!(base_units("2 apt"), Ok(200_000_000));
+        assert_eq!(base_units(".00000001APT"), Ok(1));
+        assert_eq!(base_units("0.100000000APT"), Ok(10_000_000));
+        assert_eq!(
+            base_units("0.000000001APT"),
+            Err(AmountError::TooPrecise { decimals: 8 })
+        );
+        assert_eq!(base_units("200000000000APT"), Err(AmountError::TooLarge));
+        assert_eq!(base_units("1.5"), Err(AmountError::Invalid));
+        assert_eq!(base_units("APT"), Err(AmountError::Invalid));
+        assert_eq!(base_units("-1APT"), Err(AmountError::Invalid));
+    }
+}
@@ -0,0 +1,232 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Resolves Aptos Names, like `alice.apt` or `wallet.alice.apt`, to the address they point to,
+//! so that `/mint?name=alice.apt` funds whoever owns the name. Names are looked up in the
+//! registry of the Aptos Names Service (ANS) contract the faucet is configured with, and refused
+//! when they are not registered, expired or do not point to an address.
+
+use crate::{
+    counters,
+    error::{FaucetError, InvalidField},
+};
+use anyhow::format_err;
+use aptos_rest_client::Client;
+use aptos_sdk::types::account_address::AccountAddress;
+use serde::Serialize;
+use serde_json::Value;
+use std::{fmt, str::FromStr};
+
+/// Top level domain of Aptos Names
+pub const SUFFIX: &str = ".apt";
+
+/// Labels longer than this cannot be registered
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// A name as registered: its domain and, for subdomains, the subdomain
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Name {
+    pub subdomain: Option<String>,
+    pub domain: String,
+}
+
+impl FromStr for Name {
+    type Err = InvalidField;
+
+    /// Parses `[subdomain.]domain[.apt]`, case insensitively
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidField::new("name", "is not a valid Aptos name");
+        let lowercase = name.trim().to_ascii_lowercase();
+        let labels: Vec<_> = lowercase
+            .strip_suffix(SUFFIX)
+            .unwrap_or(&lowercase)
+            .split('.')
+            .collect();
+        if !labels.iter().all(|label| valid_label(label)) {
+            return Err(invalid());
+        }
+        match labels.as_slice() {
+            [domain] => Ok(Name {
+                subdomain: None,
+                domain: domain.to_string(),
+            }),
+            [subdomain, domain] => Ok(Name {
+                subdomain: Some(subdomain.to_string()),
+                domain: domain.to_string(),
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.subdomain {
+            Some(subdomain) => write!(f, "{}.{}{}", subdomain, self.domain, SUFFIX),
+            None => write!(f, "{}{}", self.domain, SUFFIX),
+        }
+    }
+}
+
+fn valid_label(label: &str) -> bool {
+    !label.is_empty()
+        && label.len() <= MAX_LABEL_LENGTH
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Key of the registry table, as the REST API encodes it. Move options are encoded as vectors
+/// of at most one element
+#[derive(Serialize)]
+struct RecordKey<'a> {
+    subdomain_name: OptionValue<&'a str>,
+    domain_name: &'a str,
+}
+
+#[derive(Serialize)]
+struct OptionValue<T> {
+    vec: Vec<T>,
+}
+
+/// The address `name` points to, as registered with the ANS `contract`
+pub async fn resolve(
+    client: &Client,
+    contract: AccountAddress,
+    name: &Name,
+) -> Result<AccountAddress, FaucetError> {
+    let module = format!("{}::domains", contract.to_hex_literal());
+    let registry_type = format!("{}::NameRegistryV1", module);
+    let lookup = client.get_account_resource(contract, &registry_type);
+    let response = counters::node_request("get_account_resource", lookup)
+        .await
+        .map_err(FaucetError::Node)?;
+    let now_secs = response.state().timestamp_usecs / 1_000_000;
+    let registry = response
+        .into_inner()
+        .ok_or_else(|| FaucetError::Node(format_err!("{} does not exist", registry_type)))?;
+    // Move u128s are returned as strings
+    let handle = &registry.data["registry"]["handle"];
+    let handle = handle
+        .as_str()
+        .and_then(|handle| handle.parse::<u128>().ok())
+        .ok_or_else(|| {
+            FaucetError::Node(format_err!(
+                "Invalid registry handle in {}: {}",
+                registry_type,
+                handle
+            ))
+        })?;
+
+    let key = RecordKey {
+        subdomain_name: OptionValue {
+            vec: name.subdomain.as_deref().into_iter().collect(),
+        },
+        domain_name: &name.domain,
+    };
+    let lookup = client.get_table_item(
+        handle,
+        &format!("{}::NameRecordKeyV1", module),
+        &format!("{}::NameRecordV1", module),
+        key,
+    );
+    // The registry was just read, so a failed lookup means the name is not in it
+    let record = match counters::node_request("get_table_item", lookup).await {
+        Ok(record) => record.into_inner(),
+        Err(_) => {
+            return Err(FaucetError::UnresolvedName(format!(
+                "'{}' is not registered",
+                name
+            )))
+        }
+    };
+    target(name, &record, now_secs)
+}
+
+/// The address `record` points `name` to, as of the ledger timestamp `now_secs`
+fn target(name: &Name, record: &Value, now_secs: u64) -> Result<AccountAddress, FaucetError> {
+    // Move u64s are returned as strings
+    let expiration = &record["expiration_time_sec"];
+    let expiration = expiration
+        .as_u64()
+        .or_else(|| expiration.as_str()?.parse().ok())
+        .ok_or_else(|| {
+            FaucetError::Node(format_err!("Invalid record of '{}': {}", name, record))
+        })?;
+    if expiration <= now_secs {
+        return Err(FaucetError::UnresolvedName(format!(
+            "'{}' has expired",
+            name
+        )));
+    }
+    record["target_address"]["vec"]
+        .get(0)
+        .and_then(Value::as_str)
+        .and_then(|address| AccountAddress::from_hex_literal(address).ok())
+        .ok_or_else(|| {
+            FaucetError::UnresolvedName(format!("'{}' does not point to an address", name))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn names_are_parsed() {
+        let name: Name = "Alice.apt".parse().unwrap();
+        assert_eq!(
+            name,
+            Name {
+                subdomain: None,
+                domain: "alice".to_string(),
+            }
+        );
+        assert_eq!(name.to_string(), "alice.apt");
+
+        let name: Name = "wallet.alice".parse().unwrap();
+        assert_eq!(name.subdomain.as_deref(), Some("wallet"));
+        assert_eq!(name.to_string(), "wallet.alice.apt");
+
+        for invalid in &[
+            "",
+            ".apt",
+            "a.b.c.apt",
+            "-alice.apt",
+            "al ice.apt",
+            "alice..apt",
+        ] {
+            assert!(invalid.parse::<Name>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn records_point_to_their_target_until_they_expire() {
+        let name: Name = "alice.apt".parse().unwrap();
+        let record = json!({
+            "property_version": "0",
+            "expiration_time_sec": "100",
+            "target_address": { "vec": ["0x1"] },
+        });
+        assert_eq!(
+            target(&name, &record, 99).unwrap(),
+            AccountAddress::from_hex_literal("0x1").unwrap()
+        );
+        assert!(matches!(
+            target(&name, &record, 100),
+            Err(FaucetError::UnresolvedName(_))
+        ));
+
+        let record = json!({
+            "expiration_time_sec": "100",
+            "target_address": { "vec": [] },
+        });
+        assert!(matches!(
+            target(&name, &record, 99),
+            Err(FaucetError::UnresolvedName(_))
+        ));
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Append-only record of every funding decision, kept apart from the service log for compliance
+//! and abuse investigations. Each decision is a JSON object on its own line. Once the file
+//! reaches `max_bytes` it is rotated to `<path>.1`, shifting older files up to `<path>.<max_files>`.
+
+use crate::{error::FaucetError, mint::Response, request_log::current_request_id};
+use anyhow::{format_err, Result};
+use aptos_crypto::HashValue;
+use aptos_infallible::Mutex;
+use aptos_logger::error;
+use aptos_sdk::types::account_address::AccountAddress;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditLogConfig {
+    pub path: PathBuf,
+    /// Size at which the file is rotated
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+    /// Rotated files kept, older ones are deleted
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+}
+
+fn default_max_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_max_files() -> usize {
+    10
+}
+
+impl AuditLogConfig {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            max_bytes: default_max_bytes(),
+            max_files: default_max_files(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Granted,
+    Rejected,
+    /// Signed for the caller, but never submitted
+    DryRun,
+    /// Not a request, the delegated funder's authentication key was rotated
+    KeyRotated,
+}
+
+/// One line of the audit log
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    /// Id of the request, as returned in its `x-request-id` header
+    pub request_id: Option<String>,
+    pub decision: Decision,
+    /// Why the request was rejected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub client_ip: Option<IpAddr>,
+    pub receiver: Option<AccountAddress>,
+    pub requested_amount: u64,
+    /// Amount minted, after caps and the maximum amount were applied
+    pub amount: Option<u64>,
+    pub txn_hashes: Vec<HashValue>,
+}
+
+impl AuditEntry {
+    pub fn new(
+        client_ip: Option<IpAddr>,
+        receiver: Option<AccountAddress>,
+        requested_amount: u64,
+        result: &Result<(u64, Response), FaucetError>,
+    ) -> Self {
+        let (decision, reason, amount, txn_hashes) = match result {
+            Ok((amount, Response::Funded(response))) => (
+                Decision::Granted,
+                None,
+                Some(*amount),
+                response.txn_hashes.clone(),
+            ),
+            Ok((amount, Response::SubmittedTxns(txns))) => (
+                Decision::Granted,
+                None,
+                Some(*amount),
+                txns.iter()
+                    .map(|txn| txn.clone().committed_hash())
+                    .collect(),
+            ),
+            Ok((amount, Response::DryRun(_))) => {
+                (Decision::DryRun, None, Some(*amount), Vec::new())
+            }
+            Err(err) => (Decision::Rejected, Some(err.to_string()), None, Vec::new()),
+        };
+        Self {
+            timestamp: Utc::now(),
+            request_id: current_request_id(),
+            decision,
+            reason,
+            client_ip,
+            receiver,
+            requested_amount,
+            amount,
+            txn_hashes,
+        }
+    }
+
+    /// Records the rotation of `funder`'s authentication key by `txn_hash`
+    pub fn key_rotation(funder: AccountAddress, txn_hash: HashValue) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            request_id: None,
+            decision: Decision::KeyRotated,
+            reason: None,
+            client_ip: None,
+            receiver: Some(funder),
+            requested_amount: 0,
+            amount: None,
+            txn_hashes: vec![txn_hash],
+        }
+    }
+}
+
+pub struct AuditLog {
+    config: AuditLogConfig,
+    /// The open file and its size
+    file: Mutex<(File, u64)>,
+}
+
+impl AuditLog {
+    pub fn open(config: AuditLogConfig) -> Result<Self> {
+        let file = open_append(&config.path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            config,
+            file: Mutex::new((file, size)),
+        })
+    }
+
+    /// Appends `entry`. Failures are logged rather than returned, so that a full disk does not
+    /// take the faucet down
+    pub fn record(&self, entry: &AuditEntry) {
+        if let Err(err) = self.append(entry) {
+            error!(
+                "[faucet]: unable to write to audit log {:?}: {}",
+                self.config.path, err
+            );
+        }
+    }
+
+    fn append(&self, entry: &AuditEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock();
+        if file.1 > 0 && file.1 + line.len() as u64 > self.config.max_bytes {
+            *file = (self.rotate()?, 0);
+        }
+        file.0.write_all(&line)?;
+        file.1 += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shifts every file up by one, dropping the oldest, and opens a fresh file at `path`
+    fn rotate(&self) -> Result<File> {
+        let path = &self.config.path;
+        let rotated = |index: usize| PathBuf::from(format!("{}.{}", path.display(), index));
+
+        if self.config.max_files == 0 {
+            std::fs::remove_file(path)?;
+        } else {
+            for index in (1..self.config.max_files).rev() {
+                if rotated(index).exists() {
+                    std::fs::rename(rotated(index), rotated(index + 1))?;
+                }
+            }
+            std::fs::rename(path, rotated(1))?;
+        }
+        open_append(path)
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| format_err!("Unable to open audit log {:?}: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejection(reason: &str) -> AuditEntry {
+        AuditEntry {
+            // Fixed so that every line has the same length
+            timestamp: "2022-06-01T12:00:00Z".parse().unwrap(),
+            ..AuditEntry::new(
+                Some("10.0.0.1".parse().unwrap()),
+                Some(AccountAddress::new([1; AccountAddress::LENGTH])),
+                100,
+                &Err(FaucetError::Blocked(reason.to_string())),
+            )
+        }
+    }
+
+    #[test]
+    fn entries_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let line_len = serde_json::to_vec(&rejection("0")).unwrap().len() as u64 + 1;
+        let log = AuditLog::open(AuditLogConfig {
+            path: path.clone(),
+            max_bytes: line_len * 2,
+            max_files: 2,
+        })
+        .unwrap();
+
+        for reason in 0..7 {
+            log.record(&rejection(&reason.to_string()));
+        }
+
+        let read = |path: PathBuf| -> Vec<AuditEntry> {
+            std::fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        };
+        let reasons = |entries: Vec<AuditEntry>| -> Vec<String> {
+            entries
+                .into_iter()
+                .map(|entry| entry.reason.unwrap())
+                .collect()
+        };
+        assert_eq!(reasons(read(path.clone())), vec!["6"]);
+        assert_eq!(
+            reasons(read(dir.path().join("audit.jsonl.1"))),
+            vec!["4", "5"]
+        );
+        assert_eq!(
+            reasons(read(dir.path().join("audit.jsonl.2"))),
+            vec!["2", "3"]
+        );
+        assert!(!dir.path().join("audit.jsonl.3").exists());
+
+        let entry = read(path).remove(0);
+        assert_eq!(entry.decision, Decision::Rejected);
+        assert_eq!(entry.amount, None);
+    }
+}
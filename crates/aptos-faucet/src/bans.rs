@@ -0,0 +1,341 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Temporarily bans clients that keep breaking the rules. Each client IP's violations, requests
+//! refused for its own doing and failed signature challenges, are counted per minute, and a
+//! client going over a threshold is banned. Each further ban of a client lasts twice as long as
+//! its previous one. Bans are listed by `GET /admin/bans`, and saved to `state_file` when set, so
+//! that restarts do not lift them.
+
+use crate::{counters, error::FaucetError};
+use anyhow::{bail, format_err, Result};
+use aptos_infallible::Mutex;
+use aptos_logger::{error, info};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Violations are counted over fixed windows of this length
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Clients whose violations are counted at once, the counts of older windows are dropped beyond
+const MAX_TRACKED_CLIENTS: usize = 100_000;
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BanConfig {
+    /// Refused requests per minute that get a client banned
+    pub max_rejections_per_minute: u32,
+    /// Failed signature challenges per minute that get a client banned
+    pub max_challenge_failures_per_minute: u32,
+    /// Length of a client's first ban, each further ban lasts twice as long as the previous one
+    pub ban_secs: u64,
+    /// Longest a ban lasts. A client not banned again for this long after its last ban ended
+    /// starts over with a first ban
+    pub max_ban_secs: u64,
+    /// File the bans are saved to, and loaded from at startup
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_file: Option<PathBuf>,
+}
+
+impl BanConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.ban_secs == 0 {
+            bail!("bans.ban_secs must be at least 1");
+        }
+        if self.max_ban_secs < self.ban_secs {
+            bail!("bans.max_ban_secs must be at least bans.ban_secs");
+        }
+        Ok(())
+    }
+}
+
+impl Default for BanConfig {
+    fn default() -> Self {
+        Self {
+            max_rejections_per_minute: 30,
+            max_challenge_failures_per_minute: 5,
+            ban_secs: 600,
+            max_ban_secs: 86400,
+            state_file: None,
+        }
+    }
+}
+
+/// A client's ban, as returned by `GET /admin/bans`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Ban {
+    /// Bans of the client so far, this one included
+    pub count: u32,
+    /// Why the client was banned
+    pub reason: String,
+    pub banned_until_secs: u64,
+}
+
+/// What a violation is counted as
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Violation {
+    Rejection,
+    ChallengeFailure,
+}
+
+impl Violation {
+    /// Label of the bans over this violation's threshold
+    fn label(self) -> &'static str {
+        match self {
+            Violation::Rejection => "rejections",
+            Violation::ChallengeFailure => "challenge_failures",
+        }
+    }
+
+    /// The violation a refused request counts as, if its refusal is the client's doing
+    fn of(err: &FaucetError) -> Option<Self> {
+        match err {
+            FaucetError::ChallengeFailed(_) => Some(Violation::ChallengeFailure),
+            FaucetError::InvalidParams(_)
+            | FaucetError::InvalidFields(_)
+            | FaucetError::AmountTooSmall { .. }
+            | FaucetError::UnknownChain(_)
+            | FaucetError::RateLimited { .. }
+            | FaucetError::Blocked(_)
+            | FaucetError::InvalidCoupon
+            | FaucetError::Unauthorized(_) => Some(Violation::Rejection),
+            _ => None,
+        }
+    }
+}
+
+/// Violations of a client in the current window
+struct Violations {
+    window_start: Instant,
+    rejections: u32,
+    challenge_failures: u32,
+}
+
+struct State {
+    violations: HashMap<IpAddr, Violations>,
+    /// Current and recent bans, by client
+    bans: BTreeMap<IpAddr, Ban>,
+}
+
+pub struct Bans {
+    config: BanConfig,
+    state: Mutex<State>,
+}
+
+impl Bans {
+    /// Starts with the bans saved to the config's `state_file`, if any
+    pub fn load(config: BanConfig) -> Result<Self> {
+        config.validate()?;
+        let bans = match &config.state_file {
+            Some(path) => load_bans(path)?,
+            None => BTreeMap::new(),
+        };
+        Ok(Self {
+            config,
+            state: Mutex::new(State {
+                violations: HashMap::new(),
+                bans,
+            }),
+        })
+    }
+
+    /// Time left of the ban of `client_ip`, `None` if it is not banned
+    pub fn remaining(&self, client_ip: IpAddr) -> Option<Duration> {
+        let until = self.state.lock().bans.get(&client_ip)?.banned_until_secs;
+        until
+            .checked_sub(unix_secs(SystemTime::now()))
+            .filter(|remaining| *remaining > 0)
+            .map(Duration::from_secs)
+    }
+
+    /// Counts the refusal of a request of `client_ip` against it, banning it once it goes over a
+    /// threshold
+    pub fn record(&self, client_ip: IpAddr, err: &FaucetError) {
+        let violation = match Violation::of(err) {
+            Some(violation) => violation,
+            None => return,
+        };
+        let now = Instant::now();
+        let mut state = self.state.lock();
+        if state.violations.len() >= MAX_TRACKED_CLIENTS {
+            state
+                .violations
+                .retain(|_, violations| now.duration_since(violations.window_start) < WINDOW);
+        }
+        let violations = state
+            .violations
+            .entry(client_ip)
+            .or_insert_with(|| Violations {
+                window_start: now,
+                rejections: 0,
+                challenge_failures: 0,
+            });
+        if now.duration_since(violations.window_start) >= WINDOW {
+            *violations = Violations {
+                window_start: now,
+                rejections: 0,
+                challenge_failures: 0,
+            };
+        }
+        let reason = match violation {
+            Violation::Rejection => {
+                violations.rejections += 1;
+                (violations.rejections > self.config.max_rejections_per_minute)
+                    .then(|| "too many refused requests")
+            }
+            Violation::ChallengeFailure => {
+                violations.challenge_failures += 1;
+                (violations.challenge_failures > self.config.max_challenge_failures_per_minute)
+                    .then(|| "too many failed challenges")
+            }
+        };
+        if let Some(reason) = reason {
+            state.violations.remove(&client_ip);
+            counters::BANS.with_label_values(&[violation.label()]).inc();
+            self.ban(&mut state, client_ip, reason);
+        }
+    }
+
+    /// Current bans, by client
+    pub fn bans(&self) -> BTreeMap<IpAddr, Ban> {
+        let now = unix_secs(SystemTime::now());
+        self.state
+            .lock()
+            .bans
+            .iter()
+            .filter(|(_, ban)| ban.banned_until_secs > now)
+            .map(|(client_ip, ban)| (*client_ip, ban.clone()))
+            .collect()
+    }
+
+    fn ban(&self, state: &mut State, client_ip: IpAddr, reason: &str) {
+        let now = unix_secs(SystemTime::now());
+        let forget_after = self.config.max_ban_secs;
+        state
+            .bans
+            .retain(|_, ban| now.saturating_sub(ban.banned_until_secs) < forget_after);
+
+        let count = state.bans.get(&client_ip).map_or(0, |ban| ban.count) + 1;
+        let length = self
+            .config
+            .ban_secs
+            .saturating_mul(1 << (count - 1).min(32))
+            .min(self.config.max_ban_secs);
+        let ban = Ban {
+            count,
+            reason: reason.to_string(),
+            banned_until_secs: now + length,
+        };
+        info!(
+            "[faucet]: banned {} for {} seconds, {}",
+            client_ip, length, reason
+        );
+        state.bans.insert(client_ip, ban);
+
+        if let Some(path) = &self.config.state_file {
+            if let Err(err) = save_bans(path, &state.bans) {
+                error!("[faucet]: unable to save bans: {}", err);
+            }
+        }
+    }
+}
+
+fn load_bans(path: &Path) -> Result<BTreeMap<IpAddr, Ban>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(err) => return Err(format_err!("Unable to read {:?}: {}", path, err)),
+    };
+    serde_json::from_slice(&bytes).map_err(|err| format_err!("Unable to parse {:?}: {}", path, err))
+}
+
+/// Replaces the file in one rename, so a crash never leaves it half written
+fn save_bans(path: &Path, bans: &BTreeMap<IpAddr, Ban>) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, serde_json::to_vec_pretty(bans)?)
+        .map_err(|err| format_err!("Unable to write {:?}: {}", tmp_path, err))?;
+    fs::rename(&tmp_path, path).map_err(|err| format_err!("Unable to write {:?}: {}", path, err))
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refused() -> FaucetError {
+        FaucetError::InvalidParams("nope".to_string())
+    }
+
+    #[test]
+    fn clients_over_a_threshold_are_banned_for_longer_each_time() {
+        let bans = Bans::load(BanConfig {
+            max_rejections_per_minute: 2,
+            ..BanConfig::default()
+        })
+        .unwrap();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        // Failures of the faucet itself are not the client's doing
+        for _ in 0..5 {
+            bans.record(ip, &FaucetError::Expired);
+        }
+        bans.record(ip, &refused());
+        bans.record(ip, &refused());
+        assert_eq!(bans.remaining(ip), None);
+
+        bans.record(ip, &refused());
+        let remaining = bans.remaining(ip).unwrap();
+        assert!(remaining > Duration::from_secs(590) && remaining <= Duration::from_secs(600));
+        assert!(bans.remaining("10.0.0.2".parse().unwrap()).is_none());
+
+        for _ in 0..3 {
+            bans.record(ip, &refused());
+        }
+        let remaining = bans.remaining(ip).unwrap();
+        assert!(remaining > Duration::from_secs(1190));
+        assert_eq!(bans.bans()[&ip].count, 2);
+    }
+
+    #[test]
+    fn challenge_failures_have_their_own_threshold() {
+        let bans = Bans::load(BanConfig {
+            max_challenge_failures_per_minute: 1,
+            ..BanConfig::default()
+        })
+        .unwrap();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let failed = || FaucetError::ChallengeFailed("wrong signature".to_string());
+
+        bans.record(ip, &failed());
+        assert_eq!(bans.remaining(ip), None);
+        bans.record(ip, &failed());
+        assert_eq!(bans.bans()[&ip].reason, "too many failed challenges");
+    }
+
+    #[test]
+    fn bans_survive_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = BanConfig {
+            max_rejections_per_minute: 0,
+            state_file: Some(dir.path().join("bans.json")),
+            ..BanConfig::default()
+        };
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        Bans::load(config.clone()).unwrap().record(ip, &refused());
+
+        let bans = Bans::load(config).unwrap();
+        assert!(bans.remaining(ip).is_some());
+        assert_eq!(bans.bans()[&ip].count, 1);
+    }
+}
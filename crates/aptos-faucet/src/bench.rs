@@ -0,0 +1,212 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Load generator for capacity testing a faucet, behind `aptos-faucet bench`. Mint requests for
+//! freshly generated accounts are sent at a fixed rate, whether or not earlier ones were answered,
+//! so that a saturated faucet shows up as growing latencies and errors rather than as a lower
+//! request rate.
+
+use anyhow::{bail, Result};
+use aptos_crypto::{ed25519::Ed25519PrivateKey, PrivateKey, Uniform};
+use aptos_sdk::types::transaction::authenticator::AuthenticationKey;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    time::{Duration, Instant},
+};
+use url::Url;
+
+#[derive(Clone, Debug)]
+pub struct BenchConfig {
+    /// Base URL of the faucet, e.g. `http://localhost:8000`
+    pub target: Url,
+    /// Requests sent per second
+    pub rps: u32,
+    pub duration: Duration,
+    /// Amount minted by each request, in base units
+    pub amount: u64,
+    /// Requests without a response by then are counted as unanswered
+    pub timeout: Duration,
+}
+
+/// Outcome of a single request
+enum Outcome {
+    Answered { status: u16, latency: Duration },
+    Unanswered,
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct BenchReport {
+    pub elapsed: Duration,
+    pub requests: usize,
+    pub succeeded: usize,
+    /// Requests answered with an error, by status code
+    pub failed: BTreeMap<u16, usize>,
+    /// Requests that failed to connect or timed out
+    pub unanswered: usize,
+    /// Of the answered requests
+    pub latency: Latencies,
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct Latencies {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Latencies {
+    fn new(mut latencies: Vec<Duration>) -> Self {
+        latencies.sort_unstable();
+        let percentile = |p: usize| match latencies.len() {
+            0 => Duration::ZERO,
+            len => latencies[(len * p / 100).min(len - 1)],
+        };
+        Self {
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: latencies.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+impl BenchReport {
+    /// Share of the requests that were not funded
+    pub fn error_rate(&self) -> f64 {
+        match self.requests {
+            0 => 0.0,
+            requests => (requests - self.succeeded) as f64 / requests as f64,
+        }
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Requests:    {} in {:.1}s ({:.1}/s)",
+            self.requests,
+            self.elapsed.as_secs_f64(),
+            self.requests as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+        )?;
+        writeln!(f, "Succeeded:   {}", self.succeeded)?;
+        for (status, count) in &self.failed {
+            writeln!(f, "Status {}:  {}", status, count)?;
+        }
+        writeln!(f, "Unanswered:  {}", self.unanswered)?;
+        writeln!(f, "Error rate:  {:.2}%", self.error_rate() * 100.0)?;
+        write!(
+            f,
+            "Latency:     p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+            self.latency.p50, self.latency.p90, self.latency.p99, self.latency.max
+        )
+    }
+}
+
+/// Sends `config.rps` mint requests per second to `config.target` for `config.duration`, then
+/// waits for the last ones to be answered
+pub async fn run(config: BenchConfig) -> Result<BenchReport> {
+    if config.rps == 0 {
+        bail!("rps must be at least 1");
+    }
+    let client = reqwest::Client::builder().timeout(config.timeout).build()?;
+    let mut target = config.target;
+    if !target.path().ends_with('/') {
+        target.set_path(&format!("{}/", target.path()));
+    }
+    let mint_url = target.join("mint")?;
+
+    let start = Instant::now();
+    let mut interval = tokio::time::interval(Duration::from_secs(1) / config.rps);
+    let mut requests = Vec::new();
+    while start.elapsed() < config.duration {
+        interval.tick().await;
+        requests.push(tokio::spawn(send(
+            client.clone(),
+            mint_url.clone(),
+            config.amount,
+        )));
+    }
+
+    let mut report = BenchReport {
+        requests: requests.len(),
+        ..BenchReport::default()
+    };
+    let mut latencies = Vec::with_capacity(requests.len());
+    for request in requests {
+        match request.await? {
+            Outcome::Answered { status, latency } => {
+                if (200..300).contains(&status) {
+                    report.succeeded += 1;
+                } else {
+                    *report.failed.entry(status).or_default() += 1;
+                }
+                latencies.push(latency);
+            }
+            Outcome::Unanswered => report.unanswered += 1,
+        }
+    }
+    report.elapsed = start.elapsed();
+    report.latency = Latencies::new(latencies);
+    Ok(report)
+}
+
+/// Requests `amount` for a newly generated account
+async fn send(client: reqwest::Client, mut url: Url, amount: u64) -> Outcome {
+    let key = Ed25519PrivateKey::generate(&mut rand::rngs::OsRng);
+    let address = AuthenticationKey::ed25519(&key.public_key()).derived_address();
+    url.query_pairs_mut()
+        .append_pair("address", &address.to_hex_literal())
+        .append_pair("amount", &amount.to_string());
+
+    let start = Instant::now();
+    match client.post(url).send().await {
+        Ok(response) => Outcome::Answered {
+            status: response.status().as_u16(),
+            latency: start.elapsed(),
+        },
+        Err(_) => Outcome::Unanswered,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::setup_service;
+
+    #[tokio::test]
+    async fn requests_are_reported() {
+        let (_chain, service) = setup_service(Some(100));
+        let (address, server) =
+            warp::serve(crate::routes(service)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let report = run(BenchConfig {
+            target: Url::parse(&format!("http://{}/", address)).unwrap(),
+            rps: 20,
+            duration: Duration::from_millis(500),
+            amount: 10,
+            timeout: Duration::from_secs(10),
+        })
+        .await
+        .unwrap();
+
+        assert!(report.requests >= 5, "{}", report);
+        assert_eq!(report.succeeded, report.requests, "{}", report);
+        assert_eq!(report.unanswered, 0);
+        assert!(report.latency.p50 <= report.latency.p99);
+        assert!(report.latency.max > Duration::ZERO);
+        assert_eq!(report.error_rate(), 0.0);
+    }
+
+    #[test]
+    fn percentiles() {
+        let latencies = Latencies::new((1..=100).rev().map(Duration::from_millis).collect());
+        assert_eq!(latencies.p50, Duration::from_millis(51));
+        assert_eq!(latencies.p90, Duration::from_millis(91));
+        assert_eq!(latencies.p99, Duration::from_millis(100));
+        assert_eq!(latencies.max, Duration::from_millis(100));
+    }
+}
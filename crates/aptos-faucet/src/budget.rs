@@ -0,0 +1,148 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! A service-wide cap on the amount minted per time window. Unlike per-client limits, this bounds
+//! how fast the mint account can be drained no matter how requests are spread across clients.
+
+use aptos_infallible::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Returned when a request would take the faucet over its [`SpendBudget`]
+#[derive(Debug)]
+pub struct BudgetExhausted {
+    /// Time until the current window ends and the budget is replenished
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for BudgetExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The faucet has reached its minting budget, try again in {} seconds",
+            self.retry_after.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for BudgetExhausted {}
+
+/// What is left of a [`SpendBudget`]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BudgetQuota {
+    pub limit: u64,
+    pub remaining: u64,
+    /// Seconds until the window ends and the budget is replenished
+    pub resets_in_secs: u64,
+}
+
+struct Window {
+    start: Instant,
+    spent: u64,
+}
+
+/// Allows at most `limit` coins to be minted per fixed `window`
+pub struct SpendBudget {
+    limit: u64,
+    window: Duration,
+    current: Mutex<Window>,
+}
+
+impl SpendBudget {
+    pub fn new(limit: u64, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            current: Mutex::new(Window {
+                start: Instant::now(),
+                spent: 0,
+            }),
+        }
+    }
+
+    /// Reserves `amount` from the current window, failing if that would exceed the limit
+    pub fn try_spend(&self, amount: u64) -> Result<(), BudgetExhausted> {
+        let mut current = self.current.lock();
+        let elapsed = current.start.elapsed();
+        if elapsed >= self.window {
+            current.start = Instant::now();
+            current.spent = 0;
+        }
+
+        match current.spent.checked_add(amount) {
+            Some(spent) if spent <= self.limit => {
+                current.spent = spent;
+                Ok(())
+            }
+            _ => Err(BudgetExhausted {
+                retry_after: self.window.saturating_sub(current.start.elapsed()),
+            }),
+        }
+    }
+
+    /// Returns a reservation that was never minted, e.g. because submission failed
+    pub fn refund(&self, amount: u64) {
+        let mut current = self.current.lock();
+        current.spent = current.spent.saturating_sub(amount);
+    }
+
+    /// Amount that can still be minted in the current window
+    pub fn remaining(&self) -> u64 {
+        let current = self.current.lock();
+        if current.start.elapsed() >= self.window {
+            self.limit
+        } else {
+            self.limit.saturating_sub(current.spent)
+        }
+    }
+
+    pub fn quota(&self) -> BudgetQuota {
+        let current = self.current.lock();
+        let elapsed = current.start.elapsed();
+        let (remaining, resets_in) = if elapsed >= self.window {
+            (self.limit, self.window)
+        } else {
+            (
+                self.limit.saturating_sub(current.spent),
+                self.window - elapsed,
+            )
+        };
+        BudgetQuota {
+            limit: self.limit,
+            remaining,
+            resets_in_secs: resets_in.as_secs(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_is_enforced_within_a_window() {
+        let budget = SpendBudget::new(100, Duration::from_secs(3600));
+
+        budget.try_spend(60).unwrap();
+        let err = budget.try_spend(50).unwrap_err();
+        assert!(err.retry_after <= Duration::from_secs(3600));
+        assert_eq!(budget.remaining(), 40);
+
+        budget.refund(60);
+        budget.try_spend(100).unwrap();
+        assert_eq!(budget.remaining(), 0);
+    }
+
+    #[test]
+    fn budget_resets_with_the_window() {
+        let budget = SpendBudget::new(100, Duration::from_millis(10));
+
+        budget.try_spend(100).unwrap();
+        budget.try_spend(1).unwrap_err();
+        std::thread::sleep(Duration::from_millis(20));
+        budget.try_spend(100).unwrap();
+    }
+}
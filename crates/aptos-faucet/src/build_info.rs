@@ -0,0 +1,51 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! What the running faucet was built from, served by `GET /version`.
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Version of the faucet crate
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Short hash of the commit the faucet was built from, `unknown` outside of a git checkout
+pub const GIT_REV: &str = env!("GIT_REV");
+
+/// Seconds since the epoch at which the build script last ran, or `SOURCE_DATE_EPOCH`
+const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
+
+/// Cargo profile the faucet was built with, `release` or `debug`
+const BUILD_PROFILE: &str = env!("BUILD_PROFILE");
+
+/// Comma separated cargo features the faucet was built with, e.g. `tokio-console`
+const BUILD_FEATURES: &str = env!("BUILD_FEATURES");
+
+/// Returned by `GET /version`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BuildInfo {
+    pub version: String,
+    pub git_sha: String,
+    pub build_timestamp: Option<DateTime<Utc>>,
+    pub profile: String,
+    pub features: Vec<String>,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        Self {
+            version: VERSION.to_string(),
+            git_sha: GIT_REV.to_string(),
+            build_timestamp: BUILD_TIMESTAMP
+                .parse()
+                .ok()
+                .and_then(|secs| Utc.timestamp_opt(secs, 0).single()),
+            profile: BUILD_PROFILE.to_string(),
+            features: BUILD_FEATURES
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+}
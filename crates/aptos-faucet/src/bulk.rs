@@ -0,0 +1,246 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Funds every receiver of an uploaded list, for operators handing out grants to a whole event
+//! at once, behind `POST /admin/bulk_fund`. Lists are CSV with an `address,amount` line per
+//! receiver, or a JSON array of `{"address": ..., "amount": ...}` objects. Each receiver is
+//! funded like a mint request without any of the limits or checks, and the outcome of every row
+//! is reported back in the list's format.
+
+use crate::{
+    mint::{self, MintParams, Response},
+    receiver, Service,
+};
+use aptos_sdk::types::account_address::AccountAddress;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// Rows accepted in a single upload
+pub const MAX_ROWS: usize = 10_000;
+
+/// Rows funded at the same time
+const CONCURRENCY: usize = 10;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl Format {
+    /// The format of a body sent with `content_type`, JSON unless it says CSV
+    pub fn from_content_type(content_type: Option<&str>) -> Self {
+        match content_type {
+            Some(content_type) if content_type.contains("csv") => Format::Csv,
+            _ => Format::Json,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv",
+            Format::Json => "application/json",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Json => "json",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BulkRow {
+    #[serde(deserialize_with = "receiver::deserialize_address")]
+    pub address: AccountAddress,
+    /// In base units
+    pub amount: u64,
+}
+
+/// Parses an uploaded list, failing on the first invalid row so that nothing is funded from a
+/// list that needs fixing
+pub fn parse(format: Format, body: &[u8]) -> Result<Vec<BulkRow>, String> {
+    let rows = match format {
+        Format::Json => serde_json::from_slice::<Vec<BulkRow>>(body)
+            .map_err(|err| format!("Invalid JSON list: {}", err))?,
+        Format::Csv => parse_csv(body)?,
+    };
+    if rows.is_empty() || rows.len() > MAX_ROWS {
+        return Err(format!(
+            "The list must have between 1 and {} rows, it has {}",
+            MAX_ROWS,
+            rows.len()
+        ));
+    }
+    Ok(rows)
+}
+
+fn parse_csv(body: &[u8]) -> Result<Vec<BulkRow>, String> {
+    let body = std::str::from_utf8(body).map_err(|_| "The list is not UTF-8".to_string())?;
+    let mut rows = Vec::new();
+    for (index, line) in body.lines().enumerate() {
+        let line = line.trim();
+        // An optional header, and blank lines
+        if line.is_empty() || (index == 0 && line.starts_with("address")) {
+            continue;
+        }
+        let invalid = |what: &str| format!("Line {}: {} in '{}'", index + 1, what, line);
+        let (address, amount) = line
+            .split_once(',')
+            .ok_or_else(|| invalid("expected 'address,amount'"))?;
+        let address = receiver::parse_address("address", address.trim())
+            .map_err(|err| invalid(&err.to_string()))?;
+        let amount = amount
+            .trim()
+            .parse()
+            .map_err(|_| invalid("invalid amount"))?;
+        rows.push(BulkRow { address, amount });
+    }
+    Ok(rows)
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkStatus {
+    Funded,
+    /// The faucet runs with `dry_run`, nothing was submitted
+    DryRun,
+    Failed,
+}
+
+impl BulkStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            BulkStatus::Funded => "funded",
+            BulkStatus::DryRun => "dry_run",
+            BulkStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Outcome of a single row
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BulkResult {
+    pub address: AccountAddress,
+    pub amount: u64,
+    pub status: BulkStatus,
+    /// `0x` prefixed hashes of the submitted transactions
+    #[serde(default)]
+    pub txn_hashes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Returned by `POST /admin/bulk_fund`, with the results in the order of the list
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BulkReport {
+    pub funded: usize,
+    pub failed: usize,
+    pub results: Vec<BulkResult>,
+}
+
+impl BulkReport {
+    /// One line per row, with the transaction hashes separated by spaces
+    pub fn to_csv(&self) -> String {
+        let mut csv = "address,amount,status,txn_hashes,error\n".to_string();
+        for result in &self.results {
+            writeln!(
+                csv,
+                "{},{},{},{},\"{}\"",
+                result.address.to_hex_literal(),
+                result.amount,
+                result.status.as_str(),
+                result.txn_hashes.join(" "),
+                result.error.as_deref().unwrap_or("").replace('"', "\"\"")
+            )
+            .expect("writing to a string cannot fail");
+        }
+        csv
+    }
+}
+
+/// Funds every row, a few at a time, and reports how each went
+pub async fn fund_all(service: &Service, rows: Vec<BulkRow>) -> BulkReport {
+    let results: Vec<BulkResult> = futures::stream::iter(rows)
+        .map(|row| fund_row(service, row))
+        .buffered(CONCURRENCY)
+        .collect()
+        .await;
+    let count = |status| {
+        results
+            .iter()
+            .filter(|result| result.status == status)
+            .count()
+    };
+    BulkReport {
+        funded: count(BulkStatus::Funded),
+        failed: count(BulkStatus::Failed),
+        results,
+    }
+}
+
+async fn fund_row(service: &Service, row: BulkRow) -> BulkResult {
+    let params = MintParams {
+        address: Some(row.address.to_hex_literal()),
+        ..MintParams::default()
+    };
+    let (status, txn_hashes, error) = match mint::fund(service, params, row.amount).await {
+        Ok(Response::Funded(response)) => (
+            BulkStatus::Funded,
+            response
+                .txn_hashes
+                .iter()
+                .map(|hash| hash.to_hex_literal())
+                .collect(),
+            None,
+        ),
+        Ok(Response::SubmittedTxns(txns)) => (
+            BulkStatus::Funded,
+            txns.into_iter()
+                .map(|txn| txn.committed_hash().to_hex_literal())
+                .collect(),
+            None,
+        ),
+        Ok(Response::DryRun(_)) => (BulkStatus::DryRun, Vec::new(), None),
+        Err(err) => (BulkStatus::Failed, Vec::new(), Some(format!("{:#}", err))),
+    };
+    BulkResult {
+        address: row.address,
+        amount: row.amount,
+        status,
+        txn_hashes,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_are_parsed() {
+        let csv = "address,amount\n0x1,100\n\n  0x2 , 200\n";
+        assert_eq!(
+            parse(Format::Csv, csv.as_bytes()).unwrap(),
+            vec![
+                BulkRow {
+                    address: AccountAddress::from_hex_literal("0x1").unwrap(),
+                    amount: 100,
+                },
+                BulkRow {
+                    address: AccountAddress::from_hex_literal("0x2").unwrap(),
+                    amount: 200,
+                },
+            ]
+        );
+        let err = parse(Format::Csv, b"0x1,100\n0x2,lots").unwrap_err();
+        assert!(err.starts_with("Line 2: invalid amount"), "{}", err);
+        assert!(parse(Format::Csv, b"address,amount\n").is_err());
+
+        let json = r#"[{"address": "0x1", "amount": 100}]"#;
+        assert_eq!(parse(Format::Json, json.as_bytes()).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,314 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Recovers from chain resets. Devnets are wiped every so often and restarted from a new genesis,
+//! after which the funder's local sequence number is far ahead of the chain's, and a delegated
+//! funder no longer exists. Every [`CHECK_INTERVAL`] the chain is checked for a reset: its genesis
+//! transaction changed, its ledger version went backwards, or the funder vanished while the node
+//! answers. The faucet then forgets the transactions it tracked, resets its sequence numbers to
+//! the new chain's and, when delegated, delegates to a new funder, instead of failing every
+//! request until it is restarted.
+//!
+//! Nodes behind a load balancer may each be a few versions apart, so the ledger version going
+//! backwards only counts as a reset when the genesis transaction cannot be looked up, e.g. on
+//! nodes that pruned it.
+
+use crate::{counters, Service};
+use anyhow::Result;
+use aptos_infallible::Mutex;
+use aptos_logger::{info, warn};
+use aptos_rest_client::aptos_api_types::HashValue;
+use aptos_sdk::types::account_address::AccountAddress;
+use std::{fmt, sync::Weak, time::Duration};
+
+/// How often the chain is checked for a reset
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Why the chain is deemed reset
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChainReset {
+    GenesisChanged { from: HashValue, to: HashValue },
+    VersionWentBackwards { from: u64, to: u64 },
+    FunderVanished(AccountAddress),
+}
+
+impl ChainReset {
+    /// Label of the reason in metrics
+    fn reason(&self) -> &'static str {
+        match self {
+            ChainReset::GenesisChanged { .. } => "genesis_changed",
+            ChainReset::VersionWentBackwards { .. } => "version_went_backwards",
+            ChainReset::FunderVanished(_) => "funder_vanished",
+        }
+    }
+}
+
+impl fmt::Display for ChainReset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChainReset::GenesisChanged { from, to } => {
+                write!(f, "the genesis changed from {} to {}", from, to)
+            }
+            ChainReset::VersionWentBackwards { from, to } => {
+                write!(f, "the ledger version went back from {} to {}", from, to)
+            }
+            ChainReset::FunderVanished(address) => write!(f, "the funder {} vanished", address),
+        }
+    }
+}
+
+/// What the chain looked like at the previous checks
+#[derive(Default)]
+struct Seen {
+    genesis: Option<HashValue>,
+    highest_version: u64,
+    /// The funder, once it was found on chain
+    funder: Option<AccountAddress>,
+}
+
+impl Seen {
+    /// Compares the chain with what was seen before, then remembers it
+    fn update(
+        &mut self,
+        genesis: Option<HashValue>,
+        version: u64,
+        funder: AccountAddress,
+        funder_found: bool,
+    ) -> Option<ChainReset> {
+        let reset = match (self.genesis, genesis) {
+            (Some(from), Some(to)) if from != to => Some(ChainReset::GenesisChanged { from, to }),
+            (_, None) if version < self.highest_version => Some(ChainReset::VersionWentBackwards {
+                from: self.highest_version,
+                to: version,
+            }),
+            _ if !funder_found && self.funder == Some(funder) => {
+                Some(ChainReset::FunderVanished(funder))
+            }
+            _ => None,
+        };
+
+        if reset.is_some() {
+            *self = Seen::default();
+        }
+        self.genesis = genesis.or(self.genesis);
+        self.highest_version = self.highest_version.max(version);
+        if funder_found {
+            self.funder = Some(funder);
+        }
+        reset
+    }
+}
+
+/// The chain as seen by [`check`]
+#[derive(Default)]
+pub(crate) struct ChainWatch {
+    seen: Mutex<Seen>,
+    /// A reset whose recovery failed, retried at the next check
+    unrecovered: Mutex<Option<ChainReset>>,
+}
+
+/// Checks for a reset every [`CHECK_INTERVAL`] while the service leads, until it is dropped
+pub(crate) async fn run(service: Weak<Service>) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let service = match service.upgrade() {
+            Some(service) => service,
+            None => return,
+        };
+        if !service.is_leader() {
+            continue;
+        }
+        if let Err(err) = check(&service).await {
+            warn!("[faucet]: unable to check for a chain reset: {:#}", err);
+        }
+    }
+}
+
+/// Looks for signs that the chain was reset since the previous check, recovering from it if so.
+/// Returns why the chain was deemed reset
+pub async fn check(service: &Service) -> Result<Option<ChainReset>> {
+    let ledger = service.client.get_ledger_information();
+    let version = counters::node_request("get_ledger_information", ledger)
+        .await?
+        .into_inner()
+        .version;
+    let genesis = service.client.get_transaction_by_version(0);
+    let genesis = counters::node_request("get_transaction", genesis)
+        .await
+        .ok()
+        .and_then(|txn| Some(txn.inner().transaction_info().ok()?.hash));
+    let funder = service.faucet_account.lock().await.address();
+    let funder_found = counters::node_request("get_account", service.client.get_account(funder))
+        .await
+        .is_ok();
+
+    let watch = &service.chain_watch;
+    let detected = watch
+        .seen
+        .lock()
+        .update(genesis, version, funder, funder_found);
+    if let Some(reset) = &detected {
+        warn!("[faucet]: the chain was reset, {}, recovering", reset);
+        counters::CHAIN_RESETS
+            .with_label_values(&[reset.reason()])
+            .inc();
+    }
+    let reset = match detected.or_else(|| watch.unrecovered.lock().take()) {
+        Some(reset) => reset,
+        None => return Ok(None),
+    };
+    if let Err(err) = recover(service, genesis).await {
+        *watch.unrecovered.lock() = Some(reset);
+        return Err(err);
+    }
+    Ok(Some(reset))
+}
+
+/// Starts over on the chain with the genesis `genesis`
+async fn recover(service: &Service, genesis: Option<HashValue>) -> Result<()> {
+    match &service.delegation {
+        // The mint capability was delegated on the previous chain
+        Some(delegation) => {
+            reset_sequence_number(&delegation.root).await?;
+            service.outstanding.clear();
+            service.redelegate().await?;
+            if let Some(cache) = &service.funder_cache {
+                cache.invalidate().await;
+            }
+        }
+        None => {
+            let onchain_seq = reset_sequence_number(service).await?;
+            if let Some(leases) = &service.sequence_leases {
+                // Replicas agree on the chain by its genesis, without one each starts over
+                let chain = genesis.map_or_else(
+                    || uuid::Uuid::new_v4().to_string(),
+                    |genesis| genesis.to_string(),
+                );
+                leases.reset(onchain_seq, &chain).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Forgets the funder's transactions and resets its sequence number to the on-chain one,
+/// returning it
+async fn reset_sequence_number(service: &Service) -> Result<u64> {
+    service.outstanding.clear();
+    if let Some(cache) = &service.funder_cache {
+        cache.invalidate().await;
+    }
+    let mut funder = service.faucet_account.lock().await;
+    let address = funder.address();
+    let account =
+        counters::node_request("get_account", service.client.get_account(address)).await?;
+    let onchain_seq = account.inner().sequence_number;
+    info!(
+        "[faucet]: resetting the sequence number of {} from {} to {}",
+        address,
+        funder.sequence_number(),
+        onchain_seq
+    );
+    *funder.sequence_number_mut() = onchain_seq;
+    Ok(onchain_seq)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        delegate_mint_account,
+        mint::{self, MintParams},
+        test_utils::{setup_service, InjectedFailures, MockAccount},
+        Endowment,
+    };
+    use warp::http::StatusCode;
+
+    fn params(receiver: AccountAddress) -> MintParams {
+        MintParams {
+            address: Some(receiver.to_hex_literal()),
+            wait: Some(true),
+            ..MintParams::default()
+        }
+    }
+
+    #[test]
+    fn lagging_nodes_are_not_resets() {
+        let (genesis, funder) = (
+            HashValue::from(aptos_crypto::HashValue::random()),
+            AccountAddress::random(),
+        );
+        let mut seen = Seen::default();
+        assert_eq!(seen.update(Some(genesis), 10, funder, true), None);
+        assert_eq!(seen.update(Some(genesis), 8, funder, true), None);
+        assert_eq!(seen.highest_version, 10);
+
+        // Without the genesis to tell, the version going backwards is a reset
+        assert_eq!(
+            seen.update(None, 9, funder, true),
+            Some(ChainReset::VersionWentBackwards { from: 10, to: 9 })
+        );
+        assert_eq!(
+            seen.update(None, 9, funder, false),
+            Some(ChainReset::FunderVanished(funder))
+        );
+        // Until the funder is found again, it is not missed
+        assert_eq!(seen.update(None, 9, funder, false), None);
+    }
+
+    #[tokio::test]
+    async fn funders_start_over_after_a_reset() {
+        let (chain, service) = setup_service(None);
+        let funder = service.faucet_account.lock().await.address();
+        mint::fund(&service, params(AccountAddress::random()), 100)
+            .await
+            .unwrap();
+        assert_eq!(check(&service).await.unwrap(), None);
+        assert_eq!(service.faucet_account.lock().await.sequence_number(), 2);
+
+        chain.reset();
+        chain.create_account(funder, MockAccount::new(0));
+        assert!(matches!(
+            check(&service).await.unwrap(),
+            Some(ChainReset::GenesisChanged { .. })
+        ));
+        assert_eq!(service.faucet_account.lock().await.sequence_number(), 0);
+        let receiver = AccountAddress::random();
+        mint::fund(&service, params(receiver), 100).await.unwrap();
+        assert_eq!(chain.account(receiver).unwrap().balance, 100);
+    }
+
+    #[tokio::test]
+    async fn delegated_funders_are_replaced_after_a_reset() {
+        let (chain, root) = setup_service(None);
+        let root_address = root.faucet_account.lock().await.address();
+        let endowment = Endowment {
+            amount: 1_000,
+            top_up_below: None,
+        };
+        let service = delegate_mint_account(root, None, endowment, None)
+            .await
+            .unwrap();
+        let previous = service.faucet_account.lock().await.address();
+        assert_eq!(check(&service).await.unwrap(), None);
+
+        chain.reset();
+        chain.create_account(root_address, MockAccount::new(0));
+        // Recovering is retried until it succeeds
+        chain.set_failures(InjectedFailures {
+            reject_submissions: Some(StatusCode::SERVICE_UNAVAILABLE),
+            ..InjectedFailures::default()
+        });
+        assert!(check(&service).await.is_err());
+        chain.set_failures(InjectedFailures::default());
+        assert!(check(&service).await.unwrap().is_some());
+        let funder = service.faucet_account.lock().await.address();
+        assert_ne!(funder, previous);
+        assert_eq!(chain.account(funder).unwrap().balance, 1_000);
+
+        let receiver = AccountAddress::random();
+        mint::fund(&service, params(receiver), 100).await.unwrap();
+        assert_eq!(chain.account(receiver).unwrap().balance, 100);
+    }
+}
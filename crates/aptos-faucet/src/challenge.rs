@@ -0,0 +1,208 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Proof that the requester controls the account being funded. The requester fetches a nonce
+//! with `GET /challenge?address=<address>`, signs the nonce's UTF-8 bytes with the private key
+//! behind the account's authentication key, and passes `nonce`, `signature` and `pub_key` along
+//! with the mint request. This stops bots from funding arbitrary pre-generated addresses.
+
+use crate::{
+    error::FaucetError,
+    receiver, rejection,
+    request_log::{request_context, with_request_id},
+    Service,
+};
+use aptos_crypto::{
+    ed25519::{Ed25519PublicKey, Ed25519Signature},
+    Signature,
+};
+use aptos_infallible::Mutex;
+use aptos_sdk::types::{
+    account_address::AccountAddress, transaction::authenticator::AuthenticationKey,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    convert::{Infallible, TryFrom},
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+pub fn challenge_routes(
+    service: Arc<Service>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    // GET /challenge?address=xxx
+    warp::path!("challenge")
+        .and(warp::get())
+        .and(warp::any().map(move || service.clone()))
+        .and(warp::query().map(move |params: ChallengeParams| params))
+        .and(request_context())
+        .and_then(|service, params, context| with_request_id(context, handle(service, params)))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChallengeParams {
+    pub address: String,
+}
+
+async fn handle(
+    service: Arc<Service>,
+    params: ChallengeParams,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let challenges = match &service.challenges {
+        Some(challenges) => challenges,
+        None => {
+            return Ok(rejection::reply(
+                StatusCode::NOT_FOUND,
+                "not_found",
+                "This faucet does not use challenges",
+            ))
+        }
+    };
+    let address = match receiver::parse_address("address", &params.address) {
+        Ok(address) => address,
+        Err(err) => return Ok(rejection::faucet_error(&FaucetError::from(err))),
+    };
+
+    Ok(Box::new(warp::reply::json(&challenges.issue(address))))
+}
+
+/// Returned by `GET /challenge`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Challenge {
+    /// Hex encoded nonce to sign
+    pub nonce: String,
+    /// Seconds until the nonce can no longer be used
+    pub expires_in_secs: u64,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum ChallengeError {
+    /// The request did not include a nonce, signature and public key
+    Missing,
+    /// The nonce was never issued, was issued for another account, or expired
+    InvalidNonce,
+    /// The public key does not match the account's authentication key
+    WrongKey,
+    InvalidSignature,
+}
+
+impl fmt::Display for ChallengeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChallengeError::Missing => write!(
+                f,
+                "This faucet requires a signed challenge: provide 'nonce', 'signature' and 'pub_key'"
+            ),
+            ChallengeError::InvalidNonce => write!(f, "Unknown or expired challenge nonce"),
+            ChallengeError::WrongKey => {
+                write!(f, "'pub_key' does not match the account's authentication key")
+            }
+            ChallengeError::InvalidSignature => write!(f, "Invalid challenge signature"),
+        }
+    }
+}
+
+impl std::error::Error for ChallengeError {}
+
+/// Nonces issued and not yet used or expired
+pub struct ChallengeStore {
+    ttl: Duration,
+    nonces: Mutex<HashMap<String, (AccountAddress, Instant)>>,
+}
+
+impl ChallengeStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            nonces: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Issues a single use nonce for funding `address`
+    pub fn issue(&self, address: AccountAddress) -> Challenge {
+        let mut bytes = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut bytes);
+        let nonce = hex::encode(bytes);
+
+        let mut nonces = self.nonces.lock();
+        let now = Instant::now();
+        nonces.retain(|_, (_, expiry)| *expiry > now);
+        nonces.insert(nonce.clone(), (address, now + self.ttl));
+
+        Challenge {
+            nonce,
+            expires_in_secs: self.ttl.as_secs(),
+        }
+    }
+
+    /// Checks that `signature` is `pub_key`'s signature of a nonce issued for `address`. The
+    /// nonce is used up, even if the signature turns out to be invalid.
+    pub fn verify(
+        &self,
+        address: AccountAddress,
+        nonce: &str,
+        pub_key: &Ed25519PublicKey,
+        signature: &str,
+    ) -> Result<(), ChallengeError> {
+        match self.nonces.lock().remove(nonce) {
+            Some((issued_for, expiry)) if issued_for == address && expiry > Instant::now() => (),
+            _ => return Err(ChallengeError::InvalidNonce),
+        }
+
+        // Accounts that do not exist yet are created with the key's derived address as both
+        // address and authentication key
+        if AuthenticationKey::ed25519(pub_key).derived_address() != address {
+            return Err(ChallengeError::WrongKey);
+        }
+
+        let signature = hex::decode(signature.trim_start_matches("0x"))
+            .ok()
+            .and_then(|bytes| Ed25519Signature::try_from(bytes.as_slice()).ok())
+            .ok_or(ChallengeError::InvalidSignature)?;
+        signature
+            .verify_arbitrary_msg(nonce.as_bytes(), pub_key)
+            .map_err(|_| ChallengeError::InvalidSignature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_crypto::{ed25519::Ed25519PrivateKey, PrivateKey, SigningKey, Uniform};
+
+    #[test]
+    fn nonces_are_single_use_and_bound_to_the_account() {
+        let store = ChallengeStore::new(Duration::from_secs(60));
+        let key = Ed25519PrivateKey::generate(&mut rand::rngs::OsRng);
+        let pub_key = key.public_key();
+        let address = AuthenticationKey::ed25519(&pub_key).derived_address();
+        let sign =
+            |nonce: &str| hex::encode(key.sign_arbitrary_message(nonce.as_bytes()).to_bytes());
+
+        let challenge = store.issue(address);
+        let signature = sign(&challenge.nonce);
+        store
+            .verify(address, &challenge.nonce, &pub_key, &signature)
+            .unwrap();
+        assert_eq!(
+            store.verify(address, &challenge.nonce, &pub_key, &signature),
+            Err(ChallengeError::InvalidNonce)
+        );
+
+        let challenge = store.issue(AccountAddress::random());
+        assert_eq!(
+            store.verify(address, &challenge.nonce, &pub_key, &sign(&challenge.nonce)),
+            Err(ChallengeError::InvalidNonce)
+        );
+
+        let challenge = store.issue(address);
+        assert_eq!(
+            store.verify(address, &challenge.nonce, &pub_key, &sign("something else")),
+            Err(ChallengeError::InvalidSignature)
+        );
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fault injection, so that SDK and wallet teams can test their retry logic against a faucet
+//! failing the way a loaded one does. A share of the requests to the faucet's API is answered
+//! with a `429` or a `500` without being handled, and another share is delayed before being
+//! handled. Injected responses carry an [`INJECTED_HEADER`]. Health, readiness, metrics and
+//! admin requests are left alone.
+
+use crate::{counters, error::FaucetError, rejection};
+use anyhow::{bail, format_err, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use warp::{Filter, Rejection, Reply};
+
+/// Set on responses made up by chaos mode
+pub const INJECTED_HEADER: &str = "x-faucet-chaos";
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChaosConfig {
+    /// Share of requests answered with a `429`, from 0 to 1
+    pub rate_limited: f64,
+    /// Share of requests answered with a `500`, from 0 to 1
+    pub server_error: f64,
+    /// Share of requests delayed by `delay_ms` before being answered, from 0 to 1
+    pub delayed: f64,
+    pub delay_ms: u64,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            rate_limited: 0.05,
+            server_error: 0.05,
+            delayed: 0.1,
+            delay_ms: 2_000,
+        }
+    }
+}
+
+impl ChaosConfig {
+    pub fn validate(&self) -> Result<()> {
+        for (name, share) in [
+            ("rate_limited", self.rate_limited),
+            ("server_error", self.server_error),
+            ("delayed", self.delayed),
+        ] {
+            if !(0.0..=1.0).contains(&share) {
+                bail!("chaos.{} must be between 0 and 1", name);
+            }
+        }
+        if self.rate_limited + self.server_error > 1.0 {
+            bail!("chaos.rate_limited and chaos.server_error must add up to at most 1");
+        }
+        Ok(())
+    }
+}
+
+/// Replies with an injected error, after an injected delay if any, and otherwise rejects, so
+/// that the request goes on to the actual routes once the delay has passed
+pub fn inject(
+    config: Option<ChaosConfig>,
+) -> impl Filter<Extract = (Box<dyn Reply>,), Error = Rejection> + Clone {
+    warp::any().and_then(move || {
+        let config = config.clone();
+        async move {
+            let config = match config {
+                Some(config) => config,
+                None => return Err(warp::reject::not_found()),
+            };
+            let (fault, delayed) = {
+                let mut rng = rand::thread_rng();
+                (rng.gen::<f64>(), rng.gen::<f64>() < config.delayed)
+            };
+
+            if delayed {
+                counters::CHAOS.with_label_values(&["delayed"]).inc();
+                tokio::time::sleep(Duration::from_millis(config.delay_ms)).await;
+            }
+            if fault < config.rate_limited {
+                counters::CHAOS.with_label_values(&["rate_limited"]).inc();
+                Ok(injected(FaucetError::RateLimited {
+                    message: "Too many requests, try again shortly".to_string(),
+                    retry_after: Duration::from_secs(1),
+                }))
+            } else if fault < config.rate_limited + config.server_error {
+                counters::CHAOS.with_label_values(&["server_error"]).inc();
+                Ok(injected(FaucetError::Node(format_err!(
+                    "Unable to reach the node"
+                ))))
+            } else {
+                Err(warp::reject::not_found())
+            }
+        }
+    })
+}
+
+/// Replies like the faucet does when refusing or failing a request with `err`, so that clients
+/// cannot tell them apart but for the [`INJECTED_HEADER`]
+fn injected(err: FaucetError) -> Box<dyn Reply> {
+    Box::new(warp::reply::with_header(
+        rejection::faucet_error(&err),
+        INJECTED_HEADER,
+        "injected",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rejection::RejectionBody;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn faults_are_injected() {
+        let always_rate_limited = ChaosConfig {
+            rate_limited: 1.0,
+            delayed: 0.0,
+            ..ChaosConfig::default()
+        };
+        let reply = warp::test::request()
+            .reply(&inject(Some(always_rate_limited)))
+            .await;
+        assert_eq!(reply.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(reply.headers()[INJECTED_HEADER], "injected");
+        assert_eq!(reply.headers()["retry-after"], "1");
+        let body: RejectionBody = serde_json::from_slice(reply.body()).unwrap();
+        assert_eq!(body.error, "rate_limited");
+
+        let never = ChaosConfig {
+            rate_limited: 0.0,
+            server_error: 0.0,
+            delayed: 0.0,
+            ..ChaosConfig::default()
+        };
+        assert!(!warp::test::request().matches(&inject(Some(never))).await);
+        assert!(!warp::test::request().matches(&inject(None)).await);
+
+        let invalid = ChaosConfig {
+            rate_limited: 0.6,
+            server_error: 0.6,
+            ..ChaosConfig::default()
+        };
+        assert!(invalid.validate().is_err());
+        assert!(ChaosConfig::default().validate().is_ok());
+    }
+}
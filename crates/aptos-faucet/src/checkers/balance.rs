@@ -0,0 +1,136 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Stops already rich accounts from being funded again, by looking up the receiver's balance
+//! on chain.
+
+use super::{CheckRequest, Checker, Verdict};
+use crate::counters;
+use aptos_rest_client::Client;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+/// What happens to requests for receivers holding too much
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceAction {
+    /// Refuse receivers holding more than `max_balance`
+    Deny,
+    /// Refuse requests that would bring the receiver above `max_balance`
+    DenyIfExceeded,
+    /// Mint at most enough to bring the receiver up to `max_balance`
+    Reduce,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BalanceConfig {
+    pub max_balance: u64,
+    pub action: BalanceAction,
+}
+
+/// Receivers whose balance cannot be looked up are allowed
+pub struct BalanceChecker {
+    client: Client,
+    config: BalanceConfig,
+}
+
+impl BalanceChecker {
+    pub fn new(client: Client, config: BalanceConfig) -> Self {
+        Self { client, config }
+    }
+}
+
+#[async_trait]
+impl Checker for BalanceChecker {
+    async fn check(&self, request: &CheckRequest) -> Verdict {
+        let receiver = match request.receiver {
+            Some(receiver) => receiver,
+            None => return Verdict::Allow,
+        };
+        let balance = self.client.get_account_balance(receiver);
+        let balance = match counters::node_request("get_account_balance", balance).await {
+            Ok(balance) => balance.into_inner().get(),
+            // Usually an account that does not exist yet
+            Err(_) => return Verdict::Allow,
+        };
+
+        let max_balance = self.config.max_balance;
+        match self.config.action {
+            BalanceAction::Deny if balance > max_balance => Verdict::Deny(format!(
+                "Account already holds more than {} coins",
+                max_balance
+            )),
+            BalanceAction::DenyIfExceeded
+                if balance.saturating_add(request.amount) > max_balance =>
+            {
+                Verdict::Deny(format!(
+                    "Account holds {} coins, funding it {} more would exceed the ceiling of {}",
+                    balance, request.amount, max_balance
+                ))
+            }
+            BalanceAction::Reduce if balance.saturating_add(request.amount) > max_balance => {
+                Verdict::Cap(max_balance.saturating_sub(balance))
+            }
+            _ => Verdict::Allow,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock_chain::MockAccount, test_utils::setup_fullnode};
+    use aptos_sdk::types::account_address::AccountAddress;
+
+    #[tokio::test]
+    async fn rich_receivers_are_refused_or_reduced() {
+        let rich = AccountAddress::random();
+        let (chain, endpoint) = setup_fullnode(rich);
+        chain.create_account(rich, MockAccount::new(900));
+        let client = Client::new(endpoint.parse().unwrap());
+        let check = |receiver| CheckRequest {
+            receiver: Some(receiver),
+            amount: 500,
+            ..CheckRequest::default()
+        };
+
+        let deny = BalanceChecker::new(
+            client.clone(),
+            BalanceConfig {
+                max_balance: 800,
+                action: BalanceAction::Deny,
+            },
+        );
+        assert!(matches!(deny.check(&check(rich)).await, Verdict::Deny(_)));
+        let unknown = AccountAddress::random();
+        assert_eq!(deny.check(&check(unknown)).await, Verdict::Allow);
+
+        let deny_if_exceeded = BalanceChecker::new(
+            client.clone(),
+            BalanceConfig {
+                max_balance: 1200,
+                action: BalanceAction::DenyIfExceeded,
+            },
+        );
+        match deny_if_exceeded.check(&check(rich)).await {
+            Verdict::Deny(reason) => assert!(reason.contains("ceiling of 1200"), "{}", reason),
+            verdict => panic!("unexpected verdict {:?}", verdict),
+        }
+        let small = CheckRequest {
+            amount: 300,
+            ..check(rich)
+        };
+        assert_eq!(deny_if_exceeded.check(&small).await, Verdict::Allow);
+
+        let reduce = BalanceChecker::new(
+            client,
+            BalanceConfig {
+                max_balance: 1000,
+                action: BalanceAction::Reduce,
+            },
+        );
+        assert_eq!(reduce.check(&check(rich)).await, Verdict::Cap(100));
+        assert_eq!(reduce.check(&check(unknown)).await, Verdict::Allow);
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Halves the amount an address may be minted with each further request it makes within a
+//! window, rather than refusing it outright, so that repeated testing keeps working while
+//! draining the faucet through one address gets quickly unrewarding. Requests are counted when
+//! checked, whether or not they end up funded.
+
+use super::{CheckRequest, Checker, Verdict};
+use aptos_infallible::Mutex;
+use aptos_sdk::types::account_address::AccountAddress;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DecayConfig {
+    /// Cap on an address's first request in a window, halved for each further one
+    pub maximum_amount: u64,
+    /// Length of the window, starting with an address's first request
+    pub window_secs: u64,
+    /// Requests are never capped below this
+    pub floor_amount: u64,
+}
+
+impl Default for DecayConfig {
+    fn default() -> Self {
+        Self {
+            maximum_amount: 100_000_000,
+            window_secs: 86400,
+            floor_amount: 1,
+        }
+    }
+}
+
+struct Window {
+    start: Instant,
+    requests: u32,
+}
+
+pub struct DecayChecker {
+    config: DecayConfig,
+    windows: Mutex<HashMap<AccountAddress, Window>>,
+}
+
+impl DecayChecker {
+    pub fn new(config: DecayConfig) -> Self {
+        Self {
+            config,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a request for `receiver`, returning the most it may be minted
+    fn cap(&self, receiver: AccountAddress, now: Instant) -> u64 {
+        let window = Duration::from_secs(self.config.window_secs);
+        let mut windows = self.windows.lock();
+        windows.retain(|_, current| now.saturating_duration_since(current.start) < window);
+
+        let current = windows.entry(receiver).or_insert(Window {
+            start: now,
+            requests: 0,
+        });
+        let halvings = current.requests;
+        current.requests = current.requests.saturating_add(1);
+        self.config
+            .maximum_amount
+            .checked_shr(halvings)
+            .unwrap_or(0)
+            .max(self.config.floor_amount)
+    }
+}
+
+#[async_trait]
+impl Checker for DecayChecker {
+    async fn check(&self, request: &CheckRequest) -> Verdict {
+        match request.receiver {
+            Some(receiver) => Verdict::Cap(self.cap(receiver, Instant::now())),
+            None => Verdict::Allow,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cap_halves_with_each_request() {
+        let checker = DecayChecker::new(DecayConfig {
+            maximum_amount: 1000,
+            window_secs: 3600,
+            floor_amount: 100,
+        });
+        let receiver = AccountAddress::random();
+        let now = Instant::now();
+
+        assert_eq!(checker.cap(receiver, now), 1000);
+        assert_eq!(checker.cap(receiver, now), 500);
+        assert_eq!(checker.cap(receiver, now), 250);
+        assert_eq!(checker.cap(receiver, now), 125);
+        assert_eq!(checker.cap(receiver, now), 100);
+        assert_eq!(checker.cap(AccountAddress::random(), now), 1000);
+
+        // The allowance is restored once the window has passed
+        let later = now + Duration::from_secs(3600);
+        assert_eq!(checker.cap(receiver, later), 1000);
+    }
+}
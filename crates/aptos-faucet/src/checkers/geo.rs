@@ -0,0 +1,223 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Denies or caps requests by the client's country, as required by some hosted testnets'
+//! compliance policies. Countries are looked up in the MaxMind GeoLite2/GeoIP2 Country database,
+//! loaded from its CSV distribution.
+
+use super::{CheckRequest, Checker, Verdict};
+use crate::ip_limit::canonical;
+use anyhow::{format_err, Result};
+use async_trait::async_trait;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeoConfig {
+    /// `GeoLite2-Country-Blocks-IPv4.csv` and `GeoLite2-Country-Blocks-IPv6.csv`, or any subset
+    pub blocks_files: Vec<PathBuf>,
+    /// `GeoLite2-Country-Locations-en.csv`, mapping the blocks' geoname ids to countries
+    pub locations_file: PathBuf,
+    /// ISO 3166-1 alpha-2 codes of countries whose requests are refused
+    pub deny_countries: Vec<String>,
+    /// ISO 3166-1 alpha-2 codes of countries whose requests are capped at
+    /// `throttle_maximum_amount`
+    pub throttle_countries: Vec<String>,
+    pub throttle_maximum_amount: u64,
+}
+
+/// Maps IP ranges to ISO country codes
+#[derive(Default)]
+pub struct GeoIpDatabase {
+    v4: Vec<(u32, u32, String)>,
+    v6: Vec<(u128, u128, String)>,
+}
+
+impl GeoIpDatabase {
+    /// Builds a database from networks and the country each belongs to
+    pub fn from_networks(networks: impl IntoIterator<Item = (IpNet, String)>) -> Self {
+        let mut database = GeoIpDatabase::default();
+        for (network, country) in networks {
+            match network {
+                IpNet::V4(net) => {
+                    database
+                        .v4
+                        .push((net.network().into(), net.broadcast().into(), country))
+                }
+                IpNet::V6(net) => {
+                    database
+                        .v6
+                        .push((net.network().into(), net.broadcast().into(), country))
+                }
+            }
+        }
+        database.v4.sort_by_key(|(start, _, _)| *start);
+        database.v6.sort_by_key(|(start, _, _)| *start);
+        database
+    }
+
+    /// Loads the MaxMind Country CSV database
+    pub fn load_csv(blocks_files: &[PathBuf], locations_file: &Path) -> Result<Self> {
+        let locations = read(locations_file)?;
+        let countries = parse_locations(&locations);
+
+        let mut networks = Vec::new();
+        for blocks_file in blocks_files {
+            let blocks = read(blocks_file)?;
+            networks.extend(parse_blocks(&blocks, &countries).map_err(|err| {
+                format_err!("Unable to parse GeoIP blocks {:?}: {}", blocks_file, err)
+            })?);
+        }
+        Ok(Self::from_networks(networks))
+    }
+
+    /// The ISO country code `ip` is registered in, if known
+    pub fn country(&self, ip: IpAddr) -> Option<&str> {
+        match canonical(ip) {
+            IpAddr::V4(ip) => lookup(&self.v4, u32::from(ip)),
+            IpAddr::V6(ip) => lookup(&self.v6, u128::from(ip)),
+        }
+    }
+}
+
+fn lookup<T: Ord + Copy>(ranges: &[(T, T, String)], ip: T) -> Option<&str> {
+    // Networks do not overlap, so only the last range starting at or before `ip` can hold it
+    let index = ranges.partition_point(|(start, _, _)| *start <= ip);
+    let (_, end, country) = ranges.get(index.checked_sub(1)?)?;
+    (ip <= *end).then(|| country.as_str())
+}
+
+fn read(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path)
+        .map_err(|err| format_err!("Unable to read GeoIP database {:?}: {}", path, err))
+}
+
+/// Reads `geoname_id,locale_code,continent_code,continent_name,country_iso_code,...` rows
+fn parse_locations(contents: &str) -> HashMap<&str, String> {
+    contents
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut columns = line.split(',');
+            let geoname_id = columns.next()?;
+            let country = columns.nth(3)?;
+            (!country.is_empty()).then(|| (geoname_id, country.to_uppercase()))
+        })
+        .collect()
+}
+
+/// Reads `network,geoname_id,registered_country_geoname_id,...` rows
+fn parse_blocks(contents: &str, countries: &HashMap<&str, String>) -> Result<Vec<(IpNet, String)>> {
+    let mut networks = Vec::new();
+    for line in contents.lines().skip(1) {
+        let mut columns = line.split(',');
+        let network: IpNet = match columns.next() {
+            Some(network) if !network.is_empty() => network.parse()?,
+            _ => continue,
+        };
+        let geoname_id = columns.next().unwrap_or_default();
+        let registered_geoname_id = columns.next().unwrap_or_default();
+        if let Some(country) = countries
+            .get(geoname_id)
+            .or_else(|| countries.get(registered_geoname_id))
+        {
+            networks.push((network, country.clone()));
+        }
+    }
+    Ok(networks)
+}
+
+pub struct GeoChecker {
+    database: GeoIpDatabase,
+    deny_countries: HashSet<String>,
+    throttle_countries: HashSet<String>,
+    throttle_maximum_amount: u64,
+}
+
+impl GeoChecker {
+    pub fn new(database: GeoIpDatabase, config: &GeoConfig) -> Self {
+        let upper = |countries: &[String]| countries.iter().map(|c| c.to_uppercase()).collect();
+        Self {
+            database,
+            deny_countries: upper(&config.deny_countries),
+            throttle_countries: upper(&config.throttle_countries),
+            throttle_maximum_amount: config.throttle_maximum_amount,
+        }
+    }
+
+    pub fn load(config: &GeoConfig) -> Result<Self> {
+        let database = GeoIpDatabase::load_csv(&config.blocks_files, &config.locations_file)?;
+        Ok(Self::new(database, config))
+    }
+}
+
+#[async_trait]
+impl Checker for GeoChecker {
+    async fn check(&self, request: &CheckRequest) -> Verdict {
+        let country = match request.client_ip.and_then(|ip| self.database.country(ip)) {
+            Some(country) => country,
+            None => return Verdict::Allow,
+        };
+
+        if self.deny_countries.contains(country) {
+            Verdict::Deny(format!("Requests from {} are not allowed", country))
+        } else if self.throttle_countries.contains(country) {
+            Verdict::Cap(self.throttle_maximum_amount)
+        } else {
+            Verdict::Allow
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCATIONS: &str = "\
+geoname_id,locale_code,continent_code,continent_name,country_iso_code,country_name,is_in_european_union
+1,en,EU,Europe,DE,Germany,1
+2,en,AS,Asia,KR,\"Korea, Republic of\",0
+";
+    const BLOCKS: &str = "\
+network,geoname_id,registered_country_geoname_id,represented_country_geoname_id,is_anonymous_proxy,is_satellite_provider
+10.0.0.0/16,1,1,,0,0
+10.1.0.0/16,,2,,0,0
+2001:db8::/32,2,2,,0,0
+";
+
+    #[tokio::test]
+    async fn countries_are_denied_and_throttled() {
+        let countries = parse_locations(LOCATIONS);
+        let database = GeoIpDatabase::from_networks(parse_blocks(BLOCKS, &countries).unwrap());
+        assert_eq!(database.country("10.0.3.4".parse().unwrap()), Some("DE"));
+        assert_eq!(database.country("10.1.0.1".parse().unwrap()), Some("KR"));
+        assert_eq!(database.country("10.2.0.1".parse().unwrap()), None);
+
+        let checker = GeoChecker::new(
+            database,
+            &GeoConfig {
+                deny_countries: vec!["kr".to_string()],
+                throttle_countries: vec!["DE".to_string()],
+                throttle_maximum_amount: 10,
+                ..GeoConfig::default()
+            },
+        );
+        let check = |ip: &str| CheckRequest {
+            client_ip: Some(ip.parse().unwrap()),
+            amount: 100,
+            ..CheckRequest::default()
+        };
+        assert_eq!(checker.check(&check("10.0.0.1")).await, Verdict::Cap(10));
+        assert!(matches!(
+            checker.check(&check("2001:db8::1")).await,
+            Verdict::Deny(_)
+        ));
+        assert_eq!(checker.check(&check("192.168.0.1")).await, Verdict::Allow);
+    }
+}
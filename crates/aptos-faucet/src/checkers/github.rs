@@ -0,0 +1,182 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Ties requests to a GitHub identity for sybil resistance. Frontends run the GitHub OAuth flow
+//! and pass the resulting access token as `Authorization: Bearer <token>`. The faucet introspects
+//! the token with GitHub's `/user` API, then enforces a minimum account age and a daily quota of
+//! requests per identity.
+
+use super::{CheckRequest, Checker, Verdict};
+use crate::request_log::current_request_id;
+use anyhow::{format_err, Result};
+use aptos_infallible::Mutex;
+use aptos_logger::warn;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitHubConfig {
+    /// GitHub API root, overridable for GitHub Enterprise
+    pub api_url: String,
+    /// Accounts younger than this are refused
+    pub min_account_age_days: u64,
+    /// Requests allowed per identity per UTC day
+    pub daily_requests: u64,
+    pub timeout_ms: u64,
+}
+
+impl Default for GitHubConfig {
+    fn default() -> Self {
+        Self {
+            api_url: "https://api.github.com".to_string(),
+            min_account_age_days: 30,
+            daily_requests: 3,
+            timeout_ms: 5000,
+        }
+    }
+}
+
+/// The subset of GitHub's `/user` response the checker uses
+#[derive(Debug, Deserialize)]
+struct GitHubUser {
+    id: u64,
+    login: String,
+    created_at: DateTime<Utc>,
+}
+
+const MISSING_TOKEN: &str =
+    "Sign in with GitHub, and pass the token as 'Authorization: Bearer <token>'";
+
+pub struct GitHubChecker {
+    config: GitHubConfig,
+    client: reqwest::Client,
+    /// Requests made per GitHub user id, on the UTC day they were made
+    usage: Mutex<HashMap<u64, (i64, u64)>>,
+}
+
+impl GitHubChecker {
+    pub fn new(config: GitHubConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            // GitHub rejects API requests without a user agent
+            .user_agent("aptos-faucet")
+            .build()?;
+        Ok(Self {
+            config,
+            client,
+            usage: Mutex::new(HashMap::new()),
+        })
+    }
+
+    async fn user(&self, token: &str) -> Result<GitHubUser> {
+        let response = self
+            .client
+            .get(format!(
+                "{}/user",
+                self.config.api_url.trim_end_matches('/')
+            ))
+            .bearer_auth(token)
+            .header("accept", "application/vnd.github+json")
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(format_err!(
+                "GitHub rejected the token: {}",
+                response.status()
+            ));
+        }
+        Ok(serde_json::from_slice(&response.bytes().await?)?)
+    }
+
+    /// Counts a request against the user's quota for today, unless it is used up
+    fn use_quota(&self, user: &GitHubUser, now: DateTime<Utc>) -> bool {
+        let today = now.timestamp().div_euclid(86400);
+        let mut usage = self.usage.lock();
+        usage.retain(|_, (day, _)| *day == today);
+
+        let (_, used) = usage.entry(user.id).or_insert((today, 0));
+        if *used >= self.config.daily_requests {
+            return false;
+        }
+        *used += 1;
+        true
+    }
+
+    fn verdict(&self, user: &GitHubUser, now: DateTime<Utc>) -> Verdict {
+        let age = now.signed_duration_since(user.created_at);
+        if age < chrono::Duration::days(self.config.min_account_age_days as i64) {
+            return Verdict::Deny(format!(
+                "GitHub account {} must be at least {} days old",
+                user.login, self.config.min_account_age_days
+            ));
+        }
+        if !self.use_quota(user, now) {
+            return Verdict::Deny(format!(
+                "GitHub account {} has used its {} requests for today",
+                user.login, self.config.daily_requests
+            ));
+        }
+        Verdict::Allow
+    }
+}
+
+#[async_trait]
+impl Checker for GitHubChecker {
+    async fn check(&self, request: &CheckRequest) -> Verdict {
+        let token = request
+            .authorization
+            .as_deref()
+            .and_then(|header| header.strip_prefix("Bearer "));
+        let token = match token {
+            Some(token) => token.trim(),
+            None => return Verdict::Deny(MISSING_TOKEN.to_string()),
+        };
+
+        match self.user(token).await {
+            Ok(user) => self.verdict(&user, Utc::now()),
+            Err(err) => {
+                warn!(
+                    request_id = current_request_id(),
+                    "[faucet]: GitHub token introspection failed: {}", err
+                );
+                Verdict::Deny("Unable to verify the GitHub token".to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn account_age_and_quota_are_enforced() {
+        let checker = GitHubChecker::new(GitHubConfig {
+            min_account_age_days: 30,
+            daily_requests: 2,
+            ..GitHubConfig::default()
+        })
+        .unwrap();
+        let now: DateTime<Utc> = "2022-06-01T12:00:00Z".parse().unwrap();
+        let user = |id, created_at: &str| GitHubUser {
+            id,
+            login: format!("user{}", id),
+            created_at: created_at.parse().unwrap(),
+        };
+
+        let young = user(1, "2022-05-20T00:00:00Z");
+        assert!(matches!(checker.verdict(&young, now), Verdict::Deny(_)));
+
+        let old = user(2, "2015-01-01T00:00:00Z");
+        assert_eq!(checker.verdict(&old, now), Verdict::Allow);
+        assert_eq!(checker.verdict(&old, now), Verdict::Allow);
+        assert!(matches!(checker.verdict(&old, now), Verdict::Deny(_)));
+
+        // The quota resets on the next UTC day
+        let tomorrow = now + chrono::Duration::days(1);
+        assert_eq!(checker.verdict(&old, tomorrow), Verdict::Allow);
+    }
+}
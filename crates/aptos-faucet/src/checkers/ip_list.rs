@@ -0,0 +1,238 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Applies stricter treatment to clients on a periodically refreshed list of networks, such as
+//! Tor exit nodes or cloud providers' published IP ranges. Most draining comes from these.
+
+use super::{CheckRequest, Checker, Verdict};
+use crate::ip_limit::canonical;
+use anyhow::{format_err, Result};
+use aptos_infallible::RwLock;
+use aptos_logger::{info, warn};
+use async_trait::async_trait;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+/// How a list is published
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IpListFormat {
+    /// One address or CIDR network per line, `#` starts a comment. This is the format of the
+    /// Tor bulk exit list, https://check.torproject.org/torbulkexitlist
+    PlainText,
+    /// https://ip-ranges.amazonaws.com/ip-ranges.json
+    Aws,
+    /// https://www.gstatic.com/ipranges/cloud.json
+    Gcp,
+}
+
+/// What happens to requests from listed networks
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IpListAction {
+    Deny,
+    /// Mint at most this much per request
+    Cap(u64),
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct IpListConfig {
+    /// Shown in logs and in the reason given to denied clients
+    pub name: String,
+    pub url: String,
+    pub format: IpListFormat,
+    pub action: IpListAction,
+    #[serde(default = "default_refresh_interval_secs")]
+    pub refresh_interval_secs: u64,
+}
+
+fn default_refresh_interval_secs() -> u64 {
+    3600
+}
+
+pub struct IpListChecker {
+    config: IpListConfig,
+    networks: Arc<RwLock<Vec<IpNet>>>,
+}
+
+impl IpListChecker {
+    /// A checker over a fixed set of networks, which is never refreshed
+    pub fn new(config: IpListConfig, networks: Vec<IpNet>) -> Self {
+        Self {
+            config,
+            networks: Arc::new(RwLock::new(networks)),
+        }
+    }
+
+    /// Fetches the list, then keeps refreshing it in the background every
+    /// `refresh_interval_secs`. If a refresh fails, the previous list stays in use.
+    pub async fn start(config: IpListConfig) -> Result<Self> {
+        let client = reqwest::Client::new();
+        let networks = fetch(&client, &config).await?;
+        info!(
+            "[faucet]: loaded {} networks from IP list {}",
+            networks.len(),
+            config.name
+        );
+        let checker = Self::new(config.clone(), networks);
+
+        let networks = Arc::downgrade(&checker.networks);
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(config.refresh_interval_secs));
+            // The first tick completes immediately, and the list was just fetched
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                // Stop once the checker is gone
+                let networks = match networks.upgrade() {
+                    Some(networks) => networks,
+                    None => return,
+                };
+                match fetch(&client, &config).await {
+                    Ok(refreshed) => *networks.write() = refreshed,
+                    Err(err) => warn!(
+                        "[faucet]: failed to refresh IP list {}, keeping the previous one: {}",
+                        config.name, err
+                    ),
+                }
+            }
+        });
+
+        Ok(checker)
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = canonical(ip);
+        self.networks
+            .read()
+            .iter()
+            .any(|network| network.contains(&ip))
+    }
+}
+
+#[async_trait]
+impl Checker for IpListChecker {
+    async fn check(&self, request: &CheckRequest) -> Verdict {
+        match request.client_ip {
+            Some(ip) if self.contains(ip) => match &self.config.action {
+                IpListAction::Deny => Verdict::Deny(format!(
+                    "Requests from {} addresses are not allowed",
+                    self.config.name
+                )),
+                IpListAction::Cap(cap) => Verdict::Cap(*cap),
+            },
+            _ => Verdict::Allow,
+        }
+    }
+}
+
+async fn fetch(client: &reqwest::Client, config: &IpListConfig) -> Result<Vec<IpNet>> {
+    let body = client
+        .get(&config.url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    parse(config.format, &body)
+        .map_err(|err| format_err!("Unable to parse IP list {}: {}", config.name, err))
+}
+
+fn parse(format: IpListFormat, body: &str) -> Result<Vec<IpNet>> {
+    match format {
+        IpListFormat::PlainText => body
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+            .map(parse_network)
+            .collect(),
+        IpListFormat::Aws | IpListFormat::Gcp => {
+            let ranges: serde_json::Value = serde_json::from_str(body)?;
+            let keys: &[&str] = match format {
+                IpListFormat::Aws => &["prefixes", "ipv6_prefixes"],
+                _ => &["prefixes"],
+            };
+            keys.iter()
+                .filter_map(|key| ranges.get(key)?.as_array())
+                .flatten()
+                .filter_map(|prefix| {
+                    ["ip_prefix", "ipv6_prefix", "ipv4Prefix", "ipv6Prefix"]
+                        .iter()
+                        .find_map(|field| prefix.get(field)?.as_str())
+                })
+                .map(parse_network)
+                .collect()
+        }
+    }
+}
+
+/// Parses a CIDR network, or a single address
+fn parse_network(network: &str) -> Result<IpNet> {
+    if let Ok(network) = network.parse() {
+        return Ok(network);
+    }
+    let ip: IpAddr = network
+        .parse()
+        .map_err(|_| format_err!("invalid network {}", network))?;
+    let prefix_len = if ip.is_ipv4() { 32 } else { 128 };
+    Ok(IpNet::new(ip, prefix_len)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_parse() {
+        let tor = parse(
+            IpListFormat::PlainText,
+            "# Tor exits\n185.220.101.1\n\n2a0b:f4c2::/40 # a network\n",
+        )
+        .unwrap();
+        assert_eq!(tor.len(), 2);
+
+        let aws = parse(
+            IpListFormat::Aws,
+            r#"{"prefixes": [{"ip_prefix": "3.5.140.0/22", "region": "ap-northeast-2"}],
+                "ipv6_prefixes": [{"ipv6_prefix": "2600:1f14::/35"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(aws.len(), 2);
+
+        let gcp = parse(
+            IpListFormat::Gcp,
+            r#"{"prefixes": [{"ipv4Prefix": "34.80.0.0/15"}, {"ipv6Prefix": "2600:1900::/35"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(gcp.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn listed_clients_are_capped() {
+        let checker = IpListChecker::new(
+            IpListConfig {
+                name: "datacenter".to_string(),
+                url: String::new(),
+                format: IpListFormat::PlainText,
+                action: IpListAction::Cap(10),
+                refresh_interval_secs: default_refresh_interval_secs(),
+            },
+            vec!["3.5.140.0/22".parse().unwrap()],
+        );
+        let check = |ip: &str| CheckRequest {
+            client_ip: Some(ip.parse().unwrap()),
+            amount: 100,
+            ..CheckRequest::default()
+        };
+
+        assert_eq!(checker.check(&check("3.5.141.7")).await, Verdict::Cap(10));
+        assert_eq!(
+            checker.check(&check("::ffff:3.5.141.7")).await,
+            Verdict::Cap(10)
+        );
+        assert_eq!(checker.check(&check("3.5.144.1")).await, Verdict::Allow);
+    }
+}
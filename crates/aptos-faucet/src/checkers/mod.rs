@@ -0,0 +1,82 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Checkers look at each mint request before it is processed and decide whether it is allowed,
+//! allowed with a reduced amount, or denied. Every configured checker runs, and the strictest
+//! verdict wins.
+
+use aptos_sdk::types::account_address::AccountAddress;
+use async_trait::async_trait;
+use std::{net::IpAddr, sync::Arc};
+
+pub mod balance;
+pub mod decay;
+pub mod geo;
+pub mod github;
+pub mod ip_list;
+pub mod reputation;
+pub mod sybil;
+
+pub use balance::{BalanceAction, BalanceChecker, BalanceConfig};
+pub use decay::{DecayChecker, DecayConfig};
+pub use geo::{GeoChecker, GeoConfig, GeoIpDatabase};
+pub use github::{GitHubChecker, GitHubConfig};
+pub use ip_list::{IpListAction, IpListChecker, IpListConfig, IpListFormat};
+pub use reputation::{
+    HttpReputationProvider, ReputationChecker, ReputationConfig, ReputationProvider,
+};
+pub use sybil::{Finding, SybilConfig, SybilDetector};
+
+/// What a checker knows about a mint request
+#[derive(Clone, Debug, Default)]
+pub struct CheckRequest {
+    /// The client's address, if it could be determined
+    pub client_ip: Option<IpAddr>,
+    /// The request's `Authorization` header
+    pub authorization: Option<String>,
+    /// Account to be funded, if the request names a valid one
+    pub receiver: Option<AccountAddress>,
+    /// Amount requested, before any caps are applied
+    pub amount: u64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Verdict {
+    Allow,
+    /// Allow the request, minting at most this much
+    Cap(u64),
+    /// Refuse the request, with a reason that is returned to the client
+    Deny(String),
+}
+
+impl Verdict {
+    /// Combines two verdicts, keeping the strictest
+    pub fn and(self, other: Verdict) -> Verdict {
+        match (self, other) {
+            (Verdict::Deny(reason), _) | (_, Verdict::Deny(reason)) => Verdict::Deny(reason),
+            (Verdict::Cap(lhs), Verdict::Cap(rhs)) => Verdict::Cap(std::cmp::min(lhs, rhs)),
+            (Verdict::Cap(cap), Verdict::Allow) | (Verdict::Allow, Verdict::Cap(cap)) => {
+                Verdict::Cap(cap)
+            }
+            (Verdict::Allow, Verdict::Allow) => Verdict::Allow,
+        }
+    }
+}
+
+#[async_trait]
+pub trait Checker: Send + Sync {
+    async fn check(&self, request: &CheckRequest) -> Verdict;
+}
+
+#[async_trait]
+impl<T: Checker + ?Sized> Checker for Arc<T> {
+    async fn check(&self, request: &CheckRequest) -> Verdict {
+        (**self).check(request).await
+    }
+}
+
+/// Runs every checker against `request`, returning the strictest verdict
+pub async fn check_all(checkers: &[Box<dyn Checker>], request: &CheckRequest) -> Verdict {
+    let verdicts = futures::future::join_all(checkers.iter().map(|c| c.check(request))).await;
+    verdicts.into_iter().fold(Verdict::Allow, Verdict::and)
+}
@@ -0,0 +1,235 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Scores clients with an IP reputation feed, so operators can plug their existing abuse
+//! intelligence into the faucet. Any feed can be used by implementing [`ReputationProvider`],
+//! [`HttpReputationProvider`] covers services answering a JSON lookup per address.
+
+use super::{CheckRequest, Checker, Verdict};
+use crate::request_log::current_request_id;
+use anyhow::{format_err, Result};
+use aptos_logger::warn;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, net::IpAddr, path::PathBuf, time::Duration};
+
+#[async_trait]
+pub trait ReputationProvider: Send + Sync {
+    /// Scores `ip` from 0 (trusted) to 100 (known abuser), or `None` if the address is unknown
+    async fn score(&self, ip: IpAddr) -> Result<Option<f64>>;
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReputationConfig {
+    /// Lookup URL, `{ip}` is replaced with the client's address
+    pub url: String,
+    /// Headers sent with every lookup, e.g. an API key
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Further headers, with their values read from files, see [`crate::secrets`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub header_files: BTreeMap<String, PathBuf>,
+    /// JSON pointer to the score in the response, e.g. `/data/abuseConfidenceScore`
+    pub score_pointer: String,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Scores at or above this are denied
+    pub deny_threshold: Option<f64>,
+    /// Scores at or above this are capped at `cap_maximum_amount`
+    pub cap_threshold: Option<f64>,
+    #[serde(default)]
+    pub cap_maximum_amount: u64,
+}
+
+fn default_timeout_ms() -> u64 {
+    1000
+}
+
+/// Looks up scores with an HTTP GET per address, reading the score out of the JSON response
+pub struct HttpReputationProvider {
+    client: reqwest::Client,
+    url: String,
+    headers: BTreeMap<String, String>,
+    score_pointer: String,
+}
+
+impl HttpReputationProvider {
+    pub fn new(config: &ReputationConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()?;
+        Ok(Self {
+            client,
+            url: config.url.clone(),
+            headers: config.headers.clone(),
+            score_pointer: config.score_pointer.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl ReputationProvider for HttpReputationProvider {
+    async fn score(&self, ip: IpAddr) -> Result<Option<f64>> {
+        let mut request = self.client.get(self.url.replace("{ip}", &ip.to_string()));
+        for (name, value) in &self.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        let response = request.send().await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body: serde_json::Value =
+            serde_json::from_slice(&response.error_for_status()?.bytes().await?)?;
+
+        match body.pointer(&self.score_pointer) {
+            None | Some(serde_json::Value::Null) => Ok(None),
+            Some(score) => score
+                .as_f64()
+                .or_else(|| score.as_str()?.parse().ok())
+                .map(Some)
+                .ok_or_else(|| format_err!("score {} is not a number", score)),
+        }
+    }
+}
+
+/// Denies or caps clients whose score reaches the configured thresholds. Clients are allowed if
+/// the provider fails, so an outage of the feed does not take the faucet down with it.
+pub struct ReputationChecker<P> {
+    provider: P,
+    deny_threshold: Option<f64>,
+    cap_threshold: Option<f64>,
+    cap_maximum_amount: u64,
+}
+
+impl<P: ReputationProvider> ReputationChecker<P> {
+    pub fn new(provider: P, config: &ReputationConfig) -> Self {
+        Self {
+            provider,
+            deny_threshold: config.deny_threshold,
+            cap_threshold: config.cap_threshold,
+            cap_maximum_amount: config.cap_maximum_amount,
+        }
+    }
+}
+
+#[async_trait]
+impl<P: ReputationProvider> Checker for ReputationChecker<P> {
+    async fn check(&self, request: &CheckRequest) -> Verdict {
+        let ip = match request.client_ip {
+            Some(ip) => ip,
+            None => return Verdict::Allow,
+        };
+        let score = match self.provider.score(ip).await {
+            Ok(Some(score)) => score,
+            Ok(None) => return Verdict::Allow,
+            Err(err) => {
+                warn!(
+                    request_id = current_request_id(),
+                    "[faucet]: reputation lookup for {} failed: {}", ip, err
+                );
+                return Verdict::Allow;
+            }
+        };
+
+        let reached = |threshold: Option<f64>| threshold.map_or(false, |t| score >= t);
+        if reached(self.deny_threshold) {
+            Verdict::Deny("Requests from this address are not allowed".to_string())
+        } else if reached(self.cap_threshold) {
+            Verdict::Cap(self.cap_maximum_amount)
+        } else {
+            Verdict::Allow
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct FixedScores(HashMap<IpAddr, f64>);
+
+    #[async_trait]
+    impl ReputationProvider for FixedScores {
+        async fn score(&self, ip: IpAddr) -> Result<Option<f64>> {
+            Ok(self.0.get(&ip).copied())
+        }
+    }
+
+    #[tokio::test]
+    async fn scores_map_to_verdicts() {
+        let provider = FixedScores(
+            vec![
+                ("10.0.0.1".parse().unwrap(), 95.0),
+                ("10.0.0.2".parse().unwrap(), 60.0),
+                ("10.0.0.3".parse().unwrap(), 5.0),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let checker = ReputationChecker::new(
+            provider,
+            &ReputationConfig {
+                url: String::new(),
+                headers: BTreeMap::new(),
+                header_files: BTreeMap::new(),
+                score_pointer: String::new(),
+                timeout_ms: default_timeout_ms(),
+                deny_threshold: Some(90.0),
+                cap_threshold: Some(50.0),
+                cap_maximum_amount: 10,
+            },
+        );
+        let check = |ip: &str| CheckRequest {
+            client_ip: Some(ip.parse().unwrap()),
+            amount: 100,
+            ..CheckRequest::default()
+        };
+
+        assert!(matches!(
+            checker.check(&check("10.0.0.1")).await,
+            Verdict::Deny(_)
+        ));
+        assert_eq!(checker.check(&check("10.0.0.2")).await, Verdict::Cap(10));
+        assert_eq!(checker.check(&check("10.0.0.3")).await, Verdict::Allow);
+        assert_eq!(checker.check(&check("10.0.0.4")).await, Verdict::Allow);
+    }
+
+    #[tokio::test]
+    async fn http_provider_reads_score() {
+        use warp::Filter;
+
+        let feed = warp::path!("check" / String)
+            .and(warp::header::exact("key", "secret"))
+            .map(|ip: String| {
+                let score = if ip == "10.0.0.1" { 100 } else { 0 };
+                warp::reply::json(&serde_json::json!({ "data": { "abuseConfidenceScore": score } }))
+            });
+        let (address, server) = warp::serve(feed).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let provider = HttpReputationProvider::new(&ReputationConfig {
+            url: format!("http://{}/check/{{ip}}", address),
+            headers: vec![("key".to_string(), "secret".to_string())]
+                .into_iter()
+                .collect(),
+            header_files: BTreeMap::new(),
+            score_pointer: "/data/abuseConfidenceScore".to_string(),
+            timeout_ms: default_timeout_ms(),
+            deny_threshold: None,
+            cap_threshold: None,
+            cap_maximum_amount: 0,
+        })
+        .unwrap();
+
+        assert_eq!(
+            provider.score("10.0.0.1".parse().unwrap()).await.unwrap(),
+            Some(100.0)
+        );
+        assert_eq!(
+            provider.score("10.0.0.2".parse().unwrap()).await.unwrap(),
+            Some(0.0)
+        );
+    }
+}
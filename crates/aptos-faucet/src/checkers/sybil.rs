@@ -0,0 +1,370 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Looks for sybil clusters in the recent funding history, and temporarily blocks what it finds:
+//!
+//! * A single IP funding many distinct addresses. The IP is blocked.
+//! * Many funded addresses sweeping their coins to a common sink right away. The IPs that funded
+//!   them, and the sink itself, are blocked.
+//!
+//! Analysis runs in the background, findings are listed by `GET /admin/sybil`.
+
+use super::{CheckRequest, Checker, Verdict};
+use aptos_infallible::Mutex;
+use aptos_logger::{info, warn};
+use aptos_rest_client::{
+    aptos_api_types::{Transaction, TransactionPayload},
+    Client,
+};
+use aptos_sdk::types::account_address::AccountAddress;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Findings kept for the admin endpoint
+const MAX_FINDINGS: usize = 100;
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SybilConfig {
+    /// How often the funding history is analyzed
+    pub analysis_interval_secs: u64,
+    /// How far back the funding history is analyzed
+    pub window_secs: u64,
+    /// Distinct addresses a single IP may fund within the window
+    pub max_addresses_per_ip: usize,
+    /// Funded addresses sweeping to the same sink before it is considered a cluster
+    pub min_sweep_sources: usize,
+    /// Funded addresses whose outgoing transactions are inspected per analysis
+    pub max_sweep_lookups: usize,
+    /// How long IPs and addresses found in a cluster are blocked
+    pub block_secs: u64,
+}
+
+impl Default for SybilConfig {
+    fn default() -> Self {
+        Self {
+            analysis_interval_secs: 300,
+            window_secs: 86400,
+            max_addresses_per_ip: 20,
+            min_sweep_sources: 5,
+            max_sweep_lookups: 500,
+            block_secs: 86400,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Finding {
+    /// One IP funded many addresses
+    IpCluster {
+        ip: IpAddr,
+        addresses: Vec<AccountAddress>,
+        detected_at_secs: u64,
+        blocked_until_secs: u64,
+    },
+    /// Many funded addresses sent their coins to the same sink
+    Sweep {
+        sink: AccountAddress,
+        addresses: Vec<AccountAddress>,
+        ips: Vec<IpAddr>,
+        detected_at_secs: u64,
+        blocked_until_secs: u64,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Blocked {
+    Ip(IpAddr),
+    Address(AccountAddress),
+}
+
+struct FundingRecord {
+    client_ip: Option<IpAddr>,
+    receiver: AccountAddress,
+    at: SystemTime,
+}
+
+#[derive(Default)]
+struct State {
+    history: VecDeque<FundingRecord>,
+    blocks: HashMap<Blocked, SystemTime>,
+    findings: VecDeque<Finding>,
+}
+
+pub struct SybilDetector {
+    config: SybilConfig,
+    state: Mutex<State>,
+}
+
+impl SybilDetector {
+    pub fn new(config: SybilConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Adds a successful funding to the history
+    pub fn record(&self, client_ip: Option<IpAddr>, receiver: AccountAddress) {
+        let now = SystemTime::now();
+        let mut state = self.state.lock();
+        state.history.push_back(FundingRecord {
+            client_ip,
+            receiver,
+            at: now,
+        });
+        let window = Duration::from_secs(self.config.window_secs);
+        while matches!(state.history.front(), Some(record) if record.at + window < now) {
+            state.history.pop_front();
+        }
+    }
+
+    /// Most recent findings first
+    pub fn findings(&self) -> Vec<Finding> {
+        self.state.lock().findings.iter().rev().cloned().collect()
+    }
+
+    /// Analyzes the history every `analysis_interval_secs` in the background
+    pub fn start(self: Arc<Self>, client: Client) {
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(self.config.analysis_interval_secs));
+            loop {
+                interval.tick().await;
+                self.analyze_ips();
+                self.analyze_sweeps(&client).await;
+            }
+        });
+    }
+
+    fn analyze_ips(&self) {
+        let mut by_ip: HashMap<IpAddr, BTreeSet<AccountAddress>> = HashMap::new();
+        {
+            let state = self.state.lock();
+            for record in &state.history {
+                if let Some(ip) = record.client_ip {
+                    by_ip.entry(ip).or_default().insert(record.receiver);
+                }
+            }
+        }
+
+        for (ip, addresses) in by_ip {
+            // Already blocked clusters are not reported again on every analysis
+            if addresses.len() > self.config.max_addresses_per_ip
+                && !self.is_blocked(Blocked::Ip(ip))
+            {
+                self.flag(
+                    vec![Blocked::Ip(ip)],
+                    |detected_at_secs, blocked_until_secs| Finding::IpCluster {
+                        ip,
+                        addresses: addresses.into_iter().collect(),
+                        detected_at_secs,
+                        blocked_until_secs,
+                    },
+                );
+            }
+        }
+    }
+
+    async fn analyze_sweeps(&self, client: &Client) {
+        let funded: Vec<(AccountAddress, Option<IpAddr>)> = {
+            let state = self.state.lock();
+            let mut seen = BTreeSet::new();
+            state
+                .history
+                .iter()
+                .rev()
+                .filter(|record| seen.insert(record.receiver))
+                .take(self.config.max_sweep_lookups)
+                .map(|record| (record.receiver, record.client_ip))
+                .collect()
+        };
+
+        let mut sweeps = Vec::new();
+        for (address, client_ip) in funded {
+            match client
+                .get_account_transactions(address, None, Some(25))
+                .await
+            {
+                Ok(txns) => {
+                    for sink in transfer_recipients(&txns.into_inner()) {
+                        sweeps.push((address, client_ip, sink));
+                    }
+                }
+                Err(err) => warn!(
+                    "[faucet]: unable to fetch transactions of {} for sybil analysis: {}",
+                    address, err
+                ),
+            }
+        }
+
+        self.flag_sweeps(sweeps);
+    }
+
+    /// Flags sinks receiving coins from at least `min_sweep_sources` funded addresses
+    fn flag_sweeps(&self, sweeps: Vec<(AccountAddress, Option<IpAddr>, AccountAddress)>) {
+        let mut by_sink: HashMap<AccountAddress, (BTreeSet<AccountAddress>, BTreeSet<IpAddr>)> =
+            HashMap::new();
+        for (address, client_ip, sink) in sweeps {
+            let (addresses, ips) = by_sink.entry(sink).or_default();
+            addresses.insert(address);
+            ips.extend(client_ip);
+        }
+
+        for (sink, (addresses, ips)) in by_sink {
+            if addresses.len() < self.config.min_sweep_sources
+                || self.is_blocked(Blocked::Address(sink))
+            {
+                continue;
+            }
+            let mut blocked: Vec<_> = ips.iter().copied().map(Blocked::Ip).collect();
+            blocked.push(Blocked::Address(sink));
+            self.flag(blocked, |detected_at_secs, blocked_until_secs| {
+                Finding::Sweep {
+                    sink,
+                    addresses: addresses.into_iter().collect(),
+                    ips: ips.into_iter().collect(),
+                    detected_at_secs,
+                    blocked_until_secs,
+                }
+            });
+        }
+    }
+
+    fn flag(&self, blocked: Vec<Blocked>, finding: impl FnOnce(u64, u64) -> Finding) {
+        let now = SystemTime::now();
+        let until = now + Duration::from_secs(self.config.block_secs);
+        let finding = finding(unix_secs(now), unix_secs(until));
+        info!("[faucet]: sybil cluster detected: {:?}", finding);
+
+        let mut state = self.state.lock();
+        for blocked in blocked {
+            state.blocks.insert(blocked, until);
+        }
+        state.findings.push_back(finding);
+        if state.findings.len() > MAX_FINDINGS {
+            state.findings.pop_front();
+        }
+    }
+
+    fn is_blocked(&self, blocked: Blocked) -> bool {
+        let mut state = self.state.lock();
+        match state.blocks.get(&blocked) {
+            Some(until) if *until > SystemTime::now() => true,
+            Some(_) => {
+                state.blocks.remove(&blocked);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+#[async_trait]
+impl Checker for SybilDetector {
+    async fn check(&self, request: &CheckRequest) -> Verdict {
+        let ip_blocked = request
+            .client_ip
+            .map_or(false, |ip| self.is_blocked(Blocked::Ip(ip)));
+        let receiver_blocked = request
+            .receiver
+            .map_or(false, |address| self.is_blocked(Blocked::Address(address)));
+        if ip_blocked || receiver_blocked {
+            Verdict::Deny("Temporarily blocked for suspected abuse".to_string())
+        } else {
+            Verdict::Allow
+        }
+    }
+}
+
+/// Recipients of coin transfers sent by the transactions
+fn transfer_recipients(txns: &[Transaction]) -> Vec<AccountAddress> {
+    txns.iter()
+        .filter_map(|txn| match txn {
+            Transaction::UserTransaction(txn) => match &txn.request.payload {
+                TransactionPayload::ScriptFunctionPayload(payload)
+                    if payload.function.to_string().ends_with("::Coin::transfer")
+                        || payload
+                            .function
+                            .to_string()
+                            .ends_with("::TestCoin::transfer") =>
+                {
+                    let to = payload.arguments.first()?.as_str()?;
+                    AccountAddress::from_hex_literal(to).ok()
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector() -> SybilDetector {
+        SybilDetector::new(SybilConfig {
+            max_addresses_per_ip: 2,
+            min_sweep_sources: 2,
+            ..SybilConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn ips_funding_many_addresses_are_blocked() {
+        let detector = detector();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        for _ in 0..3 {
+            detector.record(Some(ip), AccountAddress::random());
+        }
+        detector.analyze_ips();
+
+        assert!(matches!(
+            detector.findings().as_slice(),
+            [Finding::IpCluster { addresses, .. }] if addresses.len() == 3
+        ));
+        let request = CheckRequest {
+            client_ip: Some(ip),
+            ..CheckRequest::default()
+        };
+        assert!(matches!(detector.check(&request).await, Verdict::Deny(_)));
+    }
+
+    #[tokio::test]
+    async fn sweeps_to_a_common_sink_are_blocked() {
+        let detector = detector();
+        let sink = AccountAddress::random();
+        let ip: IpAddr = "10.0.0.2".parse().unwrap();
+        detector.flag_sweeps(vec![
+            (AccountAddress::random(), Some(ip), sink),
+            (AccountAddress::random(), None, sink),
+            (AccountAddress::random(), None, AccountAddress::random()),
+        ]);
+
+        assert_eq!(detector.findings().len(), 1);
+        let request = CheckRequest {
+            receiver: Some(sink),
+            ..CheckRequest::default()
+        };
+        assert!(matches!(detector.check(&request).await, Verdict::Deny(_)));
+        let request = CheckRequest {
+            client_ip: Some(ip),
+            ..CheckRequest::default()
+        };
+        assert!(matches!(detector.check(&request).await, Verdict::Deny(_)));
+    }
+}
@@ -0,0 +1,774 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    audit::AuditLogConfig,
+    bans::BanConfig,
+    chaos::ChaosConfig,
+    checkers::{
+        BalanceConfig, DecayConfig, GeoChecker, GeoConfig, GitHubConfig, IpListConfig,
+        ReputationConfig, SybilConfig,
+    },
+    connection_limits::ConnectionLimitsConfig,
+    cooldown::CooldownConfig,
+    events::{kafka::KafkaConfig, nats::NatsConfig},
+    funder::FundingMode,
+    ip_limit::IpRateLimitConfig,
+    leader::LeaderElectionConfig,
+    partner::PartnerConfig,
+    preflight,
+    request_limits::RequestLimitsConfig,
+    schedule::StandingOrder,
+    secrets,
+    security_headers::SecurityHeadersConfig,
+    sequence_leases::SequenceLeaseConfig,
+    sharding::ShardingConfig,
+    tls::TlsConfig,
+};
+use anyhow::{bail, format_err, Result};
+use aptos::common::types::EncodingType;
+use aptos_config::keys::ConfigKey;
+use aptos_crypto::ed25519::Ed25519PrivateKey;
+use aptos_sdk::types::{
+    account_address::AccountAddress,
+    account_config::aptos_root_address,
+    chain_id::{self, ChainId, NamedChain},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+};
+
+/// Everything needed to start a faucet, either from a config file or from command line flags.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaucetConfig {
+    /// Address to listen on, a port of 0 picks an ephemeral port
+    pub address: SocketAddr,
+    /// Further addresses to listen on, e.g. `[::]:80` next to `0.0.0.0:80` for dual-stack
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub additional_addresses: Vec<SocketAddr>,
+    /// Aptos fullnode/validator REST endpoint
+    pub server_url: String,
+    /// Chain ID of the network the faucet is connecting to
+    #[serde(deserialize_with = "chain_id::deserialize_config_chain_id")]
+    pub chain_id: ChainId,
+    /// Private key for creating test accounts and minting coins, takes precedence over
+    /// `mint_key_file_path`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mint_key: Option<ConfigKey<Ed25519PrivateKey>>,
+    /// Path to the BCS encoded private key for creating test accounts and minting coins
+    pub mint_key_file_path: PathBuf,
+    /// Address of the account to send transactions from, defaults to the aptos root address
+    pub mint_account_address: Option<AccountAddress>,
+    /// Maximum amount of coins to mint per request
+    pub maximum_amount: Option<u64>,
+    /// Maximum amount of coins minted to each caller per UTC day, across all its requests, see
+    /// [`crate::daily_limit`]
+    pub daily_maximum_amount: Option<u64>,
+    /// Amount minted for requests without one, which are refused when empty
+    pub default_amount: Option<u64>,
+    /// Smallest amount a request may ask for, other than 0 to only create the account
+    pub minimum_amount: u64,
+    /// Mint directly from the mint account rather than a freshly delegated account
+    pub do_not_delegate: bool,
+    /// Coins minted into the delegated account when it is created, to pay for its gas
+    pub delegate_endowment: u64,
+    /// Balance below which the delegated account mints itself back up to
+    /// `delegate_endowment`, it is never topped up when empty
+    pub delegate_top_up_below: Option<u64>,
+    /// File the delegated account is saved to, and reused from after a restart
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delegate_state_file: Option<PathBuf>,
+    /// Rotate the delegated account's authentication key once it is this many days old, never
+    /// when empty
+    pub delegate_key_rotation_days: Option<u64>,
+    /// Mint new coins, or transfer them from the mint account's balance on networks where it
+    /// has no mint capability. Transfers are never delegated
+    pub funding_mode: FundingMode,
+    /// Sign transactions but never submit them, returning them to the caller instead.
+    /// Delegation is skipped, as it would have to submit transactions
+    pub dry_run: bool,
+    /// Run against an in-memory chain instead of `server_url`, so the faucet API can be used
+    /// without any node. A random mint key is used if none can be loaded
+    pub mock_chain: bool,
+    /// Maximum amount of coins minted across all requests per `spend_budget_window_secs`,
+    /// unbounded when empty
+    pub spend_budget: Option<u64>,
+    /// Length of the window `spend_budget` applies to
+    pub spend_budget_window_secs: u64,
+    /// Mint requests allowed per client address and subnet
+    pub ip_rate_limit: IpRateLimitConfig,
+    /// Refuse to fund an address again within a cooldown of its last funding
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cooldown: Option<CooldownConfig>,
+    /// Identify clients by the first `X-Forwarded-For` entry rather than the peer address
+    pub trust_x_forwarded_for: bool,
+    /// Path every route is mounted under, e.g. `/faucet`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<String>,
+    /// Deny or cap requests by the client's country
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geo: Option<GeoConfig>,
+    /// Lists of networks, such as Tor exits or cloud providers, whose requests are denied or
+    /// capped
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ip_lists: Vec<IpListConfig>,
+    /// Deny or cap requests by the score an IP reputation service gives the client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reputation: Option<ReputationConfig>,
+    /// Deny or reduce requests for receivers holding, or that would end up holding, more than
+    /// `max_balance`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance_limit: Option<BalanceConfig>,
+    /// Halve the amount an address may be minted with each further request in a window
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decay: Option<DecayConfig>,
+    /// Require requesters to sign a nonce from `GET /challenge` with the key of the account
+    /// being funded
+    pub signature_challenge: bool,
+    /// Seconds a challenge nonce stays valid
+    pub challenge_ttl_secs: u64,
+    /// Require a GitHub identity with every request, see `checkers::github`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub github: Option<GitHubConfig>,
+    /// Look for sybil clusters in the funding history and temporarily block them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sybil: Option<SybilConfig>,
+    /// Temporarily ban clients with too many refused requests or failed challenges, see `bans`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bans: Option<BanConfig>,
+    /// Run as one of several replicas, of which only the elected leader submits transactions,
+    /// see `leader`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leader_election: Option<LeaderElectionConfig>,
+    /// Split receivers between replicas, each funding its own shard from its own account, see
+    /// `sharding`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sharding: Option<ShardingConfig>,
+    /// Share the funder between replicas, which lease its sequence numbers from Redis, see
+    /// `sequence_leases`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sequence_leases: Option<SequenceLeaseConfig>,
+    /// Bearer token for the `/admin` endpoints, which are disabled without one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_token: Option<String>,
+    /// File holding `admin_token`, see `secrets`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_token_file: Option<PathBuf>,
+    /// Shared secrets of the partners allowed to sign their mint requests, see `partner`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partners: Option<PartnerConfig>,
+    /// Mint requests processed at once, unbounded when empty. Partner requests waiting for a slot
+    /// go before anonymous ones
+    pub max_concurrent_mints: Option<usize>,
+    /// Drop mint requests that waited this long for a processing slot, unbounded when empty
+    pub max_queue_wait_ms: Option<u64>,
+    /// Reuse the funder's sequence number looked up for a mint request for this long, 0 looks it
+    /// up for every request
+    pub funder_cache_ttl_ms: u64,
+    /// Look after the funder's transactions not committed this long after their submission,
+    /// resubmitting those the node lost and abandoning expired ones. 0 never does
+    pub stuck_transaction_secs: u64,
+    /// Transactions the funder may have submitted ahead of the on-chain sequence number. Mint
+    /// requests beyond that wait for earlier transactions to commit
+    pub max_in_flight_transactions: u64,
+    /// Versions the ledger must be past a request's transactions for `wait_for=finalized`
+    pub finality_version_lag: u64,
+    /// Address of the Aptos Names Service contract, letting mint requests name their receiver
+    /// like `name=alice.apt`
+    pub ans_contract_address: Option<AccountAddress>,
+    /// Record every funding decision in a rotating JSONL file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audit_log: Option<AuditLogConfig>,
+    /// Publish every funding decision to a Kafka topic, see `events`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kafka: Option<KafkaConfig>,
+    /// Publish every funding decision to a NATS subject, see `events`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nats: Option<NatsConfig>,
+    /// Start even if the node is on a different chain than `chain_id`, logging an error
+    /// instead of refusing
+    pub allow_chain_id_mismatch: bool,
+    /// Before serving, wait until the node's ledger timestamp is within this many seconds of
+    /// the wall clock
+    pub max_sync_lag_secs: Option<u64>,
+    /// Fund a throwaway account at startup, `GET /ready` fails until it succeeds
+    pub self_test: bool,
+    /// Security headers added to every response
+    pub security_headers: SecurityHeadersConfig,
+    /// Size limits of every request, see `request_limits`
+    pub request_limits: RequestLimitsConfig,
+    /// Timeouts and limit of client connections, see `connection_limits`
+    pub connection_limits: ConnectionLimitsConfig,
+    /// Serve HTTPS with this certificate, reloaded as it changes, see `tls`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+    /// Log 1 in N info lines of the modules listed, by module path, see `log_sampling`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub log_sampling: BTreeMap<String, u64>,
+    /// Inject 429s, 500s and delays into API requests, for testing clients. Never enable it on
+    /// a faucet users depend on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chaos: Option<ChaosConfig>,
+    /// Addresses funded on a schedule, see `schedule`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub standing_orders: Vec<StandingOrder>,
+}
+
+impl Default for FaucetConfig {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from((Ipv4Addr::LOCALHOST, 80)),
+            additional_addresses: Vec::new(),
+            server_url: "https://testnet.aptoslabs.com/".to_string(),
+            chain_id: ChainId::new(NamedChain::TESTNET.id()),
+            mint_key: None,
+            mint_key_file_path: PathBuf::from("/opt/aptos/etc/mint.key"),
+            mint_account_address: None,
+            maximum_amount: None,
+            daily_maximum_amount: None,
+            default_amount: None,
+            minimum_amount: 1,
+            do_not_delegate: false,
+            delegate_endowment: 100_000_000_000,
+            delegate_top_up_below: None,
+            delegate_state_file: None,
+            delegate_key_rotation_days: None,
+            funding_mode: FundingMode::Mint,
+            dry_run: false,
+            mock_chain: false,
+            spend_budget: None,
+            spend_budget_window_secs: 3600,
+            ip_rate_limit: IpRateLimitConfig::default(),
+            cooldown: None,
+            trust_x_forwarded_for: false,
+            path_prefix: None,
+            geo: None,
+            ip_lists: Vec::new(),
+            reputation: None,
+            balance_limit: None,
+            decay: None,
+            signature_challenge: false,
+            challenge_ttl_secs: 300,
+            github: None,
+            sybil: None,
+            bans: None,
+            leader_election: None,
+            sharding: None,
+            sequence_leases: None,
+            admin_token: None,
+            admin_token_file: None,
+            partners: None,
+            max_concurrent_mints: None,
+            max_queue_wait_ms: None,
+            funder_cache_ttl_ms: 500,
+            stuck_transaction_secs: 30,
+            max_in_flight_transactions: 50,
+            finality_version_lag: 10,
+            ans_contract_address: None,
+            audit_log: None,
+            kafka: None,
+            nats: None,
+            allow_chain_id_mismatch: false,
+            max_sync_lag_secs: None,
+            self_test: false,
+            security_headers: SecurityHeadersConfig::default(),
+            request_limits: RequestLimitsConfig::default(),
+            connection_limits: ConnectionLimitsConfig::default(),
+            tls: None,
+            log_sampling: BTreeMap::new(),
+            chaos: None,
+            standing_orders: Vec::new(),
+        }
+    }
+}
+
+/// Comments emitted above each top level field by [`FaucetConfig::to_commented_yaml`]
+const FIELD_DOCS: &[(&str, &str)] = &[
+    (
+        "address",
+        "Address to listen on, a port of 0 picks an ephemeral port",
+    ),
+    (
+        "additional_addresses",
+        "Further addresses to listen on, e.g. \"[::]:80\" next to 0.0.0.0:80 for dual-stack",
+    ),
+    ("server_url", "Aptos fullnode/validator REST endpoint"),
+    (
+        "chain_id",
+        "Chain ID of the network the faucet is connecting to, e.g. 2 for TESTNET, 4 for TESTING",
+    ),
+    (
+        "mint_key",
+        "Hex encoded private key for minting, takes precedence over mint_key_file_path",
+    ),
+    (
+        "mint_key_file_path",
+        "Path to the BCS encoded private key for creating test accounts and minting coins",
+    ),
+    (
+        "mint_account_address",
+        "Account to send transactions from, defaults to the aptos root address (a550c18)",
+    ),
+    (
+        "maximum_amount",
+        "Maximum amount of coins to mint per request, unbounded when empty",
+    ),
+    (
+        "daily_maximum_amount",
+        "Maximum amount of coins minted to each caller, partner or IP, per UTC day, unbounded when empty",
+    ),
+    (
+        "default_amount",
+        "Amount minted for requests without one, which are refused when empty",
+    ),
+    (
+        "minimum_amount",
+        "Smallest amount a request may ask for, smaller ones but 0 are refused",
+    ),
+    (
+        "do_not_delegate",
+        "Mint directly from the mint account rather than a freshly delegated account",
+    ),
+    (
+        "delegate_endowment",
+        "Coins minted into the delegated account when it is created, to pay for its gas",
+    ),
+    (
+        "delegate_top_up_below",
+        "Balance below which the delegated account mints itself back up to delegate_endowment, \
+         never topped up when empty",
+    ),
+    (
+        "delegate_state_file",
+        "File the delegated account's key is saved to, and reused from after a restart, \
+         instead of delegating to a new account on every start",
+    ),
+    (
+        "delegate_key_rotation_days",
+        "Rotate the delegated account's key once it is this many days old, never when empty",
+    ),
+    (
+        "funding_mode",
+        "mint new coins, or transfer them from the mint account's balance when it cannot mint",
+    ),
+    (
+        "dry_run",
+        "Sign transactions but never submit them, returning the BCS encoded transactions instead",
+    ),
+    (
+        "mock_chain",
+        "Run against an in-memory chain instead of server_url, for local development",
+    ),
+    (
+        "spend_budget",
+        "Maximum amount of coins minted across all requests per window, unbounded when empty",
+    ),
+    (
+        "spend_budget_window_secs",
+        "Length in seconds of the window spend_budget applies to",
+    ),
+    (
+        "ip_rate_limit",
+        "Mint requests allowed per window from an address (per_ip), IPv4 /24 (per_ipv4_24) \
+         and IPv6 /64 (per_ipv6_64), unlimited when empty",
+    ),
+    (
+        "cooldown",
+        "Seconds an address has to wait between fundings (cooldown_secs), remembering the \
+         most recently funded addresses up to capacity",
+    ),
+    (
+        "trust_x_forwarded_for",
+        "Identify clients by X-Forwarded-For, only enable behind a proxy that sets it",
+    ),
+    (
+        "path_prefix",
+        "Path every route is mounted under, e.g. /faucet, served at the root when empty",
+    ),
+    (
+        "geo",
+        "Deny or cap requests by country, using the MaxMind Country CSV database",
+    ),
+    (
+        "ip_lists",
+        "Periodically refreshed lists of networks, e.g. Tor exits or cloud IP ranges, whose \
+         requests are denied or capped",
+    ),
+    (
+        "reputation",
+        "Deny or cap requests by the score an HTTP IP reputation service gives the client",
+    ),
+    (
+        "balance_limit",
+        "Deny receivers already holding more than max_balance (action: deny), deny requests \
+         that would take them above it (action: deny_if_exceeded), or reduce such requests \
+         (action: reduce)",
+    ),
+    (
+        "decay",
+        "Cap an address's first request in window_secs at maximum_amount, and halve the cap with \
+         each further one, down to floor_amount",
+    ),
+    (
+        "signature_challenge",
+        "Require requesters to sign a nonce from GET /challenge with the funded account's key",
+    ),
+    (
+        "challenge_ttl_secs",
+        "Seconds a challenge nonce stays valid",
+    ),
+    (
+        "github",
+        "Require a GitHub token with every request, with a minimum account age and daily quota",
+    ),
+    (
+        "sybil",
+        "Look for sybil clusters in the funding history and temporarily block them",
+    ),
+    (
+        "bans",
+        "Temporarily ban clients with too many refused requests or failed challenges, for \
+         longer each time, listed by GET /admin/bans",
+    ),
+    (
+        "leader_election",
+        "Elect the replica submitting transactions through a lease in Redis, the others stand by \
+         and only serve read-only endpoints",
+    ),
+    (
+        "sharding",
+        "Split receivers between replicas by a hash of their address, each replica funding its \
+         own shard from its own funder account",
+    ),
+    (
+        "sequence_leases",
+        "Share the funder between replicas, which lease ranges of its sequence numbers from Redis",
+    ),
+    (
+        "admin_token",
+        "Bearer token for the /admin endpoints, which are disabled without one",
+    ),
+    (
+        "admin_token_file",
+        "File holding admin_token, e.g. a mounted secret",
+    ),
+    (
+        "partners",
+        "Shared secrets by partner name (secrets), for mint requests signed with HMAC-SHA256, \
+         and the allowed clock skew (replay_window_secs)",
+    ),
+    (
+        "max_concurrent_mints",
+        "Mint requests processed at once, unbounded when empty. Waiting partner requests go first",
+    ),
+    (
+        "max_queue_wait_ms",
+        "Drop mint requests that waited this long for a slot, requires max_concurrent_mints",
+    ),
+    (
+        "funder_cache_ttl_ms",
+        "Reuse the funder's sequence number across mint requests for this long, 0 disables",
+    ),
+    (
+        "stuck_transaction_secs",
+        "Resubmit or abandon transactions not committed this long after submission, 0 disables",
+    ),
+    (
+        "max_in_flight_transactions",
+        "Transactions submitted ahead of the on-chain sequence number before mints wait",
+    ),
+    (
+        "finality_version_lag",
+        "Versions the ledger must be past a request's transactions for wait_for=finalized",
+    ),
+    (
+        "ans_contract_address",
+        "Aptos Names Service contract resolving name=alice.apt on mint requests, unset disables",
+    ),
+    (
+        "audit_log",
+        "Record every funding decision in a JSONL file, rotated at max_bytes",
+    ),
+    (
+        "kafka",
+        "Publish every funding decision to topic through the Kafka REST Proxy at \
+         rest_proxy_url, validated against value_schema_id when set",
+    ),
+    (
+        "nats",
+        "Publish every funding decision to subject on the NATS server at address, \
+         authenticating with token or user and password when set",
+    ),
+    (
+        "allow_chain_id_mismatch",
+        "Start even if the node is on a different chain than chain_id, logging an error instead",
+    ),
+    (
+        "max_sync_lag_secs",
+        "Before serving, wait until the node's ledger is within this many seconds of the clock",
+    ),
+    (
+        "self_test",
+        "Fund a throwaway account at startup, GET /ready fails until it succeeds",
+    ),
+    (
+        "security_headers",
+        "Headers added to every response. Only set hsts_max_age_secs when clients reach the \
+         faucet over TLS",
+    ),
+    (
+        "request_limits",
+        "Largest request body (max_body_bytes), headers (max_header_bytes) and URI \
+         (max_uri_length) accepted",
+    ),
+    (
+        "connection_limits",
+        "Seconds clients have to send a request's headers (header_timeout_secs) and the next \
+         request (idle_timeout_secs), and connections served at once (max_connections)",
+    ),
+    (
+        "tls",
+        "Serve HTTPS with the PEM certificate chain at cert_path and key at key_path, loaded \
+         again when either changes, checked every reload_interval_secs, or on SIGUSR1",
+    ),
+    (
+        "log_sampling",
+        "Log 1 in N info lines of each module path listed, e.g. aptos_faucet::request_log: 100. \
+         Failed requests are always logged",
+    ),
+    (
+        "chaos",
+        "Answer shares of API requests with a 429 (rate_limited) or a 500 (server_error), and \
+         delay a share (delayed) by delay_ms, for testing clients' retries",
+    ),
+    (
+        "standing_orders",
+        "Fund each address with amount every interval_secs, or only up to amount with \
+         top_up: true",
+    ),
+];
+
+impl FaucetConfig {
+    pub fn load(path: &std::path::Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .map_err(|err| format_err!("Unable to open config {:?}: {}", path, err))?;
+        let mut config: Self = serde_yaml::from_reader(file)
+            .map_err(|err| format_err!("Unable to parse config {:?}: {}", path, err))?;
+        config.resolve_secrets()?;
+        Ok(config)
+    }
+
+    /// Reads the secrets of the options set to a file, see [`crate::secrets`]. The `_file`
+    /// options are cleared, so that resolving again is a no-op
+    pub fn resolve_secrets(&mut self) -> Result<()> {
+        secrets::resolve(
+            "admin_token",
+            &mut self.admin_token,
+            &mut self.admin_token_file,
+        )?;
+        if let Some(partners) = &mut self.partners {
+            secrets::resolve_all(
+                "partners.secret",
+                &mut partners.secrets,
+                &mut partners.secret_files,
+            )?;
+        }
+        if let Some(reputation) = &mut self.reputation {
+            secrets::resolve_all(
+                "reputation.header",
+                &mut reputation.headers,
+                &mut reputation.header_files,
+            )?;
+        }
+        if let Some(kafka) = &mut self.kafka {
+            secrets::resolve_all("kafka.header", &mut kafka.headers, &mut kafka.header_files)?;
+        }
+        if let Some(nats) = &mut self.nats {
+            secrets::resolve("nats.token", &mut nats.token, &mut nats.token_file)?;
+            secrets::resolve("nats.password", &mut nats.password, &mut nats.password_file)?;
+        }
+        if let Some(leader_election) = &mut self.leader_election {
+            secrets::resolve(
+                "leader_election.password",
+                &mut leader_election.password,
+                &mut leader_election.password_file,
+            )?;
+        }
+        if let Some(sequence_leases) = &mut self.sequence_leases {
+            secrets::resolve(
+                "sequence_leases.password",
+                &mut sequence_leases.password,
+                &mut sequence_leases.password_file,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Serializes the config as YAML, with a comment describing each field
+    pub fn to_commented_yaml(&self) -> Result<String> {
+        let yaml = serde_yaml::to_string(self)?;
+
+        let mut output = String::new();
+        for line in yaml.lines() {
+            let field = line
+                .split(':')
+                .next()
+                .filter(|_| !line.starts_with(|c: char| c.is_whitespace() || c == '-'));
+            if let Some((_, doc)) =
+                field.and_then(|field| FIELD_DOCS.iter().find(|(name, _)| *name == field))
+            {
+                writeln!(output, "# {}", doc)?;
+            }
+            writeln!(output, "{}", line)?;
+        }
+        Ok(output)
+    }
+
+    pub fn load_mint_key(&self) -> Result<Ed25519PrivateKey> {
+        if let Some(key) = &self.mint_key {
+            return Ok(key.private_key());
+        }
+
+        EncodingType::BCS
+            .load_key::<Ed25519PrivateKey>("mint key", &self.mint_key_file_path)
+            .map_err(|err| {
+                format_err!(
+                    "Unable to load mint key from {:?}: {}",
+                    self.mint_key_file_path,
+                    err
+                )
+            })
+    }
+
+    /// The account transactions are sent from
+    pub fn faucet_address(&self) -> AccountAddress {
+        self.mint_account_address.unwrap_or_else(aptos_root_address)
+    }
+
+    /// Whether the faucet delegates to a fresh account at startup. Delegating submits
+    /// transactions, which a dry run must never do. Transfers spend the mint account's own
+    /// balance, so there is no capability to delegate
+    pub fn delegates(&self) -> bool {
+        !self.do_not_delegate && !self.dry_run && self.funding_mode == FundingMode::Mint
+    }
+
+    /// Checks that the mint key loads, the server URL parses, and that the node is reachable and
+    /// on the configured chain, without starting the faucet.
+    pub async fn validate(&self) -> Result<()> {
+        preflight::check_key(self)?;
+        if let Some(geo) = &self.geo {
+            GeoChecker::load(geo)?;
+        }
+        self.security_headers.headers()?;
+        self.request_limits.validate()?;
+        self.connection_limits.validate()?;
+        if let Some(tls) = &self.tls {
+            tls.validate()?;
+        }
+        if let Some(bans) = &self.bans {
+            bans.validate()?;
+        }
+        if let Some(leader_election) = &self.leader_election {
+            leader_election.validate()?;
+            if self.delegates() {
+                bail!("leader_election requires do_not_delegate");
+            }
+        }
+        if let Some(sharding) = &self.sharding {
+            sharding.validate()?;
+            if self.delegates() {
+                bail!("sharding requires do_not_delegate");
+            }
+        }
+        if let Some(sequence_leases) = &self.sequence_leases {
+            sequence_leases.validate()?;
+            if self.delegates() {
+                bail!("sequence_leases requires do_not_delegate");
+            }
+        }
+        if let Some((module, _)) = self.log_sampling.iter().find(|(_, rate)| **rate == 0) {
+            bail!("log_sampling.{} must be at least 1", module);
+        }
+        if let Some(chaos) = &self.chaos {
+            chaos.validate()?;
+        }
+        for order in &self.standing_orders {
+            order.validate()?;
+        }
+        if self.max_concurrent_mints == Some(0) {
+            bail!("max_concurrent_mints must be at least 1");
+        }
+        if self.delegate_key_rotation_days == Some(0) {
+            bail!("delegate_key_rotation_days must be at least 1");
+        }
+        if self.max_in_flight_transactions == 0 {
+            bail!("max_in_flight_transactions must be at least 1");
+        }
+        if self.max_queue_wait_ms.is_some() && self.max_concurrent_mints.is_none() {
+            bail!("max_queue_wait_ms requires max_concurrent_mints");
+        }
+        if let (Some(daily), Some(maximum)) = (self.daily_maximum_amount, self.maximum_amount) {
+            if daily < maximum {
+                bail!("daily_maximum_amount must not be below maximum_amount");
+            }
+        }
+        if self.delegate_top_up_below > Some(self.delegate_endowment) {
+            bail!("delegate_top_up_below must not be above delegate_endowment");
+        }
+        preflight::check_node(self).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commented_yaml_round_trips() {
+        let config = FaucetConfig {
+            maximum_amount: Some(1_000),
+            ..FaucetConfig::default()
+        };
+
+        let yaml = config.to_commented_yaml().unwrap();
+        assert!(yaml.contains("# Aptos fullnode/validator REST endpoint\nserver_url:"));
+
+        let parsed: FaucetConfig = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed, config);
+    }
+
+    #[test]
+    fn secrets_load_from_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("admin_token"), "admin-secret\n").unwrap();
+        std::fs::write(dir.path().join("ci"), "ci-secret").unwrap();
+        let path = dir.path().join("faucet.yaml");
+        std::fs::write(
+            &path,
+            format!(
+                "admin_token_file: {0}/admin_token\npartners:\n  secret_files:\n    ci: {0}/ci\n",
+                dir.path().display()
+            ),
+        )
+        .unwrap();
+
+        let config = FaucetConfig::load(&path).unwrap();
+        assert_eq!(config.admin_token.as_deref(), Some("admin-secret"));
+        assert_eq!(config.admin_token_file, None);
+        let partners = config.partners.unwrap();
+        assert_eq!(partners.secrets["ci"], "ci-secret");
+        assert!(partners.secret_files.is_empty());
+    }
+
+    #[test]
+    fn named_chain_ids_parse() {
+        let config: FaucetConfig = serde_yaml::from_str("chain_id: TESTING").unwrap();
+        assert_eq!(config.chain_id, ChainId::test());
+    }
+}
@@ -0,0 +1,401 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Keeps slow or idle clients from tying up the server's connections. A connection must send the
+//! headers of each request within `header_timeout_secs` of its first byte, or of the connection
+//! being accepted for its first request, and is closed after `idle_timeout_secs` without a new
+//! request once a response was sent. At most `max_connections` connections are served at once,
+//! further ones wait in the listen backlog until a connection closes. With TLS, a connection must
+//! also complete its handshake within `header_timeout_secs`.
+
+use crate::{
+    counters,
+    tls::{Stream, Tls},
+};
+use anyhow::{bail, Result};
+use aptos_logger::warn;
+use futures::Future;
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::Infallible,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpListener,
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{Instant, Sleep},
+};
+use warp::{
+    hyper::{
+        self,
+        server::accept,
+        service::{make_service_fn, service_fn, Service},
+        Body, Request,
+    },
+    Filter, Rejection, Reply,
+};
+
+/// Wait before accepting again after a failed accept, e.g. when out of file descriptors
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionLimitsConfig {
+    /// Seconds a client has to send the headers of a request
+    pub header_timeout_secs: u64,
+    /// Seconds a connection is kept open waiting for the next request
+    pub idle_timeout_secs: u64,
+    /// Connections served at once
+    pub max_connections: usize,
+}
+
+impl Default for ConnectionLimitsConfig {
+    fn default() -> Self {
+        Self {
+            header_timeout_secs: 10,
+            idle_timeout_secs: 60,
+            max_connections: 1024,
+        }
+    }
+}
+
+impl ConnectionLimitsConfig {
+    pub fn validate(&self) -> Result<()> {
+        for (name, limit) in [
+            ("header_timeout_secs", self.header_timeout_secs as usize),
+            ("idle_timeout_secs", self.idle_timeout_secs as usize),
+            ("max_connections", self.max_connections),
+        ] {
+            if limit == 0 {
+                bail!("connection_limits.{} must be at least 1", name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Address of the client a request came from, for requests served by [`serve`]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PeerAddr(pub SocketAddr);
+
+/// Serves `routes` on the connections accepted by `listener` until `shutdown` completes, over
+/// TLS with `tls`
+pub(crate) fn serve<F>(
+    routes: F,
+    listener: TcpListener,
+    limits: ConnectionLimitsConfig,
+    tls: Option<Tls>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> impl Future<Output = Result<(), hyper::Error>>
+where
+    F: Filter<Error = Rejection> + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let service = warp::service(routes);
+    // Requests served outside of `warp::serve` have no remote address, so the client's is passed
+    // along as an extension
+    let make_service = make_service_fn(move |connection: &Connection| {
+        let peer_addr = PeerAddr(connection.peer_addr);
+        let service = service.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut request: Request<Body>| {
+                request.extensions_mut().insert(peer_addr);
+                service.clone().call(request)
+            }))
+        }
+    });
+    hyper::Server::builder(accept::from_stream(incoming(listener, limits, tls)))
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown)
+}
+
+/// Accepts connections as long as fewer than `max_connections` are open
+fn incoming(
+    listener: TcpListener,
+    limits: ConnectionLimitsConfig,
+    tls: Option<Tls>,
+) -> impl futures::Stream<Item = Result<Connection, Infallible>> {
+    let slots = Arc::new(Semaphore::new(limits.max_connections));
+    futures::stream::unfold(
+        (listener, slots, limits, tls),
+        |(listener, slots, limits, tls)| async move {
+            let slot = slots
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("the semaphore is never closed");
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer_addr)) => {
+                        let stream = match &tls {
+                            Some(tls) => tls.accept(stream),
+                            None => Stream::Plain(stream),
+                        };
+                        let connection = Connection::new(stream, peer_addr, &limits, slot);
+                        return Some((Ok(connection), (listener, slots, limits, tls)));
+                    }
+                    Err(err) => {
+                        warn!("[faucet]: unable to accept a connection: {}", err);
+                        tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                    }
+                }
+            }
+        },
+    )
+}
+
+/// What a connection is waiting for
+enum Phase {
+    /// The rest of a request's headers, `at_line_start` once a line just ended
+    Headers { at_line_start: bool },
+    /// The response to a request
+    Processing,
+    /// The next request
+    Idle,
+}
+
+/// A client connection, failing reads once the client is too slow to send headers or stays idle
+/// for too long
+pub(crate) struct Connection {
+    stream: Stream,
+    peer_addr: SocketAddr,
+    phase: Phase,
+    /// Ends the current phase, unless it is `Processing`
+    deadline: Pin<Box<Sleep>>,
+    header_timeout: Duration,
+    idle_timeout: Duration,
+    /// Given back when the connection closes
+    _slot: OwnedSemaphorePermit,
+}
+
+impl Connection {
+    fn new(
+        stream: Stream,
+        peer_addr: SocketAddr,
+        limits: &ConnectionLimitsConfig,
+        slot: OwnedSemaphorePermit,
+    ) -> Self {
+        let header_timeout = Duration::from_secs(limits.header_timeout_secs);
+        Self {
+            stream,
+            peer_addr,
+            phase: Phase::Headers {
+                at_line_start: false,
+            },
+            deadline: Box::pin(tokio::time::sleep(header_timeout)),
+            header_timeout,
+            idle_timeout: Duration::from_secs(limits.idle_timeout_secs),
+            _slot: slot,
+        }
+    }
+
+    /// Follows the request through `read`, bytes just read from the client
+    fn on_read(&mut self, read: &[u8]) {
+        if read.is_empty() {
+            return;
+        }
+        if let Phase::Idle = self.phase {
+            self.phase = Phase::Headers {
+                at_line_start: false,
+            };
+            self.deadline
+                .as_mut()
+                .reset(Instant::now() + self.header_timeout);
+        }
+        if let Phase::Headers { at_line_start } = &mut self.phase {
+            // Headers end with an empty line, whether lines end with CRLF or a bare LF
+            for byte in read {
+                match byte {
+                    b'\n' if *at_line_start => {
+                        self.phase = Phase::Processing;
+                        return;
+                    }
+                    b'\n' => *at_line_start = true,
+                    b'\r' => (),
+                    _ => *at_line_start = false,
+                }
+            }
+        }
+    }
+
+    /// Starts waiting for the next request once the response is being sent
+    fn on_write(&mut self, written: usize) {
+        if written > 0 && matches!(self.phase, Phase::Processing) {
+            self.phase = Phase::Idle;
+            self.deadline
+                .as_mut()
+                .reset(Instant::now() + self.idle_timeout);
+        }
+    }
+
+    /// Fails a pending read once the current phase is over
+    fn poll_deadline(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let phase = match self.phase {
+            Phase::Headers { .. } => "headers",
+            Phase::Idle => "idle",
+            Phase::Processing => return Poll::Pending,
+        };
+        match self.deadline.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                counters::CONNECTION_TIMEOUTS
+                    .with_label_values(&[phase])
+                    .inc();
+                Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("{} timed out", phase),
+                )))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        match Pin::new(&mut this.stream).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                this.on_read(&buf.filled()[filled..]);
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => this.poll_deadline(cx),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.stream).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            this.on_write(written);
+        }
+        result
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.stream).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(written)) = result {
+            this.on_write(written);
+        }
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    async fn start(limits: ConnectionLimitsConfig) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let routes = warp::any()
+            .and(warp::ext::get::<PeerAddr>())
+            .map(|peer_addr: PeerAddr| peer_addr.0.ip().to_string());
+        tokio::spawn(serve(
+            routes,
+            listener,
+            limits,
+            None,
+            futures::future::pending(),
+        ));
+        address
+    }
+
+    /// Everything the server sends until it closes the connection
+    async fn read_to_close(stream: &mut TcpStream) -> String {
+        let mut response = Vec::new();
+        let _ = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response))
+            .await
+            .expect("the connection is closed");
+        String::from_utf8(response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn slow_and_idle_clients_are_disconnected() {
+        let address = start(ConnectionLimitsConfig {
+            header_timeout_secs: 1,
+            idle_timeout_secs: 1,
+            ..ConnectionLimitsConfig::default()
+        })
+        .await;
+
+        // Headers that never end
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: faucet\r\n")
+            .await
+            .unwrap();
+        assert!(!read_to_close(&mut stream).await.contains("200 OK"));
+
+        // A served request, then nothing
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: faucet\r\n\r\n")
+            .await
+            .unwrap();
+        let response = read_to_close(&mut stream).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("127.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn connections_beyond_the_limit_wait() {
+        let address = start(ConnectionLimitsConfig {
+            header_timeout_secs: 1,
+            max_connections: 1,
+            ..ConnectionLimitsConfig::default()
+        })
+        .await;
+
+        let mut first = TcpStream::connect(address).await.unwrap();
+        let mut second = TcpStream::connect(address).await.unwrap();
+        second
+            .write_all(b"GET / HTTP/1.1\r\nhost: faucet\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        // Served once the first connection times out
+        let started = std::time::Instant::now();
+        let response = read_to_close(&mut second).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(started.elapsed() >= Duration::from_millis(900));
+        assert_eq!(read_to_close(&mut first).await, "");
+    }
+}
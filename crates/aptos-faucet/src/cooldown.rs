@@ -0,0 +1,136 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Refuses to fund an address again within a cooldown of its last funding. Only the most
+//! recently funded addresses are remembered, in memory, which makes this the cheapest check and
+//! the first one requests go through.
+
+use aptos_infallible::Mutex;
+use aptos_sdk::types::account_address::AccountAddress;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CooldownConfig {
+    /// Time an address has to wait between fundings
+    pub cooldown_secs: u64,
+    /// Addresses remembered, the least recently funded ones are forgotten first
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+}
+
+fn default_capacity() -> usize {
+    100_000
+}
+
+impl CooldownConfig {
+    pub fn new(cooldown_secs: u64) -> Self {
+        Self {
+            cooldown_secs,
+            capacity: default_capacity(),
+        }
+    }
+}
+
+struct Funded {
+    /// When each remembered address was last funded
+    at: HashMap<AccountAddress, Instant>,
+    /// Fundings from oldest to newest. Entries of addresses funded again or cancelled since are
+    /// stale, and skipped when evicting
+    order: VecDeque<(AccountAddress, Instant)>,
+}
+
+pub struct Cooldown {
+    config: CooldownConfig,
+    funded: Mutex<Funded>,
+}
+
+impl Cooldown {
+    pub fn new(config: CooldownConfig) -> Self {
+        Self {
+            config,
+            funded: Mutex::new(Funded {
+                at: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Time left before `address` may be funded again, `None` if it is not cooling down
+    pub fn remaining(&self, address: AccountAddress) -> Option<Duration> {
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+        let elapsed = self.funded.lock().at.get(&address)?.elapsed();
+        cooldown
+            .checked_sub(elapsed)
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Starts `address`'s cooldown, or returns the time left if it is already cooling down
+    pub fn start(&self, address: AccountAddress) -> Result<(), Duration> {
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+        let mut funded = self.funded.lock();
+        if let Some(at) = funded.at.get(&address) {
+            let elapsed = at.elapsed();
+            if elapsed < cooldown {
+                return Err(cooldown - elapsed);
+            }
+        }
+
+        let now = Instant::now();
+        funded.at.insert(address, now);
+        funded.order.push_back((address, now));
+        while let Some(&(oldest, at)) = funded.order.front() {
+            let stale = funded.at.get(&oldest) != Some(&at);
+            if !stale && funded.at.len() <= self.config.capacity && at.elapsed() < cooldown {
+                break;
+            }
+            funded.order.pop_front();
+            if !stale {
+                funded.at.remove(&oldest);
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends the cooldown started for a request that ended up not funding `address`
+    pub fn cancel(&self, address: AccountAddress) {
+        self.funded.lock().at.remove(&address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_cool_down() {
+        let cooldown = Cooldown::new(CooldownConfig {
+            cooldown_secs: 3600,
+            capacity: 2,
+        });
+        let (a, b, c) = (
+            AccountAddress::random(),
+            AccountAddress::random(),
+            AccountAddress::random(),
+        );
+
+        cooldown.start(a).unwrap();
+        let retry_after = cooldown.start(a).unwrap_err();
+        assert!(retry_after > Duration::from_secs(3590));
+        assert!(cooldown.remaining(a).is_some());
+        assert_eq!(cooldown.remaining(b), None);
+
+        cooldown.cancel(a);
+        cooldown.start(a).unwrap();
+        cooldown.start(b).unwrap();
+        // Over capacity, the least recently funded address is forgotten
+        cooldown.start(c).unwrap();
+        cooldown.start(b).unwrap_err();
+        cooldown.start(c).unwrap_err();
+        cooldown.start(a).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+use aptos_metrics_core::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, TextEncoder,
+};
+use once_cell::sync::Lazy;
+use std::future::Future;
+
+/// Mint requests refused, labeled by why. `node_error` and `insufficient_balance` point at the
+/// faucet itself, the other reasons at the clients
+pub static REJECTED_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_faucet_rejected_requests",
+        "Number of mint requests rejected, by reason",
+        &["reason"]
+    )
+    .unwrap()
+});
+
+pub static FUNDED_REQUESTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "aptos_faucet_funded_requests",
+        "Number of mint requests funded"
+    )
+    .unwrap()
+});
+
+/// Mint requests signed by partners, by partner and by whether they were `funded`, a `dry_run`
+/// or `refused`. Only requests whose signature verified are counted
+pub static PARTNER_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_faucet_partner_requests",
+        "Number of signed mint requests, by partner and outcome",
+        &["partner", "outcome"]
+    )
+    .unwrap()
+});
+
+/// Amount funded by partners' requests, in base units
+pub static PARTNER_AMOUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_faucet_partner_amount",
+        "Amount funded by signed mint requests, by partner",
+        &["partner"]
+    )
+    .unwrap()
+});
+
+/// Clients banned, by the threshold they went over: `rejections` or `challenge_failures`
+pub static BANS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_faucet_bans",
+        "Number of clients banned, by violation",
+        &["violation"]
+    )
+    .unwrap()
+});
+
+/// Connections closed for being too slow, by what they were too slow at: sending the
+/// `headers` of a request, or sending a new request while `idle`
+pub static CONNECTION_TIMEOUTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_faucet_connection_timeouts",
+        "Number of connections closed for being too slow, by phase",
+        &["phase"]
+    )
+    .unwrap()
+});
+
+/// Reloads of the TLS certificate, by whether it was `reloaded` or `failed` to load, see `tls`
+pub static TLS_RELOADS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_faucet_tls_reloads",
+        "Number of reloads of the TLS certificate, by outcome",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+/// Faults injected by chaos mode, by kind: `rate_limited`, `server_error` or `delayed`
+pub static CHAOS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_faucet_chaos_injected",
+        "Number of faults injected by chaos mode, by kind",
+        &["fault"]
+    )
+    .unwrap()
+});
+
+/// Funding events, by sink and by whether they were `published`, `failed` to publish or
+/// `dropped` because the queue was full
+pub static EVENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_faucet_events",
+        "Number of funding events, by sink and publishing outcome",
+        &["sink", "outcome"]
+    )
+    .unwrap()
+});
+
+/// Standing order runs, by whether they `funded` the address, `skipped` a top up of an address
+/// holding enough, or `failed`
+pub static STANDING_ORDERS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_faucet_standing_orders",
+        "Number of standing order runs, by outcome",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+/// Mint requests waiting for a processing slot, by lane
+pub static QUEUED_REQUESTS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "aptos_faucet_queued_requests",
+        "Number of mint requests waiting to be processed, by lane",
+        &["lane"]
+    )
+    .unwrap()
+});
+
+/// On-chain balance of the account the faucet funds from
+pub static FUNDER_BALANCE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_faucet_funder_balance",
+        "Balance of the funder account on chain"
+    )
+    .unwrap()
+});
+
+/// Next sequence number the faucet will sign with. Running far ahead of the on-chain sequence
+/// number means transactions are not being committed
+pub static FUNDER_LOCAL_SEQUENCE_NUMBER: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_faucet_funder_local_sequence_number",
+        "Sequence number of the funder account cached by the faucet"
+    )
+    .unwrap()
+});
+
+/// Ranges of sequence numbers leased from Redis, see `sequence_leases`
+pub static SEQUENCE_LEASES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "aptos_faucet_sequence_leases",
+        "Ranges of the funder's sequence numbers leased by this replica"
+    )
+    .unwrap()
+});
+
+/// 1 while this replica holds the leader lease, see `leader`
+pub static LEADER: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_faucet_leader",
+        "Whether this replica is the leader submitting transactions"
+    )
+    .unwrap()
+});
+
+pub static FUNDER_ONCHAIN_SEQUENCE_NUMBER: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "aptos_faucet_funder_onchain_sequence_number",
+        "Sequence number of the funder account on chain"
+    )
+    .unwrap()
+});
+
+/// Filler transactions submitted for sequence numbers later transactions of the funder waited on,
+/// see `gaps`
+pub static SEQUENCE_NUMBER_GAPS_FILLED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "aptos_faucet_sequence_number_gaps_filled",
+        "Number of filler transactions submitted for sequence number gaps"
+    )
+    .unwrap()
+});
+
+/// Transactions of the funder that had not committed long after their submission, by whether
+/// they were `resubmitted` or `abandoned` once expired, see `stuck`
+pub static STUCK_TRANSACTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_faucet_stuck_transactions",
+        "Number of stuck transactions, by whether they were resubmitted or abandoned",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+/// Scheduled rotations of the delegated funder's key, by whether they were `rotated` or `failed`
+pub static FUNDER_KEY_ROTATIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_faucet_funder_key_rotations",
+        "Number of scheduled rotations of the delegated funder's key, by outcome",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+/// Chain resets the faucet recovered from, by why the chain was deemed reset: `genesis_changed`,
+/// `version_went_backwards` or `funder_vanished`, see `chain_reset`
+pub static CHAIN_RESETS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_faucet_chain_resets",
+        "Number of chain resets detected, by reason",
+        &["reason"]
+    )
+    .unwrap()
+});
+
+/// Lookups of the funder's sequence number, by whether the cache had it (`hit`) or the node was
+/// asked (`miss`)
+pub static FUNDER_CACHE: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aptos_faucet_funder_cache",
+        "Number of lookups of the funder's sequence number, by cache outcome",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+/// Latency of the faucet's requests to the node's REST API, by `operation`: `get_account`,
+/// `get_account_balance`, `submit`, `wait`, `get_transaction` or `get_ledger_information`. Tells a
+/// slow node apart from a slow faucet
+pub static NODE_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "aptos_faucet_node_request_duration_seconds",
+        "Latency of requests to the node's REST API, by operation",
+        &["operation"]
+    )
+    .unwrap()
+});
+
+/// Sends `request` to the node, recording its latency under `operation`
+pub async fn node_request<F: Future>(operation: &'static str, request: F) -> F::Output {
+    let _timer = NODE_REQUEST_DURATION
+        .with_label_values(&[operation])
+        .start_timer();
+    request.await
+}
+
+/// Every registered metric, in the Prometheus text format
+pub fn encode() -> String {
+    let mut buffer = Vec::new();
+    if let Err(err) = TextEncoder::new().encode(&aptos_metrics_core::gather(), &mut buffer) {
+        aptos_logger::error!("[faucet]: unable to encode metrics: {}", err);
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
@@ -0,0 +1,85 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Single use codes for larger grants, e.g. for hackathons. Operators issue them with
+//! `POST /admin/coupons?amount=<amount>&count=<count>`, and requesters redeem one by passing
+//! `coupon=<code>` to `/mint`. A coupon mints its own amount, without the faucet's maximum amount,
+//! checker caps, IP limits or spend budget applying. Coupons are kept in memory, and are lost
+//! when the faucet restarts.
+
+use aptos_infallible::Mutex;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Coupons issued together, as returned by `POST /admin/coupons`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct IssuedCoupons {
+    /// Amount each coupon mints
+    pub amount: u64,
+    pub codes: Vec<String>,
+}
+
+/// Coupons issued and not yet redeemed
+#[derive(Default)]
+pub struct CouponStore {
+    coupons: Mutex<HashMap<String, u64>>,
+}
+
+impl CouponStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Issues `count` coupons minting `amount` each
+    pub fn issue(&self, amount: u64, count: usize) -> IssuedCoupons {
+        let mut coupons = self.coupons.lock();
+        let codes = (0..count)
+            .map(|_| {
+                let mut bytes = [0u8; 16];
+                rand::rngs::OsRng.fill_bytes(&mut bytes);
+                let code = hex::encode(bytes);
+                coupons.insert(code.clone(), amount);
+                code
+            })
+            .collect();
+        IssuedCoupons { amount, codes }
+    }
+
+    /// Uses up `code`, returning the amount it mints
+    pub fn redeem(&self, code: &str) -> Option<u64> {
+        self.coupons.lock().remove(code)
+    }
+
+    /// Makes a redeemed coupon usable again, e.g. because funding failed
+    pub fn restore(&self, code: String, amount: u64) {
+        self.coupons.lock().insert(code, amount);
+    }
+
+    /// Coupons not redeemed yet, by code
+    pub fn outstanding(&self) -> HashMap<String, u64> {
+        self.coupons.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coupons_are_single_use() {
+        let store = CouponStore::new();
+        let issued = store.issue(1_000, 2);
+        assert_eq!(issued.codes.len(), 2);
+        assert_ne!(issued.codes[0], issued.codes[1]);
+
+        let code = &issued.codes[0];
+        assert_eq!(store.redeem(code), Some(1_000));
+        assert_eq!(store.redeem(code), None);
+        assert_eq!(store.outstanding().len(), 1);
+
+        store.restore(code.clone(), 1_000);
+        assert_eq!(store.redeem(code), Some(1_000));
+        assert_eq!(store.redeem("unknown"), None);
+    }
+}
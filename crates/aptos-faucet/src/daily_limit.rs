@@ -0,0 +1,360 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Caps the amount each caller is funded per UTC day, independently of the `maximum_amount`
+//! capping every single request. Callers are told apart by their partner, or else by their IP, so
+//! that spreading requests over many receivers does not get around the cap. `Authorization`
+//! headers are not, as they are only verified when GitHub checks are on. Requests are capped at
+//! what is left of their caller's allowance, and refused once it is used up.
+//!
+//! Usage is kept in Redis when the replicas already share one, for leader election or sequence
+//! leases, so that every replica enforces the same cap. Otherwise it is kept in memory, and the
+//! cap applies to each process on its own. Either way usage is forgotten when the day ends.
+
+use crate::redis;
+use anyhow::{format_err, Result};
+use aptos_infallible::{Mutex, MutexGuard};
+use aptos_logger::warn;
+use aptos_sdk::types::account_address::AccountAddress;
+use chrono::{DateTime, Utc};
+use std::{collections::HashMap, convert::TryFrom, fmt, net::IpAddr, time::Duration};
+
+const SECS_PER_DAY: i64 = 86400;
+
+/// How long the store may take to answer
+const REDIS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Reserves up to ARGV[1] of the allowance of ARGV[2] kept at KEYS[1], returning the amount
+/// reserved, or -1 if less than ARGV[3] is left. The key expires ARGV[4] seconds after the day
+const RESERVE_SCRIPT: &str = "local funded = tonumber(redis.call('get', KEYS[1]) or '0') \
+     local reserved = math.min(tonumber(ARGV[1]), math.max(tonumber(ARGV[2]) - funded, 0)) \
+     if reserved < tonumber(ARGV[3]) then return -1 end \
+     redis.call('incrby', KEYS[1], reserved) \
+     redis.call('expire', KEYS[1], ARGV[4]) \
+     return reserved";
+
+/// Hands back up to ARGV[1] of the allowance kept at KEYS[1]
+const REFUND_SCRIPT: &str = "local funded = tonumber(redis.call('get', KEYS[1]) or '0') \
+     if funded > 0 then redis.call('decrby', KEYS[1], math.min(funded, tonumber(ARGV[1]))) end \
+     return 0";
+
+/// Who an allowance belongs to
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Caller {
+    /// Signed requests, by partner name
+    Partner(String),
+    Ip(IpAddr),
+    /// Requests whose client is unknown, e.g. from embedders without an IP, by receiver
+    Receiver(AccountAddress),
+}
+
+impl Caller {
+    pub fn new(partner: Option<&str>, client_ip: Option<IpAddr>, receiver: AccountAddress) -> Self {
+        match (partner, client_ip) {
+            (Some(partner), _) => Caller::Partner(partner.to_string()),
+            (None, Some(ip)) => Caller::Ip(ip),
+            (None, None) => Caller::Receiver(receiver),
+        }
+    }
+
+    /// Part of the Redis key of the caller's allowance
+    fn key(&self) -> String {
+        match self {
+            Caller::Partner(partner) => format!("partner:{}", partner),
+            Caller::Ip(ip) => format!("ip:{}", ip),
+            Caller::Receiver(receiver) => format!("receiver:{}", receiver),
+        }
+    }
+}
+
+impl fmt::Display for Caller {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Caller::Partner(partner) => write!(f, "Partner '{}'", partner),
+            Caller::Ip(ip) => write!(f, "{}", ip),
+            Caller::Receiver(receiver) => write!(f, "{}", receiver),
+        }
+    }
+}
+
+/// Returned when a caller has been funded its whole daily maximum
+#[derive(Debug, Eq, PartialEq)]
+pub struct DailyLimitReached {
+    pub caller: Caller,
+    pub limit: u64,
+    /// Time until the UTC day ends and the allowance is replenished
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for DailyLimitReached {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} was funded its daily maximum of {}, try again in {} seconds",
+            self.caller,
+            self.limit,
+            self.retry_after.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for DailyLimitReached {}
+
+/// The Redis server keeping the usage of every replica
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RedisStore {
+    pub address: String,
+    pub password: Option<String>,
+    /// Prefix of the keys, followed by the day and the caller
+    pub key: String,
+}
+
+struct Usage {
+    /// Days since the epoch the amounts below were funded on
+    day: i64,
+    funded: HashMap<Caller, u64>,
+}
+
+/// Allows each caller at most `limit` coins per UTC day
+pub struct DailyLimit {
+    limit: u64,
+    redis: Option<RedisStore>,
+    usage: Mutex<Usage>,
+}
+
+impl DailyLimit {
+    /// Keeps usage in memory, for this process alone
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            redis: None,
+            usage: Mutex::new(Usage {
+                day: 0,
+                funded: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Keeps usage in `redis`, shared by every replica using it
+    pub fn with_redis(limit: u64, redis: RedisStore) -> Self {
+        Self {
+            redis: Some(redis),
+            ..Self::new(limit)
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Reserves up to `amount` of `caller`'s allowance for today, returning the amount reserved.
+    /// Fails with a [`DailyLimitReached`] if less than `minimum` is left, or if the store cannot
+    /// be reached
+    pub async fn reserve(&self, caller: &Caller, amount: u64, minimum: u64) -> Result<u64> {
+        let now = Utc::now();
+        let minimum = minimum.max(1);
+        let reserved = match &self.redis {
+            Some(redis) => {
+                let (amount, limit, min, expiry) = (
+                    amount.to_string(),
+                    self.limit.to_string(),
+                    minimum.to_string(),
+                    (until_tomorrow(now).as_secs() + 60).to_string(),
+                );
+                let reserve = redis::eval(
+                    &redis.address,
+                    redis.password.as_deref(),
+                    RESERVE_SCRIPT,
+                    &[redis_key(redis, caller, now).as_str()],
+                    &[
+                        amount.as_str(),
+                        limit.as_str(),
+                        min.as_str(),
+                        expiry.as_str(),
+                    ],
+                );
+                let reserved = tokio::time::timeout(REDIS_TIMEOUT, reserve)
+                    .await
+                    .map_err(|_| format_err!("Checking the daily limit timed out"))?
+                    .map_err(|err| format_err!("Unable to check the daily limit: {:#}", err))?;
+                u64::try_from(reserved).ok()
+            }
+            None => self.reserve_at(caller, amount, minimum, now),
+        };
+        reserved.ok_or_else(|| {
+            DailyLimitReached {
+                caller: caller.clone(),
+                limit: self.limit,
+                retry_after: until_tomorrow(now),
+            }
+            .into()
+        })
+    }
+
+    fn reserve_at(
+        &self,
+        caller: &Caller,
+        amount: u64,
+        minimum: u64,
+        now: DateTime<Utc>,
+    ) -> Option<u64> {
+        let mut usage = self.usage_on(now);
+        let funded = usage.funded.entry(caller.clone()).or_default();
+        let reserved = std::cmp::min(amount, self.limit.saturating_sub(*funded));
+        if reserved < minimum {
+            return None;
+        }
+        *funded += reserved;
+        Some(reserved)
+    }
+
+    /// Returns a reservation that was never funded, e.g. because submission failed
+    pub async fn refund(&self, caller: &Caller, amount: u64) {
+        let now = Utc::now();
+        match &self.redis {
+            Some(redis) => {
+                let amount = amount.to_string();
+                let refund = redis::eval(
+                    &redis.address,
+                    redis.password.as_deref(),
+                    REFUND_SCRIPT,
+                    &[redis_key(redis, caller, now).as_str()],
+                    &[amount.as_str()],
+                );
+                match tokio::time::timeout(REDIS_TIMEOUT, refund).await {
+                    Ok(Ok(_)) => (),
+                    Ok(Err(err)) => warn!("[faucet]: unable to refund the daily limit: {:#}", err),
+                    Err(_) => warn!("[faucet]: refunding the daily limit timed out"),
+                }
+            }
+            None => {
+                let mut usage = self.usage_on(now);
+                if let Some(funded) = usage.funded.get_mut(caller) {
+                    *funded = funded.saturating_sub(amount);
+                    if *funded == 0 {
+                        usage.funded.remove(caller);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The usage of the day `now` falls on, forgetting that of previous days
+    fn usage_on(&self, now: DateTime<Utc>) -> MutexGuard<'_, Usage> {
+        let today = day(now);
+        let mut usage = self.usage.lock();
+        if usage.day != today {
+            usage.day = today;
+            usage.funded.clear();
+        }
+        usage
+    }
+}
+
+fn redis_key(redis: &RedisStore, caller: &Caller, now: DateTime<Utc>) -> String {
+    format!("{}:{}:{}", redis.key, day(now), caller.key())
+}
+
+/// Days since the epoch
+fn day(now: DateTime<Utc>) -> i64 {
+    now.timestamp().div_euclid(SECS_PER_DAY)
+}
+
+fn until_tomorrow(now: DateTime<Utc>) -> Duration {
+    let elapsed = now.timestamp().rem_euclid(SECS_PER_DAY) as u64;
+    Duration::from_secs(SECS_PER_DAY as u64 - elapsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::fake_server;
+    use chrono::TimeZone;
+
+    fn ip(ip: &str) -> Caller {
+        Caller::Ip(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn allowance_is_capped_then_exhausted() {
+        let limit = DailyLimit::new(100);
+        let caller = ip("10.0.0.1");
+        let now = Utc.ymd(2022, 8, 1).and_hms(23, 0, 0);
+
+        assert_eq!(limit.reserve_at(&caller, 60, 1, now), Some(60));
+        // Capped at what is left
+        assert_eq!(limit.reserve_at(&caller, 60, 1, now), Some(40));
+        assert_eq!(limit.reserve_at(&caller, 60, 1, now), None);
+        assert_eq!(until_tomorrow(now), Duration::from_secs(3600));
+
+        // Other callers have their own allowance
+        assert_eq!(limit.reserve_at(&ip("10.0.0.2"), 100, 1, now), Some(100));
+        // And it is replenished the next day, when the previous day is forgotten
+        let tomorrow = Utc.ymd(2022, 8, 2).and_hms(0, 0, 1);
+        assert_eq!(limit.reserve_at(&caller, 100, 1, tomorrow), Some(100));
+        assert_eq!(limit.usage.lock().funded.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn leftovers_below_the_minimum_are_refused() {
+        let limit = DailyLimit::new(100);
+        let caller = ip("10.0.0.1");
+
+        assert_eq!(limit.reserve(&caller, 90, 20).await.unwrap(), 90);
+        let err = limit.reserve(&caller, 90, 20).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<DailyLimitReached>().unwrap().caller,
+            caller
+        );
+        // Refunded allowances are forgotten
+        limit.refund(&caller, 90).await;
+        assert!(limit.usage.lock().funded.is_empty());
+        assert_eq!(limit.reserve(&caller, 100, 20).await.unwrap(), 100);
+    }
+
+    #[test]
+    fn callers_are_told_apart_by_partner_then_ip() {
+        let receiver = AccountAddress::random();
+        let client_ip = Some("10.0.0.1".parse().unwrap());
+
+        assert_eq!(
+            Caller::new(Some("ci"), client_ip, receiver),
+            Caller::Partner("ci".to_string())
+        );
+        assert_eq!(Caller::new(None, client_ip, receiver), ip("10.0.0.1"));
+        assert_eq!(
+            Caller::new(None, None, receiver),
+            Caller::Receiver(receiver)
+        );
+    }
+
+    #[tokio::test]
+    async fn usage_is_kept_in_redis() {
+        let store = RedisStore {
+            address: fake_server(vec![100, -1, 0]).await,
+            password: Some("secret".to_string()),
+            key: "aptos-faucet:daily".to_string(),
+        };
+        let limit = DailyLimit::with_redis(100, store);
+        let caller = ip("10.0.0.1");
+
+        assert_eq!(limit.reserve(&caller, 100, 1).await.unwrap(), 100);
+        let err = limit.reserve(&caller, 100, 1).await.unwrap_err();
+        assert!(err.downcast_ref::<DailyLimitReached>().is_some());
+        limit.refund(&caller, 100).await;
+        // Nothing is kept in memory
+        assert!(limit.usage.lock().funded.is_empty());
+
+        // Failing to reach the store is not taken for the limit being reached
+        let unreachable = DailyLimit::with_redis(
+            100,
+            RedisStore {
+                address: "127.0.0.1:1".to_string(),
+                password: None,
+                key: "aptos-faucet:daily".to_string(),
+            },
+        );
+        let err = unreachable.reserve(&caller, 100, 1).await.unwrap_err();
+        assert!(err.downcast_ref::<DailyLimitReached>().is_none());
+    }
+}
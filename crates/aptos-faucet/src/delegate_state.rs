@@ -0,0 +1,161 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! The delegated mint account, saved to a file so that a restarted faucet keeps minting from it
+//! rather than delegating to yet another account and abandoning the funded one.
+
+use anyhow::{format_err, Result};
+use aptos_config::keys::ConfigKey;
+use aptos_crypto::{ed25519::Ed25519PrivateKey, ValidCryptoMaterial};
+use aptos_sdk::types::{
+    account_address::AccountAddress, transaction::authenticator::AuthenticationKey, LocalAccount,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::TryFrom,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct DelegateState {
+    pub address: AccountAddress,
+    pub key: ConfigKey<Ed25519PrivateKey>,
+    /// When `key` was generated, missing from files saved before keys were rotated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_created_at: Option<DateTime<Utc>>,
+    /// Key the account is being rotated to, kept until the chain tells whether it switched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_key: Option<ConfigKey<Ed25519PrivateKey>>,
+}
+
+impl DelegateState {
+    pub fn new(key: Ed25519PrivateKey) -> Self {
+        let key = ConfigKey::new(key);
+        Self {
+            address: AuthenticationKey::ed25519(&key.public_key()).derived_address(),
+            key,
+            key_created_at: Some(Utc::now()),
+            pending_key: None,
+        }
+    }
+
+    /// The state of `account`, whose key was generated at `key_created_at`, about to rotate its
+    /// authentication key to `pending_key`
+    pub fn rotating(
+        account: &LocalAccount,
+        key_created_at: DateTime<Utc>,
+        pending_key: ConfigKey<Ed25519PrivateKey>,
+    ) -> Result<Self> {
+        let key = Ed25519PrivateKey::try_from(account.private_key().to_bytes().as_slice())
+            .map_err(|err| {
+                format_err!("Unable to copy the key of {}: {}", account.address(), err)
+            })?;
+        Ok(Self {
+            address: account.address(),
+            key: ConfigKey::new(key),
+            key_created_at: Some(key_created_at),
+            pending_key: Some(pending_key),
+        })
+    }
+
+    /// The state once `auth_key` is known to control the account: the pending key becomes the
+    /// key if it is the one, and is dropped otherwise. `None` if neither key controls it
+    pub fn settle(self, auth_key: &AuthenticationKey) -> Option<Self> {
+        let controls = |key: &ConfigKey<Ed25519PrivateKey>| {
+            AuthenticationKey::ed25519(&key.public_key()) == *auth_key
+        };
+        match self.pending_key {
+            Some(pending_key) if controls(&pending_key) => Some(Self {
+                address: self.address,
+                key: pending_key,
+                key_created_at: Some(Utc::now()),
+                pending_key: None,
+            }),
+            _ if controls(&self.key) => Some(Self {
+                pending_key: None,
+                ..self
+            }),
+            _ => None,
+        }
+    }
+
+    /// Reads the state saved at `path`, `None` if nothing was saved yet
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(format_err!("Unable to read {:?}: {}", path, err)),
+        };
+        serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|err| format_err!("Unable to parse {:?}: {}", path, err))
+    }
+
+    /// Writes the state to `path`, only readable by its owner as it holds the key. The file is
+    /// replaced in one rename, so a crash never leaves it half written
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options
+            .open(&tmp_path)
+            .map_err(|err| format_err!("Unable to create {:?}: {}", tmp_path, err))?;
+        file.write_all(&serde_json::to_vec_pretty(self)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+            .map_err(|err| format_err!("Unable to write {:?}: {}", path, err))
+    }
+
+    pub fn account(&self, sequence_number: u64) -> LocalAccount {
+        LocalAccount::new(self.address, self.key.private_key(), sequence_number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_crypto::Uniform;
+
+    #[test]
+    fn saved_state_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("delegate.json");
+        assert_eq!(DelegateState::load(&path).unwrap(), None);
+
+        let state = DelegateState::new(Ed25519PrivateKey::generate(&mut rand::rngs::OsRng));
+        state.save(&path).unwrap();
+        assert_eq!(DelegateState::load(&path).unwrap(), Some(state));
+
+        fs::write(&path, "{").unwrap();
+        assert!(DelegateState::load(&path).is_err());
+    }
+
+    #[test]
+    fn pending_keys_settle() {
+        let key = || ConfigKey::new(Ed25519PrivateKey::generate(&mut rand::rngs::OsRng));
+        let auth_key =
+            |key: &ConfigKey<Ed25519PrivateKey>| AuthenticationKey::ed25519(&key.public_key());
+        let state = DelegateState::new(Ed25519PrivateKey::generate(&mut rand::rngs::OsRng));
+        let account = state.account(0);
+        let (current, pending) = (state.key, key());
+
+        let rotating = || DelegateState::rotating(&account, Utc::now(), pending.clone()).unwrap();
+        assert_eq!(rotating().key, current);
+
+        let settled = rotating().settle(&auth_key(&pending)).unwrap();
+        assert_eq!(settled.key, pending);
+        assert_eq!(settled.pending_key, None);
+
+        let settled = rotating().settle(&auth_key(&current)).unwrap();
+        assert_eq!(settled.key, current);
+        assert_eq!(settled.pending_key, None);
+
+        assert_eq!(rotating().settle(&auth_key(&key())), None);
+    }
+}
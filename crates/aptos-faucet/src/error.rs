@@ -0,0 +1,268 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Why the faucet refused or failed a funding request. Routes answer with the status
+//! [`FaucetError::status_code`] maps each error to, and embedders calling into the library get
+//! the same typed errors.
+
+use crate::receiver;
+use serde::{Deserialize, Serialize};
+use std::{fmt, time::Duration};
+use thiserror::Error;
+use url::Url;
+use warp::http::StatusCode;
+
+#[derive(Debug, Error)]
+pub enum FaucetError {
+    // Validation
+    /// The request does not say which account to fund, or how much
+    #[error("{0}")]
+    InvalidParams(String),
+    /// Request fields are malformed or conflict with each other
+    #[error("{}", describe(.0))]
+    InvalidFields(Vec<InvalidField>),
+    /// The amount is below the configured minimum
+    #[error("'amount' must be at least {minimum}")]
+    AmountTooSmall { minimum: u64 },
+    /// The requested `chain_id` is not served by the faucet
+    #[error("This faucet does not serve chain '{0}'")]
+    UnknownChain(String),
+    /// The account to create already exists
+    #[error("The account already exists")]
+    AccountExists,
+    /// The name to fund is not registered, expired or does not point to an address, see
+    /// [`crate::ans`]
+    #[error("{0}")]
+    UnresolvedName(String),
+
+    // Limits
+    /// The client is over its address or subnet limit
+    #[error("{message}")]
+    RateLimited {
+        message: String,
+        retry_after: Duration,
+    },
+    /// The faucet minted its whole budget for the current window
+    #[error("{message}")]
+    BudgetExhausted {
+        message: String,
+        retry_after: Duration,
+    },
+    /// The client went over a violation threshold and is temporarily banned, see
+    /// [`crate::bans`]
+    #[error("{message}")]
+    Banned {
+        message: String,
+        retry_after: Duration,
+    },
+    /// The request was still waiting for a processing slot when its client gave up on it, see
+    /// [`crate::mint_queue`]
+    #[error("The request waited too long to be processed")]
+    Expired,
+    /// This replica is a standby, and leaves funding to the leader, see [`crate::leader`]
+    #[error("This faucet replica is on standby, try again shortly")]
+    Standby,
+    /// The receiver is funded by the replica of another shard, see [`crate::sharding`]
+    #[error("{}", describe_shard(.shard, .url))]
+    WrongShard { shard: u32, url: Option<Url> },
+
+    // Checks
+    /// The signature challenge was missing or invalid
+    #[error("{0}")]
+    ChallengeFailed(String),
+    /// A checker denied the request
+    #[error("{0}")]
+    Blocked(String),
+    /// The coupon was never issued or was already redeemed
+    #[error("Unknown or already redeemed coupon")]
+    InvalidCoupon,
+    /// The request's partner signature was invalid
+    #[error("{0}")]
+    Unauthorized(String),
+
+    // Node and funding
+    /// The node could not be queried for what the request needs
+    #[error("{0}")]
+    Node(anyhow::Error),
+    /// The funding transactions could not be built, submitted or committed
+    #[error("{0}")]
+    Funding(anyhow::Error),
+}
+
+/// A request field that is malformed or conflicts with another, so that frontends can point at
+/// the offending input
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct InvalidField {
+    pub field: String,
+    /// What is wrong with it, to be read after the field's name
+    pub reason: String,
+}
+
+impl InvalidField {
+    pub fn new(field: &str, reason: impl fmt::Display) -> Self {
+        Self {
+            field: field.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for InvalidField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}' {}", self.field, self.reason)
+    }
+}
+
+impl From<receiver::FieldError> for InvalidField {
+    fn from(err: receiver::FieldError) -> Self {
+        InvalidField::new(err.field, err.reason)
+    }
+}
+
+impl From<receiver::FieldError> for FaucetError {
+    fn from(err: receiver::FieldError) -> Self {
+        FaucetError::InvalidFields(vec![err.into()])
+    }
+}
+
+impl From<InvalidField> for FaucetError {
+    fn from(field: InvalidField) -> Self {
+        FaucetError::InvalidFields(vec![field])
+    }
+}
+
+fn describe_shard(shard: &u32, url: &Option<Url>) -> String {
+    match url {
+        Some(url) => format!("The receiver is funded by shard {}, at {}", shard, url),
+        None => format!("The receiver is funded by shard {}", shard),
+    }
+}
+
+fn describe(fields: &[InvalidField]) -> String {
+    fields
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl FaucetError {
+    /// Cause the rejection is counted under
+    pub fn reason(&self) -> &'static str {
+        match self {
+            FaucetError::InvalidParams(_)
+            | FaucetError::InvalidFields(_)
+            | FaucetError::AmountTooSmall { .. }
+            | FaucetError::UnknownChain(_) => "bad_params",
+            FaucetError::AccountExists => "account_exists",
+            FaucetError::UnresolvedName(_) => "unresolved_name",
+            FaucetError::RateLimited { .. } => "rate_limited",
+            FaucetError::BudgetExhausted { .. } => "budget_exhausted",
+            FaucetError::Banned { .. } => "banned",
+            FaucetError::Expired => "expired",
+            FaucetError::Standby => "standby",
+            FaucetError::WrongShard { .. } => "wrong_shard",
+            FaucetError::ChallengeFailed(_) => "challenge_failed",
+            FaucetError::Blocked(_) => "blocked",
+            FaucetError::InvalidCoupon => "invalid_coupon",
+            FaucetError::Unauthorized(_) => "unauthorized",
+            // The VM reports the faucet account running dry as INSUFFICIENT_BALANCE_*
+            FaucetError::Funding(err) if format!("{:#}", err).contains("INSUFFICIENT_BALANCE") => {
+                "insufficient_balance"
+            }
+            FaucetError::Node(_) | FaucetError::Funding(_) => "node_error",
+        }
+    }
+
+    /// Status the request is answered with
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            FaucetError::InvalidParams(_)
+            | FaucetError::InvalidFields(_)
+            | FaucetError::AmountTooSmall { .. }
+            | FaucetError::UnknownChain(_) => StatusCode::BAD_REQUEST,
+            FaucetError::AccountExists => StatusCode::CONFLICT,
+            FaucetError::UnresolvedName(_) => StatusCode::NOT_FOUND,
+            FaucetError::RateLimited { .. } | FaucetError::BudgetExhausted { .. } => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            FaucetError::Expired | FaucetError::Standby => StatusCode::SERVICE_UNAVAILABLE,
+            FaucetError::WrongShard { .. } => StatusCode::MISDIRECTED_REQUEST,
+            FaucetError::ChallengeFailed(_)
+            | FaucetError::Banned { .. }
+            | FaucetError::Blocked(_)
+            | FaucetError::InvalidCoupon => StatusCode::FORBIDDEN,
+            FaucetError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            FaucetError::Node(_) | FaucetError::Funding(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// When the client may try again, for requests refused by a limit
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            FaucetError::RateLimited { retry_after, .. }
+            | FaucetError::BudgetExhausted { retry_after, .. }
+            | FaucetError::Banned { retry_after, .. } => Some(*retry_after),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::format_err;
+
+    #[test]
+    fn errors_map_to_statuses() {
+        let limited = FaucetError::RateLimited {
+            message: "slow down".to_string(),
+            retry_after: Duration::from_secs(5),
+        };
+        assert_eq!(limited.status_code(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(limited.retry_after(), Some(Duration::from_secs(5)));
+        assert_eq!(limited.to_string(), "slow down");
+
+        let banned = FaucetError::Banned {
+            message: "go away".to_string(),
+            retry_after: Duration::from_secs(600),
+        };
+        assert_eq!(banned.status_code(), StatusCode::FORBIDDEN);
+        assert_eq!(banned.reason(), "banned");
+        assert_eq!(banned.retry_after(), Some(Duration::from_secs(600)));
+
+        assert_eq!(
+            FaucetError::Standby.status_code(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        let misdirected = FaucetError::WrongShard {
+            shard: 1,
+            url: None,
+        };
+        assert_eq!(misdirected.status_code(), StatusCode::MISDIRECTED_REQUEST);
+        assert_eq!(misdirected.to_string(), "The receiver is funded by shard 1");
+
+        let too_small = FaucetError::AmountTooSmall { minimum: 10 };
+        assert_eq!(too_small.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(too_small.to_string(), "'amount' must be at least 10");
+        assert_eq!(too_small.retry_after(), None);
+
+        let invalid = FaucetError::InvalidFields(vec![
+            InvalidField::new("amount", "must be a number"),
+            InvalidField::new("wait", "must be true or false"),
+        ]);
+        assert_eq!(invalid.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            invalid.to_string(),
+            "'amount' must be a number, 'wait' must be true or false"
+        );
+
+        let dry = FaucetError::Funding(format_err!("Move abort: EINSUFFICIENT_BALANCE"));
+        assert_eq!(dry.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(dry.reason(), "insufficient_balance");
+        assert_eq!(
+            FaucetError::Node(format_err!("unreachable")).reason(),
+            "node_error"
+        );
+    }
+}
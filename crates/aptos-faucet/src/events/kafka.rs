@@ -0,0 +1,199 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Produces events to a Kafka topic through a Kafka REST Proxy (v2 API), which keeps the
+//! faucet free of a native Kafka client.
+
+use super::EventSink;
+use crate::audit::AuditEntry;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
+use url::Url;
+
+/// Records without a schema
+const JSON_CONTENT_TYPE: &str = "application/vnd.kafka.json.v2+json";
+/// Records validated against a JSON Schema from the schema registry
+const JSON_SCHEMA_CONTENT_TYPE: &str = "application/vnd.kafka.jsonschema.v2+json";
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct KafkaConfig {
+    /// Base URL of the REST Proxy, e.g. `http://kafka-rest:8082`
+    pub rest_proxy_url: String,
+    pub topic: String,
+    /// Id of the registered JSON Schema the events are validated against. Events are produced
+    /// as schemaless JSON without one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_schema_id: Option<u32>,
+    /// Headers sent with every request, e.g. credentials
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Further headers, with their values read from files, see [`crate::secrets`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub header_files: BTreeMap<String, PathBuf>,
+    /// Events waiting to be produced, further ones are dropped
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_queue_size() -> usize {
+    10_000
+}
+
+fn default_timeout_ms() -> u64 {
+    5_000
+}
+
+impl KafkaConfig {
+    pub fn new(rest_proxy_url: String, topic: String) -> Self {
+        Self {
+            rest_proxy_url,
+            topic,
+            value_schema_id: None,
+            headers: BTreeMap::new(),
+            header_files: BTreeMap::new(),
+            queue_size: default_queue_size(),
+            timeout_ms: default_timeout_ms(),
+        }
+    }
+}
+
+/// Response to a produce request, with an entry per record
+#[derive(Debug, Deserialize)]
+struct ProduceResponse {
+    offsets: Vec<RecordOffset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordOffset {
+    #[serde(default)]
+    error: Option<String>,
+}
+
+pub struct KafkaProducer {
+    client: reqwest::Client,
+    /// Produce URL of the topic
+    url: Url,
+    config: KafkaConfig,
+}
+
+impl KafkaProducer {
+    pub fn new(config: KafkaConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()?;
+        let url = Url::parse(&format!(
+            "{}/topics/{}",
+            config.rest_proxy_url.trim_end_matches('/'),
+            config.topic
+        ))?;
+        Ok(Self {
+            client,
+            url,
+            config,
+        })
+    }
+}
+
+#[async_trait]
+impl EventSink for KafkaProducer {
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
+    async fn send(&self, events: &[AuditEntry]) -> Result<()> {
+        let records: Vec<_> = events
+            .iter()
+            .map(|event| json!({ "value": event }))
+            .collect();
+        let (content_type, body) = match self.config.value_schema_id {
+            Some(schema_id) => (
+                JSON_SCHEMA_CONTENT_TYPE,
+                json!({ "value_schema_id": schema_id, "records": records }),
+            ),
+            None => (JSON_CONTENT_TYPE, json!({ "records": records })),
+        };
+
+        let mut request = self
+            .client
+            .post(self.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(serde_json::to_vec(&body)?);
+        for (name, value) in &self.config.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        let response: ProduceResponse =
+            serde_json::from_slice(&request.send().await?.error_for_status()?.bytes().await?)?;
+
+        let errors: Vec<_> = response
+            .offsets
+            .iter()
+            .filter_map(|offset| offset.error.as_deref())
+            .collect();
+        if let Some(error) = errors.first() {
+            bail!(
+                "{} of {} records were refused: {}",
+                errors.len(),
+                events.len(),
+                error
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{audit::Decision, error::FaucetError};
+    use aptos_infallible::Mutex;
+    use std::sync::Arc;
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn events_are_produced_to_the_topic() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let proxy = warp::path!("topics" / "faucet-events")
+            .and(warp::post())
+            .and(warp::header::exact(
+                "content-type",
+                JSON_SCHEMA_CONTENT_TYPE,
+            ))
+            .and(warp::body::bytes())
+            .map({
+                let received = received.clone();
+                move |body: bytes::Bytes| {
+                    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                    received.lock().push(body);
+                    warp::reply::json(&json!({ "offsets": [{ "partition": 0, "offset": 0 }] }))
+                }
+            });
+        let (address, server) = warp::serve(proxy).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let producer = KafkaProducer::new(KafkaConfig {
+            value_schema_id: Some(7),
+            ..KafkaConfig::new(format!("http://{}/", address), "faucet-events".to_string())
+        })
+        .unwrap();
+        let rejected = AuditEntry::new(
+            None,
+            None,
+            10,
+            &Err(FaucetError::InvalidParams("bad".to_string())),
+        );
+        producer.send(&[rejected]).await.unwrap();
+
+        let body = received.lock().pop().unwrap();
+        assert_eq!(body["value_schema_id"], 7);
+        let event: AuditEntry =
+            serde_json::from_value(body["records"][0]["value"].clone()).unwrap();
+        assert_eq!(event.decision, Decision::Rejected);
+        assert_eq!(event.requested_amount, 10);
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Publishes every funding decision, funded or rejected, so that analytics pipelines can follow
+//! faucet activity without scraping the chain. Events are the [`AuditEntry`]s the audit log
+//! records. They are queued and published in batches in the background, so that a slow or
+//! unavailable broker never delays requests: once the queue is full, new events are dropped.
+//!
+//! Events can go to Kafka or NATS, and other brokers can be added by implementing
+//! [`EventSink`].
+
+pub mod kafka;
+pub mod nats;
+
+use crate::{audit::AuditEntry, counters};
+use anyhow::Result;
+use aptos_logger::warn;
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+
+/// Events published in a single call to [`EventSink::send`]
+const MAX_BATCH_SIZE: usize = 100;
+
+/// Where events are published to
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    /// Labels the sink's `aptos_faucet_events` counts
+    fn name(&self) -> &'static str;
+
+    /// Publishes `events`, in order
+    async fn send(&self, events: &[AuditEntry]) -> Result<()>;
+}
+
+pub struct EventPublisher {
+    name: &'static str,
+    sender: mpsc::Sender<AuditEntry>,
+}
+
+impl EventPublisher {
+    /// Starts publishing to `sink` in the background, with up to `queue_size` events waiting.
+    /// Must be called from within a tokio runtime
+    pub fn start(sink: impl EventSink + 'static, queue_size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(queue_size);
+        let name = sink.name();
+        tokio::spawn(publish(sink, receiver));
+        Self { name, sender }
+    }
+
+    /// Queues `event`, dropping it if the queue is full
+    pub fn publish(&self, event: AuditEntry) {
+        if self.sender.try_send(event).is_err() {
+            counters::EVENTS
+                .with_label_values(&[self.name, "dropped"])
+                .inc();
+        }
+    }
+}
+
+/// Sends queued events in batches until the publisher is dropped
+async fn publish(sink: impl EventSink, mut receiver: mpsc::Receiver<AuditEntry>) {
+    while let Some(event) = receiver.recv().await {
+        let mut batch = vec![event];
+        while batch.len() < MAX_BATCH_SIZE {
+            match receiver.try_recv() {
+                Ok(event) => batch.push(event),
+                Err(_) => break,
+            }
+        }
+
+        match sink.send(&batch).await {
+            Ok(()) => counters::EVENTS
+                .with_label_values(&[sink.name(), "published"])
+                .inc_by(batch.len() as u64),
+            Err(err) => {
+                warn!(
+                    "[faucet]: failed to publish {} events to {}: {}",
+                    batch.len(),
+                    sink.name(),
+                    err
+                );
+                counters::EVENTS
+                    .with_label_values(&[sink.name(), "failed"])
+                    .inc_by(batch.len() as u64);
+            }
+        }
+    }
+}
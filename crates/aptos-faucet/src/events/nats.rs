@@ -0,0 +1,236 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Publishes events to a NATS subject, speaking the plain text client protocol over TCP, for
+//! deployments that don't run Kafka. Each event is its own message. The connection is opened on
+//! the first batch and reopened after any error.
+
+use super::EventSink;
+use crate::audit::AuditEntry;
+use anyhow::{bail, format_err, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{path::PathBuf, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+    sync::Mutex,
+};
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NatsConfig {
+    /// `host:port` of a NATS server, TLS is not supported
+    pub address: String,
+    pub subject: String,
+    /// Authentication token, if the server requires one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// File holding `token`, see [`crate::secrets`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_file: Option<PathBuf>,
+    /// File holding `password`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_file: Option<PathBuf>,
+    /// Events waiting to be published, further ones are dropped
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_queue_size() -> usize {
+    10_000
+}
+
+fn default_timeout_ms() -> u64 {
+    5_000
+}
+
+impl NatsConfig {
+    pub fn new(address: String, subject: String) -> Self {
+        Self {
+            address,
+            subject,
+            token: None,
+            user: None,
+            password: None,
+            token_file: None,
+            password_file: None,
+            queue_size: default_queue_size(),
+            timeout_ms: default_timeout_ms(),
+        }
+    }
+}
+
+type Connection = BufStream<TcpStream>;
+
+pub struct NatsPublisher {
+    config: NatsConfig,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl NatsPublisher {
+    pub fn new(config: NatsConfig) -> Self {
+        Self {
+            config,
+            connection: Mutex::new(None),
+        }
+    }
+
+    async fn connect(&self) -> Result<Connection> {
+        let mut connection = BufStream::new(TcpStream::connect(&self.config.address).await?);
+        let info = read_line(&mut connection).await?;
+        if !info.starts_with("INFO") {
+            bail!("expected INFO from the server, got {:?}", info);
+        }
+
+        let options = json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": "aptos-faucet",
+            "lang": "rust",
+            "version": env!("CARGO_PKG_VERSION"),
+            "auth_token": self.config.token,
+            "user": self.config.user,
+            "pass": self.config.password,
+        });
+        connection
+            .write_all(format!("CONNECT {}\r\n", options).as_bytes())
+            .await?;
+        ping(&mut connection).await?;
+        Ok(connection)
+    }
+
+    async fn publish(&self, connection: &mut Connection, events: &[AuditEntry]) -> Result<()> {
+        for event in events {
+            let payload = serde_json::to_vec(event)?;
+            connection
+                .write_all(format!("PUB {} {}\r\n", self.config.subject, payload.len()).as_bytes())
+                .await?;
+            connection.write_all(&payload).await?;
+            connection.write_all(b"\r\n").await?;
+        }
+        // The server answers a PING only after processing everything sent before it, and reports
+        // refused messages with an -ERR on the way
+        ping(connection).await
+    }
+}
+
+/// Flushes the connection and waits for the server's PONG
+async fn ping(connection: &mut Connection) -> Result<()> {
+    connection.write_all(b"PING\r\n").await?;
+    connection.flush().await?;
+    loop {
+        let line = read_line(connection).await?;
+        match line.as_str() {
+            "PONG" => return Ok(()),
+            "PING" => {
+                connection.write_all(b"PONG\r\n").await?;
+                connection.flush().await?;
+            }
+            _ if line.starts_with("-ERR") => bail!("server error: {}", line),
+            // +OK, or INFO updates about the cluster
+            _ => {}
+        }
+    }
+}
+
+async fn read_line(connection: &mut Connection) -> Result<String> {
+    let mut line = String::new();
+    if connection.read_line(&mut line).await? == 0 {
+        bail!("connection closed by the server");
+    }
+    Ok(line.trim_end().to_string())
+}
+
+#[async_trait]
+impl EventSink for NatsPublisher {
+    fn name(&self) -> &'static str {
+        "nats"
+    }
+
+    async fn send(&self, events: &[AuditEntry]) -> Result<()> {
+        let mut connection = self.connection.lock().await;
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let result = tokio::time::timeout(timeout, async {
+            if connection.is_none() {
+                *connection = Some(self.connect().await?);
+            }
+            self.publish(connection.as_mut().unwrap(), events).await
+        })
+        .await
+        .unwrap_or_else(|_| Err(format_err!("timed out after {:?}", timeout)));
+
+        if result.is_err() {
+            *connection = None;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{audit::Decision, error::FaucetError};
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    #[tokio::test]
+    async fn events_are_published_to_the_subject() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut socket = BufStream::new(socket);
+            socket.write_all(b"INFO {}\r\n").await.unwrap();
+            socket.flush().await.unwrap();
+
+            let mut payloads = Vec::new();
+            loop {
+                let line = read_line(&mut socket).await.unwrap();
+                if line.starts_with("CONNECT") {
+                    assert!(line.contains(r#""auth_token":"secret""#));
+                } else if line == "PING" {
+                    socket.write_all(b"PONG\r\n").await.unwrap();
+                    socket.flush().await.unwrap();
+                    if !payloads.is_empty() {
+                        return payloads;
+                    }
+                } else {
+                    let (subject, len) = line
+                        .strip_prefix("PUB ")
+                        .and_then(|args| args.split_once(' '))
+                        .unwrap();
+                    assert_eq!(subject, "faucet.events");
+                    let mut payload = vec![0; len.parse::<usize>().unwrap() + 2];
+                    socket.read_exact(&mut payload).await.unwrap();
+                    payload.truncate(payload.len() - 2);
+                    payloads.push(payload);
+                }
+            }
+        });
+
+        let publisher = NatsPublisher::new(NatsConfig {
+            token: Some("secret".to_string()),
+            ..NatsConfig::new(address.to_string(), "faucet.events".to_string())
+        });
+        let rejected = AuditEntry::new(
+            None,
+            None,
+            10,
+            &Err(FaucetError::InvalidParams("bad".to_string())),
+        );
+        publisher.send(&[rejected]).await.unwrap();
+
+        let payloads = server.await.unwrap();
+        assert_eq!(payloads.len(), 1);
+        let event: AuditEntry = serde_json::from_slice(&payloads[0]).unwrap();
+        assert_eq!(event.decision, Decision::Rejected);
+        assert_eq!(event.requested_amount, 10);
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! `POST /fund`, with the JSON request and response of the newer "tap" faucet, so that clients
+//! written against either faucet work unchanged. Requests go through the same checks as
+//! `/mint`.
+
+use crate::{
+    amount::Amount,
+    client_ip,
+    error::{FaucetError, InvalidField},
+    mint::{self, MintParams, Response},
+    mint_queue, partner,
+    request_log::{request_context, with_request_id},
+    Service,
+};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::{convert::Infallible, net::IpAddr, sync::Arc, time::Instant};
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+pub fn fund_route(
+    service: Arc<Service>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let max_body_bytes = service.request_limits.max_body_bytes;
+    // POST /fund {"address": "0x...", "amount": 100}
+    warp::path!("fund")
+        .and(warp::post())
+        .and(client_ip(service.clone()))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::any().map(move || service.clone()))
+        .and(partner::signed_request_and_body(max_body_bytes))
+        .and(mint_queue::deadline())
+        .and(request_context())
+        .and_then(
+            |client_ip, authorization, service, signed, body, deadline, context| {
+                with_request_id(
+                    context,
+                    handle(service, client_ip, authorization, signed, body, deadline),
+                )
+            },
+        )
+}
+
+/// Body of `POST /fund`, one of the receiver fields is required
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct FundRequest {
+    /// In base units, defaults to the service's default amount
+    pub amount: Option<u64>,
+    pub auth_key: Option<String>,
+    pub address: Option<String>,
+    /// Hex encoded ed25519 public key
+    pub pub_key: Option<String>,
+}
+
+/// Returned by a successful `POST /fund`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FundResponse {
+    /// `0x` prefixed hashes of the transactions creating and funding the account
+    pub txn_hashes: Vec<String>,
+}
+
+/// Body of every refused `POST /fund`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FundError {
+    pub message: String,
+    /// `InvalidRequest`, `Rejected` or `AptosApiError`
+    pub error_code: String,
+    #[serde(default)]
+    pub rejection_reasons: Vec<RejectionReason>,
+    /// The malformed or conflicting fields of an `InvalidRequest`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<InvalidField>,
+    #[serde(default)]
+    pub txn_hashes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RejectionReason {
+    pub reason: String,
+    /// Same as the `reason` label of `aptos_faucet_rejected_requests`
+    pub code: String,
+}
+
+async fn handle(
+    service: Arc<Service>,
+    client_ip: Option<IpAddr>,
+    authorization: Option<String>,
+    signed: Option<partner::SignedRequest>,
+    body: Bytes,
+    deadline: Option<Instant>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let request: FundRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(err) => {
+            return Ok(error_reply(
+                StatusCode::BAD_REQUEST,
+                "InvalidRequest",
+                format!("Invalid request body: {}", err),
+                Vec::new(),
+                Vec::new(),
+            ))
+        }
+    };
+    let params = MintParams {
+        amount: request.amount.map(Amount::Base),
+        auth_key: request.auth_key,
+        address: request.address,
+        pub_key: request.pub_key,
+        deadline,
+        ..MintParams::default()
+    };
+
+    let txns = match mint::mint(&service, params, client_ip, authorization, signed).await {
+        Ok((_, Response::Funded(response))) => {
+            return Ok(Box::new(warp::reply::json(&FundResponse {
+                txn_hashes: response
+                    .txn_hashes
+                    .iter()
+                    .map(|hash| hash.to_hex_literal())
+                    .collect(),
+            })))
+        }
+        Ok((_, Response::SubmittedTxns(txns))) | Ok((_, Response::DryRun(txns))) => txns,
+        Err(err) => return Ok(refused(err)),
+    };
+    Ok(Box::new(warp::reply::json(&FundResponse {
+        txn_hashes: txns
+            .into_iter()
+            .map(|txn| txn.committed_hash().to_hex_literal())
+            .collect(),
+    })))
+}
+
+fn refused(err: FaucetError) -> Box<dyn warp::Reply> {
+    let status = err.status_code();
+    let (error_code, rejection_reasons) = match &err {
+        FaucetError::InvalidParams(_)
+        | FaucetError::InvalidFields(_)
+        | FaucetError::AmountTooSmall { .. }
+        | FaucetError::UnknownChain(_)
+        | FaucetError::AccountExists
+        | FaucetError::UnresolvedName(_) => ("InvalidRequest", Vec::new()),
+        FaucetError::Node(_) | FaucetError::Funding(_) => ("AptosApiError", Vec::new()),
+        _ => (
+            "Rejected",
+            vec![RejectionReason {
+                reason: err.to_string(),
+                code: err.reason().to_string(),
+            }],
+        ),
+    };
+    let errors = match &err {
+        FaucetError::InvalidFields(errors) => errors.clone(),
+        _ => Vec::new(),
+    };
+    let reply = error_reply(
+        status,
+        error_code,
+        err.to_string(),
+        rejection_reasons,
+        errors,
+    );
+    match err.retry_after() {
+        Some(retry_after) => Box::new(warp::reply::with_header(
+            reply,
+            "retry-after",
+            retry_after.as_secs().max(1).to_string(),
+        )),
+        None => reply,
+    }
+}
+
+fn error_reply(
+    status: StatusCode,
+    error_code: &str,
+    message: String,
+    rejection_reasons: Vec<RejectionReason>,
+    errors: Vec<InvalidField>,
+) -> Box<dyn warp::Reply> {
+    Box::new(warp::reply::with_status(
+        warp::reply::json(&FundError {
+            message,
+            error_code: error_code.to_string(),
+            rejection_reasons,
+            errors,
+            txn_hashes: Vec::new(),
+        }),
+        status,
+    ))
+}
@@ -0,0 +1,77 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! How the faucet gets coins to receivers. A [`Funder`] only builds the transaction payloads,
+//! while the mint endpoints take care of sequence numbers, signing and submission, so new
+//! funding strategies, such as a different coin or a custom module, do not touch the HTTP layer.
+
+use aptos_sdk::{
+    transaction_builder::aptos_stdlib,
+    types::{account_address::AccountAddress, transaction::TransactionPayload},
+};
+use serde::{Deserialize, Serialize};
+use std::{str::FromStr, sync::Arc};
+
+/// Builds the transactions sent by the funder account
+pub trait Funder: Send + Sync {
+    /// Payload creating the receiver's account
+    fn create_account(&self, receiver: AccountAddress) -> TransactionPayload {
+        aptos_stdlib::encode_account_create_account(receiver)
+    }
+
+    /// Payload getting `amount` to `receiver`, whose account exists by the time it executes
+    fn fund(&self, receiver: AccountAddress, amount: u64) -> TransactionPayload;
+}
+
+/// Mints new coins, which requires the funder to hold the mint capability
+pub struct MintFunder;
+
+impl Funder for MintFunder {
+    fn fund(&self, receiver: AccountAddress, amount: u64) -> TransactionPayload {
+        aptos_stdlib::encode_test_coin_mint(receiver, amount)
+    }
+}
+
+/// Transfers coins from the funder's own balance, for networks where the faucet has no mint
+/// authority
+pub struct TransferFunder;
+
+impl Funder for TransferFunder {
+    fn fund(&self, receiver: AccountAddress, amount: u64) -> TransactionPayload {
+        aptos_stdlib::encode_test_coin_transfer(receiver, amount)
+    }
+}
+
+/// The built-in funders, as selected in the config file or on the command line
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FundingMode {
+    /// See [`MintFunder`]
+    Mint,
+    /// See [`TransferFunder`]
+    Transfer,
+}
+
+impl FundingMode {
+    pub fn funder(self) -> Arc<dyn Funder> {
+        match self {
+            FundingMode::Mint => Arc::new(MintFunder),
+            FundingMode::Transfer => Arc::new(TransferFunder),
+        }
+    }
+}
+
+impl FromStr for FundingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mint" => Ok(FundingMode::Mint),
+            "transfer" => Ok(FundingMode::Transfer),
+            _ => Err(format!(
+                "unknown funding mode '{}', expected mint or transfer",
+                s
+            )),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Caches the funder account's on-chain sequence number for a short time. Every mint request
+//! needs it, so that at high request rates these lookups, rather than the submissions, would
+//! make up most of the faucet's load on the node. Concurrent requests missing the cache share a
+//! single lookup.
+
+use crate::counters;
+use anyhow::Result;
+use aptos_rest_client::Client;
+use aptos_sdk::types::account_address::AccountAddress;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+struct Entry {
+    address: AccountAddress,
+    fetched_at: Instant,
+    sequence_number: u64,
+}
+
+pub struct FunderCache {
+    ttl: Duration,
+    entry: Mutex<Option<Entry>>,
+}
+
+impl FunderCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entry: Mutex::new(None),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// The on-chain sequence number of `address`, looked up at most `ttl` ago
+    pub async fn sequence_number(&self, client: &Client, address: AccountAddress) -> Result<u64> {
+        let mut entry = self.entry.lock().await;
+        if let Some(cached) = entry.as_ref() {
+            if cached.address == address && cached.fetched_at.elapsed() < self.ttl {
+                counters::FUNDER_CACHE.with_label_values(&["hit"]).inc();
+                return Ok(cached.sequence_number);
+            }
+        }
+
+        counters::FUNDER_CACHE.with_label_values(&["miss"]).inc();
+        let account = counters::node_request("get_account", client.get_account(address)).await?;
+        let sequence_number = account.inner().sequence_number;
+        *entry = Some(Entry {
+            address,
+            fetched_at: Instant::now(),
+            sequence_number,
+        });
+        Ok(sequence_number)
+    }
+
+    /// Forgets the cached sequence number, e.g. once submissions failed because it was stale
+    pub async fn invalidate(&self) {
+        *self.entry.lock().await = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock_chain::MockAccount, test_utils::setup_fullnode};
+
+    #[tokio::test]
+    async fn sequence_numbers_are_cached_until_invalidated() {
+        let funder = AccountAddress::random();
+        let (chain, endpoint) = setup_fullnode(funder);
+        chain.create_account(funder, MockAccount::new(100));
+        let client = Client::new(endpoint.parse().unwrap());
+        let cache = FunderCache::new(Duration::from_secs(3600));
+
+        assert_eq!(cache.sequence_number(&client, funder).await.unwrap(), 0);
+        chain.create_account(
+            funder,
+            MockAccount {
+                sequence_number: 5,
+                ..MockAccount::new(100)
+            },
+        );
+        assert_eq!(cache.sequence_number(&client, funder).await.unwrap(), 0);
+
+        cache.invalidate().await;
+        assert_eq!(cache.sequence_number(&client, funder).await.unwrap(), 5);
+    }
+}
@@ -0,0 +1,329 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fills the funder's sequence number gaps. Transactions are submitted ahead of the ones before
+//! them committing, so when one of them is lost, e.g. dropped by mempool or never reaching it,
+//! every later transaction waits on it in mempool until they all expire. The funder's submitted
+//! transactions are tracked until they commit, and every [`CHECK_INTERVAL`] each sequence number
+//! that expired or was never submitted while later ones are still pending is taken by a filler
+//! transaction, transferring nothing from the funder to itself, so the later ones go through.
+//! Replicas sharing the funder only fill the sequence numbers they leased, see
+//! [`crate::sequence_leases`].
+
+use crate::{counters, Service};
+use anyhow::Result;
+use aptos_infallible::Mutex;
+use aptos_logger::{info, warn};
+use aptos_sdk::{
+    transaction_builder::aptos_stdlib::{self, ScriptFunctionCall},
+    types::{account_address::AccountAddress, transaction::SignedTransaction},
+};
+use std::{
+    collections::BTreeMap,
+    sync::Weak,
+    time::{Duration, Instant},
+};
+
+/// How often gaps are looked for
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+pub(crate) struct TrackedTxn {
+    pub txn: SignedTransaction,
+    /// When it was last submitted
+    pub submitted_at: Instant,
+}
+
+impl TrackedTxn {
+    pub fn expired(&self, now_secs: u64) -> bool {
+        self.txn.expiration_timestamp_secs() <= now_secs
+    }
+
+    /// The account the transaction creates or sends coins to, none for other transactions
+    pub fn receiver(&self) -> Option<AccountAddress> {
+        match ScriptFunctionCall::decode(self.txn.payload())? {
+            ScriptFunctionCall::AccountCreateAccount { auth_key, .. } => Some(auth_key),
+            ScriptFunctionCall::TestCoinMint { dst_addr, .. } => Some(dst_addr),
+            ScriptFunctionCall::CoinTransfer { to, .. } => Some(to),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Tracked {
+    address: Option<AccountAddress>,
+    /// By sequence number
+    txns: BTreeMap<u64, TrackedTxn>,
+}
+
+/// The funder's transactions submitted but not yet seen committed
+#[derive(Default)]
+pub struct Outstanding {
+    tracked: Mutex<Tracked>,
+}
+
+impl Outstanding {
+    /// Tracks `txn` of the funder `address`, just submitted
+    pub fn record(&self, address: AccountAddress, txn: &SignedTransaction) {
+        let mut tracked = self.tracked.lock();
+        // The funder was replaced, its transactions are no concern of the new one
+        if tracked.address != Some(address) {
+            tracked.address = Some(address);
+            tracked.txns.clear();
+        }
+        tracked.txns.insert(
+            txn.sequence_number(),
+            TrackedTxn {
+                txn: txn.clone(),
+                submitted_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Forgets the transactions committed below `onchain_seq`, returning those of `address`
+    /// submitted longer than `threshold` ago
+    pub(crate) fn submitted_before(
+        &self,
+        address: AccountAddress,
+        onchain_seq: u64,
+        threshold: Duration,
+    ) -> Vec<TrackedTxn> {
+        let mut tracked = self.tracked.lock();
+        if tracked.address != Some(address) {
+            return Vec::new();
+        }
+        tracked.txns = tracked.txns.split_off(&onchain_seq);
+        tracked
+            .txns
+            .values()
+            .filter(|tracked| tracked.submitted_at.elapsed() >= threshold)
+            .cloned()
+            .collect()
+    }
+
+    /// Stops tracking the transaction with `sequence_number`
+    pub(crate) fn forget(&self, address: AccountAddress, sequence_number: u64) {
+        let mut tracked = self.tracked.lock();
+        if tracked.address == Some(address) {
+            tracked.txns.remove(&sequence_number);
+        }
+    }
+
+    /// Forgets every transaction, e.g. once the chain they were submitted to was reset
+    pub(crate) fn clear(&self) {
+        self.tracked.lock().txns.clear();
+    }
+
+    /// Whether any transaction of `address` is still tracked
+    pub(crate) fn is_empty(&self, address: AccountAddress) -> bool {
+        let tracked = self.tracked.lock();
+        tracked.address != Some(address) || tracked.txns.is_empty()
+    }
+
+    /// Forgets the committed transactions, returning up to `limit` sequence numbers below
+    /// `local_seq` that later pending transactions wait on, as of the ledger timestamp `now_secs`
+    fn gaps(
+        &self,
+        address: AccountAddress,
+        onchain_seq: u64,
+        local_seq: u64,
+        now_secs: u64,
+        limit: usize,
+    ) -> Vec<u64> {
+        let mut tracked = self.tracked.lock();
+        if tracked.address != Some(address) {
+            return Vec::new();
+        }
+        tracked.txns = tracked.txns.split_off(&onchain_seq);
+
+        let highest_pending = tracked
+            .txns
+            .range(..local_seq)
+            .rev()
+            .find(|(_, txn)| !txn.expired(now_secs))
+            .map(|(sequence_number, _)| *sequence_number);
+        let highest_pending = match highest_pending {
+            Some(sequence_number) => sequence_number,
+            None => return Vec::new(),
+        };
+        (onchain_seq..highest_pending)
+            .filter(|sequence_number| {
+                !tracked
+                    .txns
+                    .get(sequence_number)
+                    .map_or(false, |txn| !txn.expired(now_secs))
+            })
+            .take(limit)
+            .collect()
+    }
+}
+
+/// Fills gaps every [`CHECK_INTERVAL`] while the service leads, until it is dropped
+pub(crate) async fn run(service: Weak<Service>) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let service = match service.upgrade() {
+            Some(service) => service,
+            None => return,
+        };
+        if !service.is_leader() {
+            continue;
+        }
+        if let Err(err) = fill_gaps(&service).await {
+            warn!("[faucet]: unable to fill sequence number gaps: {:#}", err);
+        }
+    }
+}
+
+/// Submits a filler transaction for each gap, returning how many were submitted
+pub async fn fill_gaps(service: &Service) -> Result<usize> {
+    let (address, local_seq) = {
+        let funder = service.faucet_account.lock().await;
+        (funder.address(), funder.sequence_number())
+    };
+    let account =
+        counters::node_request("get_account", service.client.get_account(address)).await?;
+    let now_secs = account.state().timestamp_usecs / 1_000_000;
+    let mut gaps = service.outstanding.gaps(
+        address,
+        account.inner().sequence_number,
+        local_seq,
+        now_secs,
+        // As many as the transactions the funder may have in flight
+        service.max_in_flight_transactions as usize,
+    );
+    // The sequence numbers other replicas leased are theirs to fill
+    if let Some(leases) = &service.sequence_leases {
+        gaps.retain(|sequence_number| leases.owns(*sequence_number));
+    }
+    if gaps.is_empty() {
+        return Ok(0);
+    }
+
+    let fillers = {
+        let funder = service.faucet_account.lock().await;
+        if funder.address() != address {
+            return Ok(0);
+        }
+        gaps.into_iter()
+            .map(|sequence_number| {
+                let raw_txn = service
+                    .transaction_factory
+                    .payload(aptos_stdlib::encode_test_coin_transfer(address, 0))
+                    .sender(address)
+                    .sequence_number(sequence_number)
+                    .build();
+                funder.sign_transaction(raw_txn)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut filled = 0;
+    for txn in &fillers {
+        match counters::node_request("submit", service.client.submit(txn)).await {
+            Ok(_) => {
+                service.outstanding.record(address, txn);
+                filled += 1;
+            }
+            Err(err) => warn!(
+                "[faucet]: unable to fill sequence number {} of {}: {}",
+                txn.sequence_number(),
+                address,
+                err
+            ),
+        }
+    }
+    counters::SEQUENCE_NUMBER_GAPS_FILLED.inc_by(filled as u64);
+    info!(
+        "[faucet]: filled {} sequence number gaps of {}",
+        filled, address
+    );
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mint::{self, MintParams},
+        redis::fake_server,
+        sequence_leases::SequenceLeaseConfig,
+        test_utils::{setup_service, setup_service_with},
+    };
+    use aptos_sdk::{
+        transaction_builder::TransactionFactory,
+        types::{chain_id::ChainId, LocalAccount},
+    };
+
+    fn txn(funder: &LocalAccount, sequence_number: u64, expiration_secs: u64) -> SignedTransaction {
+        let raw_txn = TransactionFactory::new(ChainId::test())
+            .payload(aptos_stdlib::encode_test_coin_transfer(funder.address(), 0))
+            .sender(funder.address())
+            .sequence_number(sequence_number)
+            .expiration_timestamp_secs(expiration_secs)
+            .build();
+        funder.sign_transaction(raw_txn)
+    }
+
+    #[test]
+    fn gaps_are_found_below_pending_transactions() {
+        let outstanding = Outstanding::default();
+        let funder = LocalAccount::generate(&mut rand::rngs::OsRng);
+        let address = funder.address();
+        // 11 expired and 12 was never submitted, while 13 and 14 are still pending
+        outstanding.record(address, &txn(&funder, 10, 100));
+        outstanding.record(address, &txn(&funder, 11, 50));
+        outstanding.record(address, &txn(&funder, 13, 100));
+        outstanding.record(address, &txn(&funder, 14, 100));
+
+        assert_eq!(outstanding.gaps(address, 10, 15, 60, 50), vec![11, 12]);
+        // Nothing is pending once everything expired
+        assert!(outstanding.gaps(address, 10, 15, 100, 50).is_empty());
+        assert!(outstanding
+            .gaps(AccountAddress::random(), 10, 15, 60, 50)
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn lost_transactions_are_filled() {
+        let (chain, service) = setup_service(None);
+        let receiver = AccountAddress::random();
+        // Sequence number 0 is used by a transaction that never reaches the node
+        *service.faucet_account.lock().await.sequence_number_mut() += 1;
+
+        let params = MintParams {
+            address: Some(receiver.to_hex_literal()),
+            ..MintParams::default()
+        };
+        mint::fund(&service, params, 100).await.unwrap();
+        assert!(chain.account(receiver).is_none());
+
+        assert_eq!(fill_gaps(&service).await.unwrap(), 1);
+        assert_eq!(chain.account(receiver).unwrap().balance, 100);
+        assert_eq!(fill_gaps(&service).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn gaps_leased_by_other_replicas_are_left_alone() {
+        // Another replica leased sequence number 0
+        let redis_address = fake_server(vec![1]).await;
+        let (chain, service) = setup_service_with(|builder| {
+            builder.sequence_leases(SequenceLeaseConfig {
+                password: Some("secret".to_string()),
+                ..SequenceLeaseConfig::new(redis_address)
+            })
+        });
+        let receiver = AccountAddress::random();
+
+        let params = MintParams {
+            address: Some(receiver.to_hex_literal()),
+            ..MintParams::default()
+        };
+        mint::fund(&service, params, 100).await.unwrap();
+        assert_eq!(service.faucet_account.lock().await.sequence_number(), 3);
+        assert_eq!(fill_gaps(&service).await.unwrap(), 0);
+        assert!(chain.account(receiver).is_none());
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Limits how many mint requests a client may make per time window. Requests are counted per
+//! address, and also per IPv4 /24 and IPv6 /64 prefix, since abusers rotate addresses within a
+//! subnet to get around per-address caps.
+
+use aptos_infallible::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::{Duration, Instant},
+};
+
+/// Request thresholds per window, a missing threshold is unlimited
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IpRateLimitConfig {
+    /// Requests allowed from a single address
+    pub per_ip: Option<u64>,
+    /// Requests allowed from all addresses sharing an IPv4 /24 prefix
+    pub per_ipv4_24: Option<u64>,
+    /// Requests allowed from all addresses sharing an IPv6 /64 prefix
+    pub per_ipv6_64: Option<u64>,
+    /// Length of the window the thresholds apply to
+    pub window_secs: u64,
+}
+
+impl Default for IpRateLimitConfig {
+    fn default() -> Self {
+        Self {
+            per_ip: None,
+            per_ipv4_24: None,
+            per_ipv6_64: None,
+            window_secs: 86400,
+        }
+    }
+}
+
+impl IpRateLimitConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.per_ip.is_some() || self.per_ipv4_24.is_some() || self.per_ipv6_64.is_some()
+    }
+}
+
+/// Returned when a client has used up its requests for the current window
+#[derive(Debug)]
+pub struct RateLimited {
+    /// The address or subnet that hit its threshold
+    pub key: IpAddr,
+    pub prefix_len: u8,
+    /// Time until the current window ends
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Too many requests from {}/{}, try again in {} seconds",
+            self.key,
+            self.prefix_len,
+            self.retry_after.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// What is left of one of the thresholds a client falls under
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct IpQuota {
+    /// The address or subnet the threshold applies to, e.g. `10.0.0.0/24`
+    pub subnet: String,
+    pub limit: u64,
+    pub remaining: u64,
+    /// Seconds until the window ends and the counts reset
+    pub resets_in_secs: u64,
+}
+
+struct Counters {
+    start: Instant,
+    counts: HashMap<(IpAddr, u8), u64>,
+}
+
+pub struct IpRateLimiter {
+    config: IpRateLimitConfig,
+    counters: Mutex<Counters>,
+}
+
+impl IpRateLimiter {
+    pub fn new(config: IpRateLimitConfig) -> Self {
+        Self {
+            config,
+            counters: Mutex::new(Counters {
+                start: Instant::now(),
+                counts: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Counts a request from `ip`, failing without counting it if any threshold it falls under
+    /// has already been reached.
+    pub fn check(&self, ip: IpAddr) -> Result<(), RateLimited> {
+        let window = Duration::from_secs(self.config.window_secs);
+        let mut counters = self.counters.lock();
+        if counters.start.elapsed() >= window {
+            counters.start = Instant::now();
+            counters.counts.clear();
+        }
+
+        let ip = canonical(ip);
+        let keys = self.keys(ip);
+        for (key, limit) in &keys {
+            if counters.counts.get(key).copied().unwrap_or(0) >= *limit {
+                return Err(RateLimited {
+                    key: key.0,
+                    prefix_len: key.1,
+                    retry_after: window.saturating_sub(counters.start.elapsed()),
+                });
+            }
+        }
+        for (key, _) in keys {
+            *counters.counts.entry(key).or_insert(0) += 1;
+        }
+        Ok(())
+    }
+
+    /// What is left of every threshold `ip` falls under, without counting a request
+    pub fn quota(&self, ip: IpAddr) -> Vec<IpQuota> {
+        let window = Duration::from_secs(self.config.window_secs);
+        let counters = self.counters.lock();
+        let elapsed = counters.start.elapsed();
+        // An expired window is only reset by the next request
+        let expired = elapsed >= window;
+        let resets_in = if expired { window } else { window - elapsed };
+
+        self.keys(canonical(ip))
+            .into_iter()
+            .map(|(key, limit)| {
+                let used = match counters.counts.get(&key) {
+                    Some(count) if !expired => *count,
+                    _ => 0,
+                };
+                IpQuota {
+                    subnet: format!("{}/{}", key.0, key.1),
+                    limit,
+                    remaining: limit.saturating_sub(used),
+                    resets_in_secs: resets_in.as_secs(),
+                }
+            })
+            .collect()
+    }
+
+    /// The counters `ip` is tracked under, along with their thresholds
+    fn keys(&self, ip: IpAddr) -> Vec<((IpAddr, u8), u64)> {
+        let mut keys = Vec::new();
+        if let Some(limit) = self.config.per_ip {
+            let prefix_len = if ip.is_ipv4() { 32 } else { 128 };
+            keys.push(((ip, prefix_len), limit));
+        }
+        match ip {
+            IpAddr::V4(ip) => {
+                if let Some(limit) = self.config.per_ipv4_24 {
+                    let subnet = Ipv4Addr::from(u32::from(ip) & 0xffff_ff00);
+                    keys.push(((subnet.into(), 24), limit));
+                }
+            }
+            IpAddr::V6(ip) => {
+                if let Some(limit) = self.config.per_ipv6_64 {
+                    let subnet = Ipv6Addr::from(u128::from(ip) & !(u64::MAX as u128));
+                    keys.push(((subnet.into(), 64), limit));
+                }
+            }
+        }
+        keys
+    }
+}
+
+/// IPv4 clients reaching a dual stack listener show up as IPv4-mapped IPv6 addresses, count them
+/// as the IPv4 addresses they are
+pub(crate) fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.to_ipv4() {
+            Some(v4) if v6.segments()[..6] == [0, 0, 0, 0, 0, 0xffff] => IpAddr::V4(v4),
+            _ => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subnets_are_limited_separately_from_addresses() {
+        let limiter = IpRateLimiter::new(IpRateLimitConfig {
+            per_ip: Some(2),
+            per_ipv4_24: Some(3),
+            per_ipv6_64: Some(1),
+            ..IpRateLimitConfig::default()
+        });
+
+        limiter.check("10.0.0.1".parse().unwrap()).unwrap();
+        limiter.check("10.0.0.1".parse().unwrap()).unwrap();
+        let err = limiter.check("10.0.0.1".parse().unwrap()).unwrap_err();
+        assert_eq!(err.prefix_len, 32);
+
+        limiter.check("10.0.0.2".parse().unwrap()).unwrap();
+        let err = limiter.check("10.0.0.3".parse().unwrap()).unwrap_err();
+        assert_eq!(err.key, "10.0.0.0".parse::<IpAddr>().unwrap());
+        assert_eq!(err.prefix_len, 24);
+        limiter.check("10.0.1.1".parse().unwrap()).unwrap();
+
+        limiter.check("2001:db8::1".parse().unwrap()).unwrap();
+        let err = limiter.check("2001:db8::2".parse().unwrap()).unwrap_err();
+        assert_eq!(err.prefix_len, 64);
+        limiter.check("2001:db8:0:1::1".parse().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn mapped_ipv4_counts_as_ipv4() {
+        let limiter = IpRateLimiter::new(IpRateLimitConfig {
+            per_ipv4_24: Some(1),
+            ..IpRateLimitConfig::default()
+        });
+
+        limiter.check("10.0.0.1".parse().unwrap()).unwrap();
+        limiter
+            .check("::ffff:10.0.0.2".parse().unwrap())
+            .unwrap_err();
+    }
+}
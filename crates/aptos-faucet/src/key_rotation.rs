@@ -0,0 +1,44 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Rotates the delegated funder's authentication key on a schedule, so that a key leaked from a
+//! long-lived devnet faucet stops being of use within the rotation period. The funder keeps its
+//! address, so clients and monitoring are unaffected. Key ages are saved in the delegate state
+//! file, so restarts do not postpone rotations.
+
+use crate::{counters, Service};
+use aptos_logger::error;
+use std::{sync::Weak, time::Duration};
+
+/// How often the key's age is checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Rotates the funder's key whenever it is `period` old while the service leads, until it is
+/// dropped
+pub(crate) async fn run(service: Weak<Service>, period: Duration) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL.min(period));
+    loop {
+        interval.tick().await;
+        let service = match service.upgrade() {
+            Some(service) => service,
+            None => return,
+        };
+        if !service.is_leader() {
+            continue;
+        }
+        if service.funder_key_age().map_or(true, |age| age < period) {
+            continue;
+        }
+        match service.rotate_funder_key().await {
+            Ok(()) => counters::FUNDER_KEY_ROTATIONS
+                .with_label_values(&["rotated"])
+                .inc(),
+            Err(err) => {
+                counters::FUNDER_KEY_ROTATIONS
+                    .with_label_values(&["failed"])
+                    .inc();
+                error!("[faucet]: unable to rotate the funder's key: {:#}", err);
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Active/passive replicas. Replicas compete for a lease kept in Redis, and only the one holding
+//! it submits transactions, so that replicas never sign with the same sequence numbers. The
+//! others are standbys: they serve the read-only endpoints and refuse funding requests with a
+//! `503`. The leader renews its lease every `renew_interval_ms`, and steps down once it could not
+//! renew it for `lease_ms - renew_interval_ms`, which leaves its last transactions time to be
+//! submitted before a standby can take over.
+//!
+//! Taking and renewing the lease is a single script run by Redis, see [`crate::redis`].
+
+use crate::{counters, redis};
+use anyhow::{bail, format_err, Result};
+use aptos_infallible::Mutex;
+use aptos_logger::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    sync::Weak,
+    time::{Duration, Instant},
+};
+
+/// Takes the lease if nobody holds it, or renews it if we do. Returns 1 if we hold it afterwards
+const ACQUIRE_SCRIPT: &str = "local holder = redis.call('get', KEYS[1]) \
+     if holder == false then return redis.call('set', KEYS[1], ARGV[1], 'PX', ARGV[2]) and 1 \
+     elseif holder == ARGV[1] then return redis.call('pexpire', KEYS[1], ARGV[2]) \
+     else return 0 end";
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LeaderElectionConfig {
+    /// `host:port` of the Redis server holding the lease
+    pub redis_address: String,
+    /// Key of the lease, the same for every replica of a faucet
+    #[serde(default = "default_key")]
+    pub key: String,
+    /// Password of the Redis server, if it requires one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// File holding `password`, see [`crate::secrets`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_file: Option<PathBuf>,
+    /// How long the lease lasts without being renewed
+    #[serde(default = "default_lease_ms")]
+    pub lease_ms: u64,
+    /// How often the lease is taken or renewed
+    #[serde(default = "default_renew_interval_ms")]
+    pub renew_interval_ms: u64,
+}
+
+fn default_key() -> String {
+    "aptos-faucet:leader".to_string()
+}
+
+fn default_lease_ms() -> u64 {
+    10_000
+}
+
+fn default_renew_interval_ms() -> u64 {
+    3_000
+}
+
+impl LeaderElectionConfig {
+    pub fn new(redis_address: String) -> Self {
+        Self {
+            redis_address,
+            key: default_key(),
+            password: None,
+            password_file: None,
+            lease_ms: default_lease_ms(),
+            renew_interval_ms: default_renew_interval_ms(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.renew_interval_ms == 0 {
+            bail!("leader_election.renew_interval_ms must be at least 1");
+        }
+        if self.lease_ms < 2 * self.renew_interval_ms {
+            bail!("leader_election.lease_ms must be at least twice renew_interval_ms");
+        }
+        Ok(())
+    }
+}
+
+pub struct LeaderElection {
+    config: LeaderElectionConfig,
+    /// Identifies this replica in the lease
+    id: String,
+    /// Until when this replica may act as the leader, `None` for standbys
+    leading_until: Mutex<Option<Instant>>,
+}
+
+impl LeaderElection {
+    pub fn new(config: LeaderElectionConfig) -> Self {
+        Self {
+            config,
+            id: uuid::Uuid::new_v4().to_string(),
+            leading_until: Mutex::new(None),
+        }
+    }
+
+    /// Whether this replica holds the lease, and may submit transactions
+    pub fn is_leader(&self) -> bool {
+        self.leading_until
+            .lock()
+            .map_or(false, |until| Instant::now() < until)
+    }
+
+    /// Runs one round of the election, updating whether this replica leads
+    async fn elect(&self) {
+        let was_leader = self.is_leader();
+        let started = Instant::now();
+        let timeout = Duration::from_millis(self.config.renew_interval_ms);
+        let result = tokio::time::timeout(timeout, self.acquire())
+            .await
+            .unwrap_or_else(|_| Err(format_err!("timed out after {:?}", timeout)));
+        match result {
+            // Redis counts the lease from when it ran the script, after `started`
+            Ok(true) => {
+                let lease = self.config.lease_ms - self.config.renew_interval_ms;
+                *self.leading_until.lock() = Some(started + Duration::from_millis(lease));
+            }
+            Ok(false) => *self.leading_until.lock() = None,
+            // Leads on until the lease may have expired
+            Err(err) => warn!("[faucet]: unable to renew the lease: {:#}", err),
+        }
+
+        let is_leader = self.is_leader();
+        counters::LEADER.set(is_leader as i64);
+        match (was_leader, is_leader) {
+            (false, true) => info!("[faucet]: took the lease, submitting transactions"),
+            (true, false) => warn!("[faucet]: lost the lease, standing by"),
+            _ => (),
+        }
+    }
+
+    /// Takes or renews the lease, returning whether this replica holds it
+    async fn acquire(&self) -> Result<bool> {
+        let lease_ms = self.config.lease_ms.to_string();
+        let held = redis::eval(
+            &self.config.redis_address,
+            self.config.password.as_deref(),
+            ACQUIRE_SCRIPT,
+            &[self.config.key.as_str()],
+            &[self.id.as_str(), lease_ms.as_str()],
+        )
+        .await?;
+        Ok(held == 1)
+    }
+}
+
+/// Takes or renews the lease every `renew_interval_ms`, until the election is dropped
+pub(crate) async fn run(election: Weak<LeaderElection>) {
+    let period = match election.upgrade() {
+        Some(election) => {
+            info!(
+                "[faucet]: competing for the lease {} as {}",
+                election.config.key, election.id
+            );
+            Duration::from_millis(election.config.renew_interval_ms)
+        }
+        None => return,
+    };
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        match election.upgrade() {
+            Some(election) => election.elect().await,
+            None => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::fake_server;
+
+    #[tokio::test]
+    async fn only_the_lease_holder_leads() {
+        let election = LeaderElection::new(LeaderElectionConfig {
+            password: Some("secret".to_string()),
+            ..LeaderElectionConfig::new(fake_server(vec![1, 1, 0]).await)
+        });
+        assert!(!election.is_leader());
+
+        election.elect().await;
+        assert!(election.is_leader());
+        election.elect().await;
+        assert!(election.is_leader());
+        // Another replica took over
+        election.elect().await;
+        assert!(!election.is_leader());
+    }
+
+    #[tokio::test]
+    async fn leaders_step_down_once_the_lease_may_have_expired() {
+        let election = LeaderElection::new(LeaderElectionConfig {
+            password: Some("secret".to_string()),
+            lease_ms: 400,
+            renew_interval_ms: 100,
+            ..LeaderElectionConfig::new(fake_server(vec![1]).await)
+        });
+        election.elect().await;
+        assert!(election.is_leader());
+
+        // Redis is gone, the lease is kept until it may have expired
+        election.elect().await;
+        assert!(election.is_leader());
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        assert!(!election.is_leader());
+    }
+}
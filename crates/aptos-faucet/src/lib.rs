@@ -10,7 +10,15 @@
 //! Launch faucet service locally and connect to Testnet:
 //!
 //! ```bash
-//! cargo run --bin aptos-faucet -- -c TESTNET -m <mint-private-key-path> -s http://localhost:8080 -p 8081
+//! cargo run --bin aptos-faucet -- run -c TESTNET -m <mint-private-key-path> -s http://localhost:8080 -p 8081
+//! ```
+//!
+//! Or generate a commented config file, edit it, then validate and run it:
+//!
+//! ```bash
+//! cargo run --bin aptos-faucet -- generate-config --output faucet.yaml
+//! cargo run --bin aptos-faucet -- validate-config --config faucet.yaml
+//! cargo run --bin aptos-faucet -- run --config faucet.yaml
 //! ```
 //!
 //! Check help doc for options details:
@@ -18,51 +26,1035 @@
 //! ```bash
 //! cargo run -p aptos-faucet -- -h
 //! ```
+//!
+//! ## Embed service
+//!
+//! Tests and local tooling can run a faucet in-process with [`run`], which returns the bound
+//! address and a [`ShutdownHandle`]. [`test_utils`] provides a mock fullnode with injectable
+//! failures for testing faucet integrations without a node.
 
 use anyhow::Result;
-use aptos_logger::info;
+use aptos_config::keys::ConfigKey;
+use aptos_crypto::{ed25519::Ed25519PrivateKey, Uniform};
+use aptos_logger::{info, warn};
 use aptos_rest_client::Client;
 use aptos_sdk::{
     transaction_builder::{aptos_stdlib, TransactionFactory},
-    types::{chain_id::ChainId, LocalAccount},
+    types::{
+        account_address::AccountAddress, chain_id::ChainId,
+        transaction::authenticator::AuthenticationKey, LocalAccount,
+    },
 };
+use audit::{AuditEntry, AuditLog};
+use bans::{BanConfig, Bans};
+use budget::SpendBudget;
+use build_info::BuildInfo;
+use chain_reset::ChainWatch;
+use challenge::ChallengeStore;
+use chaos::ChaosConfig;
+use checkers::{BalanceChecker, BalanceConfig, Checker, SybilConfig, SybilDetector};
+use chrono::{DateTime, Utc};
+use connection_limits::PeerAddr;
+use cooldown::{Cooldown, CooldownConfig};
+use coupon::CouponStore;
+use daily_limit::{DailyLimit, RedisStore};
+use delegate_state::DelegateState;
+use events::EventPublisher;
+use funder::{Funder, FundingMode};
+use funder_cache::FunderCache;
 use futures::lock::Mutex;
+use gaps::Outstanding;
+use ip_limit::{IpRateLimitConfig, IpRateLimiter};
+use leader::{LeaderElection, LeaderElectionConfig};
+use log_sampling::LogSampler;
+use mint_queue::MintQueue;
+use partner::{PartnerAuth, PartnerConfig};
+use request_limits::RequestLimitsConfig;
+use request_log::{request_context, with_request_id, REQUEST_ID_HEADER, TRACEPARENT_HEADER};
 use reqwest::StatusCode;
-use std::{convert::Infallible, fmt, sync::Arc};
+use schedule::StandingOrder;
+use security_headers::SecurityHeadersConfig;
+use self_test::{SelfTest, SelfTestStatus};
+use sequence_leases::{SequenceLeaseConfig, SequenceLeases};
+use serde::{Deserialize, Serialize};
+use sharding::ShardingConfig;
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+use tokio::sync::OnceCell;
 use url::Url;
-use warp::{http, Filter, Rejection, Reply};
+use warp::{
+    http::{self, HeaderMap},
+    Filter, Rejection, Reply,
+};
 
+pub mod accounts;
+pub mod admin;
+pub mod amount;
+pub mod ans;
+pub mod audit;
+pub mod bans;
+pub mod bench;
+pub mod budget;
+pub mod build_info;
+pub mod bulk;
+pub mod chain_reset;
+pub mod challenge;
+pub mod chaos;
+pub mod checkers;
+pub mod config;
+pub mod connection_limits;
+pub mod cooldown;
+pub mod counters;
+pub mod coupon;
+pub mod daily_limit;
+pub mod delegate_state;
+pub mod error;
+pub mod events;
+pub mod fund;
+pub mod funder;
+pub mod funder_cache;
+pub mod gaps;
+pub mod ip_limit;
+pub mod key_rotation;
+pub mod leader;
+pub mod log_sampling;
 pub mod mint;
+pub mod mint_queue;
+pub mod mock_chain;
+pub mod otlp;
+pub mod partner;
+pub mod preflight;
+#[cfg(feature = "cpu-profiling")]
+pub mod profiling;
+pub mod quota;
+pub mod receiver;
+pub mod redis;
+pub mod rejection;
+pub mod request_limits;
+pub mod request_log;
+pub mod schedule;
+pub mod secrets;
+pub mod security_headers;
+pub mod self_test;
+pub mod sequence_leases;
+pub mod server;
+pub mod sharding;
+pub mod stuck;
+pub mod test_utils;
+pub mod tls;
+
+pub use config::FaucetConfig;
+pub use error::FaucetError;
+
+/// How often the funder account's balance and sequence number are exported
+const FUNDER_POLL_INTERVAL: Duration = Duration::from_secs(30);
+pub use server::{run, run_with_logger, ShutdownHandle};
 
 pub struct Service {
     pub faucet_account: Mutex<LocalAccount>,
+    chain_id: ChainId,
     transaction_factory: TransactionFactory,
     client: Client,
     endpoint: String,
     maximum_amount: Option<u64>,
+    default_amount: Option<u64>,
+    minimum_amount: u64,
+    dry_run: bool,
+    /// Builds the transactions getting coins to receivers
+    funding: Arc<dyn Funder>,
+    /// Only set for delegated funders, see [`delegate_mint_account`]
+    delegation: Option<Arc<Delegation>>,
+    spend_budget: Option<Arc<SpendBudget>>,
+    daily_limit: Option<Arc<DailyLimit>>,
+    /// Only set when the funder's sequence number may be cached, see [`funder_cache`]
+    funder_cache: Option<Arc<FunderCache>>,
+    ip_limiter: Option<Arc<IpRateLimiter>>,
+    cooldown: Option<Arc<Cooldown>>,
+    trust_x_forwarded_for: bool,
+    checkers: Arc<Vec<Box<dyn Checker>>>,
+    challenges: Option<Arc<ChallengeStore>>,
+    sybil: Option<Arc<SybilDetector>>,
+    /// Clients temporarily banned for breaking the rules, see [`bans`]
+    bans: Option<Arc<Bans>>,
+    /// Only set for replicas electing the one submitting transactions, see [`leader`]
+    leader: Option<Arc<LeaderElection>>,
+    /// Only set when receivers are split between replicas, see [`sharding`]
+    sharding: Option<ShardingConfig>,
+    /// Only set when replicas share the funder, see [`sequence_leases`]
+    sequence_leases: Option<Arc<SequenceLeases>>,
+    admin_token: Option<String>,
+    partners: Option<Arc<PartnerAuth>>,
+    mint_queue: Option<Arc<MintQueue>>,
+    audit_log: Option<Arc<AuditLog>>,
+    events: Arc<Vec<EventPublisher>>,
+    self_test: Option<Arc<SelfTest>>,
+    coupons: Arc<CouponStore>,
+    /// Added to every response
+    security_headers: HeaderMap,
+    /// Size limits of every request, see [`request_limits`]
+    request_limits: RequestLimitsConfig,
+    /// Decides which info lines of busy modules are logged, see [`log_sampling`]
+    log_sampler: Arc<LogSampler>,
+    /// Faults injected into API requests, see [`chaos`]
+    chaos: Option<ChaosConfig>,
+    standing_orders: Arc<Vec<StandingOrder>>,
+    /// The funder's transactions that may leave sequence number gaps, see [`gaps`]
+    outstanding: Arc<Outstanding>,
+    /// What the chain looked like, to tell when it was reset, see [`chain_reset`]
+    chain_watch: ChainWatch,
+    /// Age at which uncommitted transactions are looked after, see [`stuck`]
+    stuck_threshold: Option<Duration>,
+    /// Transactions the funder may have submitted ahead of the on-chain sequence number, mint
+    /// requests wait for earlier ones to commit beyond that
+    max_in_flight_transactions: u64,
+    /// Versions the ledger must be past a transaction for requests waiting for finality
+    finality_version_lag: u64,
+    /// Only set when the delegated funder's key is rotated, see [`key_rotation`]
+    key_rotation: Option<Duration>,
+    /// Only set when receivers may be named by their Aptos name, see [`ans`]
+    ans_contract: Option<AccountAddress>,
+    /// The process' logger, whose filter `PUT /admin/log_level` changes
+    logger: Option<Arc<aptos_logger::Logger>>,
+    /// Path segments every route is mounted under
+    path_prefix: Vec<String>,
+    /// Decimals of the minted coin, fetched on first use
+    coin_decimals: Arc<OnceCell<u8>>,
+    started_at: Instant,
 }
 
 impl Service {
-    pub fn new(
-        endpoint: String,
-        chain_id: ChainId,
-        faucet_account: LocalAccount,
-        maximum_amount: Option<u64>,
-    ) -> Self {
-        let client = Client::new(Url::parse(&endpoint).expect("Invalid rest endpoint"));
+    pub fn endpoint(&self) -> &String {
+        &self.endpoint
+    }
+
+    /// Whether this replica may submit transactions, always without leader election
+    pub fn is_leader(&self) -> bool {
+        self.leader
+            .as_ref()
+            .map_or(true, |leader| leader.is_leader())
+    }
+
+    /// What clients may rely on this faucet for, reported by `GET /health` so that they can
+    /// adapt to older deployments
+    pub fn capabilities(&self) -> Vec<String> {
+        let mut capabilities = vec!["json_responses", "wait", "accounts", "fund"];
+        if self.admin_token.is_some() {
+            capabilities.push("bulk_fund");
+        }
+        if self.challenges.is_some() {
+            capabilities.push("challenge");
+        }
+        capabilities.into_iter().map(str::to_string).collect()
+    }
+
+    /// Starts the analyses and polling that run alongside request handling. Must be called
+    /// from within a tokio runtime
+    pub fn start_background_tasks(self: &Arc<Self>) {
+        if let Some(leader) = &self.leader {
+            tokio::spawn(leader::run(Arc::downgrade(leader)));
+        }
+        if let Some(sybil) = &self.sybil {
+            sybil.clone().start(self.client.clone());
+        }
+        if let Some(self_test) = &self.self_test {
+            let (self_test, service) = (self_test.clone(), self.clone());
+            tokio::spawn(async move { self_test.run(&service).await });
+        }
+        // Receivers of other shards are funded by their own replicas
+        let orders = self.standing_orders.iter().filter(|order| {
+            self.sharding
+                .as_ref()
+                .map_or(true, |sharding| sharding.owns(order.address))
+        });
+        for order in orders {
+            tokio::spawn(schedule::run(order.clone(), Arc::downgrade(self)));
+        }
+        tokio::spawn(poll_funder(Arc::downgrade(self)));
+        tokio::spawn(gaps::run(Arc::downgrade(self)));
+        tokio::spawn(chain_reset::run(Arc::downgrade(self)));
+        if let Some(threshold) = self.stuck_threshold {
+            tokio::spawn(stuck::run(Arc::downgrade(self), threshold));
+        }
+        if let (Some(period), Some(_)) = (self.key_rotation, &self.delegation) {
+            tokio::spawn(key_rotation::run(Arc::downgrade(self), period));
+        }
+    }
+
+    /// Mints the funder back up to its endowment once its balance is below the endowment's
+    /// threshold, returning the amount minted
+    pub async fn top_up_funder(&self) -> Result<u64> {
+        let (endowment, below) = match self.delegation.as_ref().map(|d| d.endowment) {
+            Some(Endowment {
+                amount,
+                top_up_below: Some(below),
+            }) => (amount, below),
+            _ => return Ok(0),
+        };
+        let address = self.faucet_account.lock().await.address();
+        let balance = self
+            .client
+            .get_account_balance(address)
+            .await?
+            .into_inner()
+            .get();
+        if balance >= below || balance >= endowment {
+            return Ok(0);
+        }
+
+        let amount = endowment - balance;
+        let params = mint::MintParams {
+            address: Some(address.to_hex_literal()),
+            wait: Some(true),
+            ..mint::MintParams::default()
+        };
+        match mint::fund(self, params, amount).await? {
+            mint::Response::Funded(response) if response.txns.iter().all(|txn| txn.success) => {
+                info!(
+                    "[faucet]: topped up {} from {} to {}",
+                    address, balance, endowment
+                );
+                Ok(amount)
+            }
+            _ => Err(anyhow::format_err!("the top up of {} failed", address)),
+        }
+    }
+
+    /// Delegates to a fresh account and switches to funding from it, returning its address.
+    /// Transactions already signed by the previous account are still submitted
+    pub async fn redelegate(&self) -> Result<AccountAddress> {
+        let delegation = self.delegation.as_ref().ok_or_else(|| {
+            anyhow::format_err!("The faucet does not mint from a delegated account")
+        })?;
+        let _redelegating = delegation.redelegating.lock().await;
+
+        let delegated_account = delegation.delegate().await?;
+        let address = delegated_account.address();
+        let previous = std::mem::replace(&mut *self.faucet_account.lock().await, delegated_account);
+        warn!(
+            "[faucet]: redelegated from {} to {}",
+            previous.address(),
+            address
+        );
+        Ok(address)
+    }
+
+    /// Rotates the delegated funder's authentication key to a fresh key, saved to the delegate
+    /// state file. The funder keeps its address, balance and mint capability
+    pub async fn rotate_funder_key(&self) -> Result<()> {
+        let delegation = self.delegation.as_ref().ok_or_else(|| {
+            anyhow::format_err!("The faucet does not mint from a delegated account")
+        })?;
+        let _redelegating = delegation.redelegating.lock().await;
+
+        // Held throughout, as anything signed with the previous key after the rotation would be
+        // rejected
+        let mut funder = self.faucet_account.lock().await;
+        let address = funder.address();
+        let key = ConfigKey::new(Ed25519PrivateKey::generate(&mut rand::rngs::OsRng));
+        let auth_key = AuthenticationKey::ed25519(&key.public_key());
+        // The new key is saved before the chain can switch to it, so that a crash cannot lose it
+        let state = DelegateState::rotating(&funder, *delegation.key_created_at.lock(), key)?;
+        if let Some(path) = &delegation.state_file {
+            state.save(path)?;
+        }
+
+        let txn = funder.sign_with_transaction_builder(self.transaction_factory.payload(
+            aptos_stdlib::encode_account_rotate_authentication_key(auth_key.to_vec()),
+        ));
+        let outcome = match counters::node_request("submit", self.client.submit(&txn)).await {
+            Ok(_) => mint::committed(self, &txn).await,
+            Err(err) => {
+                *funder.sequence_number_mut() -= 1;
+                Err(err.into())
+            }
+        };
+        let state = match outcome {
+            Ok((committed, _)) if committed.success => {
+                state.settle(&auth_key).map(|state| (state, committed.hash))
+            }
+            Ok((committed, _)) => {
+                // The previous key is kept, the rotation having failed for sure
+                if let (Some(path), Some(state)) = (
+                    &delegation.state_file,
+                    state.settle(&funder.authentication_key()),
+                ) {
+                    state.save(path)?;
+                }
+                anyhow::bail!(
+                    "Rotating the key of {} failed: {}",
+                    address,
+                    committed.vm_status
+                );
+            }
+            // The rotation may have gone through all the same, which the account tells. The new
+            // key is left pending in the state file otherwise, for the next start to settle
+            Err(err) => {
+                let account =
+                    counters::node_request("get_account", self.client.get_account(address));
+                match account.await {
+                    Ok(account) if account.inner().authentication_key == auth_key => state
+                        .settle(&auth_key)
+                        .map(|state| (state, txn.clone().committed_hash())),
+                    _ => return Err(err),
+                }
+            }
+        };
+        let (state, hash) = state.ok_or_else(|| {
+            anyhow::format_err!("The rotated key of {} could not be settled", address)
+        })?;
+
+        if let Some(path) = &delegation.state_file {
+            state.save(path).map_err(|err| {
+                anyhow::format_err!(
+                    "The rotated key of {} could not be saved, it is settled on restart: {}",
+                    address,
+                    err
+                )
+            })?;
+        }
+        *funder = state.account(funder.sequence_number());
+        *delegation.key_created_at.lock() = Utc::now();
+        drop(funder);
+        warn!(
+            "[faucet]: rotated the authentication key of {} to {}",
+            address, auth_key
+        );
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record(&AuditEntry::key_rotation(address, hash));
+        }
+        Ok(())
+    }
+
+    /// How long the delegated funder has been using its key, `None` without delegation
+    pub(crate) fn funder_key_age(&self) -> Option<Duration> {
+        let delegation = self.delegation.as_ref()?;
+        let created_at = *delegation.key_created_at.lock();
+        (Utc::now() - created_at).to_std().ok()
+    }
+
+    /// A service talking to the same node with the same settings, but funding from `funder`
+    fn with_funder(&self, funder: LocalAccount, maximum_amount: Option<u64>) -> Self {
         Service {
-            faucet_account: Mutex::new(faucet_account),
-            transaction_factory: TransactionFactory::new(chain_id)
-                .with_gas_unit_price(1)
-                .with_transaction_expiration_time(30),
-            client,
-            endpoint,
+            faucet_account: Mutex::new(funder),
+            chain_id: self.chain_id,
+            transaction_factory: self.transaction_factory.clone(),
+            client: self.client.clone(),
+            endpoint: self.endpoint.clone(),
             maximum_amount,
+            default_amount: self.default_amount,
+            minimum_amount: self.minimum_amount,
+            dry_run: self.dry_run,
+            funding: self.funding.clone(),
+            delegation: self.delegation.clone(),
+            spend_budget: self.spend_budget.clone(),
+            daily_limit: self.daily_limit.clone(),
+            funder_cache: self
+                .funder_cache
+                .as_ref()
+                .map(|cache| Arc::new(FunderCache::new(cache.ttl()))),
+            ip_limiter: self.ip_limiter.clone(),
+            cooldown: self.cooldown.clone(),
+            trust_x_forwarded_for: self.trust_x_forwarded_for,
+            checkers: self.checkers.clone(),
+            challenges: self.challenges.clone(),
+            sybil: self.sybil.clone(),
+            bans: self.bans.clone(),
+            leader: self.leader.clone(),
+            sharding: self.sharding.clone(),
+            sequence_leases: self.sequence_leases.clone(),
+            admin_token: self.admin_token.clone(),
+            partners: self.partners.clone(),
+            mint_queue: self.mint_queue.clone(),
+            audit_log: self.audit_log.clone(),
+            events: self.events.clone(),
+            self_test: self.self_test.clone(),
+            coupons: self.coupons.clone(),
+            security_headers: self.security_headers.clone(),
+            request_limits: self.request_limits.clone(),
+            log_sampler: self.log_sampler.clone(),
+            chaos: self.chaos.clone(),
+            standing_orders: self.standing_orders.clone(),
+            outstanding: Arc::new(Outstanding::default()),
+            chain_watch: ChainWatch::default(),
+            stuck_threshold: self.stuck_threshold,
+            max_in_flight_transactions: self.max_in_flight_transactions,
+            finality_version_lag: self.finality_version_lag,
+            key_rotation: self.key_rotation,
+            ans_contract: self.ans_contract,
+            logger: self.logger.clone(),
+            path_prefix: self.path_prefix.clone(),
+            coin_decimals: self.coin_decimals.clone(),
+            started_at: self.started_at,
         }
     }
+}
 
-    pub fn endpoint(&self) -> &String {
-        &self.endpoint
+/// Refreshes the funder gauges and tops it up every [`FUNDER_POLL_INTERVAL`], until the service
+/// is dropped
+async fn poll_funder(service: Weak<Service>) {
+    let mut interval = tokio::time::interval(FUNDER_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let service = match service.upgrade() {
+            Some(service) => service,
+            None => return,
+        };
+
+        let (address, local_seq) = {
+            let funder = service.faucet_account.lock().await;
+            (funder.address(), funder.sequence_number())
+        };
+        counters::FUNDER_LOCAL_SEQUENCE_NUMBER.set(local_seq as i64);
+        match counters::node_request("get_account", service.client.get_account(address)).await {
+            Ok(account) => counters::FUNDER_ONCHAIN_SEQUENCE_NUMBER
+                .set(account.into_inner().sequence_number as i64),
+            Err(err) => warn!(
+                "[faucet]: unable to fetch the sequence number of {}: {}",
+                address, err
+            ),
+        }
+        let balance = service.client.get_account_balance(address);
+        match counters::node_request("get_account_balance", balance).await {
+            Ok(balance) => counters::FUNDER_BALANCE.set(balance.into_inner().get() as i64),
+            Err(err) => warn!(
+                "[faucet]: unable to fetch the balance of {}: {}",
+                address, err
+            ),
+        }
+        if let Err(err) = service.top_up_funder().await {
+            warn!("[faucet]: unable to top up {}: {}", address, err);
+        }
+    }
+}
+
+/// Builds a [`Service`], only the REST endpoint and the funder account are required.
+///
+/// ```ignore
+/// let service = ServiceBuilder::new("http://localhost:8080")
+///     .chain_id(ChainId::test())
+///     .funder(faucet_account)
+///     .maximum_amount(1_000_000)
+///     .build()?;
+/// ```
+pub struct ServiceBuilder {
+    endpoint: String,
+    chain_id: ChainId,
+    funder: Option<LocalAccount>,
+    maximum_amount: Option<u64>,
+    default_amount: Option<u64>,
+    minimum_amount: u64,
+    max_gas_amount: u64,
+    gas_unit_price: u64,
+    transaction_expiration_secs: u64,
+    dry_run: bool,
+    funding: Arc<dyn Funder>,
+    spend_budget: Option<SpendBudget>,
+    daily_maximum_amount: Option<u64>,
+    funder_cache_ttl: Option<Duration>,
+    stuck_threshold: Option<Duration>,
+    max_in_flight_transactions: u64,
+    finality_version_lag: u64,
+    key_rotation: Option<Duration>,
+    ans_contract: Option<AccountAddress>,
+    logger: Option<Arc<aptos_logger::Logger>>,
+    ip_rate_limit: Option<IpRateLimitConfig>,
+    cooldown: Option<CooldownConfig>,
+    trust_x_forwarded_for: bool,
+    checkers: Vec<Box<dyn Checker>>,
+    balance_limit: Option<BalanceConfig>,
+    challenge_ttl: Option<Duration>,
+    sybil: Option<Arc<SybilDetector>>,
+    bans: Option<BanConfig>,
+    leader_election: Option<LeaderElectionConfig>,
+    sharding: Option<ShardingConfig>,
+    sequence_leases: Option<SequenceLeaseConfig>,
+    admin_token: Option<String>,
+    partners: Option<PartnerConfig>,
+    max_concurrent_mints: Option<usize>,
+    max_queue_wait: Option<Duration>,
+    audit_log: Option<Arc<AuditLog>>,
+    events: Vec<EventPublisher>,
+    self_test: Option<Arc<SelfTest>>,
+    security_headers: SecurityHeadersConfig,
+    request_limits: RequestLimitsConfig,
+    log_sampling: BTreeMap<String, u64>,
+    chaos: Option<ChaosConfig>,
+    standing_orders: Vec<StandingOrder>,
+    path_prefix: Vec<String>,
+}
+
+impl ServiceBuilder {
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            chain_id: ChainId::test(),
+            funder: None,
+            maximum_amount: None,
+            default_amount: None,
+            minimum_amount: 1,
+            max_gas_amount: 2_000,
+            gas_unit_price: 1,
+            transaction_expiration_secs: 30,
+            dry_run: false,
+            funding: FundingMode::Mint.funder(),
+            spend_budget: None,
+            daily_maximum_amount: None,
+            funder_cache_ttl: None,
+            stuck_threshold: None,
+            max_in_flight_transactions: 50,
+            finality_version_lag: 10,
+            key_rotation: None,
+            ans_contract: None,
+            logger: None,
+            ip_rate_limit: None,
+            cooldown: None,
+            trust_x_forwarded_for: false,
+            checkers: Vec::new(),
+            balance_limit: None,
+            challenge_ttl: None,
+            sybil: None,
+            bans: None,
+            leader_election: None,
+            sharding: None,
+            sequence_leases: None,
+            admin_token: None,
+            partners: None,
+            max_concurrent_mints: None,
+            max_queue_wait: None,
+            audit_log: None,
+            events: Vec::new(),
+            self_test: None,
+            security_headers: SecurityHeadersConfig::default(),
+            request_limits: RequestLimitsConfig::default(),
+            log_sampling: BTreeMap::new(),
+            chaos: None,
+            standing_orders: Vec::new(),
+            path_prefix: Vec::new(),
+        }
+    }
+
+    /// Chain the transactions are signed for, defaults to the local testing chain
+    pub fn chain_id(mut self, chain_id: ChainId) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    /// Account that creates and funds the requested accounts
+    pub fn funder(mut self, funder: LocalAccount) -> Self {
+        self.funder = Some(funder);
+        self
+    }
+
+    /// Upper bound on the amount minted per request, unbounded by default
+    pub fn maximum_amount(mut self, maximum_amount: u64) -> Self {
+        self.maximum_amount = Some(maximum_amount);
+        self
+    }
+
+    /// Amount minted for requests without one, which are refused by default
+    pub fn default_amount(mut self, default_amount: u64) -> Self {
+        self.default_amount = Some(default_amount);
+        self
+    }
+
+    /// Smallest amount a request may ask for, 1 by default
+    pub fn minimum_amount(mut self, minimum_amount: u64) -> Self {
+        self.minimum_amount = minimum_amount;
+        self
+    }
+
+    pub fn max_gas_amount(mut self, max_gas_amount: u64) -> Self {
+        self.max_gas_amount = max_gas_amount;
+        self
+    }
+
+    pub fn gas_unit_price(mut self, gas_unit_price: u64) -> Self {
+        self.gas_unit_price = gas_unit_price;
+        self
+    }
+
+    pub fn transaction_expiration_secs(mut self, transaction_expiration_secs: u64) -> Self {
+        self.transaction_expiration_secs = transaction_expiration_secs;
+        self
+    }
+
+    /// Sign transactions but never submit them, returning them to the caller instead
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Whether receivers are funded by minting, the default, or by transfers from the funder's
+    /// balance
+    pub fn funding_mode(self, funding_mode: FundingMode) -> Self {
+        self.funding(funding_mode.funder())
+    }
+
+    /// Builds the transactions getting coins to receivers with `funding`, see [`funder`]
+    pub fn funding(mut self, funding: Arc<dyn Funder>) -> Self {
+        self.funding = funding;
+        self
+    }
+
+    /// Caps the total amount minted by HTTP requests in each `window`, across all clients.
+    /// Requests beyond the cap are refused until the window resets
+    pub fn spend_budget(mut self, limit: u64, window: Duration) -> Self {
+        self.spend_budget = Some(SpendBudget::new(limit, window));
+        self
+    }
+
+    /// Upper bound on the amount each receiver address is funded per UTC day, across all its
+    /// requests. Unbounded by default
+    pub fn daily_maximum_amount(mut self, daily_maximum_amount: u64) -> Self {
+        self.daily_maximum_amount = Some(daily_maximum_amount);
+        self
+    }
+
+    /// Reuses the funder's on-chain sequence number looked up by a mint request for further
+    /// requests within `ttl`, instead of looking it up for each of them. Not cached by default
+    pub fn funder_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.funder_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Resubmits the funder's transactions not committed `threshold` after their submission if
+    /// the node lost them, and abandons them once expired, see [`stuck`]. Off by default
+    pub fn stuck_transaction_threshold(mut self, threshold: Duration) -> Self {
+        self.stuck_threshold = Some(threshold);
+        self
+    }
+
+    /// Transactions the funder may have submitted ahead of the on-chain sequence number, 50 by
+    /// default. Mint requests beyond that wait for earlier transactions to commit
+    pub fn max_in_flight_transactions(mut self, max_in_flight_transactions: u64) -> Self {
+        self.max_in_flight_transactions = max_in_flight_transactions;
+        self
+    }
+
+    /// Versions the ledger must be past a request's transactions for them to count as final for
+    /// `wait_for=finalized`, 10 by default
+    pub fn finality_version_lag(mut self, finality_version_lag: u64) -> Self {
+        self.finality_version_lag = finality_version_lag;
+        self
+    }
+
+    /// Rotates the delegated funder's authentication key once it is `period` old, see
+    /// [`key_rotation`]. Never rotated by default, nor without delegation
+    pub fn delegate_key_rotation(mut self, period: Duration) -> Self {
+        self.key_rotation = Some(period);
+        self
+    }
+
+    /// Lets mint requests name their receiver by its Aptos name, resolved with the ANS contract
+    /// published at `contract`, see [`ans`]
+    pub fn ans_contract(mut self, contract: AccountAddress) -> Self {
+        self.ans_contract = Some(contract);
+        self
+    }
+
+    /// The process' logger, letting `PUT /admin/log_level` change what it logs
+    pub fn logger(mut self, logger: Arc<aptos_logger::Logger>) -> Self {
+        self.logger = Some(logger);
+        self
+    }
+
+    /// Limits mint requests per client address and per IPv4 /24 and IPv6 /64 subnet
+    pub fn ip_rate_limit(mut self, config: IpRateLimitConfig) -> Self {
+        self.ip_rate_limit = Some(config);
+        self
+    }
+
+    /// Refuses to fund an address again within a cooldown of its last funding
+    pub fn cooldown(mut self, config: CooldownConfig) -> Self {
+        self.cooldown = Some(config);
+        self
+    }
+
+    /// Identify clients by the first `X-Forwarded-For` entry rather than the peer address.
+    /// Only enable this behind a proxy that sets the header, otherwise clients can spoof it
+    pub fn trust_x_forwarded_for(mut self, trust_x_forwarded_for: bool) -> Self {
+        self.trust_x_forwarded_for = trust_x_forwarded_for;
+        self
+    }
+
+    /// Adds a checker that every mint request must pass, see [`checkers`]
+    pub fn checker(mut self, checker: impl Checker + 'static) -> Self {
+        self.checkers.push(Box::new(checker));
+        self
+    }
+
+    /// Refuse or reduce requests for receivers already holding more than the configured
+    /// balance, see [`checkers::balance`]
+    pub fn balance_limit(mut self, config: BalanceConfig) -> Self {
+        self.balance_limit = Some(config);
+        self
+    }
+
+    /// Require requesters to prove control of the account being funded by signing a nonce from
+    /// `GET /challenge`, see [`challenge`]. Nonces expire after `ttl`
+    pub fn signature_challenge(mut self, ttl: Duration) -> Self {
+        self.challenge_ttl = Some(ttl);
+        self
+    }
+
+    /// Analyze the funding history for sybil clusters and temporarily block them, see
+    /// [`checkers::sybil`]. The analysis only starts once [`Service::start_background_tasks`]
+    /// is called
+    pub fn sybil_detection(mut self, config: SybilConfig) -> Self {
+        let sybil = Arc::new(SybilDetector::new(config));
+        self.checkers.push(Box::new(sybil.clone()));
+        self.sybil = Some(sybil);
+        self
+    }
+
+    /// Temporarily ban clients going over `config`'s violation thresholds, see [`bans`]
+    pub fn bans(mut self, config: BanConfig) -> Self {
+        self.bans = Some(config);
+        self
+    }
+
+    /// Only submit transactions while holding the lease described by `config`, see [`leader`].
+    /// The election runs once [`Service::start_background_tasks`] is called, until then the
+    /// service is a standby
+    pub fn leader_election(mut self, config: LeaderElectionConfig) -> Self {
+        self.leader_election = Some(config);
+        self
+    }
+
+    /// Only fund the receivers of `config.shard`, see [`sharding`]
+    pub fn sharding(mut self, config: ShardingConfig) -> Self {
+        self.sharding = Some(config);
+        self
+    }
+
+    /// Sign with sequence numbers leased from the Redis server of `config`, so that several
+    /// replicas share the funder, see [`sequence_leases`]
+    pub fn sequence_leases(mut self, config: SequenceLeaseConfig) -> Self {
+        self.sequence_leases = Some(config);
+        self
+    }
+
+    /// Serve the `/admin` endpoints to requests bearing `token`, see [`admin`]
+    pub fn admin_token(mut self, token: impl Into<String>) -> Self {
+        self.admin_token = Some(token.into());
+        self
+    }
+
+    /// Accept mint requests signed with the partners' shared secrets, see [`partner`]. Signed
+    /// requests skip the IP limits, the signature challenge and the checkers
+    pub fn partners(mut self, config: PartnerConfig) -> Self {
+        self.partners = Some(config);
+        self
+    }
+
+    /// Process at most `max_concurrent` mint requests at once, see [`mint_queue`]. Waiting
+    /// partner requests are processed before anonymous ones
+    pub fn max_concurrent_mints(mut self, max_concurrent: usize) -> Self {
+        self.max_concurrent_mints = Some(max_concurrent);
+        self
+    }
+
+    /// Drop mint requests that waited `max_wait` for a processing slot, see [`mint_queue`]. Only
+    /// applies along with [`Self::max_concurrent_mints`]
+    pub fn max_queue_wait(mut self, max_wait: Duration) -> Self {
+        self.max_queue_wait = Some(max_wait);
+        self
+    }
+
+    /// Record every funding decision in `audit_log`, see [`audit`]
+    pub fn audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(Arc::new(audit_log));
+        self
+    }
+
+    /// Publish every funding decision with `events`, see [`events`]. Can be called several
+    /// times to publish to several sinks
+    pub fn event_publisher(mut self, events: EventPublisher) -> Self {
+        self.events.push(events);
+        self
+    }
+
+    /// Fund a throwaway account at startup, and report the outcome in `GET /ready`, see
+    /// [`self_test`]. The self-test only runs once [`Service::start_background_tasks`] is called
+    pub fn self_test(mut self) -> Self {
+        self.self_test = Some(Arc::new(SelfTest::new()));
+        self
+    }
+
+    /// Security headers added to every response, see [`security_headers`]
+    pub fn security_headers(mut self, config: SecurityHeadersConfig) -> Self {
+        self.security_headers = config;
+        self
+    }
+
+    /// Size limits of every request, see [`request_limits`]
+    pub fn request_limits(mut self, config: RequestLimitsConfig) -> Self {
+        self.request_limits = config;
+        self
+    }
+
+    /// Log only 1 in N info lines of the modules in `rates`, by module path, see
+    /// [`log_sampling`]
+    pub fn log_sampling(mut self, rates: &BTreeMap<String, u64>) -> Self {
+        self.log_sampling = rates.clone();
+        self
+    }
+
+    /// Inject faults into API requests, for testing clients, see [`chaos`]
+    pub fn chaos(mut self, config: ChaosConfig) -> Self {
+        self.chaos = Some(config);
+        self
+    }
+
+    /// Fund an address on a schedule, see [`schedule`]. Orders only start running once
+    /// [`Service::start_background_tasks`] is called
+    pub fn standing_order(mut self, order: StandingOrder) -> Self {
+        self.standing_orders.push(order);
+        self
+    }
+
+    /// Mount every route under `prefix`, e.g. `/faucet`, rather than at the root
+    pub fn path_prefix(mut self, prefix: &str) -> Self {
+        self.path_prefix = prefix
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(str::to_string)
+            .collect();
+        self
+    }
+
+    pub fn build(self) -> Result<Service> {
+        let funder = self
+            .funder
+            .ok_or_else(|| anyhow::format_err!("A funder account is required"))?;
+        let url = Url::parse(&self.endpoint).map_err(|err| {
+            anyhow::format_err!("Invalid rest endpoint {}: {}", self.endpoint, err)
+        })?;
+        if self.max_concurrent_mints == Some(0) {
+            return Err(anyhow::format_err!(
+                "At least one mint request must be processed at once"
+            ));
+        }
+        if self.max_in_flight_transactions == 0 {
+            return Err(anyhow::format_err!(
+                "At least one transaction must be allowed in flight"
+            ));
+        }
+        if let Some(sharding) = &self.sharding {
+            sharding.validate()?;
+        }
+        if let Some(sequence_leases) = &self.sequence_leases {
+            sequence_leases.validate()?;
+        }
+        if let Some(chaos) = &self.chaos {
+            chaos.validate()?;
+        }
+        for order in &self.standing_orders {
+            order.validate()?;
+        }
+        // Node requests carry the trace context of the request they are sent for
+        let client = Client::new(url).with_headers(request_log::outbound_headers);
+        let mut checkers = self.checkers;
+        if let Some(config) = self.balance_limit {
+            checkers.push(Box::new(BalanceChecker::new(client.clone(), config)));
+        }
+        // Replicas sharing a Redis share their callers' daily allowances as well
+        let daily_limit_store = match (&self.sequence_leases, &self.leader_election) {
+            (Some(config), _) => Some(RedisStore {
+                address: config.redis_address.clone(),
+                password: config.password.clone(),
+                key: format!("{}:daily", config.key),
+            }),
+            (None, Some(config)) => Some(RedisStore {
+                address: config.redis_address.clone(),
+                password: config.password.clone(),
+                key: format!("{}:daily", config.key),
+            }),
+            (None, None) => None,
+        };
+
+        Ok(Service {
+            faucet_account: Mutex::new(funder),
+            chain_id: self.chain_id,
+            transaction_factory: TransactionFactory::new(self.chain_id)
+                .with_max_gas_amount(self.max_gas_amount)
+                .with_gas_unit_price(self.gas_unit_price)
+                .with_transaction_expiration_time(self.transaction_expiration_secs),
+            client,
+            endpoint: self.endpoint,
+            maximum_amount: self.maximum_amount,
+            default_amount: self.default_amount,
+            minimum_amount: self.minimum_amount,
+            dry_run: self.dry_run,
+            funding: self.funding,
+            delegation: None,
+            spend_budget: self.spend_budget.map(Arc::new),
+            daily_limit: self.daily_maximum_amount.map(|limit| {
+                Arc::new(match daily_limit_store {
+                    Some(store) => DailyLimit::with_redis(limit, store),
+                    None => DailyLimit::new(limit),
+                })
+            }),
+            funder_cache: self
+                .funder_cache_ttl
+                .filter(|ttl| !ttl.is_zero())
+                .map(|ttl| Arc::new(FunderCache::new(ttl))),
+            ip_limiter: self
+                .ip_rate_limit
+                .map(|config| Arc::new(IpRateLimiter::new(config))),
+            cooldown: self.cooldown.map(|config| Arc::new(Cooldown::new(config))),
+            trust_x_forwarded_for: self.trust_x_forwarded_for,
+            checkers: Arc::new(checkers),
+            challenges: self
+                .challenge_ttl
+                .map(|ttl| Arc::new(ChallengeStore::new(ttl))),
+            sybil: self.sybil,
+            bans: match self.bans {
+                Some(config) => Some(Arc::new(Bans::load(config)?)),
+                None => None,
+            },
+            leader: self
+                .leader_election
+                .map(|config| Arc::new(LeaderElection::new(config))),
+            sharding: self.sharding,
+            sequence_leases: self
+                .sequence_leases
+                .map(|config| Arc::new(SequenceLeases::new(config))),
+            admin_token: self.admin_token,
+            partners: self
+                .partners
+                .map(|config| Arc::new(PartnerAuth::new(config))),
+            mint_queue: self
+                .max_concurrent_mints
+                .map(|max_concurrent| MintQueue::new(max_concurrent, self.max_queue_wait)),
+            audit_log: self.audit_log,
+            events: Arc::new(self.events),
+            self_test: self.self_test,
+            coupons: Arc::new(CouponStore::new()),
+            security_headers: self.security_headers.headers()?,
+            request_limits: self.request_limits,
+            log_sampler: Arc::new(LogSampler::new(&self.log_sampling)),
+            chaos: self.chaos,
+            standing_orders: Arc::new(self.standing_orders),
+            outstanding: Arc::new(Outstanding::default()),
+            chain_watch: ChainWatch::default(),
+            stuck_threshold: self.stuck_threshold,
+            max_in_flight_transactions: self.max_in_flight_transactions,
+            finality_version_lag: self.finality_version_lag,
+            key_rotation: self.key_rotation,
+            ans_contract: self.ans_contract,
+            logger: self.logger,
+            path_prefix: self.path_prefix,
+            coin_decimals: Arc::new(OnceCell::new()),
+            started_at: Instant::now(),
+        })
     }
 }
 
@@ -70,28 +1062,64 @@ pub fn routes(
     service: Arc<Service>,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
     let mint = mint::mint_routes(service.clone());
-    let health = health_route(service);
+    let challenge = challenge::challenge_routes(service.clone());
+    let admin = admin::admin_routes(service.clone());
+    let health = health_route(service.clone());
+    let security_headers = service.security_headers.clone();
+    let request_limits = request_limits::check(service.request_limits.clone());
+    let log_sampler = service.log_sampler.clone();
+    let chaos = chaos::inject(service.chaos.clone());
+    let prefix = service
+        .path_prefix
+        .iter()
+        .fold(warp::any().boxed(), |prefix, segment| {
+            prefix.and(warp::path(segment.clone())).boxed()
+        });
+    let api = health
+        .or(ready_route(service.clone()))
+        .or(metrics_route())
+        .or(version_route())
+        .or(admin)
+        .or(chaos
+            .or(quota::quota_route(service.clone()))
+            .or(fund::fund_route(service.clone()))
+            .or(accounts::accounts_route(service.clone()))
+            .or(challenge)
+            .or(mint));
 
-    health
-        .or(mint)
-        .with(warp::log::custom(|info| {
-            info!(
-                "{} \"{} {} {:?}\" {} \"{}\" \"{}\" {:?}",
-                OptFmt(info.remote_addr()),
-                info.method(),
-                info.path(),
-                info.version(),
-                info.status().as_u16(),
-                OptFmt(info.referer()),
-                OptFmt(info.user_agent()),
-                info.elapsed(),
-            )
-        }))
+    warp::any()
+        .map(Instant::now)
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(request_log::raw_query())
+        .and(client_ip(service))
+        .and(warp::header::optional::<String>("user-agent"))
+        .and(warp::any().map(move || log_sampler.clone()))
+        .and(
+            request_limits
+                .and(prefix)
+                .and(api)
+                .recover(rejection::handle_rejection),
+        )
+        .map(request_log::access_log)
+        .with(warp::reply::with::headers(security_headers))
         .with(
             warp::cors()
                 .allow_any_origin()
-                .allow_headers(vec![http::header::CONTENT_TYPE])
-                .allow_methods(vec!["POST"]),
+                .allow_headers(vec![
+                    http::header::CONTENT_TYPE,
+                    http::header::AUTHORIZATION,
+                    http::header::HeaderName::from_static(REQUEST_ID_HEADER),
+                    http::header::HeaderName::from_static(TRACEPARENT_HEADER),
+                    http::header::HeaderName::from_static(mint_queue::DEADLINE_HEADER),
+                ])
+                // Browser code only sees the headers listed here
+                .expose_headers(vec![
+                    http::header::HeaderName::from_static(REQUEST_ID_HEADER),
+                    http::header::HeaderName::from_static(TRACEPARENT_HEADER),
+                    http::header::RETRY_AFTER,
+                ])
+                .allow_methods(vec!["POST", "GET", "PUT"]),
         )
 }
 
@@ -101,20 +1129,165 @@ fn health_route(
     warp::path!("health")
         .and(warp::get())
         .and(warp::any().map(move || service.clone()))
-        .and_then(handle_health)
+        .and(warp::header::optional::<String>("accept"))
+        .and(request_context())
+        .and_then(|service, accept: Option<String>, context| {
+            // Probes written against the plain text body keep getting it
+            let plain_text = accept.map_or(false, |accept| accept.contains("text/plain"));
+            with_request_id(context, handle_health(service, plain_text))
+        })
 }
 
-async fn handle_health(service: Arc<Service>) -> Result<Box<dyn warp::Reply>, Infallible> {
+async fn handle_health(
+    service: Arc<Service>,
+    plain_text: bool,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
     let faucet_address = service.faucet_account.lock().await.address();
-    let faucet_account = service.client.get_account(faucet_address).await;
-
-    match faucet_account {
-        Ok(account) => Ok(Box::new(account.inner().sequence_number.to_string())),
-        Err(err) => Ok(Box::new(warp::reply::with_status(
-            err.to_string(),
+    let faucet_account =
+        counters::node_request("get_account", service.client.get_account(faucet_address)).await;
+    let (status, sequence_number, error) = match faucet_account {
+        Ok(account) => (StatusCode::OK, Some(account.inner().sequence_number), None),
+        Err(err) => (
             StatusCode::INTERNAL_SERVER_ERROR,
-        ))),
+            None,
+            Some(err.to_string()),
+        ),
+    };
+
+    if plain_text {
+        let body = match (sequence_number, error) {
+            (Some(sequence_number), _) => sequence_number.to_string(),
+            (None, error) => error.unwrap_or_default(),
+        };
+        return Ok(Box::new(warp::reply::with_status(body, status)));
     }
+    Ok(Box::new(warp::reply::with_status(
+        warp::reply::json(&Health {
+            version: build_info::VERSION.to_string(),
+            git_commit: build_info::GIT_REV.to_string(),
+            uptime_secs: service.started_at.elapsed().as_secs(),
+            chain: mint::Network {
+                chain_id: service.chain_id.id(),
+                name: service.chain_id.to_string(),
+            },
+            funder_address: faucet_address,
+            sequence_number,
+            error,
+            capabilities: service.capabilities(),
+        }),
+        status,
+    )))
+}
+
+/// Returned by `GET /health`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Health {
+    pub version: String,
+    pub git_commit: String,
+    pub uptime_secs: u64,
+    /// The chain the faucet is configured for
+    pub chain: mint::Network,
+    pub funder_address: AccountAddress,
+    /// Of the funder account, missing if the node could not be reached
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sequence_number: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// See [`Service::capabilities`]
+    pub capabilities: Vec<String>,
+}
+
+/// Ready once the self-test, if any, has passed
+fn ready_route(
+    service: Arc<Service>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("ready")
+        .and(warp::get())
+        .and(warp::any().map(move || service.clone()))
+        .and(request_context())
+        .and_then(|service, context| with_request_id(context, handle_ready(service)))
+}
+
+async fn handle_ready(service: Arc<Service>) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let self_test = service
+        .self_test
+        .as_ref()
+        .map(|self_test| self_test.status());
+    let leader = service.leader.as_ref().map(|leader| leader.is_leader());
+    // Standbys only run the self-test once they lead, and are ready for read-only requests until
+    // then
+    let status = match (&self_test, leader) {
+        (None, _) | (Some(SelfTestStatus::Passed), _) => StatusCode::OK,
+        (Some(SelfTestStatus::Pending), Some(false)) => StatusCode::OK,
+        (Some(_), _) => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    Ok(Box::new(warp::reply::with_status(
+        warp::reply::json(&Readiness {
+            ready: status == StatusCode::OK,
+            self_test,
+            leader,
+        }),
+        status,
+    )))
+}
+
+/// Returned by `GET /ready`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub self_test: Option<SelfTestStatus>,
+    /// Whether this replica holds the lease, only with leader election
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leader: Option<bool>,
+}
+
+/// Identifies the faucet build, see [`build_info`]
+fn version_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("version")
+        .and(warp::get())
+        .and(request_context())
+        .and_then(|context| {
+            with_request_id(context, async {
+                Ok(Box::new(warp::reply::json(&BuildInfo::current())) as Box<dyn warp::Reply>)
+            })
+        })
+}
+
+/// Serves the faucet's counters to Prometheus
+fn metrics_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("metrics")
+        .and(warp::get())
+        .and(request_context())
+        .and_then(|context| {
+            with_request_id(context, async {
+                Ok(Box::new(counters::encode()) as Box<dyn warp::Reply>)
+            })
+        })
+}
+
+/// Extracts the client's IP address, from `X-Forwarded-For` if the service trusts it
+fn client_ip(
+    service: Arc<Service>,
+) -> impl Filter<Extract = (Option<IpAddr>,), Error = Infallible> + Clone {
+    // Requests served by the faucet's own server carry their client's address as a `PeerAddr`
+    let peer_addr = warp::ext::get::<PeerAddr>()
+        .map(|peer_addr: PeerAddr| Some(peer_addr.0))
+        .or(warp::any().map(|| None))
+        .unify();
+    warp::addr::remote()
+        .and(peer_addr)
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .map(
+            move |remote: Option<SocketAddr>,
+                  peer_addr: Option<SocketAddr>,
+                  forwarded_for: Option<String>| {
+                let forwarded_ip = forwarded_for
+                    .filter(|_| service.trust_x_forwarded_for)
+                    .and_then(|header| header.split(',').next()?.trim().parse().ok());
+                forwarded_ip.or_else(|| remote.or(peer_addr).map(|remote| remote.ip()))
+            },
+        )
 }
 
 //
@@ -133,84 +1306,187 @@ impl<T: fmt::Display> fmt::Display for OptFmt<T> {
     }
 }
 
-/// The idea is that this may be happening concurrently. If we end up in such a race, the faucets
-/// might attempt to send transactions with the same sequence number, in such an event, one will
-/// succeed and the other will hit an unwrap. Eventually all faucets should get online.
-pub async fn delegate_mint_account(
-    service: Arc<Service>,
-    server_url: String,
-    chain_id: ChainId,
-    maximum_amount: Option<u64>,
-) -> Arc<Service> {
-    // Create a new random account, then delegate to it
-    let mut delegated_account = LocalAccount::generate(&mut rand::rngs::OsRng);
-
-    // Create the account
-    let response = mint::process(
-        &service,
-        mint::MintParams {
-            amount: 100_000_000_000,
-            auth_key: None,
-            address: Some(
-                delegated_account
-                    .authentication_key()
-                    .clone()
-                    .derived_address()
-                    .to_hex_literal(),
-            ),
-            pub_key: None,
-            return_txns: Some(true),
-        },
-    )
-    .await
-    .unwrap();
-
-    match response {
-        mint::Response::SubmittedTxns(txns) => {
-            for txn in txns {
-                service
-                    .client
-                    .wait_for_signed_transaction(&txn)
-                    .await
-                    .unwrap();
+/// Coins kept in a delegated funder's account to pay for gas
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Endowment {
+    /// Minted into the account when it is created
+    pub amount: u64,
+    /// Balance below which the account mints itself back up to `amount`, never when empty
+    pub top_up_below: Option<u64>,
+}
+
+/// How a delegated funder was set up, kept to top it up and to replace it
+struct Delegation {
+    /// Funds from the account the mint capability is delegated from
+    root: Arc<Service>,
+    endowment: Endowment,
+    state_file: Option<PathBuf>,
+    /// Held while redelegating, so that concurrent requests do not each delegate to an account
+    redelegating: Mutex<()>,
+    /// When the delegated funder's key was generated
+    key_created_at: aptos_infallible::Mutex<DateTime<Utc>>,
+}
+
+impl Delegation {
+    /// The account saved by a previous run, if it can still be minted from: it exists on chain,
+    /// the saved key still controls it and it holds coins to pay for gas. It usually cannot
+    /// after the chain was reset, and a new account is delegated to. A key rotation the run was
+    /// interrupted in is settled by which key the account has. Fails when the node is
+    /// unreachable, rather than abandoning a funded account
+    async fn saved_account(&self) -> Result<Option<LocalAccount>> {
+        let path = match &self.state_file {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let saved = match DelegateState::load(path)? {
+            Some(saved) => saved,
+            None => return Ok(None),
+        };
+        let client = &self.root.client;
+        let address = saved.address;
+        let abandoned = |reason: String| {
+            warn!(
+                "[faucet]: saved delegated account {} {}, delegating to a new one",
+                address, reason
+            );
+            Ok(None)
+        };
+
+        let account = match client.get_account(saved.address).await {
+            Ok(account) => account.into_inner(),
+            Err(err) => {
+                let root = self.root.faucet_account.lock().await.address();
+                if let Err(root_err) = client.get_account(root).await {
+                    anyhow::bail!(
+                        "Unable to verify the saved delegated account {}: {}",
+                        saved.address,
+                        root_err
+                    );
+                }
+                return abandoned(format!("not found: {}", err));
             }
+        };
+        let rotating = saved.pending_key.is_some();
+        let saved = match saved.settle(&account.authentication_key) {
+            Some(saved) => saved,
+            None => return abandoned("is controlled by another key".to_string()),
+        };
+        if rotating {
+            saved.save(path)?;
+            info!(
+                "[faucet]: settled the interrupted key rotation of {}, keeping {}",
+                saved.address, account.authentication_key
+            );
+        }
+        let balance = client
+            .get_account_balance(saved.address)
+            .await
+            .map(|balance| balance.into_inner().get())
+            .unwrap_or(0);
+        if balance == 0 {
+            return abandoned("has no coins to pay for gas".to_string());
         }
-        _ => panic!("Expected a set of Response::SubmittedTxns"),
+
+        info!(
+            "[faucet]: reusing the delegated account {} from {:?}, holding {}",
+            saved.address, path, balance
+        );
+        if let Some(key_created_at) = saved.key_created_at {
+            *self.key_created_at.lock() = key_created_at;
+        }
+        Ok(Some(saved.account(account.sequence_number)))
     }
 
-    // Delegate minting to the account
-    {
-        let mut faucet_account = service.faucet_account.lock().await;
+    /// Creates a new random account, then delegates to it. With a state file, the account is
+    /// saved once it holds the capability, an account saved earlier could not mint after a
+    /// restart
+    async fn delegate(&self) -> Result<LocalAccount> {
+        let service = &self.root;
+        let state = DelegateState::new(Ed25519PrivateKey::generate(&mut rand::rngs::OsRng));
+        let mut delegated_account = state.account(0);
+
+        // Create the account
+        let response = mint::process(
+            service,
+            mint::MintParams {
+                amount: Some(self.endowment.amount.into()),
+                address: Some(delegated_account.address().to_hex_literal()),
+                return_txns: Some(true),
+                ..mint::MintParams::default()
+            },
+        )
+        .await?;
+
+        match response {
+            mint::Response::SubmittedTxns(txns) => {
+                for txn in txns {
+                    service.client.wait_for_signed_transaction(&txn).await?;
+                }
+            }
+            _ => anyhow::bail!("Expected a set of Response::SubmittedTxns"),
+        }
+
+        // Delegate minting to the account
+        {
+            let mut faucet_account = service.faucet_account.lock().await;
+            service
+                .client
+                .submit_and_wait(&faucet_account.sign_with_transaction_builder(
+                    service.transaction_factory.payload(
+                        aptos_stdlib::encode_test_coin_delegate_mint_capability(
+                            delegated_account.address(),
+                        ),
+                    ),
+                ))
+                .await?;
+        }
+
+        // claim the capability!
         service
             .client
-            .submit_and_wait(&faucet_account.sign_with_transaction_builder(
-                service.transaction_factory.payload(
-                    aptos_stdlib::encode_test_coin_delegate_mint_capability(
-                        delegated_account.address(),
-                    ),
+            .submit_and_wait(
+                &delegated_account.sign_with_transaction_builder(
+                    service
+                        .transaction_factory
+                        .payload(aptos_stdlib::encode_test_coin_claim_mint_capability()),
                 ),
-            ))
-            .await
-            .unwrap();
+            )
+            .await?;
+
+        if let Some(path) = &self.state_file {
+            state.save(path)?;
+        }
+        *self.key_created_at.lock() = Utc::now();
+        Ok(delegated_account)
     }
+}
 
-    // claim the capability!
-    service
-        .client
-        .submit_and_wait(
-            &delegated_account.sign_with_transaction_builder(
-                service
-                    .transaction_factory
-                    .payload(aptos_stdlib::encode_test_coin_claim_mint_capability()),
-            ),
-        )
-        .await
-        .unwrap();
-
-    Arc::new(Service::new(
-        server_url,
-        chain_id,
-        delegated_account,
-        maximum_amount,
-    ))
+/// The idea is that this may be happening concurrently. If we end up in such a race, the faucets
+/// might attempt to send transactions with the same sequence number, in such an event, one will
+/// succeed and the other will fail to start. Eventually all faucets should get online.
+///
+/// With a `state_file`, the delegated account saved there by a previous run is reused if it
+/// exists on chain, and a newly delegated account is saved to it.
+pub async fn delegate_mint_account(
+    service: Arc<Service>,
+    maximum_amount: Option<u64>,
+    endowment: Endowment,
+    state_file: Option<&Path>,
+) -> Result<Arc<Service>> {
+    let delegation = Arc::new(Delegation {
+        root: service.clone(),
+        endowment,
+        state_file: state_file.map(Path::to_path_buf),
+        redelegating: Mutex::new(()),
+        key_created_at: aptos_infallible::Mutex::new(Utc::now()),
+    });
+    let delegated_account = match delegation.saved_account().await? {
+        Some(account) => account,
+        None => delegation.delegate().await?,
+    };
+
+    Ok(Arc::new(Service {
+        delegation: Some(delegation),
+        ..service.with_funder(delegated_account, maximum_amount)
+    }))
 }
@@ -0,0 +1,66 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Logs only 1 in N of the info lines of busy modules, so that traffic peaks do not flood the
+//! log pipeline. Rates are configured by module path, like `aptos_faucet::request_log: 100`, and
+//! apply to the module and the modules beneath it, the longest configured path winning. Failed
+//! requests are always logged.
+
+use aptos_logger::sample::{SampleRate, Sampling};
+use std::collections::BTreeMap;
+
+pub struct LogSampler {
+    /// Sampling of the info lines, by module path
+    modules: BTreeMap<String, Sampling>,
+}
+
+impl LogSampler {
+    /// Samples 1 in `rate` info lines of each module in `rates`
+    pub fn new(rates: &BTreeMap<String, u64>) -> Self {
+        Self {
+            modules: rates
+                .iter()
+                .map(|(module, rate)| {
+                    (
+                        module.clone(),
+                        Sampling::new(SampleRate::Frequency((*rate).max(1))),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether the next info line of `module`, as given by `module_path!()`, is logged
+    pub fn sample(&self, module: &str) -> bool {
+        self.modules
+            .iter()
+            .rev()
+            .find(|(path, _)| {
+                module == path.as_str()
+                    || module
+                        .strip_prefix(path.as_str())
+                        .map_or(false, |rest| rest.starts_with("::"))
+            })
+            .map_or(true, |(_, sampling)| sampling.sample())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longest_module_path_sets_the_rate() {
+        let rates = BTreeMap::from([
+            ("aptos_faucet".to_string(), 2),
+            ("aptos_faucet::request_log".to_string(), 3),
+        ]);
+        let sampler = LogSampler::new(&rates);
+        let logged = |module: &str| (0..12).filter(|_| sampler.sample(module)).count();
+
+        assert_eq!(logged("aptos_faucet::request_log"), 4);
+        assert_eq!(logged("aptos_faucet::mint"), 6);
+        assert_eq!(logged("aptos_faucet_extra"), 12);
+        assert_eq!(logged("other"), 12);
+    }
+}
@@ -1,16 +1,27 @@
 // Copyright (c) Aptos
 // SPDX-License-Identifier: Apache-2.0
 
-use aptos::common::types::EncodingType;
 use aptos_config::keys::ConfigKey;
 use aptos_crypto::ed25519::Ed25519PrivateKey;
-use aptos_logger::info;
-use aptos_sdk::types::{
-    account_address::AccountAddress, account_config::aptos_root_address, chain_id::ChainId,
-    LocalAccount,
+use aptos_faucet::{
+    audit::AuditLogConfig, bench::BenchConfig, chaos::ChaosConfig,
+    connection_limits::ConnectionLimitsConfig, cooldown::CooldownConfig, funder::FundingMode,
+    ip_limit::IpRateLimitConfig, request_limits::RequestLimitsConfig,
+    security_headers::SecurityHeadersConfig, FaucetConfig,
+};
+use aptos_logger::{aptos_logger::LogEntry, info};
+use aptos_sdk::types::{account_address::AccountAddress, chain_id::ChainId};
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    num::NonZeroUsize,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
 };
-use std::{path::Path, sync::Arc};
 use structopt::StructOpt;
+use url::Url;
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -18,11 +29,106 @@ use structopt::StructOpt;
     author = "Aptos",
     about = "Aptos Testnet utility service for creating test accounts and minting test coins"
 )]
+enum Command {
+    /// Run the faucet service
+    Run(RunArgs),
+    /// Print a commented config file, filled in from the given flags or their defaults
+    GenerateConfig(GenerateConfigArgs),
+    /// Check that a config file is usable (key loads, URLs parse, node is on the expected
+    /// chain) without starting the service
+    ValidateConfig(ValidateConfigArgs),
+    /// Send mint requests for generated accounts to a faucet at a fixed rate, then report
+    /// latency percentiles and error rates
+    Bench(BenchArgs),
+}
+
+#[derive(Debug, StructOpt)]
+struct RunArgs {
+    /// Path to a config file, as produced by `generate-config`.
+    /// When present, the remaining flags are ignored
+    #[structopt(long, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+    /// Only run the startup checks (key loads, node reachable, chain id matches, faucet account
+    /// exists and is funded), then exit with a status code identifying the first failure
+    #[structopt(long)]
+    pub check: bool,
+    /// Log format, `text` or `json` (one JSON object per line)
+    #[structopt(long, default_value = "text")]
+    pub log_format: LogFormat,
+    /// Threads serving requests, one per CPU core when not present
+    #[structopt(long)]
+    pub worker_threads: Option<NonZeroUsize>,
+    /// Most threads kept for blocking work, such as file writes, 512 when not present
+    #[structopt(long)]
+    pub max_blocking_threads: Option<NonZeroUsize>,
+    #[structopt(flatten)]
+    pub args: Args,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format '{}', expected text or json", s)),
+        }
+    }
+}
+
+fn json_format(entry: &LogEntry) -> Result<String, fmt::Error> {
+    serde_json::to_string(entry).map_err(|_| fmt::Error)
+}
+
+#[derive(Debug, StructOpt)]
+struct GenerateConfigArgs {
+    /// File to write the config to, stdout if not present
+    #[structopt(short = "o", long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+    #[structopt(flatten)]
+    pub args: Args,
+}
+
+#[derive(Debug, StructOpt)]
+struct ValidateConfigArgs {
+    /// Path to the config file to validate
+    #[structopt(long, parse(from_os_str))]
+    pub config: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct BenchArgs {
+    /// Base URL of the faucet to test, e.g. http://localhost:8000
+    #[structopt(long)]
+    pub target: Url,
+    /// Requests sent per second
+    #[structopt(long, default_value = "10")]
+    pub rps: u32,
+    /// How long to send requests for, in seconds
+    #[structopt(long, default_value = "60")]
+    pub duration: u64,
+    /// Amount minted by each request, in base units
+    #[structopt(long, default_value = "1")]
+    pub amount: u64,
+    /// Requests without a response by then are counted as unanswered, in milliseconds
+    #[structopt(long, default_value = "10000")]
+    pub timeout_ms: u64,
+}
+
+#[derive(Debug, StructOpt)]
 struct Args {
-    /// Faucet service listen address
+    /// Faucet service listen address, either an IP using --port or an IP and port such as
+    /// [::]:8000. Repeat it to listen on several addresses
     #[structopt(short = "a", long, default_value = "127.0.0.1")]
-    pub address: String,
-    /// Faucet service listen port
+    pub address: Vec<String>,
+    /// Faucet service listen port, for addresses without one
     #[structopt(short = "p", long, default_value = "80")]
     pub port: u16,
     /// Aptos fullnode/validator server URL
@@ -51,362 +157,434 @@ struct Args {
     /// Maximum amount of coins to mint.
     #[structopt(long)]
     pub maximum_amount: Option<u64>,
+    /// Maximum amount of coins minted to each caller, partner or IP, per UTC day, across all its
+    /// requests. Requests are capped at what is left, and get a 429 once nothing is
+    #[structopt(long)]
+    pub daily_maximum_amount: Option<u64>,
+    /// Amount of coins to mint for requests without one. Without a default such requests are
+    /// refused
+    #[structopt(long)]
+    pub default_amount: Option<u64>,
+    /// Smallest amount of coins a request may ask for, other than 0 to only create the account
+    #[structopt(long, default_value = "1")]
+    pub minimum_amount: u64,
     #[structopt(long)]
     pub do_not_delegate: bool,
+    /// Coins minted into the delegated account when it is created, to pay for its gas
+    #[structopt(long, default_value = "100000000000")]
+    pub delegate_endowment: u64,
+    /// Mint the delegated account back up to --delegate-endowment whenever its balance falls
+    /// below this, so long-running faucets do not run out of gas
+    #[structopt(long)]
+    pub delegate_top_up_below: Option<u64>,
+    /// Save the delegated account's key to this file, and reuse the account after a restart
+    /// rather than delegating to a new one. The file must be kept as secret as the mint key
+    #[structopt(long, parse(from_os_str))]
+    pub delegate_state_file: Option<PathBuf>,
+    /// Rotate the delegated account's authentication key once it is this many days old. The new
+    /// key is saved to --delegate-state-file
+    #[structopt(long)]
+    pub delegate_key_rotation_days: Option<u64>,
+    /// `mint` new coins, or `transfer` them from the mint account's balance on networks where
+    /// it has no mint capability. Transfers are never delegated
+    #[structopt(long, default_value = "mint")]
+    pub funding_mode: FundingMode,
+    /// Sign transactions but never submit them, returning the BCS encoded transactions instead.
+    /// Implies --do-not-delegate
+    #[structopt(long)]
+    pub dry_run: bool,
+    /// Run against an in-memory chain instead of --server-url, so the faucet API can be used
+    /// for local development without any node
+    #[structopt(long)]
+    pub mock_chain: bool,
+    /// Maximum amount of coins minted across all requests per --spend-budget-window-secs.
+    /// Requests beyond it get a 429 until the window resets
+    #[structopt(long)]
+    pub spend_budget: Option<u64>,
+    #[structopt(long, default_value = "3600")]
+    pub spend_budget_window_secs: u64,
+    /// Mint requests allowed per --ip-rate-limit-window-secs from a single client address
+    #[structopt(long)]
+    pub max_requests_per_ip: Option<u64>,
+    /// Mint requests allowed per window from all clients sharing an IPv4 /24 prefix
+    #[structopt(long)]
+    pub max_requests_per_ipv4_24: Option<u64>,
+    /// Mint requests allowed per window from all clients sharing an IPv6 /64 prefix
+    #[structopt(long)]
+    pub max_requests_per_ipv6_64: Option<u64>,
+    #[structopt(long, default_value = "86400")]
+    pub ip_rate_limit_window_secs: u64,
+    /// Refuse to fund an address again within this many seconds of its last funding
+    #[structopt(long)]
+    pub cooldown_secs: Option<u64>,
+    /// Identify clients by the first X-Forwarded-For entry rather than the peer address.
+    /// Only enable this behind a proxy that sets the header
+    #[structopt(long)]
+    pub trust_x_forwarded_for: bool,
+    /// Mount every route under this path, e.g. /faucet, for gateways routing by path
+    #[structopt(long)]
+    pub path_prefix: Option<String>,
+    /// Require requesters to prove they control the funded account, by signing a nonce from
+    /// GET /challenge with its private key
+    #[structopt(long)]
+    pub signature_challenge: bool,
+    /// Seconds a challenge nonce stays valid
+    #[structopt(long, default_value = "300")]
+    pub challenge_ttl_secs: u64,
+    /// Bearer token for the /admin endpoints, which are disabled without one
+    #[structopt(long, env = "FAUCET_ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+    /// File holding the admin token, e.g. a mounted secret, which keeps the token out of the
+    /// command line and the environment
+    #[structopt(
+        long,
+        env = "FAUCET_ADMIN_TOKEN_FILE",
+        parse(from_os_str),
+        conflicts_with = "admin-token"
+    )]
+    pub admin_token_file: Option<PathBuf>,
+    /// Process at most this many mint requests at once. Partner requests waiting for a slot go
+    /// before anonymous ones
+    #[structopt(long)]
+    pub max_concurrent_mints: Option<usize>,
+    /// Drop mint requests that waited this long for a processing slot, in milliseconds
+    #[structopt(long)]
+    pub max_queue_wait_ms: Option<u64>,
+    /// Reuse the funder's on-chain sequence number looked up for a mint request for this many
+    /// milliseconds, rather than looking it up for every request. 0 disables the cache
+    #[structopt(long, default_value = "500")]
+    pub funder_cache_ttl_ms: u64,
+    /// Resubmit the funder's transactions the node lost once this many seconds passed since
+    /// their submission, and abandon expired ones. 0 leaves them alone
+    #[structopt(long, default_value = "30")]
+    pub stuck_transaction_secs: u64,
+    /// Transactions the funder may have submitted ahead of the on-chain sequence number. Mint
+    /// requests beyond that wait for earlier transactions to commit
+    #[structopt(long, default_value = "50")]
+    pub max_in_flight_transactions: u64,
+    /// Versions the ledger must be past a request's transactions before requests with
+    /// `wait_for=finalized` are answered
+    #[structopt(long, default_value = "10")]
+    pub finality_version_lag: u64,
+    /// Address of the Aptos Names Service contract, letting mint requests name their receiver
+    /// like `name=alice.apt`
+    #[structopt(long, parse(try_from_str = AccountAddress::from_hex_literal))]
+    pub ans_contract_address: Option<AccountAddress>,
+    /// Append every funding decision to this JSONL file, rotated at 100MiB
+    #[structopt(long)]
+    pub audit_log: Option<PathBuf>,
+    /// Start even if the node is on a different chain than --chain-id, logging an error
+    /// instead of refusing
+    #[structopt(long)]
+    pub allow_chain_id_mismatch: bool,
+    /// Before serving, wait until the node's ledger timestamp is within this many seconds of
+    /// the wall clock, so that a syncing node does not expire every transaction
+    #[structopt(long)]
+    pub max_sync_lag_secs: Option<u64>,
+    /// Fund a throwaway account at startup to catch a misconfigured faucet before users do.
+    /// GET /ready fails until it succeeds
+    #[structopt(long)]
+    pub self_test: bool,
+    /// Send Strict-Transport-Security with this max-age. Only set it when clients reach the
+    /// faucet over TLS
+    #[structopt(long)]
+    pub hsts_max_age_secs: Option<u64>,
+    /// Answer 5% of API requests with a 429 and 5% with a 500, and delay 10% by 2 seconds, for
+    /// testing clients' retries. The shares can be changed in a config file
+    #[structopt(long)]
+    pub chaos: bool,
 }
 
-#[tokio::main]
-async fn main() {
-    let args: Args = Args::from_args();
-    aptos_logger::Logger::new().init();
-
-    let address: std::net::SocketAddr = format!("{}:{}", args.address, args.port)
-        .parse()
-        .expect("invalid address or port number");
-
-    info!(
-        "[faucet]: chain id: {}, server url: {} . Limit: {:?}",
-        args.chain_id,
-        args.server_url.as_str(),
-        args.maximum_amount,
-    );
-
-    let key = if let Some(key) = args.mint_key {
-        key.private_key()
-    } else {
-        EncodingType::BCS
-            .load_key::<Ed25519PrivateKey>("mint key", Path::new(&args.mint_key_file_path))
-            .unwrap()
-    };
-
-    let faucet_address: AccountAddress =
-        args.mint_account_address.unwrap_or_else(aptos_root_address);
-    let faucet_account = LocalAccount::new(faucet_address, key, 0);
-
-    // Do not use maximum amount on delegation, this allows the new delegated faucet to
-    // mint a lot for themselves!
-    let maximum_amount = if args.do_not_delegate {
-        args.maximum_amount
-    } else {
-        None
-    };
-
-    let service = Arc::new(aptos_faucet::Service::new(
-        args.server_url.clone(),
-        args.chain_id,
-        faucet_account,
-        maximum_amount,
-    ));
-
-    let actual_service = if args.do_not_delegate {
-        service
-    } else {
-        aptos_faucet::delegate_mint_account(
-            service,
-            args.server_url,
-            args.chain_id,
-            args.maximum_amount,
-        )
-        .await
-    };
-
-    info!(
-        "[faucet]: running on: {}. Minting from {}",
-        address,
-        actual_service.faucet_account.lock().await.address()
-    );
-    warp::serve(aptos_faucet::routes(actual_service))
-        .run(address)
-        .await;
-}
+impl Args {
+    fn into_config(self) -> FaucetConfig {
+        let port = self.port;
+        let mut addresses = self.address.iter().map(|address| {
+            address
+                .parse::<SocketAddr>()
+                .or_else(|_| {
+                    address
+                        .parse::<IpAddr>()
+                        .map(|ip| SocketAddr::new(ip, port))
+                })
+                .expect("invalid address or port number")
+        });
 
-#[cfg(test)]
-mod tests {
-    use aptos::op::key::GenerateKey;
-    use aptos_crypto::{ed25519::Ed25519PublicKey, hash::HashValue, PrivateKey};
-    use aptos_faucet::{routes, Service};
-    use aptos_infallible::RwLock;
-    use aptos_rest_client::{
-        aptos_api_types::{
-            AccountData, DirectWriteSet, LedgerInfo, PendingTransaction, Response,
-            TransactionPayload as TransactionPayloadData, WriteSet, WriteSetPayload,
-        },
-        FaucetClient,
-    };
-    use aptos_sdk::{
-        transaction_builder::aptos_stdlib::ScriptFunctionCall,
-        types::{
-            account_address::AccountAddress,
-            chain_id::ChainId,
-            transaction::{
-                authenticator::AuthenticationKey, SignedTransaction, Transaction,
-                TransactionPayload::Script,
+        FaucetConfig {
+            address: addresses.next().expect("at least one address"),
+            additional_addresses: addresses.collect(),
+            server_url: self.server_url,
+            chain_id: self.chain_id,
+            mint_key: self.mint_key,
+            mint_key_file_path: PathBuf::from(self.mint_key_file_path),
+            mint_account_address: self.mint_account_address,
+            maximum_amount: self.maximum_amount,
+            daily_maximum_amount: self.daily_maximum_amount,
+            default_amount: self.default_amount,
+            minimum_amount: self.minimum_amount,
+            do_not_delegate: self.do_not_delegate,
+            delegate_endowment: self.delegate_endowment,
+            delegate_top_up_below: self.delegate_top_up_below,
+            delegate_state_file: self.delegate_state_file,
+            delegate_key_rotation_days: self.delegate_key_rotation_days,
+            funding_mode: self.funding_mode,
+            dry_run: self.dry_run,
+            mock_chain: self.mock_chain,
+            spend_budget: self.spend_budget,
+            spend_budget_window_secs: self.spend_budget_window_secs,
+            ip_rate_limit: IpRateLimitConfig {
+                per_ip: self.max_requests_per_ip,
+                per_ipv4_24: self.max_requests_per_ipv4_24,
+                per_ipv6_64: self.max_requests_per_ipv6_64,
+                window_secs: self.ip_rate_limit_window_secs,
             },
-            LocalAccount,
-        },
-    };
-    use serde::Serialize;
-    use std::{
-        collections::HashMap,
-        convert::{Infallible, TryFrom, TryInto},
-        sync::{Arc, Mutex},
-    };
-    use tokio::task::yield_now;
-    use warp::{
-        body::BodyDeserializeError,
-        cors::CorsForbidden,
-        http::{header, HeaderValue, StatusCode},
-        reject::{LengthRequired, MethodNotAllowed, PayloadTooLarge, UnsupportedMediaType},
-        reply, Filter, Rejection, Reply,
-    };
-
-    type AccountStates = Arc<RwLock<HashMap<AccountAddress, AccountState>>>;
-    #[derive(Clone, Debug, Eq, PartialEq, Hash)]
-    struct AccountState {
-        pub authentication_key: AuthenticationKey,
-        pub balance: u64,
-        pub sequence_number: u64,
-    }
-
-    impl AccountState {
-        pub fn new(balance: u64) -> Self {
-            Self {
-                authentication_key: AuthenticationKey::new([1; 32]),
-                balance,
-                sequence_number: 0,
-            }
+            cooldown: self.cooldown_secs.map(CooldownConfig::new),
+            trust_x_forwarded_for: self.trust_x_forwarded_for,
+            path_prefix: self.path_prefix,
+            // Only configurable from a config file
+            geo: None,
+            ip_lists: Vec::new(),
+            reputation: None,
+            balance_limit: None,
+            decay: None,
+            signature_challenge: self.signature_challenge,
+            challenge_ttl_secs: self.challenge_ttl_secs,
+            github: None,
+            sybil: None,
+            // Only configurable from a config file
+            bans: None,
+            leader_election: None,
+            sharding: None,
+            sequence_leases: None,
+            admin_token: self.admin_token,
+            admin_token_file: self.admin_token_file,
+            partners: None,
+            max_concurrent_mints: self.max_concurrent_mints,
+            max_queue_wait_ms: self.max_queue_wait_ms,
+            funder_cache_ttl_ms: self.funder_cache_ttl_ms,
+            stuck_transaction_secs: self.stuck_transaction_secs,
+            max_in_flight_transactions: self.max_in_flight_transactions,
+            finality_version_lag: self.finality_version_lag,
+            ans_contract_address: self.ans_contract_address,
+            audit_log: self.audit_log.map(AuditLogConfig::new),
+            // Only configurable from a config file
+            kafka: None,
+            nats: None,
+            allow_chain_id_mismatch: self.allow_chain_id_mismatch,
+            max_sync_lag_secs: self.max_sync_lag_secs,
+            self_test: self.self_test,
+            security_headers: SecurityHeadersConfig {
+                hsts_max_age_secs: self.hsts_max_age_secs,
+                ..SecurityHeadersConfig::default()
+            },
+            // Only configurable from a config file
+            request_limits: RequestLimitsConfig::default(),
+            connection_limits: ConnectionLimitsConfig::default(),
+            // Only configurable from a config file
+            tls: None,
+            log_sampling: Default::default(),
+            chaos: self.chaos.then(ChaosConfig::default),
+            // Only configurable from a config file
+            standing_orders: Vec::new(),
         }
     }
+}
 
-    fn setup(maximum_amount: Option<u64>) -> (AccountStates, Arc<Service>) {
-        let key = GenerateKey::generate_ed25519_in_memory();
-        let account_address = AuthenticationKey::ed25519(&key.public_key()).derived_address();
-
-        let faucet_account = LocalAccount::new(account_address, key, 0);
-
-        let chain_id = ChainId::test();
-
-        let accounts = AccountStates::new(aptos_infallible::RwLock::new(HashMap::new()));
-        accounts
-            .write()
-            .insert(account_address, AccountState::new(0));
-
-        let last_txn = Arc::new(Mutex::new(None));
-        let last_txn_0 = last_txn.clone();
-
-        let accounts_cloned_0 = accounts.clone();
-        let accounts_cloned_1 = accounts.clone();
-        let stub = warp::path!("accounts" / String)
-            .and(warp::any().map(move || accounts_cloned_0.clone()))
-            .and_then(handle_get_account)
-            .or(warp::path!("transactions" / String)
-                .and(warp::get())
-                .and(warp::any().map(move || last_txn_0.clone()))
-                .and_then(handle_get_transaction))
-            .or(warp::path!("transactions")
-                .and(warp::post())
-                .and(warp::body::bytes())
-                .and(warp::any().map(move || (accounts_cloned_1.clone(), last_txn.clone())))
-                .and_then(handle_submit_transaction))
-            .with(
-                warp::cors()
-                    .allow_any_origin()
-                    .allow_methods(vec!["POST", "GET"])
-                    .allow_headers(vec![header::CONTENT_TYPE]),
-            )
-            .recover(handle_rejection);
-        let (address, future) = warp::serve(stub).bind_ephemeral(([127, 0, 0, 1], 0));
-        tokio::task::spawn(async move { future.await });
-
-        let service = Service::new(
-            format!("http://localhost:{}/", address.port()),
-            chain_id,
-            faucet_account,
-            maximum_amount,
-        );
-        (accounts, Arc::new(service))
-    }
-
-    async fn handle_get_account(
-        address: String,
-        accounts: AccountStates,
-    ) -> Result<impl Reply, Rejection> {
-        let reader = accounts.read();
-        let account = match AccountAddress::try_from(address.clone())
-            .or_else(|_e| AccountAddress::from_hex(address.clone()))
-        {
-            Ok(addr) => reader.get(&addr),
-            _ => None,
-        };
-        if let Some(account) = account {
-            let auth_vec: Vec<u8> = account.authentication_key.as_ref().into();
-            let account_data = AccountData {
-                authentication_key: auth_vec.into(),
-                sequence_number: account.sequence_number.into(),
-            };
-            Ok(response(&account_data))
-        } else {
-            Err(warp::reject())
+fn main() {
+    let command = Command::from_args();
+    // Built by hand so that small containers can run with fewer threads than the defaults
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Command::Run(run_args) = &command {
+        if let Some(worker_threads) = run_args.worker_threads {
+            runtime.worker_threads(worker_threads.get());
+        }
+        if let Some(max_blocking_threads) = run_args.max_blocking_threads {
+            runtime.max_blocking_threads(max_blocking_threads.get());
         }
     }
+    runtime
+        .build()
+        .expect("failed to start the runtime")
+        .block_on(run_command(command));
+}
 
-    async fn handle_get_transaction(
-        _hash: String,
-        last_txn: Arc<Mutex<Option<Transaction>>>,
-    ) -> Result<impl Reply, Rejection> {
-        last_txn.lock().unwrap().as_ref().map_or_else(
-            || Err(warp::reject()),
-            |txn| {
-                let info = aptos_rest_client::aptos_api_types::TransactionInfo {
-                    version: 0.into(),
-                    hash: HashValue::zero().into(),
-                    state_root_hash: HashValue::zero().into(),
-                    event_root_hash: HashValue::zero().into(),
-                    gas_used: 0.into(),
-                    success: true,
-                    vm_status: "Executed".to_string(),
-                    accumulator_root_hash: HashValue::zero().into(),
-                    changes: vec![],
-                };
-                let serializable_txn: aptos_rest_client::aptos_api_types::Transaction = (
-                    txn.as_signed_user_txn().unwrap(),
-                    info,
-                    dummy_payload(),
-                    Vec::new(),
-                    0,
-                )
-                    .into();
-
-                Ok(response(&serializable_txn))
-            },
-        )
+async fn run_command(command: Command) {
+    // Span export has to be set up before the logger, which otherwise claims tracing for itself
+    let exporting_spans =
+        aptos_faucet::otlp::init_from_env().expect("failed to set up span export");
+    let mut logger = aptos_logger::Logger::new();
+    if let Command::Run(RunArgs {
+        log_format: LogFormat::Json,
+        ..
+    }) = &command
+    {
+        logger.custom_format(json_format);
     }
+    let logger = logger.build();
+    if exporting_spans {
+        info!("[faucet]: exporting spans over OTLP");
+    }
+    #[cfg(feature = "tokio-console")]
+    info!("[faucet]: serving tasks to tokio-console");
 
-    async fn handle_submit_transaction(
-        txn: bytes::Bytes,
-        (accounts, last_txn): (AccountStates, Arc<Mutex<Option<Transaction>>>),
-    ) -> Result<impl Reply, Rejection> {
-        let txn: SignedTransaction = bcs::from_bytes(&txn).unwrap();
-        assert_eq!(txn.chain_id(), ChainId::test());
-
-        if let Script(script) = txn.payload() {
-            panic!("unexpected type of script: {:?}", script.args())
+    match command {
+        Command::Run(run_args) => {
+            let config = match run_args.config {
+                Some(path) => FaucetConfig::load(&path).unwrap(),
+                None => run_args.args.into_config(),
+            };
+            if run_args.check {
+                check(config).await
+            } else {
+                run(config, logger).await
+            }
+        }
+        Command::GenerateConfig(generate_args) => {
+            let yaml = generate_args
+                .args
+                .into_config()
+                .to_commented_yaml()
+                .unwrap();
+            match generate_args.output {
+                Some(path) => std::fs::write(&path, yaml).expect("failed to write config"),
+                None => print!("{}", yaml),
+            }
         }
-        if let Some(script_function) = ScriptFunctionCall::decode(txn.payload()) {
-            match script_function {
-                ScriptFunctionCall::AccountCreateAccount {
-                    auth_key: address, ..
-                } => {
-                    let mut writer = accounts.write();
-                    let previous = writer.insert(address, AccountState::new(0));
-                    assert!(previous.is_none(), "should not create account twice");
+        Command::ValidateConfig(validate_args) => {
+            let config = FaucetConfig::load(&validate_args.config).unwrap();
+            match config.validate().await {
+                Ok(()) => println!("Config {:?} is valid", validate_args.config),
+                Err(err) => {
+                    eprintln!("Config {:?} is invalid: {}", validate_args.config, err);
+                    std::process::exit(1);
                 }
-                ScriptFunctionCall::TestCoinMint {
-                    dst_addr, amount, ..
-                } => {
-                    // Sometimes we call CreateAccount and Mint at the same time (from our tests: this is a test method)
-                    // If the account doesn't exist yet, we sleep for 100ms to let the other request finish
-                    if accounts.write().get_mut(&dst_addr).is_none() {
-                        yield_now().await;
-                    }
-                    let mut writer = accounts.write();
-                    let account = writer
-                        .get_mut(&dst_addr)
-                        .expect("account should be created");
-                    account.balance += amount;
+            }
+        }
+        Command::Bench(bench_args) => {
+            let config = BenchConfig {
+                target: bench_args.target,
+                rps: bench_args.rps,
+                duration: Duration::from_secs(bench_args.duration),
+                amount: bench_args.amount,
+                timeout: Duration::from_millis(bench_args.timeout_ms),
+            };
+            match aptos_faucet::bench::run(config).await {
+                Ok(report) => println!("{}", report),
+                Err(err) => {
+                    eprintln!("Benchmark failed: {}", err);
+                    std::process::exit(1);
                 }
-                script => panic!("unexpected type of script function: {:?}", script),
             }
         }
-
-        let pending_txn = PendingTransaction {
-            hash: HashValue::zero().into(),
-            request: (&txn, dummy_payload()).into(),
-        };
-
-        *last_txn.lock().unwrap() = Some(Transaction::UserTransaction(txn));
-        Ok(response(&pending_txn))
     }
+}
 
-    fn response<T: Serialize>(body: &T) -> warp::reply::Response {
-        let li = LedgerInfo {
-            chain_id: ChainId::test().id(),
-            epoch: 1,
-            ledger_version: 5.into(),
-            ledger_timestamp: 5.into(),
-        };
-        Response::new(li, body).unwrap().into_response()
+async fn check(config: FaucetConfig) {
+    match aptos_faucet::preflight::preflight(&config).await {
+        Ok(()) => println!("All startup checks passed"),
+        Err(err) => {
+            eprintln!("Startup check failed: {}", err);
+            std::process::exit(err.exit_code());
+        }
     }
+}
 
-    fn dummy_payload() -> TransactionPayloadData {
-        TransactionPayloadData::WriteSetPayload(WriteSetPayload {
-            write_set: WriteSet::DirectWriteSet(DirectWriteSet {
-                changes: Vec::new(),
-                events: Vec::new(),
-            }),
-        })
-    }
+async fn run(config: FaucetConfig, logger: Arc<aptos_logger::Logger>) {
+    let (_address, shutdown_handle) = aptos_faucet::run_with_logger(config, Some(logger))
+        .await
+        .expect("failed to start faucet");
 
-    #[derive(Clone, Debug, Serialize, PartialEq)]
-    pub struct Error {
-        pub code: u16,
-        pub message: String,
-    }
+    shutdown_signal().await;
+    info!("[faucet]: shutting down");
+    shutdown_handle.shutdown().await.unwrap();
+}
 
-    impl Error {
-        fn new(code: StatusCode, message: String) -> Error {
-            Error {
-                code: code.as_u16(),
-                message,
-            }
-        }
+/// Waits for Ctrl-C, or for the SIGTERM deploys stop the faucet with, so that requests already
+/// accepted, including those waiting for a processing slot, are answered before exiting
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
 
-        fn status_code(&self) -> StatusCode {
-            StatusCode::from_u16(self.code).unwrap()
+        let mut terminate =
+            signal(SignalKind::terminate()).expect("failed to listen for shutdown signal");
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                result.expect("failed to listen for shutdown signal")
+            }
+            _ = terminate.recv() => (),
         }
     }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c()
+        .await
+        .expect("failed to listen for shutdown signal");
+}
 
-    async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
-        let code;
-        let body;
-
-        if err.is_not_found() {
-            code = StatusCode::NOT_FOUND;
-            body = reply::json(&Error::new(code, "Not Found".to_owned()));
-        } else if let Some(error) = err.find::<Error>() {
-            code = error.status_code();
-            body = reply::json(error);
-        } else if let Some(cause) = err.find::<CorsForbidden>() {
-            code = StatusCode::FORBIDDEN;
-            body = reply::json(&Error::new(code, cause.to_string()));
-        } else if let Some(cause) = err.find::<BodyDeserializeError>() {
-            code = StatusCode::BAD_REQUEST;
-            body = reply::json(&Error::new(code, cause.to_string()));
-        } else if let Some(cause) = err.find::<LengthRequired>() {
-            code = StatusCode::LENGTH_REQUIRED;
-            body = reply::json(&Error::new(code, cause.to_string()));
-        } else if let Some(cause) = err.find::<PayloadTooLarge>() {
-            code = StatusCode::PAYLOAD_TOO_LARGE;
-            body = reply::json(&Error::new(code, cause.to_string()));
-        } else if let Some(cause) = err.find::<UnsupportedMediaType>() {
-            code = StatusCode::UNSUPPORTED_MEDIA_TYPE;
-            body = reply::json(&Error::new(code, cause.to_string()));
-        } else if let Some(cause) = err.find::<MethodNotAllowed>() {
-            code = StatusCode::METHOD_NOT_ALLOWED;
-            body = reply::json(&Error::new(code, cause.to_string()));
-        } else {
-            code = StatusCode::INTERNAL_SERVER_ERROR;
-            body = reply::json(&Error::new(code, format!("unexpected error: {:?}", err)));
-        }
-        let mut rep = reply::with_status(body, code).into_response();
-        rep.headers_mut()
-            .insert("access-control-allow-origin", HeaderValue::from_static("*"));
-        Ok(rep)
-    }
+#[cfg(test)]
+mod tests {
+    use aptos::op::key::GenerateKey;
+    use aptos_config::keys::ConfigKey;
+    use aptos_crypto::{
+        ed25519::{Ed25519PrivateKey, Ed25519PublicKey},
+        PrivateKey, SigningKey, Uniform,
+    };
+    use aptos_faucet::{
+        admin::{Inflight, LogLevel, Redelegated},
+        audit::{AuditEntry, AuditLog, AuditLogConfig, Decision},
+        bans::{Ban, BanConfig},
+        build_info::BuildInfo,
+        bulk::{BulkReport, BulkStatus},
+        challenge::Challenge,
+        chaos::{ChaosConfig, INJECTED_HEADER},
+        checkers::{Finding, SybilConfig},
+        cooldown::CooldownConfig,
+        coupon::IssuedCoupons,
+        delegate_mint_account,
+        delegate_state::DelegateState,
+        error::InvalidField,
+        fund::{FundError, FundResponse as TapFundResponse},
+        funder::FundingMode,
+        ip_limit::IpRateLimitConfig,
+        leader::LeaderElectionConfig,
+        mint::{AmountTooSmall, InvalidParams, Network, UnknownChain},
+        mint_queue::QueueStats,
+        mock_chain::{MockAccount, MockName},
+        partner::{self, PartnerConfig, PartnerUsage},
+        quota::Quota,
+        rejection::RejectionBody,
+        request_limits::RequestLimitsConfig,
+        request_log::{REQUEST_ID_HEADER, TRACEPARENT_HEADER},
+        routes, run,
+        security_headers::SecurityHeadersConfig,
+        self_test::SelfTestStatus,
+        sharding::ShardingConfig,
+        test_utils::{setup_fullnode, setup_service, setup_service_with, InjectedFailures},
+        Endowment, FaucetConfig, Health, Readiness,
+    };
+    use aptos_rest_client::{
+        CreatedAccount, FaucetCapabilities, FaucetClient, FaucetClientError, FundResponse,
+    };
+    use aptos_sdk::types::{
+        account_address::AccountAddress,
+        chain_id::{ChainId, NamedChain},
+        transaction::{authenticator::AuthenticationKey, SignedTransaction},
+    };
+    use std::{
+        collections::{BTreeMap, HashMap},
+        convert::{TryFrom, TryInto},
+        net::IpAddr,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+    use url::Url;
+    use warp::{
+        http::{Method, StatusCode},
+        Filter,
+    };
 
     #[tokio::test]
     async fn test_mint_auth_key() {
-        let (accounts, service) = setup(None);
+        let (accounts, service) = setup_service(None);
         let filter = routes(service);
         let auth_key = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
         let amount = 13345;
@@ -415,20 +593,20 @@ mod tests {
             .path(format!("/mint?auth_key={}&amount={}", auth_key, amount).as_str())
             .reply(&filter)
             .await;
-        let values: Vec<HashValue> = serde_json::from_slice(resp.body()).unwrap();
-        assert_eq!(values.len(), 2);
-        let reader = accounts.read();
+        let response: FundResponse = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(response.txn_hashes.len(), 2);
+        assert_eq!(response.amount, amount);
         let addr = AccountAddress::try_from(
             "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d".to_owned(),
         )
         .unwrap();
-        let account = reader.get(&addr).expect("account should be created");
+        let account = accounts.account(addr).expect("account should be created");
         assert_eq!(account.balance, amount);
     }
 
     #[tokio::test]
     async fn test_mint_pub_key() {
-        let (accounts, service) = setup(None);
+        let (accounts, service) = setup_service(None);
         let filter = routes(service);
 
         let pub_key = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
@@ -438,20 +616,20 @@ mod tests {
             .path(format!("/mint?pub_key={}&amount={}", pub_key, amount).as_str())
             .reply(&filter)
             .await;
-        let values: Vec<HashValue> = serde_json::from_slice(resp.body()).unwrap();
-        assert_eq!(values.len(), 2);
-        let reader = accounts.read();
+        let response: FundResponse = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(response.txn_hashes.len(), 2);
+        assert_eq!(response.amount, amount);
         let addr = AccountAddress::try_from(
             "9FF98E82355EB13098F3B1157AC018A725C62C0E0820F422000814CDBA407835".to_owned(),
         )
         .unwrap();
-        let account = reader.get(&addr).expect("account should be created");
+        let account = accounts.account(addr).expect("account should be created");
         assert_eq!(account.balance, amount);
     }
 
     #[tokio::test]
     async fn test_mint_address() {
-        let (accounts, service) = setup(None);
+        let (accounts, service) = setup_service(None);
         let filter = routes(service);
 
         let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
@@ -462,20 +640,20 @@ mod tests {
             .reply(&filter)
             .await;
 
-        let values: Vec<HashValue> = serde_json::from_slice(resp.body()).unwrap();
-        assert_eq!(values.len(), 2);
-        let reader = accounts.read();
+        let response: FundResponse = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(response.txn_hashes.len(), 2);
+        assert_eq!(response.amount, amount);
         let addr = AccountAddress::try_from(
             "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d".to_owned(),
         )
         .unwrap();
-        let account = reader.get(&addr).expect("account should be created");
+        let account = accounts.account(addr).expect("account should be created");
         assert_eq!(account.balance, amount);
     }
 
     #[tokio::test]
     async fn test_mint_address_hex() {
-        let (accounts, service) = setup(None);
+        let (accounts, service) = setup_service(None);
         let filter = routes(service);
 
         let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
@@ -486,20 +664,20 @@ mod tests {
             .reply(&filter)
             .await;
 
-        let values: Vec<HashValue> = serde_json::from_slice(resp.body()).unwrap();
-        assert_eq!(values.len(), 2);
-        let reader = accounts.read();
+        let response: FundResponse = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(response.txn_hashes.len(), 2);
+        assert_eq!(response.amount, amount);
         let addr = AccountAddress::try_from(
             "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d".to_owned(),
         )
         .unwrap();
-        let account = reader.get(&addr).expect("account should be created");
+        let account = accounts.account(addr).expect("account should be created");
         assert_eq!(account.balance, amount);
     }
 
     #[tokio::test]
     async fn test_mint_with_txns_response() {
-        let (accounts, service) = setup(None);
+        let (accounts, service) = setup_service(None);
         let filter = routes(service);
 
         let auth_key = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
@@ -520,119 +698,2529 @@ mod tests {
         let txns: Vec<SignedTransaction> = bcs::from_bytes(&bytes).expect("valid bcs vec");
         assert_eq!(txns.len(), 2);
 
-        let reader = accounts.read();
         let addr = AccountAddress::try_from(
             "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d".to_owned(),
         )
         .unwrap();
-        let account = reader.get(&addr).expect("account should be created");
+        let account = accounts.account(addr).expect("account should be created");
         assert_eq!(account.balance, amount);
     }
 
     #[tokio::test]
     async fn test_health() {
-        let (_accounts, service) = setup(None);
+        let (_accounts, service) = setup_service(None);
+
+        let filter = routes(service.clone());
 
         let resp = warp::test::request()
             .method("GET")
             .path("/health")
-            .reply(&routes(service))
+            .reply(&filter)
             .await;
+        assert_eq!(resp.status(), 200);
+        let health: Health = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(health.version, env!("CARGO_PKG_VERSION"));
+        assert!(!health.git_commit.is_empty());
+        assert_eq!(health.chain.chain_id, ChainId::test().id());
+        assert_eq!(
+            health.funder_address,
+            service.faucet_account.lock().await.address()
+        );
+        assert_eq!(health.sequence_number, Some(0));
+        assert_eq!(health.capabilities, service.capabilities());
+        assert!(health.capabilities.contains(&"json_responses".to_string()));
 
+        // Older probes ask for the plain text sequence number
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/health")
+            .header("accept", "text/plain")
+            .reply(&filter)
+            .await;
         assert_eq!(resp.status(), 200);
         assert_eq!(resp.body(), std::string::ToString::to_string(&0).as_str());
     }
 
     #[tokio::test]
-    async fn test_mint_invalid_auth_key() {
-        let (_accounts, service) = setup(None);
+    async fn test_version() {
+        let (_accounts, service) = setup_service(None);
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/version")
+            .reply(&routes(service))
+            .await;
+        assert_eq!(resp.status(), 200);
+        let build: BuildInfo = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(build.version, env!("CARGO_PKG_VERSION"));
+        assert!(!build.git_sha.is_empty());
+        assert!(build.build_timestamp.is_some());
+        let profile = if cfg!(debug_assertions) {
+            "debug"
+        } else {
+            "release"
+        };
+        assert_eq!(build.profile, profile);
+    }
+
+    #[tokio::test]
+    async fn test_request_ids() {
+        let (_accounts, service) = setup_service(None);
         let filter = routes(service);
 
-        let auth_key = "invalid-auth-key";
+        let request_id = || async {
+            let resp = warp::test::request()
+                .method("GET")
+                .path("/health")
+                .reply(&filter)
+                .await;
+            resp.headers()[REQUEST_ID_HEADER]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        assert_ne!(request_id().await, request_id().await);
+
+        // Ids given by a gateway are kept, and echoed
+        let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/health")
+            .header(REQUEST_ID_HEADER, "gateway-123")
+            .header(TRACEPARENT_HEADER, traceparent)
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.headers()[REQUEST_ID_HEADER], "gateway-123");
+        assert_eq!(resp.headers()[TRACEPARENT_HEADER], traceparent);
+
+        // Invalid trace contexts are dropped rather than passed on
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/health")
+            .header(TRACEPARENT_HEADER, "not-a-trace-context")
+            .reply(&filter)
+            .await;
+        assert!(resp.headers().get(TRACEPARENT_HEADER).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mint_coin_amount() {
+        let (accounts, service) = setup_service(None);
+        let filter = routes(service);
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+
         let resp = warp::test::request()
             .method("POST")
-            .path(format!("/mint?auth_key={}&amount=1000000", auth_key).as_str())
+            .path(format!("/mint?address={}&amount=1.5APT", address).as_str())
+            .reply(&filter)
+            .await;
+        let response: FundResponse = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(response.amount, 150_000_000);
+        let addr = AccountAddress::try_from(address.to_owned()).unwrap();
+        assert_eq!(accounts.account(addr).unwrap().balance, 150_000_000);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path(format!("/mint?address={}&amount=0.000000001APT", address).as_str())
             .reply(&filter)
             .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: InvalidParams = serde_json::from_slice(resp.body()).unwrap();
         assert_eq!(
-            resp.body(),
-            "You must provide 'address' (preferred), 'pub_key', or 'auth_key'"
+            body.message,
+            "'amount' has more than the coin's 8 decimal places"
         );
+        assert_eq!(body.errors[0].field, "amount");
     }
 
     #[tokio::test]
-    async fn test_mint_fullnode_error() {
-        let (accounts, service) = setup(None);
-        let address = service.faucet_account.lock().await.address();
-        accounts.write().remove(&address);
+    async fn test_mint_default_and_minimum_amount() {
+        let (accounts, service) =
+            setup_service_with(|builder| builder.default_amount(500).minimum_amount(100));
         let filter = routes(service);
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
 
-        let auth_key = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
         let resp = warp::test::request()
             .method("POST")
-            .path(format!("/mint?auth_key={}&amount=1000000", auth_key).as_str())
+            .path(format!("/mint?address={}", address).as_str())
             .reply(&filter)
             .await;
+        let response: FundResponse = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(response.amount, 500);
+        let addr = AccountAddress::try_from(address.to_owned()).unwrap();
+        assert_eq!(accounts.account(addr).unwrap().balance, 500);
 
-        assert_eq!(
-            resp.body(),
-            &format!("faucet account {:?} not found", address)
-        );
+        // SDKs create accounts by minting nothing
+        let resp = warp::test::request()
+            .method("POST")
+            .path(format!("/mint?address={}&amount=0", address).as_str())
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        for amount in ["1", "99"] {
+            let resp = warp::test::request()
+                .method("POST")
+                .path(format!("/mint?address={}&amount={}", address, amount).as_str())
+                .reply(&filter)
+                .await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            let error: AmountTooSmall = serde_json::from_slice(resp.body()).unwrap();
+            assert_eq!(error.error, "amount_too_small");
+            assert_eq!(error.minimum_amount, 100);
+        }
     }
 
     #[tokio::test]
-    async fn create_account_with_client() {
-        let (_accounts, service) = setup(None);
-        let endpoint = service.endpoint().to_owned();
-        let (address, future) = warp::serve(routes(service)).bind_ephemeral(([127, 0, 0, 1], 0));
-        tokio::task::spawn(async move { future.await });
+    async fn test_mint_top_up() {
+        let (accounts, service) = setup_service(None);
+        let filter = routes(service);
+        let filter = &filter;
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let addr = AccountAddress::try_from(address.to_owned()).unwrap();
+        let mint = move |query: &str| {
+            warp::test::request()
+                .method("POST")
+                .path(format!("/mint?address={}&{}", address, query).as_str())
+                .reply(filter)
+        };
 
-        let faucet_client = FaucetClient::new(format!("http://{}", address), endpoint);
+        let resp = mint("amount=100").await;
+        assert_eq!(resp.status(), StatusCode::OK);
 
-        let pub_key: Ed25519PublicKey =
-            hex::decode(&"459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d")
-                .unwrap()
-                .as_slice()
-                .try_into()
-                .unwrap();
-        let address = AuthenticationKey::ed25519(&pub_key).derived_address();
-        assert_eq!(
-            address.to_string(),
-            "9FF98E82355EB13098F3B1157AC018A725C62C0E0820F422000814CDBA407835"
-        );
+        for expected in [150, 0] {
+            let resp = mint("top_up_to=250").await;
+            let response: FundResponse = serde_json::from_slice(resp.body()).unwrap();
+            assert_eq!(response.amount, expected);
+            assert_eq!(accounts.account(addr).unwrap().balance, 250);
+        }
 
-        let res = tokio::task::spawn_blocking(move || faucet_client.create_account(address))
-            .await
-            .unwrap();
-        res.unwrap();
+        let resp = mint("amount=10&top_up_to=250").await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // A balance that cannot be read is not taken to be 0
+        accounts.set_failures(InjectedFailures {
+            reject_balance_reads: Some(StatusCode::SERVICE_UNAVAILABLE),
+            ..InjectedFailures::default()
+        });
+        let resp = mint("top_up_to=500").await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(accounts.account(addr).unwrap().balance, 250);
     }
 
     #[tokio::test]
-    async fn fund_account_with_client() {
-        let (_accounts, service) = setup(None);
-        let endpoint = service.endpoint().to_owned();
-        let (address, future) = warp::serve(routes(service)).bind_ephemeral(([127, 0, 0, 1], 0));
-        tokio::task::spawn(async move { future.await });
+    async fn test_mint_wait() {
+        let (accounts, service) = setup_service(None);
+        let filter = routes(service);
+        let filter = &filter;
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let mint = move |query: &str| {
+            let path = format!("/mint?address={}&amount=100&{}", address, query);
+            async move {
+                let resp = warp::test::request()
+                    .method("POST")
+                    .path(path.as_str())
+                    .reply(filter)
+                    .await;
+                assert_eq!(resp.status(), StatusCode::OK);
+                serde_json::from_slice::<FundResponse>(resp.body()).unwrap()
+            }
+        };
 
-        let faucet_client = FaucetClient::new(format!("http://{}", address), endpoint);
+        let response = mint("").await;
+        assert_eq!(response.balance, None);
+        assert!(response.txns.is_empty());
 
-        let pub_key: Ed25519PublicKey =
-            hex::decode(&"459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d")
-                .unwrap()
-                .as_slice()
-                .try_into()
-                .unwrap();
-        let address = AuthenticationKey::ed25519(&pub_key).derived_address();
-        let (res1, res2) = tokio::task::spawn_blocking(move || {
-            (
-                faucet_client.create_account(address),
-                faucet_client.fund(address, 10),
-            )
-        })
-        .await
-        .unwrap();
+        let response = mint("wait=true").await;
+        assert_eq!(response.balance, Some(200));
+        assert_eq!(response.txns.len(), 1);
+        assert_eq!(response.txns[0].hash, response.txn_hashes[0]);
+        assert!(response.txns[0].success);
 
-        res1.unwrap();
-        res2.unwrap();
+        accounts.set_failures(InjectedFailures {
+            fail_transactions: true,
+            ..InjectedFailures::default()
+        });
+        let response = mint("wait=true").await;
+        assert_eq!(response.balance, Some(200));
+        assert!(!response.txns[0].success);
+
+        accounts.set_failures(InjectedFailures {
+            reject_balance_reads: Some(StatusCode::SERVICE_UNAVAILABLE),
+            ..InjectedFailures::default()
+        });
+        let response = mint("wait=true").await;
+        assert_eq!(response.balance, None);
+        assert!(response.txns[0].success);
+    }
+
+    #[tokio::test]
+    async fn test_mint_wait_for() {
+        let (_accounts, service) = setup_service_with(|builder| builder.finality_version_lag(2));
+        let filter = routes(service);
+        let filter = &filter;
+        let mint = move |address: &str, query: &str| {
+            let path = format!("/mint?address={}&amount=100&{}", address, query);
+            async move {
+                let resp = warp::test::request()
+                    .method("POST")
+                    .path(path.as_str())
+                    .reply(filter)
+                    .await;
+                assert_eq!(resp.status(), StatusCode::OK);
+                serde_json::from_slice::<FundResponse>(resp.body()).unwrap()
+            }
+        };
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let other = "c0ffee00c0ffee00c0ffee00c0ffee00c0ffee00c0ffee00c0ffee00c0ffee00";
+
+        // wait_for takes precedence over wait
+        let response = mint(address, "wait=true&wait_for=submitted").await;
+        assert!(response.txns.is_empty());
+        let response = mint(address, "wait_for=executed").await;
+        assert_eq!(response.balance, Some(200));
+
+        // Only answered once the other request moved the ledger 2 versions further
+        let (finalized, _) = tokio::join!(mint(address, "wait_for=finalized"), async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            mint(other, "").await
+        });
+        assert_eq!(finalized.balance, Some(300));
+        assert_eq!(finalized.txns.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_mint_name() {
+        let contract = AccountAddress::random();
+        let (accounts, service) = setup_service_with(|builder| builder.ans_contract(contract));
+        let filter = routes(service);
+        let filter = &filter;
+        let receiver = AccountAddress::random();
+        let now_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        accounts.register_name(
+            "alice".parse().unwrap(),
+            MockName {
+                target_address: Some(receiver),
+                expiration_time_sec: now_secs + 3600,
+            },
+        );
+        accounts.register_name(
+            "bob".parse().unwrap(),
+            MockName {
+                target_address: Some(receiver),
+                expiration_time_sec: now_secs - 1,
+            },
+        );
+
+        let mint = move |query: &str| {
+            let path = format!("/mint?amount=100&wait=true&{}", query);
+            async move {
+                warp::test::request()
+                    .method("POST")
+                    .path(path.as_str())
+                    .reply(filter)
+                    .await
+            }
+        };
+        let resp = mint("name=Alice.apt").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let response: FundResponse = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(response.balance, Some(100));
+        assert_eq!(accounts.account(receiver).unwrap().balance, 100);
+
+        let resp = mint("name=carol.apt").await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let body: RejectionBody = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body.error, "unresolved_name");
+        assert_eq!(body.message, "'carol.apt' is not registered");
+        let resp = mint("name=bob.apt").await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let body: RejectionBody = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body.message, "'bob.apt' has expired");
+        let resp = mint("name=not_a.name.apt").await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp = mint(&format!("name=alice.apt&address={}", receiver)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // Faucets without an ANS contract refuse names
+        let (_accounts, service) = setup_service(None);
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/mint?amount=100&name=alice.apt")
+            .reply(&routes(service))
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_mint_existing_account_created_concurrently() {
+        let (accounts, service) = setup_service(None);
+        let filter = routes(service);
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let receiver = AccountAddress::from_hex(address).unwrap();
+        // The node has not caught up with the account's creation, so the faucet creates it again
+        accounts.create_account(receiver, MockAccount::new(100));
+        accounts.set_failures(InjectedFailures {
+            hidden_accounts: vec![receiver],
+            ..InjectedFailures::default()
+        });
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path(format!("/mint?address={}&amount=100&wait=true", address).as_str())
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let response: FundResponse = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(response.balance, Some(200));
+        assert_eq!(response.txn_hashes.len(), 1);
+        assert!(response.txns[0].success);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path(format!("/accounts?address={}&wait=true", address).as_str())
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_mint_transfer_mode() {
+        let (accounts, service) =
+            setup_service_with(|builder| builder.funding_mode(FundingMode::Transfer));
+        let funder = service.faucet_account.lock().await.address();
+        accounts.create_account(funder, MockAccount::new(1_000));
+        let filter = routes(service);
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path(format!("/mint?address={}&amount=100&wait=true", address).as_str())
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let response: FundResponse = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(response.balance, Some(100));
+        assert!(response.txns.iter().all(|txn| txn.success));
+        // The coins come out of the funder's balance rather than being minted
+        assert_eq!(accounts.account(funder).unwrap().balance, 900);
+    }
+
+    #[tokio::test]
+    async fn test_delegated_account_top_up() {
+        let (accounts, service) = setup_service(None);
+        let endowment = Endowment {
+            amount: 1_000,
+            top_up_below: Some(500),
+        };
+        let service = delegate_mint_account(service, None, endowment, None)
+            .await
+            .unwrap();
+        let delegate = service.faucet_account.lock().await.address();
+        assert_eq!(accounts.account(delegate).unwrap().balance, 1_000);
+
+        assert_eq!(service.top_up_funder().await.unwrap(), 0);
+        let mut account = accounts.account(delegate).unwrap();
+        account.balance = 200;
+        accounts.create_account(delegate, account);
+        assert_eq!(service.top_up_funder().await.unwrap(), 800);
+        assert_eq!(accounts.account(delegate).unwrap().balance, 1_000);
+    }
+
+    #[tokio::test]
+    async fn test_delegated_account_reused() {
+        let (accounts, service) = setup_service(None);
+        let dir = tempfile::tempdir().unwrap();
+        let state_file = dir.path().join("delegate.json");
+        let endowment = Endowment {
+            amount: 1_000,
+            top_up_below: None,
+        };
+
+        let delegated = delegate_mint_account(service.clone(), None, endowment, Some(&state_file))
+            .await
+            .unwrap();
+        let delegate = delegated.faucet_account.lock().await.address();
+        assert!(state_file.exists());
+
+        // A restart mints from the saved account, without endowing another one
+        let restarted = delegate_mint_account(service.clone(), None, endowment, Some(&state_file))
+            .await
+            .unwrap();
+        assert_eq!(restarted.faucet_account.lock().await.address(), delegate);
+        assert_eq!(accounts.account(delegate).unwrap().balance, 1_000);
+
+        // A saved account missing from the chain, e.g. after a devnet reset, is replaced
+        accounts.remove_account(delegate);
+        let replaced = delegate_mint_account(service.clone(), None, endowment, Some(&state_file))
+            .await
+            .unwrap();
+        let replacement = replaced.faucet_account.lock().await.address();
+        assert_ne!(replacement, delegate);
+        let reloaded = delegate_mint_account(service.clone(), None, endowment, Some(&state_file))
+            .await
+            .unwrap();
+        assert_eq!(reloaded.faucet_account.lock().await.address(), replacement);
+
+        // So is one the saved key no longer controls, or without coins for gas
+        let mut account = accounts.account(replacement).unwrap();
+        account.authentication_key = AuthenticationKey::random();
+        accounts.create_account(replacement, account);
+        let rekeyed = delegate_mint_account(service.clone(), None, endowment, Some(&state_file))
+            .await
+            .unwrap();
+        let replacement_of_rekeyed = rekeyed.faucet_account.lock().await.address();
+        assert_ne!(replacement_of_rekeyed, replacement);
+        let replacement = replacement_of_rekeyed;
+        let mut account = accounts.account(replacement).unwrap();
+        account.balance = 0;
+        accounts.create_account(replacement, account);
+        let drained = delegate_mint_account(service.clone(), None, endowment, Some(&state_file))
+            .await
+            .unwrap();
+        let replacement_of_drained = drained.faucet_account.lock().await.address();
+        assert_ne!(replacement_of_drained, replacement);
+
+        // While the node is down, the saved account is kept rather than replaced
+        accounts.set_failures(InjectedFailures {
+            unavailable: Some(StatusCode::SERVICE_UNAVAILABLE),
+            ..InjectedFailures::default()
+        });
+        assert!(
+            delegate_mint_account(service, None, endowment, Some(&state_file))
+                .await
+                .is_err()
+        );
+        let saved = DelegateState::load(&state_file).unwrap().unwrap();
+        assert_eq!(saved.address, replacement_of_drained);
+    }
+
+    #[tokio::test]
+    async fn test_delegated_key_rotation() {
+        let (accounts, service) = setup_service(None);
+        let dir = tempfile::tempdir().unwrap();
+        let state_file = dir.path().join("delegate.json");
+        let endowment = Endowment {
+            amount: 1_000,
+            top_up_below: None,
+        };
+        let delegated = delegate_mint_account(service.clone(), None, endowment, Some(&state_file))
+            .await
+            .unwrap();
+        let delegate = delegated.faucet_account.lock().await.address();
+        let previous_key = accounts.account(delegate).unwrap().authentication_key;
+
+        delegated.rotate_funder_key().await.unwrap();
+        let rotated_key = accounts.account(delegate).unwrap().authentication_key;
+        assert_ne!(rotated_key, previous_key);
+        assert_eq!(delegated.faucet_account.lock().await.address(), delegate);
+        let saved = DelegateState::load(&state_file).unwrap().unwrap();
+        assert_eq!(saved.address, delegate);
+        assert_eq!(
+            AuthenticationKey::ed25519(&saved.key.public_key()),
+            rotated_key
+        );
+
+        // Minting goes on from the same account, and a restart picks up the new key
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let resp = warp::test::request()
+            .method("POST")
+            .path(format!("/mint?address={}&amount=100&wait=true", address).as_str())
+            .reply(&routes(delegated))
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let restarted = delegate_mint_account(service.clone(), None, endowment, Some(&state_file))
+            .await
+            .unwrap();
+        assert_eq!(restarted.faucet_account.lock().await.address(), delegate);
+
+        // A rotation that failed on chain keeps the previous key, in memory and on disk
+        accounts.set_failures(InjectedFailures {
+            fail_transactions: true,
+            ..InjectedFailures::default()
+        });
+        assert!(restarted.rotate_funder_key().await.is_err());
+        accounts.set_failures(InjectedFailures::default());
+        assert_eq!(
+            accounts.account(delegate).unwrap().authentication_key,
+            rotated_key
+        );
+        let saved = DelegateState::load(&state_file).unwrap().unwrap();
+        assert_eq!(
+            AuthenticationKey::ed25519(&saved.key.public_key()),
+            rotated_key
+        );
+        assert_eq!(saved.pending_key, None);
+        assert_eq!(
+            restarted.faucet_account.lock().await.authentication_key(),
+            rotated_key
+        );
+
+        // A restart in the middle of a rotation keeps whichever key the account has
+        let interrupted = DelegateState {
+            key: ConfigKey::new(Ed25519PrivateKey::generate(&mut rand::rngs::OsRng)),
+            pending_key: Some(saved.key),
+            ..saved
+        };
+        interrupted.save(&state_file).unwrap();
+        let restarted = delegate_mint_account(service, None, endowment, Some(&state_file))
+            .await
+            .unwrap();
+        assert_eq!(restarted.faucet_account.lock().await.address(), delegate);
+        let saved = DelegateState::load(&state_file).unwrap().unwrap();
+        assert_eq!(
+            AuthenticationKey::ed25519(&saved.key.public_key()),
+            rotated_key
+        );
+        assert_eq!(saved.pending_key, None);
+
+        // Without delegation there is no key to rotate
+        let (_accounts, undelegated) = setup_service(None);
+        assert!(undelegated.rotate_funder_key().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mint_chain_id() {
+        let (accounts, service) = setup_service(None);
+        let filter = routes(service);
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+
+        for chain_id in ["4", "TESTING"] {
+            let resp = warp::test::request()
+                .method("POST")
+                .path(format!("/mint?address={}&amount=10&chain_id={}", address, chain_id).as_str())
+                .reply(&filter)
+                .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        let addr = AccountAddress::try_from(address.to_owned()).unwrap();
+        assert_eq!(accounts.account(addr).unwrap().balance, 20);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path(format!("/mint?address={}&amount=10&chain_id=2", address).as_str())
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let error: UnknownChain = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(error.error, "unknown_chain");
+        assert_eq!(
+            error.available_networks,
+            vec![Network {
+                chain_id: 4,
+                name: "TESTING".to_string(),
+            }]
+        );
+        assert_eq!(accounts.account(addr).unwrap().balance, 20);
+    }
+
+    #[tokio::test]
+    async fn test_mint_missing_amount() {
+        let (_accounts, service) = setup_service(None);
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/mint?address=459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d")
+            .reply(&routes(service))
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: RejectionBody = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body.error, "bad_params");
+        assert_eq!(body.message, "You must provide 'amount'");
+    }
+
+    #[tokio::test]
+    async fn test_mint_invalid_auth_key() {
+        let (_accounts, service) = setup_service(None);
+        let filter = routes(service);
+
+        let auth_key = "invalid-auth-key";
+        let resp = warp::test::request()
+            .method("POST")
+            .path(format!("/mint?auth_key={}&amount=1000000", auth_key).as_str())
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: InvalidParams = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(
+            body.errors,
+            vec![InvalidField::new(
+                "auth_key",
+                "contains 'i', which is not a hex digit"
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mint_invalid_fields() {
+        let (_accounts, service) = setup_service(None);
+        let filter = routes(service);
+
+        // Every malformed field is reported, not just the first
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/mint?address=0x12g4&amount=lots&wait=maybe&wait_for=soon")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: InvalidParams = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body.error, "invalid_params");
+        let fields: Vec<_> = body.errors.iter().map(|err| err.field.as_str()).collect();
+        assert_eq!(fields, vec!["amount", "address", "wait", "wait_for"]);
+        assert_eq!(
+            body.errors[1].reason,
+            "contains 'g', which is not a hex digit"
+        );
+        assert!(body.message.starts_with("'amount' must be base units"));
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/mint?address=0x1&amount=10&top_up_to=20")
+            .reply(&filter)
+            .await;
+        let body: InvalidParams = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(
+            body.errors,
+            vec![InvalidField::new(
+                "top_up_to",
+                "cannot be combined with 'amount'"
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejections_are_counted() {
+        let (_accounts, service) = setup_service(None);
+        let filter = routes(service);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/mint?amount=1000000")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), 400);
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/metrics")
+            .reply(&filter)
+            .await;
+        let metrics = String::from_utf8(resp.body().to_vec()).unwrap();
+        assert!(metrics.contains("aptos_faucet_rejected_requests{reason=\"bad_params\"}"));
+    }
+
+    #[tokio::test]
+    async fn test_funder_gauges() {
+        let (_accounts, service) = setup_service(None);
+        service.start_background_tasks();
+        // The first poll runs right away
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/metrics")
+            .reply(&routes(service))
+            .await;
+        let metrics = String::from_utf8(resp.body().to_vec()).unwrap();
+        for gauge in [
+            "aptos_faucet_funder_balance",
+            "aptos_faucet_funder_local_sequence_number",
+            "aptos_faucet_funder_onchain_sequence_number",
+        ] {
+            assert!(metrics.contains(&format!("\n{} ", gauge)), "{}", gauge);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_node_request_duration() {
+        let (_accounts, service) = setup_service(None);
+        let filter = routes(service);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/mint?address=459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d&amount=10&wait=true")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), 200);
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/metrics")
+            .reply(&filter)
+            .await;
+        let metrics = String::from_utf8(resp.body().to_vec()).unwrap();
+        for operation in ["get_account", "submit", "wait"] {
+            assert!(
+                metrics.contains(&format!(
+                    "aptos_faucet_node_request_duration_seconds_count{{operation=\"{}\"}}",
+                    operation
+                )),
+                "{}",
+                operation
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mint_fullnode_error() {
+        let (accounts, service) = setup_service(None);
+        let address = service.faucet_account.lock().await.address();
+        accounts.remove_account(address);
+        let filter = routes(service);
+
+        let auth_key = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let resp = warp::test::request()
+            .method("POST")
+            .path(format!("/mint?auth_key={}&amount=1000000", auth_key).as_str())
+            .reply(&filter)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body: RejectionBody = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body.error, "node_error");
+        assert_eq!(
+            body.message,
+            format!("faucet account {:?} not found", address)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mint_node_unavailable() {
+        let (accounts, service) = setup_service(None);
+        accounts.set_failures(InjectedFailures {
+            unavailable: Some(StatusCode::SERVICE_UNAVAILABLE),
+            ..InjectedFailures::default()
+        });
+        let filter = routes(service);
+
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let resp = warp::test::request()
+            .method("POST")
+            .path(format!("/mint?address={}&amount=100", address).as_str())
+            .reply(&filter)
+            .await;
+
+        assert_eq!(resp.status(), 500);
+    }
+
+    #[tokio::test]
+    async fn test_mint_submission_rejected() {
+        let (accounts, service) = setup_service(None);
+        accounts.set_failures(InjectedFailures {
+            reject_submissions: Some(StatusCode::BAD_REQUEST),
+            ..InjectedFailures::default()
+        });
+        let filter = routes(service);
+
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let resp = warp::test::request()
+            .method("POST")
+            .path(format!("/mint?address={}&amount=100", address).as_str())
+            .reply(&filter)
+            .await;
+
+        assert_eq!(resp.status(), 500);
+        let addr = AccountAddress::try_from(address.to_owned()).unwrap();
+        assert!(accounts.account(addr).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mint_spend_budget() {
+        let (_accounts, service) =
+            setup_service_with(|builder| builder.spend_budget(150, Duration::from_secs(3600)));
+        let filter = routes(service);
+
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let mint = |amount: u64| {
+            warp::test::request()
+                .method("POST")
+                .path(format!("/mint?address={}&amount={}", address, amount).as_str())
+                .reply(&filter)
+        };
+
+        assert_eq!(mint(100).await.status(), 200);
+        // Dry runs are refunded, so they never eat into the budget
+        let resp = warp::test::request()
+            .method("POST")
+            .path(format!("/mint?address={}&amount=50&dry_run=true", address).as_str())
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), 200);
+
+        let resp = mint(100).await;
+        assert_eq!(resp.status(), 429);
+        assert!(resp.headers().contains_key("retry-after"));
+        assert_eq!(mint(50).await.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_mint_wait_failures_are_not_refunded() {
+        let (accounts, service) =
+            setup_service_with(|builder| builder.spend_budget(150, Duration::from_secs(3600)));
+        let filter = routes(service);
+
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let addr = AccountAddress::try_from(address.to_owned()).unwrap();
+        let mint = |query: &str| {
+            warp::test::request()
+                .method("POST")
+                .path(format!("/mint?address={}&amount=100&{}", address, query).as_str())
+                .reply(&filter)
+        };
+
+        // The transactions went through, only waiting on them failed
+        accounts.set_failures(InjectedFailures {
+            reject_transaction_reads: Some(StatusCode::SERVICE_UNAVAILABLE),
+            ..InjectedFailures::default()
+        });
+        let resp = mint("wait_for=executed").await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(accounts.account(addr).unwrap().balance, 100);
+
+        accounts.set_failures(InjectedFailures::default());
+        let resp = mint("").await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_mint_daily_maximum_amount() {
+        let (accounts, service) =
+            setup_service_with(|builder| builder.maximum_amount(100).daily_maximum_amount(150));
+        let filter = routes(service);
+        let filter = &filter;
+
+        let mint = move |ip: &'static str, address: &'static str| {
+            warp::test::request()
+                .method("POST")
+                .path(format!("/mint?address={}&amount=100", address).as_str())
+                .remote_addr(format!("{}:4000", ip).parse().unwrap())
+                .reply(filter)
+        };
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let addr = AccountAddress::try_from(address.to_owned()).unwrap();
+        let other = "c0ffee00c0ffee00c0ffee00c0ffee00c0ffee00c0ffee00c0ffee00c0ffee00";
+
+        assert_eq!(mint("10.0.0.1", address).await.status(), 200);
+        // Capped at what is left of the day's allowance, whatever the receiver
+        assert_eq!(mint("10.0.0.1", other).await.status(), 200);
+        let other_addr = AccountAddress::try_from(other.to_owned()).unwrap();
+        assert_eq!(accounts.account(other_addr).unwrap().balance, 50);
+
+        let resp = mint("10.0.0.1", address).await;
+        assert_eq!(resp.status(), 429);
+        assert!(resp.headers().contains_key("retry-after"));
+
+        // Other callers have their own allowance
+        assert_eq!(mint("10.0.0.2", address).await.status(), 200);
+        assert_eq!(accounts.account(addr).unwrap().balance, 200);
+    }
+
+    #[tokio::test]
+    async fn test_mint_max_in_flight_transactions() {
+        let (chain, service) =
+            setup_service_with(|builder| builder.max_in_flight_transactions(100));
+        chain.set_failures(InjectedFailures {
+            drop_transactions: true,
+            ..InjectedFailures::default()
+        });
+        let filter = routes(service.clone());
+
+        // 60 transactions go out without any committing, beyond the default window of 50
+        let mints = async {
+            for _ in 0..30 {
+                let resp = warp::test::request()
+                    .method("POST")
+                    .path(format!("/mint?address={}&amount=100", AccountAddress::random()).as_str())
+                    .reply(&filter)
+                    .await;
+                assert_eq!(resp.status(), 200);
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), mints)
+            .await
+            .expect("mints should not wait for transactions to commit");
+        assert_eq!(service.faucet_account.lock().await.sequence_number(), 60);
+    }
+
+    #[tokio::test]
+    async fn test_mint_audit_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let audit_log = AuditLog::open(AuditLogConfig::new(path.clone())).unwrap();
+        let (_accounts, service) = setup_service_with(|builder| {
+            builder
+                .spend_budget(100, Duration::from_secs(3600))
+                .audit_log(audit_log)
+        });
+        let filter = routes(service);
+
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let mint = |amount: u64| {
+            warp::test::request()
+                .method("POST")
+                .path(format!("/mint?address={}&amount={}", address, amount).as_str())
+                .reply(&filter)
+        };
+        let resp = mint(100).await;
+        assert_eq!(resp.status(), 200);
+        let funded: FundResponse = serde_json::from_slice(resp.body()).unwrap();
+        let request_id = resp.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(mint(100).await.status(), 429);
+
+        let entries: Vec<AuditEntry> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].decision, Decision::Granted);
+        assert_eq!(entries[0].request_id.unwrap().to_string(), request_id);
+        assert_eq!(entries[0].amount, Some(100));
+        assert_eq!(entries[0].txn_hashes, funded.txn_hashes);
+        assert_eq!(entries[1].decision, Decision::Rejected);
+        assert!(entries[1].reason.is_some());
+        assert_eq!(
+            entries[1].receiver,
+            Some(AccountAddress::from_hex(address).unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mint_subnet_rate_limit() {
+        let (_accounts, service) = setup_service_with(|builder| {
+            builder
+                .ip_rate_limit(IpRateLimitConfig {
+                    per_ipv4_24: Some(1),
+                    ..IpRateLimitConfig::default()
+                })
+                .trust_x_forwarded_for(true)
+        });
+        let filter = routes(service);
+
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let mint = |client: &'static str| {
+            warp::test::request()
+                .method("POST")
+                .path(format!("/mint?address={}&amount=10", address).as_str())
+                .header("x-forwarded-for", client)
+                .reply(&filter)
+        };
+
+        assert_eq!(mint("10.0.0.1").await.status(), 200);
+        assert_eq!(mint("10.0.0.2, 192.168.0.1").await.status(), 429);
+        assert_eq!(mint("10.0.1.1").await.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_mint_deadline() {
+        let (accounts, service) = setup_service_with(|builder| builder.max_concurrent_mints(1));
+        let filter = routes(service);
+        let mint = |deadline_ms: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/mint?address=459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d&amount=10")
+                .header("x-request-deadline-ms", deadline_ms)
+                .reply(&filter)
+        };
+
+        // The client gave up before the request could be processed
+        let resp = mint("0").await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let addr = AccountAddress::try_from(
+            "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d".to_owned(),
+        )
+        .unwrap();
+        assert!(accounts.account(addr).is_none());
+
+        assert_eq!(mint("60000").await.status(), StatusCode::OK);
+        assert_eq!(accounts.account(addr).unwrap().balance, 10);
+    }
+
+    #[tokio::test]
+    async fn test_mint_cooldown() {
+        let (accounts, service) =
+            setup_service_with(|builder| builder.cooldown(CooldownConfig::new(3600)));
+        let filter = routes(service);
+        let mint = |address: &str, amount: &str| {
+            warp::test::request()
+                .method("POST")
+                .path(format!("/mint?address={}&amount={}", address, amount).as_str())
+                .reply(&filter)
+        };
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+
+        // A refused request does not start the cooldown
+        assert_eq!(mint(address, "0").await.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(mint(address, "10").await.status(), StatusCode::OK);
+        let resp = mint(address, "10").await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(resp.headers().contains_key("retry-after"));
+        let body: RejectionBody = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body.error, "rate_limited");
+        assert!(
+            body.message.contains("was funded recently, try again in"),
+            "{}",
+            body.message
+        );
+        let addr = AccountAddress::try_from(address.to_owned()).unwrap();
+        assert_eq!(accounts.account(addr).unwrap().balance, 10);
+
+        let other = "a74fd7c46952c497e75afb0a7932586d459c77a38803bd53f3adee52703810e3";
+        assert_eq!(mint(other, "10").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_quota() {
+        let (_accounts, service) = setup_service_with(|builder| {
+            builder
+                .ip_rate_limit(IpRateLimitConfig {
+                    per_ip: Some(1),
+                    per_ipv4_24: Some(2),
+                    ..IpRateLimitConfig::default()
+                })
+                .spend_budget(100, Duration::from_secs(3600))
+                .trust_x_forwarded_for(true)
+        });
+        let filter = routes(service);
+        let filter = &filter;
+        let quota = move || async move {
+            let resp = warp::test::request()
+                .method("GET")
+                .path("/quota")
+                .header("x-forwarded-for", "10.0.0.1")
+                .reply(filter)
+                .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            serde_json::from_slice::<Quota>(resp.body()).unwrap()
+        };
+
+        let before = quota().await;
+        assert!(before.available);
+        assert_eq!(before.ip_limits.len(), 2);
+        assert_eq!(before.ip_limits[0].subnet, "10.0.0.1/32");
+        assert_eq!(before.ip_limits[0].remaining, 1);
+        assert_eq!(before.spend_budget.as_ref().unwrap().remaining, 100);
+        // Asking does not count as a request
+        assert_eq!(quota().await.ip_limits[0].remaining, 1);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/mint?address=459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d&amount=10")
+            .header("x-forwarded-for", "10.0.0.1")
+            .reply(filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let after = quota().await;
+        assert!(!after.available);
+        assert_eq!(after.ip_limits[0].remaining, 0);
+        assert_eq!(after.ip_limits[1].subnet, "10.0.0.0/24");
+        assert_eq!(after.ip_limits[1].remaining, 1);
+        assert_eq!(after.spend_budget.unwrap().remaining, 90);
+    }
+
+    #[tokio::test]
+    async fn test_mint_signature_challenge() {
+        let (accounts, service) =
+            setup_service_with(|builder| builder.signature_challenge(Duration::from_secs(60)));
+        let filter = routes(service);
+
+        let key = GenerateKey::generate_ed25519_in_memory();
+        let pub_key = key.public_key();
+        let address = AuthenticationKey::ed25519(&pub_key).derived_address();
+        let pub_key_hex = hex::encode(pub_key.to_bytes());
+
+        // Unsigned requests are refused
+        let resp = warp::test::request()
+            .method("POST")
+            .path(format!("/mint?pub_key={}&amount=10", pub_key_hex).as_str())
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), 403);
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path(format!("/challenge?address={}", address).as_str())
+            .reply(&filter)
+            .await;
+        let challenge: Challenge = serde_json::from_slice(resp.body()).unwrap();
+        let signature = key.sign_arbitrary_message(challenge.nonce.as_bytes());
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path(
+                format!(
+                    "/mint?pub_key={}&amount=10&nonce={}&signature={}",
+                    pub_key_hex,
+                    challenge.nonce,
+                    hex::encode(signature.to_bytes())
+                )
+                .as_str(),
+            )
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(accounts.account(address).unwrap().balance, 10);
+    }
+
+    #[tokio::test]
+    async fn test_admin_requires_token() {
+        let (_accounts, service) = setup_service_with(|builder| {
+            builder
+                .admin_token("secret")
+                .sybil_detection(SybilConfig::default())
+        });
+        let filter = routes(service);
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/admin/sybil")
+            .header("authorization", "Bearer wrong")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), 404);
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/admin/sybil")
+            .header("authorization", "Bearer secret")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), 200);
+        let findings: Vec<Finding> = serde_json::from_slice(resp.body()).unwrap();
+        assert!(findings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_bans() {
+        let (accounts, service) = setup_service_with(|builder| {
+            builder
+                .bans(BanConfig {
+                    max_rejections_per_minute: 2,
+                    ..BanConfig::default()
+                })
+                .trust_x_forwarded_for(true)
+                .admin_token("secret")
+        });
+        let filter = routes(service);
+        let filter = &filter;
+        let mint = move |client: &'static str, query: &'static str| async move {
+            warp::test::request()
+                .method("POST")
+                .path(&format!("/mint?{}", query))
+                .header("x-forwarded-for", client)
+                .reply(filter)
+                .await
+        };
+        let funded =
+            "address=459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d&amount=10";
+
+        for _ in 0..3 {
+            let resp = mint("10.0.0.1", "amount=10").await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+        // Even valid requests are refused until the ban ends
+        let resp = mint("10.0.0.1", funded).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let retry_after: u64 = resp.headers()["retry-after"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after > 590 && retry_after <= 600);
+        let addr = AccountAddress::try_from(
+            "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d".to_owned(),
+        )
+        .unwrap();
+        assert!(accounts.account(addr).is_none());
+
+        // Other clients are not affected
+        let resp = mint("10.0.0.2", funded).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/admin/bans")
+            .header("authorization", "Bearer secret")
+            .reply(filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let bans: BTreeMap<IpAddr, Ban> = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(bans.len(), 1);
+        let ban = &bans[&"10.0.0.1".parse::<IpAddr>().unwrap()];
+        assert_eq!(ban.count, 1);
+        assert_eq!(ban.reason, "too many refused requests");
+    }
+
+    #[tokio::test]
+    async fn test_standby() {
+        // Nothing listens there, and the election is never run, so the service stays a standby
+        let (accounts, service) = setup_service_with(|builder| {
+            builder
+                .leader_election(LeaderElectionConfig::new("127.0.0.1:1".to_string()))
+                .admin_token("secret")
+        });
+        assert!(!service.is_leader());
+        let filter = routes(service);
+        let address = AccountAddress::random();
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path(&format!("/mint?amount=10&address={}", address))
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: RejectionBody = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body.error, "standby");
+        assert_eq!(
+            body.message,
+            "This faucet replica is on standby, try again shortly"
+        );
+        assert!(accounts.account(address).is_none());
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/admin/bulk_fund")
+            .header("authorization", "Bearer secret")
+            .header("content-type", "text/csv")
+            .body(format!("{},10", address))
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // Standbys still serve the read-only endpoints
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/ready")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let readiness: Readiness = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(readiness.leader, Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_sharding() {
+        let shard_urls: Vec<Url> = (0..4)
+            .map(|shard| {
+                format!("https://faucet-{}.example.com", shard)
+                    .parse()
+                    .unwrap()
+            })
+            .collect();
+        let (accounts, service) = setup_service_with(|builder| {
+            builder.sharding(ShardingConfig {
+                shards: 4,
+                shard: 2,
+                shard_urls,
+            })
+        });
+        let filter = routes(service);
+
+        // In shard 2
+        let address = AccountAddress::try_from(
+            "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d".to_owned(),
+        )
+        .unwrap();
+        let resp = warp::test::request()
+            .method("POST")
+            .path(&format!("/mint?amount=10&address={}", address))
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(accounts.account(address).is_some());
+
+        // In shard 3
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/mint?amount=10&address=0x1")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::MISDIRECTED_REQUEST);
+        let body: RejectionBody = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body.error, "wrong_shard");
+        assert_eq!(
+            body.message,
+            "The receiver is funded by shard 3, at https://faucet-3.example.com/"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_bulk_fund() {
+        let (accounts, service) = setup_service_with(|builder| {
+            builder
+                .admin_token("secret")
+                .maximum_amount(10)
+                .spend_budget(10, Duration::from_secs(3600))
+        });
+        let filter = routes(service);
+        let first = AccountAddress::random();
+        let second = AccountAddress::random();
+
+        // Bulk funding is not limited like mint requests
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/admin/bulk_fund")
+            .header("authorization", "Bearer secret")
+            .header("content-type", "text/csv")
+            .body(format!(
+                "address,amount\n{},1000\n{},2000\n",
+                first.to_hex_literal(),
+                second
+            ))
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], "text/csv");
+        assert_eq!(
+            resp.headers()["content-disposition"],
+            "attachment; filename=\"bulk_fund_report.csv\""
+        );
+        let report = std::str::from_utf8(resp.body()).unwrap();
+        assert_eq!(report.lines().count(), 3, "{}", report);
+        assert!(report.lines().nth(1).unwrap().contains(",1000,funded,0x"));
+        assert_eq!(accounts.account(first).unwrap().balance, 1000);
+        assert_eq!(accounts.account(second).unwrap().balance, 2000);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/admin/bulk_fund")
+            .header("authorization", "Bearer secret")
+            .json(&serde_json::json!([{ "address": first.to_hex_literal(), "amount": 5 }]))
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let report: BulkReport = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!((report.funded, report.failed), (1, 0));
+        assert_eq!(report.results[0].status, BulkStatus::Funded);
+        assert_eq!(accounts.account(first).unwrap().balance, 1005);
+
+        // Nothing is funded from an invalid list
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/admin/bulk_fund")
+            .header("authorization", "Bearer secret")
+            .header("content-type", "text/csv")
+            .body(format!("{},5\nnot-an-address,5\n", first))
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(accounts.account(first).unwrap().balance, 1005);
+    }
+
+    #[tokio::test]
+    async fn test_admin_queue() {
+        let (_accounts, service) =
+            setup_service_with(|builder| builder.admin_token("secret").max_concurrent_mints(4));
+        let filter = routes(service);
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/mint?address=459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d&amount=10")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/admin/queue")
+            .header("authorization", "Bearer secret")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let stats: QueueStats = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!((stats.processing, stats.max_concurrent), (0, 4));
+        assert_eq!(stats.queued_anonymous, 0);
+        assert!(stats.drain_rate > 0.0);
+
+        // Without a bound there is no queue
+        let (_accounts, service) = setup_service_with(|builder| builder.admin_token("secret"));
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/admin/queue")
+            .header("authorization", "Bearer secret")
+            .reply(&routes(service))
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_admin_inflight() {
+        let (accounts, service) = setup_service_with(|builder| builder.admin_token("secret"));
+        let funder = service.faucet_account.lock().await.address();
+        let filter = routes(service);
+        let inflight = || {
+            warp::test::request()
+                .method("GET")
+                .path("/admin/inflight")
+                .header("authorization", "Bearer secret")
+                .reply(&filter)
+        };
+
+        let resp = inflight().await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let listed: Inflight = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(listed.funder, funder);
+        assert!(listed.transactions.is_empty());
+
+        // Transactions the node never executes stay in flight
+        accounts.set_failures(InjectedFailures {
+            drop_transactions: true,
+            ..InjectedFailures::default()
+        });
+        let receiver = AccountAddress::random();
+        let resp = warp::test::request()
+            .method("POST")
+            .path(format!("/mint?address={}&amount=10", receiver).as_str())
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let listed: Inflight = serde_json::from_slice(inflight().await.body()).unwrap();
+        assert_eq!(
+            (listed.onchain_sequence_number, listed.local_sequence_number),
+            (0, 2)
+        );
+        let sequence_numbers: Vec<_> = listed
+            .transactions
+            .iter()
+            .map(|txn| txn.sequence_number)
+            .collect();
+        assert_eq!(sequence_numbers, vec![0, 1]);
+        assert!(listed
+            .transactions
+            .iter()
+            .all(|txn| txn.receiver == Some(receiver) && txn.hash.starts_with("0x")));
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight_allows_put() {
+        let (_accounts, service) = setup_service_with(|builder| builder.admin_token("secret"));
+        let resp = warp::test::request()
+            .method("OPTIONS")
+            .path("/admin/log_level")
+            .header("origin", "https://admin.example")
+            .header("access-control-request-method", "PUT")
+            .header("access-control-request-headers", "authorization")
+            .reply(&routes(service))
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let allowed = resp.headers()["access-control-allow-methods"]
+            .to_str()
+            .unwrap();
+        assert!(allowed.contains("PUT"), "{}", allowed);
+    }
+
+    #[tokio::test]
+    async fn test_cors_tracing_headers() {
+        let (_accounts, service) = setup_service(None);
+        let filter = routes(service);
+
+        let resp = warp::test::request()
+            .method("OPTIONS")
+            .path("/mint")
+            .header("origin", "https://wallet.example")
+            .header("access-control-request-method", "POST")
+            .header(
+                "access-control-request-headers",
+                "x-request-id,traceparent,x-request-deadline-ms",
+            )
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/health")
+            .header("origin", "https://wallet.example")
+            .reply(&filter)
+            .await;
+        let exposed = resp.headers()["access-control-expose-headers"]
+            .to_str()
+            .unwrap();
+        for header in ["x-request-id", "traceparent", "retry-after"] {
+            assert!(exposed.contains(header), "{}", exposed);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admin_log_level() {
+        let put = |filter: &'static str| {
+            warp::test::request()
+                .method("PUT")
+                .path("/admin/log_level")
+                .header("authorization", "Bearer secret")
+                .body(filter)
+        };
+
+        // Only a faucet given the process' logger can change it
+        let (_accounts, service) = setup_service_with(|builder| builder.admin_token("secret"));
+        let resp = put("debug").reply(&routes(service)).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let logger = aptos_logger::Logger::builder()
+            .is_async(false)
+            .level(aptos_logger::Level::Info)
+            .build();
+        let (_accounts, service) =
+            setup_service_with(|builder| builder.admin_token("secret").logger(logger.clone()));
+        let filter = routes(service);
+
+        let resp = put(" info,aptos_faucet::mint=debug\n").reply(&filter).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let log_level: LogLevel = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(log_level.filter, "info,aptos_faucet::mint=debug");
+
+        for invalid in ["", "degub", "aptos_faucet=loud", "=debug"] {
+            let resp = put(invalid).reply(&filter).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", invalid);
+        }
+    }
+
+    #[cfg(feature = "cpu-profiling")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_admin_cpu_profile() {
+        let (_accounts, service) = setup_service_with(|builder| builder.admin_token("secret"));
+        let filter = routes(service);
+        let get = |path: &str, token: &str| {
+            warp::test::request()
+                .path(path)
+                .header("authorization", format!("Bearer {}", token))
+        };
+
+        let resp = get("/debug/pprof/profile?seconds=1", "wrong")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        for seconds in [0, 301] {
+            let path = format!("/debug/pprof/profile?seconds={}", seconds);
+            let resp = get(&path, "secret").reply(&filter).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+
+        let resp = get("/debug/pprof/profile?seconds=1", "secret")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], "image/svg+xml");
+        assert!(resp.body().starts_with(b"<?xml"));
+    }
+
+    #[tokio::test]
+    async fn test_admin_redelegate() {
+        let (accounts, service) = setup_service_with(|builder| builder.admin_token("secret"));
+
+        // Without delegation there is no account to replace
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/admin/redelegate")
+            .header("authorization", "Bearer secret")
+            .reply(&routes(service.clone()))
+            .await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let endowment = Endowment {
+            amount: 1_000,
+            top_up_below: None,
+        };
+        let service = delegate_mint_account(service, None, endowment, None)
+            .await
+            .unwrap();
+        let previous = service.faucet_account.lock().await.address();
+        let filter = routes(service.clone());
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/admin/redelegate")
+            .header("authorization", "Bearer secret")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let redelegated: Redelegated = serde_json::from_slice(resp.body()).unwrap();
+        assert_ne!(redelegated.address, previous);
+        assert_eq!(
+            service.faucet_account.lock().await.address(),
+            redelegated.address
+        );
+        assert_eq!(
+            accounts.account(redelegated.address).unwrap().balance,
+            1_000
+        );
+
+        // Requests now mint from the new account
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let resp = warp::test::request()
+            .method("POST")
+            .path(format!("/mint?address={}&amount=10&wait=true", address).as_str())
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let response: FundResponse = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(response.balance, Some(10));
+        assert_eq!(
+            accounts
+                .account(redelegated.address)
+                .unwrap()
+                .sequence_number,
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fund() {
+        let (_accounts, service) = setup_service(None);
+        let filter = routes(service);
+        let filter = &filter;
+        let fund = move |body: &'static str| {
+            warp::test::request()
+                .method("POST")
+                .path("/fund")
+                .header("content-type", "application/json")
+                .body(body)
+                .reply(filter)
+        };
+
+        let resp = fund(
+            r#"{"address": "0x459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d", "amount": 10}"#,
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let response: TapFundResponse = serde_json::from_slice(resp.body()).unwrap();
+        // Creates the account, then funds it
+        assert_eq!(response.txn_hashes.len(), 2);
+        assert!(response.txn_hashes[0].starts_with("0x"));
+
+        for body in vec![r#"{"amount": 10}"#, "not json"] {
+            let resp = fund(body).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            let error: FundError = serde_json::from_slice(resp.body()).unwrap();
+            assert_eq!(error.error_code, "InvalidRequest");
+        }
+
+        let resp = fund(r#"{"address": "0xnope", "amount": 10}"#).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let error: FundError = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(error.errors[0].field, "address");
+    }
+
+    #[tokio::test]
+    async fn test_create_account() {
+        let (accounts, service) = setup_service(None);
+        let filter = routes(service);
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let create = || {
+            warp::test::request()
+                .method("POST")
+                .path(format!("/accounts?address={}", address).as_str())
+                .reply(&filter)
+        };
+
+        let resp = create().await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let created: CreatedAccount = serde_json::from_slice(resp.body()).unwrap();
+        let address = AccountAddress::from_hex(address).unwrap();
+        assert_eq!(created.address, address);
+        let account = accounts
+            .account(address)
+            .expect("account should be created");
+        assert_eq!(account.balance, 0);
+
+        assert_eq!(create().await.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_mint_coupon() {
+        let (_accounts, service) = setup_service_with(|builder| {
+            builder
+                .admin_token("secret")
+                .maximum_amount(100)
+                .spend_budget(100, Duration::from_secs(3600))
+        });
+        let filter = routes(service);
+        let filter = &filter;
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let mint = move |query: String| {
+            warp::test::request()
+                .method("POST")
+                .path(format!("/mint?address={}&{}", address, query).as_str())
+                .reply(filter)
+        };
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/admin/coupons?amount=1000&count=2")
+            .header("authorization", "Bearer secret")
+            .reply(filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let issued: IssuedCoupons = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(issued.codes.len(), 2);
+
+        // Coupons bypass the maximum amount and the spend budget
+        let coupon = &issued.codes[0];
+        let resp = mint(format!("coupon={}", coupon)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let response: FundResponse = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(response.amount, 1000);
+
+        assert_eq!(
+            mint(format!("coupon={}", coupon)).await.status(),
+            StatusCode::FORBIDDEN
+        );
+        // Refused requests leave the coupon usable
+        let coupon = &issued.codes[1];
+        assert_eq!(
+            mint(format!("coupon={}&amount=10", coupon)).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/admin/coupons")
+            .header("authorization", "Bearer secret")
+            .reply(filter)
+            .await;
+        let outstanding: HashMap<String, u64> = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(outstanding.get(coupon), Some(&1000));
+        assert_eq!(outstanding.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_mint_partner_signed() {
+        let (_accounts, service) = setup_service_with(|builder| {
+            builder
+                .partners(PartnerConfig {
+                    secrets: vec![("ci".to_string(), "secret".to_string())]
+                        .into_iter()
+                        .collect(),
+                    secret_files: BTreeMap::new(),
+                    replay_window_secs: 300,
+                })
+                .ip_rate_limit(IpRateLimitConfig {
+                    per_ip: Some(1),
+                    ..IpRateLimitConfig::default()
+                })
+                .trust_x_forwarded_for(true)
+                .admin_token("secret")
+        });
+        let filter = routes(service);
+        let filter = &filter;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let path = |amount: u64| {
+            format!(
+                "/mint?address=459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d&amount={}",
+                amount
+            )
+        };
+        let mint = move |path: String, signature: String| async move {
+            warp::test::request()
+                .method("POST")
+                .path(path.as_str())
+                .header("x-forwarded-for", "10.0.0.1")
+                .header(partner::PARTNER_HEADER, "ci")
+                .header(partner::TIMESTAMP_HEADER, now.to_string())
+                .header(partner::SIGNATURE_HEADER, signature)
+                .reply(filter)
+                .await
+                .status()
+        };
+
+        // Signed requests are not counted against the IP limit
+        for amount in [10, 20] {
+            let signature = partner::sign("secret", &Method::POST, &path(amount), now, b"");
+            assert_eq!(mint(path(amount), signature.clone()).await, StatusCode::OK);
+            assert_eq!(
+                mint(path(amount), signature).await,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+
+        let signature = partner::sign("secret", &Method::POST, &path(30), now, b"");
+        assert_eq!(mint(path(300), signature).await, StatusCode::UNAUTHORIZED);
+        let signature = partner::sign("wrong", &Method::POST, &path(30), now, b"");
+        assert_eq!(mint(path(30), signature).await, StatusCode::UNAUTHORIZED);
+
+        // Only the requests whose signature verified are counted
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/admin/usage")
+            .header("authorization", "Bearer secret")
+            .reply(filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let usage: HashMap<String, PartnerUsage> = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(
+            usage["ci"],
+            PartnerUsage {
+                requests: 2,
+                funded: 2,
+                amount: 30,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn create_account_with_client() {
+        let (_accounts, service) = setup_service(None);
+        let endpoint = service.endpoint().to_owned();
+        let (address, future) = warp::serve(routes(service)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::task::spawn(async move { future.await });
+
+        let faucet_client = FaucetClient::new(format!("http://{}", address), endpoint);
+
+        let pub_key: Ed25519PublicKey =
+            hex::decode(&"459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d")
+                .unwrap()
+                .as_slice()
+                .try_into()
+                .unwrap();
+        let address = AuthenticationKey::ed25519(&pub_key).derived_address();
+        assert_eq!(
+            address.to_string(),
+            "9FF98E82355EB13098F3B1157AC018A725C62C0E0820F422000814CDBA407835"
+        );
+
+        let res = tokio::task::spawn_blocking(move || faucet_client.create_account(address))
+            .await
+            .unwrap();
+        res.unwrap();
+    }
+
+    #[tokio::test]
+    async fn create_account_with_client_on_older_faucets() {
+        let (accounts, service) = setup_service(None);
+        let endpoint = service.endpoint().to_owned();
+        // Faucets predating `POST /accounts`
+        let older = warp::path("accounts")
+            .map(|| warp::reply::with_status("", StatusCode::NOT_FOUND))
+            .or(routes(service));
+        let (address, future) = warp::serve(older).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::task::spawn(async move { future.await });
+
+        let faucet_client = FaucetClient::new(format!("http://{}", address), endpoint);
+        let address = AccountAddress::random();
+        let res = tokio::task::spawn_blocking(move || faucet_client.create_account(address))
+            .await
+            .unwrap();
+        res.unwrap();
+        assert_eq!(accounts.account(address).unwrap().balance, 0);
+    }
+
+    #[tokio::test]
+    async fn fund_account_with_client() {
+        let (_accounts, service) = setup_service(None);
+        let endpoint = service.endpoint().to_owned();
+        let (address, future) = warp::serve(routes(service)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::task::spawn(async move { future.await });
+
+        let faucet_client = FaucetClient::new(format!("http://{}", address), endpoint);
+
+        let pub_key: Ed25519PublicKey =
+            hex::decode(&"459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d")
+                .unwrap()
+                .as_slice()
+                .try_into()
+                .unwrap();
+        let address = AuthenticationKey::ed25519(&pub_key).derived_address();
+        let (res1, res2) = tokio::task::spawn_blocking(move || {
+            (
+                faucet_client.create_account(address),
+                faucet_client.fund(address, 10),
+            )
+        })
+        .await
+        .unwrap();
+
+        res1.unwrap();
+        res2.unwrap();
+    }
+
+    #[tokio::test]
+    async fn fund_and_wait_with_client() {
+        let (accounts, service) = setup_service(None);
+        let endpoint = service.endpoint().to_owned();
+        let (address, future) = warp::serve(routes(service)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::task::spawn(async move { future.await });
+
+        let faucet_client = FaucetClient::new(format!("http://{}", address), endpoint);
+
+        let address = AccountAddress::try_from(
+            "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d".to_owned(),
+        )
+        .unwrap();
+        let txns = tokio::task::spawn_blocking(move || faucet_client.fund_and_wait(address, 10))
+            .await
+            .unwrap()
+            .unwrap();
+
+        // One transaction creates the account, the other mints into it
+        assert_eq!(txns.len(), 2);
+        assert!(txns.iter().all(|txn| txn.success()));
+        let account = accounts
+            .account(address)
+            .expect("account should be created");
+        assert_eq!(account.balance, 10);
+    }
+
+    #[tokio::test]
+    async fn request_fund_with_client() {
+        let (_accounts, service) = setup_service(None);
+        let endpoint = service.endpoint().to_owned();
+        let (address, future) = warp::serve(routes(service)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::task::spawn(async move { future.await });
+
+        let faucet_client = FaucetClient::new(format!("http://{}", address), endpoint);
+
+        let address = AccountAddress::try_from(
+            "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d".to_owned(),
+        )
+        .unwrap();
+        let response = tokio::task::spawn_blocking(move || faucet_client.request_fund(address, 10))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(response.destination, address);
+        assert_eq!(response.amount, 10);
+        assert_eq!(response.txn_hashes.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn client_health_check() {
+        let (_accounts, service) = setup_service_with(|builder| builder.admin_token("secret"));
+        let endpoint = service.endpoint().to_owned();
+        let (address, future) = warp::serve(routes(service)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::task::spawn(async move { future.await });
+        let faucet_client = FaucetClient::new(format!("http://{}", address), endpoint.clone());
+
+        let health = tokio::task::spawn_blocking(move || faucet_client.health_check())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(health.healthy);
+        assert_eq!(health.version.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+        assert_eq!(health.chain_id, Some(ChainId::test().id()));
+        assert_eq!(health.sequence_number, Some(0));
+        assert_eq!(health.build_features, BuildInfo::current().features);
+        assert_eq!(
+            health.capabilities,
+            FaucetCapabilities {
+                json_responses: true,
+                wait: true,
+                accounts: true,
+                fund: true,
+                bulk_fund: true,
+                challenge: false,
+            }
+        );
+
+        // Older faucets answer with the plain text sequence number, and have no `GET /version`
+        let legacy = warp::path!("health").map(|| "7");
+        let (address, future) = warp::serve(legacy).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::task::spawn(async move { future.await });
+        let faucet_client = FaucetClient::new(format!("http://{}", address), endpoint);
+        let health = tokio::task::spawn_blocking(move || faucet_client.health_check())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(health.healthy);
+        assert_eq!(health.sequence_number, Some(7));
+        assert_eq!(health.version, None);
+        assert_eq!(health.capabilities, FaucetCapabilities::default());
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_typed() {
+        let (_accounts, service) =
+            setup_service_with(|builder| builder.spend_budget(15, Duration::from_secs(3600)));
+        let endpoint = service.endpoint().to_owned();
+        let filter = routes(service);
+        let (address, future) = warp::serve(filter.clone()).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::task::spawn(async move { future.await });
+        let faucet_url = format!("http://{}", address);
+        let faucet_client = FaucetClient::new(faucet_url.clone(), endpoint);
+
+        let receiver = AccountAddress::random();
+        let url = faucet_client.mint_url(receiver, 10, false).unwrap();
+        assert_eq!(
+            url.as_str(),
+            format!("{}/mint?auth_key={}&amount=10", faucet_url, receiver)
+        );
+        let err = tokio::task::spawn_blocking(move || {
+            faucet_client.request_fund(receiver, 10).unwrap();
+            faucet_client.request_fund(receiver, 10).unwrap_err()
+        })
+        .await
+        .unwrap();
+        let err = err.downcast::<FaucetClientError>().unwrap();
+        assert!(matches!(
+            err,
+            FaucetClientError::RateLimited {
+                retry_after: Some(_),
+                ..
+            }
+        ));
+        assert!(err.is_retriable());
+
+        // Tools sending the requests themselves classify the answers the same way
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/mint?auth_key=0x12zz&amount=10")
+            .reply(&filter)
+            .await;
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let err = FaucetClientError::from_response(resp.status(), resp.headers(), body);
+        assert!(matches!(
+            &err,
+            FaucetClientError::InvalidAddress { field, .. } if field == "auth_key"
+        ));
+        assert!(!err.is_retriable());
+
+        let (_accounts, standby) = setup_service_with(|builder| {
+            builder.leader_election(LeaderElectionConfig::new("127.0.0.1:1".to_string()))
+        });
+        let resp = warp::test::request()
+            .method("POST")
+            .path(&format!("/mint?amount=10&address={}", receiver))
+            .reply(&routes(standby))
+            .await;
+        let body = String::from_utf8(resp.body().to_vec()).unwrap();
+        let err = FaucetClientError::from_response(resp.status(), resp.headers(), body);
+        assert_eq!(
+            err,
+            FaucetClientError::Unavailable {
+                status: 503,
+                retry_after: None,
+                message: "This faucet replica is on standby, try again shortly".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn client_sends_with_injected_clients() {
+        let (accounts, service) = setup_service(None);
+        let endpoint = service.endpoint().to_owned();
+        let (address, future) = warp::serve(routes(service)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::task::spawn(async move { future.await });
+        let faucet_url = format!("http://{}", address);
+
+        let receiver = AccountAddress::random();
+        let (funded, proxied) = tokio::task::spawn_blocking(move || {
+            let faucet_client = FaucetClient::new(faucet_url.clone(), endpoint.clone())
+                .with_client(reqwest::blocking::Client::new())
+                .with_rest_client(reqwest::Client::new());
+            let funded = faucet_client.fund_and_wait(receiver, 10);
+
+            // Nothing listens at the proxy, so the requests only fail if they go through it
+            let proxy = reqwest::Proxy::all("http://127.0.0.1:1").unwrap();
+            let client = reqwest::blocking::Client::builder()
+                .proxy(proxy)
+                .build()
+                .unwrap();
+            let faucet_client = FaucetClient::new(faucet_url, endpoint).with_client(client);
+            (funded, faucet_client.request_fund(receiver, 10))
+        })
+        .await
+        .unwrap();
+        funded.unwrap();
+        assert_eq!(accounts.account(receiver).unwrap().balance, 10);
+        let err = proxied.unwrap_err();
+        assert!(err.downcast_ref::<FaucetClientError>().is_none());
+    }
+
+    #[tokio::test]
+    async fn run_embedded_faucet() {
+        let key = GenerateKey::generate_ed25519_in_memory();
+        let account_address = AuthenticationKey::ed25519(&key.public_key()).derived_address();
+        let (accounts, endpoint) = setup_fullnode(account_address);
+
+        let config = FaucetConfig {
+            address: ([127, 0, 0, 1], 0).into(),
+            server_url: endpoint.clone(),
+            chain_id: ChainId::test(),
+            mint_key: Some(ConfigKey::new(key)),
+            mint_account_address: Some(account_address),
+            do_not_delegate: true,
+            ..FaucetConfig::default()
+        };
+        let (address, shutdown_handle) = run(config).await.unwrap();
+
+        let faucet_client = FaucetClient::new(format!("http://{}", address), endpoint);
+        let receiver = AccountAddress::try_from(
+            "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d".to_owned(),
+        )
+        .unwrap();
+        tokio::task::spawn_blocking(move || faucet_client.fund_and_wait(receiver, 10))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(accounts.account(receiver).unwrap().balance, 10);
+
+        shutdown_handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn run_refuses_chain_id_mismatch() {
+        let key = GenerateKey::generate_ed25519_in_memory();
+        let account_address = AuthenticationKey::ed25519(&key.public_key()).derived_address();
+        let (_accounts, endpoint) = setup_fullnode(account_address);
+
+        let config = FaucetConfig {
+            address: ([127, 0, 0, 1], 0).into(),
+            server_url: endpoint,
+            chain_id: ChainId::new(NamedChain::TESTNET.id()),
+            mint_key: Some(ConfigKey::new(key)),
+            mint_account_address: Some(account_address),
+            do_not_delegate: true,
+            ..FaucetConfig::default()
+        };
+        let err = run(config.clone()).await.err().unwrap();
+        assert!(err.to_string().contains("configured for chain"), "{}", err);
+
+        let (_address, shutdown_handle) = run(FaucetConfig {
+            allow_chain_id_mismatch: true,
+            ..config
+        })
+        .await
+        .unwrap();
+        shutdown_handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn run_waits_for_sync() {
+        let key = GenerateKey::generate_ed25519_in_memory();
+        let account_address = AuthenticationKey::ed25519(&key.public_key()).derived_address();
+        let (accounts, endpoint) = setup_fullnode(account_address);
+        accounts.set_failures(InjectedFailures {
+            ledger_lag: Some(Duration::from_secs(3600)),
+            ..InjectedFailures::default()
+        });
+
+        let config = FaucetConfig {
+            address: ([127, 0, 0, 1], 0).into(),
+            server_url: endpoint,
+            chain_id: ChainId::test(),
+            mint_key: Some(ConfigKey::new(key)),
+            mint_account_address: Some(account_address),
+            do_not_delegate: true,
+            max_sync_lag_secs: Some(60),
+            ..FaucetConfig::default()
+        };
+        let mut started = tokio::spawn(run(config));
+        assert!(tokio::time::timeout(Duration::from_secs(3), &mut started)
+            .await
+            .is_err());
+
+        accounts.set_failures(InjectedFailures::default());
+        let (_address, shutdown_handle) = started.await.unwrap().unwrap();
+        shutdown_handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_self_test() {
+        let (_accounts, service) = setup_service_with(|builder| builder.self_test());
+        let filter = routes(service.clone());
+        let filter = &filter;
+        let ready = move || async move {
+            let resp = warp::test::request()
+                .method("GET")
+                .path("/ready")
+                .reply(filter)
+                .await;
+            let readiness: Readiness = serde_json::from_slice(resp.body()).unwrap();
+            (resp.status(), readiness)
+        };
+
+        let (status, readiness) = ready().await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(readiness.self_test, Some(SelfTestStatus::Pending));
+
+        service.start_background_tasks();
+        for _ in 0..50 {
+            if ready().await.0 == StatusCode::OK {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let (status, readiness) = ready().await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(readiness.ready);
+        assert_eq!(readiness.self_test, Some(SelfTestStatus::Passed));
+    }
+
+    #[tokio::test]
+    async fn test_mint_dry_run() {
+        let (accounts, service) = setup_service(None);
+        let filter = routes(service.clone());
+
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        let resp = warp::test::request()
+            .method("POST")
+            .path(format!("/mint?address={}&amount=100&dry_run=true", address).as_str())
+            .reply(&filter)
+            .await;
+
+        let bytes = hex::decode(resp.body()).expect("hex encoded response body");
+        let txns: Vec<SignedTransaction> = bcs::from_bytes(&bytes).expect("valid bcs vec");
+        assert_eq!(txns.len(), 2);
+        assert_eq!(txns[0].sequence_number(), 0);
+
+        // Nothing reached the node and the sequence number is free for the next request
+        let addr = AccountAddress::try_from(address.to_owned()).unwrap();
+        assert!(accounts.account(addr).is_none());
+        assert_eq!(service.faucet_account.lock().await.sequence_number(), 0);
+    }
+
+    #[tokio::test]
+    async fn run_with_mock_chain() {
+        let config = FaucetConfig {
+            address: ([127, 0, 0, 1], 0).into(),
+            chain_id: ChainId::test(),
+            mock_chain: true,
+            ..FaucetConfig::default()
+        };
+        let (address, shutdown_handle) = run(config).await.unwrap();
+
+        let resp = reqwest::Client::new()
+            .post(format!(
+                "http://{}/mint?address=459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d&amount=10",
+                address
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+        let response: FundResponse = resp.json().await.unwrap();
+        assert_eq!(response.txn_hashes.len(), 2);
+
+        shutdown_handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn run_with_mock_chain_refuses_unreadable_mint_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let mint_key_file_path = dir.path().join("mint.key");
+        std::fs::write(&mint_key_file_path, b"not a key").unwrap();
+        let config = FaucetConfig {
+            address: ([127, 0, 0, 1], 0).into(),
+            chain_id: ChainId::test(),
+            mock_chain: true,
+            mint_key_file_path,
+            ..FaucetConfig::default()
+        };
+        let err = run(config).await.err().unwrap();
+        assert!(err.to_string().contains("Unable to load mint key"));
+    }
+
+    #[tokio::test]
+    async fn run_on_several_addresses() {
+        let config = FaucetConfig {
+            address: ([127, 0, 0, 1], 0).into(),
+            additional_addresses: vec![([127, 0, 0, 1], 0).into()],
+            chain_id: ChainId::test(),
+            mock_chain: true,
+            ..FaucetConfig::default()
+        };
+        let (address, shutdown_handle) = run(config).await.unwrap();
+        let addresses = shutdown_handle.addresses().to_vec();
+        assert_eq!(addresses.len(), 2);
+        assert_eq!(addresses[0], address);
+        assert_ne!(addresses[0], addresses[1]);
+
+        for address in addresses {
+            let resp = reqwest::get(format!("http://{}/health", address))
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        shutdown_handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_path_prefix() {
+        let (_accounts, service) = setup_service_with(|builder| builder.path_prefix("/faucet/"));
+        let filter = routes(service);
+        let filter = &filter;
+        let request = move |method: &str, path: &str| {
+            warp::test::request()
+                .method(method)
+                .path(path)
+                .reply(filter)
+        };
+
+        assert_eq!(
+            request("GET", "/faucet/health").await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            request("GET", "/health").await.status(),
+            StatusCode::NOT_FOUND
+        );
+        let address = "459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d";
+        for path in ["/faucet/mint", "/faucet"] {
+            let path = format!("{}?address={}&amount=10", path, address);
+            assert_eq!(request("POST", &path).await.status(), StatusCode::OK);
+        }
+        let path = format!("/mint?address={}&amount=10", address);
+        assert_eq!(request("POST", &path).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_security_headers() {
+        let (_accounts, service) = setup_service(None);
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/health")
+            .reply(&routes(service))
+            .await;
+        let headers = resp.headers();
+        assert_eq!(headers["x-content-type-options"], "nosniff");
+        assert_eq!(headers["referrer-policy"], "no-referrer");
+        assert_eq!(headers["x-frame-options"], "DENY");
+        assert!(!headers.contains_key("strict-transport-security"));
+
+        let (_accounts, service) = setup_service_with(|builder| {
+            builder.security_headers(SecurityHeadersConfig {
+                hsts_max_age_secs: Some(31536000),
+                referrer_policy: None,
+                ..SecurityHeadersConfig::default()
+            })
+        });
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/health")
+            .reply(&routes(service))
+            .await;
+        let headers = resp.headers();
+        assert_eq!(
+            headers["strict-transport-security"],
+            "max-age=31536000; includeSubDomains"
+        );
+        assert!(!headers.contains_key("referrer-policy"));
+    }
+
+    #[tokio::test]
+    async fn test_chaos() {
+        let (_accounts, service) = setup_service_with(|builder| {
+            builder.chaos(ChaosConfig {
+                rate_limited: 0.0,
+                server_error: 1.0,
+                delayed: 0.0,
+                delay_ms: 0,
+            })
+        });
+        let filter = routes(service);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/mint?address=459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d&amount=10")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(resp.headers()[INJECTED_HEADER], "injected");
+
+        // Probes are left alone
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/health")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_requests() {
+        let (_accounts, service) = setup_service(None);
+        let filter = routes(service);
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/unknown")
+            .header("origin", "https://wallet.example")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert!(resp.headers().contains_key("access-control-allow-origin"));
+        assert_eq!(resp.headers()["x-content-type-options"], "nosniff");
+        let body: RejectionBody = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body.error, "not_found");
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/accounts?address=459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d&wait=maybe")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: RejectionBody = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body.error, "invalid_query");
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/mint")
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let body: RejectionBody = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body.error, "method_not_allowed");
+    }
+
+    #[tokio::test]
+    async fn test_request_limits() {
+        let (_accounts, service) = setup_service_with(|builder| {
+            builder.request_limits(RequestLimitsConfig {
+                max_body_bytes: 100,
+                max_header_bytes: 1_000,
+                max_uri_length: 200,
+            })
+        });
+        let filter = routes(service);
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/health")
+            .header("x-padding", "a".repeat(500))
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/health")
+            .header("x-padding", "a".repeat(1_000))
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+        let body: RejectionBody = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body.error, "headers_too_large");
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path(&format!("/mint?address={}&amount=1", "0".repeat(200)))
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::URI_TOO_LONG);
+        let body: RejectionBody = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body.error, "uri_too_long");
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/fund")
+            .body(format!(
+                r#"{{"address": "0x1", "amount": 1, "pad": "{}"}}"#,
+                "a".repeat(100)
+            ))
+            .reply(&filter)
+            .await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: RejectionBody = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body.error, "payload_too_large");
     }
 }
@@ -1,74 +1,709 @@
 // Copyright (c) Aptos
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::Service;
+use crate::{
+    amount::{self, Amount},
+    ans::{self, Name},
+    audit::AuditEntry,
+    challenge::ChallengeError,
+    checkers::{self, CheckRequest, Verdict},
+    client_ip, counters,
+    daily_limit::{Caller, DailyLimitReached},
+    error::{FaucetError, InvalidField},
+    mint_queue::{self, Lane},
+    partner::{self, SignedRequest},
+    receiver::{self, FieldError},
+    rejection,
+    request_log::{current_request_id, request_context, with_request_id},
+    sharding, OptFmt, Service,
+};
 use anyhow::Result;
-use aptos_crypto::{ed25519::Ed25519PublicKey, hash::HashValue};
 use aptos_logger::{error, info, warn};
-use aptos_sdk::{
-    transaction_builder::aptos_stdlib,
-    types::{
-        account_address::AccountAddress,
-        transaction::{authenticator::AuthenticationKey, SignedTransaction},
-    },
+use aptos_rest_client::{faucet::account_already_exists, CommittedTxn, FundResponse, RestError};
+use aptos_sdk::types::{
+    account_address::AccountAddress, chain_id::ChainId, transaction::SignedTransaction,
 };
 use reqwest::StatusCode;
-use serde::Deserialize;
-use std::{convert::Infallible, fmt, sync::Arc};
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::Infallible,
+    fmt,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tracing::{info_span, Instrument};
 use warp::{Filter, Rejection, Reply};
 
 pub fn mint_routes(
     service: Arc<Service>,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let max_body_bytes = service.request_limits.max_body_bytes;
     // POST /?amount=25&address=xxx
     // POST /mint?amount=25&address=xxx
     warp::path::end()
         .or(warp::path::path("mint"))
         .and(warp::post())
+        .and(client_ip(service.clone()))
+        .and(warp::header::optional::<String>("authorization"))
         .and(warp::any().map(move || service.clone()))
-        .and(warp::query().map(move |params: MintParams| params))
-        .and_then(|_, service, params| handle(service, params))
+        .and(warp::query().map(move |query: MintQuery| query))
+        .and(partner::signed_request(max_body_bytes))
+        .and(mint_queue::deadline())
+        .and(request_context())
+        .and_then(
+            |_, client_ip, authorization, service, query: MintQuery, signed, deadline, context| {
+                with_request_id(
+                    context,
+                    handle(service, query, deadline, client_ip, authorization, signed),
+                )
+            },
+        )
+}
+
+/// Decides on a mint request, then audits, publishes and counts the outcome. Returns the amount funded
+/// along with the response
+pub(crate) async fn mint(
+    service: &Service,
+    mut params: MintParams,
+    client_ip: Option<IpAddr>,
+    authorization: Option<String>,
+    signed: Option<SignedRequest>,
+) -> Result<(u64, Response), FaucetError> {
+    // Resolved up front, so that the funded address is audited. Standbys and banned clients are
+    // refused before anything else
+    let resolved = if !service.is_leader() {
+        Err(FaucetError::Standby)
+    } else if let Some(err) = banned(service, client_ip) {
+        Err(err)
+    } else {
+        resolve_name(service, &mut params).await
+    };
+    let receiver = params.receiver();
+    // Names are sharded by the address they resolve to
+    let resolved = resolved.and_then(|()| misdirected(service, receiver));
+    // Amounts that cannot be converted are rejected by `decide`, and audited as 0
+    let requested_amount = base_units(service, params.requested_field(), params.requested())
+        .await
+        .unwrap_or_default();
+    let span = info_span!(
+        "mint",
+        request_id = ?current_request_id(),
+        receiver = ?receiver,
+        amount = %OptFmt(params.amount.as_ref()),
+        top_up_to = %OptFmt(params.top_up_to.as_ref())
+    );
+    let result = match resolved {
+        Ok(()) => {
+            decide(service, params, client_ip, authorization, signed)
+                .instrument(span)
+                .await
+        }
+        Err(err) => Err(err),
+    };
+
+    if service.audit_log.is_some() || !service.events.is_empty() {
+        let entry = AuditEntry::new(client_ip, receiver, requested_amount, &result);
+        if let Some(audit_log) = &service.audit_log {
+            audit_log.record(&entry);
+        }
+        for events in service.events.iter() {
+            events.publish(entry.clone());
+        }
+    }
+
+    match &result {
+        Ok((_, Response::Funded(_))) | Ok((_, Response::SubmittedTxns(_))) => {
+            counters::FUNDED_REQUESTS.inc()
+        }
+        Ok((_, Response::DryRun(_))) => (),
+        Err(err) => {
+            counters::REJECTED_REQUESTS
+                .with_label_values(&[err.reason()])
+                .inc();
+            record_violation(service, client_ip, err);
+        }
+    }
+    result
+}
+
+/// The refusal of a request from a client that is banned
+fn banned(service: &Service, client_ip: Option<IpAddr>) -> Option<FaucetError> {
+    let retry_after = service.bans.as_ref()?.remaining(client_ip?)?;
+    Some(FaucetError::Banned {
+        message: format!(
+            "Too many refused requests, try again in {} seconds",
+            retry_after.as_secs().max(1)
+        ),
+        retry_after,
+    })
+}
+
+/// Refuses requests for receivers funded by another shard, see [`crate::sharding`]
+fn misdirected(service: &Service, receiver: Option<AccountAddress>) -> Result<(), FaucetError> {
+    let (sharding, receiver) = match (&service.sharding, receiver) {
+        (Some(sharding), Some(receiver)) => (sharding, receiver),
+        _ => return Ok(()),
+    };
+    let shard = sharding::shard_of(receiver, sharding.shards);
+    if shard == sharding.shard {
+        return Ok(());
+    }
+    Err(FaucetError::WrongShard {
+        shard,
+        url: sharding.url(shard).cloned(),
+    })
+}
+
+/// Counts a refused request against its client, see [`crate::bans`]
+fn record_violation(service: &Service, client_ip: Option<IpAddr>, err: &FaucetError) {
+    if let (Some(bans), Some(client_ip)) = (&service.bans, client_ip) {
+        bans.record(client_ip, err);
+    }
 }
 
 async fn handle(
     service: Arc<Service>,
-    params: MintParams,
+    query: MintQuery,
+    deadline: Option<Instant>,
+    client_ip: Option<IpAddr>,
+    authorization: Option<String>,
+    signed: Option<SignedRequest>,
 ) -> Result<Box<dyn warp::Reply>, Infallible> {
-    match process(&service, params).await {
-        Ok(body) => Ok(Box::new(body.to_string())),
-        Err(err) => Ok(Box::new(warp::reply::with_status(
-            err.to_string(),
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ))),
+    let params = match query.parse() {
+        Ok(params) => MintParams { deadline, ..params },
+        Err(err) => {
+            counters::REJECTED_REQUESTS
+                .with_label_values(&[err.reason()])
+                .inc();
+            record_violation(&service, client_ip, &err);
+            return Ok(refused(&service, err));
+        }
+    };
+    match mint(&service, params, client_ip, authorization, signed).await {
+        Ok((_, Response::Funded(response))) => Ok(Box::new(warp::reply::json(&response))),
+        Ok((_, body)) => Ok(Box::new(body.to_string())),
+        Err(err) => Ok(refused(&service, err)),
+    }
+}
+
+/// The reply to a refused mint request
+pub(crate) fn refused(service: &Service, err: FaucetError) -> Box<dyn warp::Reply> {
+    let status = err.status_code();
+    match err {
+        err @ FaucetError::AmountTooSmall { .. } => Box::new(warp::reply::with_status(
+            warp::reply::json(&AmountTooSmall {
+                error: "amount_too_small".to_string(),
+                message: err.to_string(),
+                minimum_amount: service.minimum_amount,
+            }),
+            status,
+        )),
+        FaucetError::InvalidFields(errors) => Box::new(warp::reply::with_status(
+            warp::reply::json(&InvalidParams {
+                error: "invalid_params".to_string(),
+                message: FaucetError::InvalidFields(errors.clone()).to_string(),
+                errors,
+            }),
+            status,
+        )),
+        err @ FaucetError::UnknownChain(_) => Box::new(warp::reply::with_status(
+            warp::reply::json(&UnknownChain {
+                error: "unknown_chain".to_string(),
+                message: err.to_string(),
+                available_networks: vec![Network {
+                    chain_id: service.chain_id.id(),
+                    name: service.chain_id.to_string(),
+                }],
+            }),
+            status,
+        )),
+        err => rejection::faucet_error(&err),
+    }
+}
+
+const MISSING_RECEIVER: &str = "You must provide 'address' (preferred), 'pub_key', or 'auth_key'";
+
+const MISSING_AMOUNT: &str = "You must provide 'amount'";
+
+const TOP_UP_WITH_AMOUNT: &str = "cannot be combined with 'amount'";
+
+const NAME_WITH_RECEIVER: &str = "cannot be combined with 'address', 'pub_key' or 'auth_key'";
+
+/// Body of the `400` for malformed or conflicting fields
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InvalidParams {
+    pub error: String,
+    pub message: String,
+    pub errors: Vec<InvalidField>,
+}
+
+/// Body of the `400` for amounts below the minimum
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AmountTooSmall {
+    pub error: String,
+    pub message: String,
+    pub minimum_amount: u64,
+}
+
+/// Body of the `400` for a `chain_id` the faucet does not serve
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UnknownChain {
+    pub error: String,
+    pub message: String,
+    /// The networks requests can be made for
+    pub available_networks: Vec<Network>,
+}
+
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Network {
+    pub chain_id: u8,
+    /// The chain's name for named chains, its id otherwise
+    pub name: String,
+}
+
+/// Points the request at the address its `name` resolves to, if it names its receiver that way
+async fn resolve_name(service: &Service, params: &mut MintParams) -> Result<(), FaucetError> {
+    let name = match params.name.take() {
+        Some(name) => name,
+        None => return Ok(()),
+    };
+    let contract = service.ans_contract.ok_or_else(|| {
+        FaucetError::InvalidParams("This faucet does not resolve names".to_string())
+    })?;
+    if params.address.is_some() || params.pub_key.is_some() || params.auth_key.is_some() {
+        return Err(InvalidField::new("name", NAME_WITH_RECEIVER).into());
+    }
+    let name: Name = name.parse()?;
+    let address = ans::resolve(&service.client, contract, &name)
+        .instrument(info_span!("resolve"))
+        .await?;
+    if service.log_sampler.sample(module_path!()) {
+        info!(
+            request_id = current_request_id(),
+            name = %name,
+            address = %address,
+            "[faucet]: resolved name"
+        );
+    }
+    params.address = Some(address.to_hex_literal());
+    Ok(())
+}
+
+/// Verifies the request's partner signature, waits for a processing slot and redeems the
+/// request's coupon, if any, then decides on the request. The coupon is only used up if the
+/// request is funded
+async fn decide(
+    service: &Service,
+    mut params: MintParams,
+    client_ip: Option<IpAddr>,
+    authorization: Option<String>,
+    signed: Option<SignedRequest>,
+) -> Result<(u64, Response), FaucetError> {
+    let partner = match (&service.partners, signed) {
+        (_, None) => None,
+        (Some(partners), Some(signed)) => Some(
+            partners
+                .verify(&signed)
+                .map_err(|err| FaucetError::Unauthorized(err.to_string()))?,
+        ),
+        (None, Some(_)) => {
+            return Err(FaucetError::Unauthorized(
+                "This faucet does not accept signed requests".to_string(),
+            ))
+        }
+    };
+    if let Some(partner) = &partner {
+        if service.log_sampler.sample(module_path!()) {
+            info!(
+                request_id = current_request_id(),
+                partner = partner,
+                "[faucet]: signed request"
+            );
+        }
+    }
+
+    let lane = if partner.is_some() {
+        Lane::Authenticated
+    } else {
+        Lane::Anonymous
+    };
+    let _slot = match &service.mint_queue {
+        Some(queue) => Some(
+            queue
+                .acquire(lane, params.deadline)
+                .instrument(info_span!("queue"))
+                .await
+                .ok_or(FaucetError::Expired)?,
+        ),
+        None => None,
+    };
+
+    let coupon = match params.coupon.take() {
+        Some(code) => match service.coupons.redeem(&code) {
+            Some(amount) => Some((code, amount)),
+            None => return Err(FaucetError::InvalidCoupon),
+        },
+        None => None,
+    };
+
+    let coupon_amount = coupon.as_ref().map(|(_, amount)| *amount);
+    let result = check_and_fund(
+        service,
+        params,
+        client_ip,
+        authorization,
+        coupon_amount,
+        partner.as_deref(),
+    )
+    .await;
+
+    if let (Some(partners), Some(partner)) = (&service.partners, &partner) {
+        let outcome = match &result {
+            Ok((amount, Response::Funded(_))) | Ok((amount, Response::SubmittedTxns(_))) => {
+                partner::Outcome::Funded(*amount)
+            }
+            Ok((_, Response::DryRun(_))) => partner::Outcome::DryRun,
+            Err(_) => partner::Outcome::Refused,
+        };
+        partners.record(partner, outcome);
+    }
+
+    if let Some((code, amount)) = coupon {
+        if !matches!(
+            result,
+            Ok((_, Response::Funded(_))) | Ok((_, Response::SubmittedTxns(_)))
+        ) {
+            service.coupons.restore(code, amount);
+        }
+    }
+    result
+}
+
+/// Runs every check the service is configured with, then funds the request if they all pass.
+/// Requests with a coupon mint the coupon's amount, without the IP limits, amount caps, daily
+/// limit and spend budget applying. Partner requests skip the IP limits, signature challenge and
+/// checkers, and have a daily limit of their own.
+/// Returns the amount funded along with the response
+async fn check_and_fund(
+    service: &Service,
+    params: MintParams,
+    client_ip: Option<IpAddr>,
+    authorization: Option<String>,
+    coupon_amount: Option<u64>,
+    partner: Option<&str>,
+) -> Result<(u64, Response), FaucetError> {
+    let is_partner = partner.is_some();
+    if let Some(chain_id) = &params.chain_id {
+        if chain_id.parse::<ChainId>().ok() != Some(service.chain_id) {
+            return Err(FaucetError::UnknownChain(chain_id.clone()));
+        }
+    }
+
+    let has_coupon = coupon_amount.is_some();
+    let cooldown = service
+        .cooldown
+        .as_ref()
+        .filter(|_| !has_coupon && !is_partner && !params.create_only);
+    if let (Some(cooldown), Some(receiver)) = (cooldown, params.receiver()) {
+        if let Some(retry_after) = cooldown.remaining(receiver) {
+            return Err(cooling_down(receiver, retry_after));
+        }
+    }
+
+    let limiter = service
+        .ip_limiter
+        .as_ref()
+        .filter(|_| !has_coupon && !is_partner);
+    if let (Some(limiter), Some(client_ip)) = (limiter, client_ip) {
+        limiter
+            .check(client_ip)
+            .map_err(|err| FaucetError::RateLimited {
+                message: err.to_string(),
+                retry_after: err.retry_after,
+            })?;
+    }
+
+    let receiver_address = params
+        .checked_receiver()?
+        .ok_or_else(|| FaucetError::InvalidParams(MISSING_RECEIVER.to_string()))?;
+    if params.amount.is_some() && params.top_up_to.is_some() {
+        return Err(InvalidField::new("top_up_to", TOP_UP_WITH_AMOUNT).into());
+    }
+    let requested_amount = match coupon_amount {
+        Some(_) if params.requested().is_some() => {
+            return Err(InvalidField::new(
+                params.requested_field(),
+                "cannot be combined with a coupon, which sets the amount",
+            )
+            .into())
+        }
+        Some(amount) => amount,
+        None if params.create_only => 0,
+        None => base_units(service, params.requested_field(), params.requested()).await?,
+    };
+    // Minting nothing only creates the account, which is how SDKs create accounts
+    if requested_amount != 0 && requested_amount < service.minimum_amount {
+        return Err(FaucetError::AmountTooSmall {
+            minimum: service.minimum_amount,
+        });
+    }
+
+    if let Some(challenges) = service.challenges.as_ref().filter(|_| !is_partner) {
+        info_span!("validate")
+            .in_scope(
+                || match (&params.nonce, &params.signature, &params.pub_key) {
+                    (Some(nonce), Some(signature), Some(pub_key)) => {
+                        // Already checked along with the receiver
+                        let pub_key = receiver::parse_pub_key(pub_key)
+                            .map_err(|_| ChallengeError::WrongKey)?;
+                        challenges.verify(receiver_address, nonce, &pub_key, signature)
+                    }
+                    _ => Err(ChallengeError::Missing),
+                },
+            )
+            .map_err(|err| FaucetError::ChallengeFailed(err.to_string()))?;
+    }
+
+    if params.create_only
+        && counters::node_request("get_account", service.client.get_account(receiver_address))
+            .await
+            .is_ok()
+    {
+        return Err(FaucetError::AccountExists);
+    }
+
+    // Only the shortfall is funded when topping up
+    let mut amount = match params.top_up_to {
+        Some(_) => requested_amount.saturating_sub(
+            balance(service, receiver_address)
+                .await
+                .map_err(FaucetError::Node)?,
+        ),
+        None => requested_amount,
+    };
+
+    let caller = Caller::new(partner, client_ip, receiver_address);
+    let receiver = Some(receiver_address);
+    let request = CheckRequest {
+        client_ip,
+        authorization,
+        receiver,
+        amount,
+    };
+    let verdict = if is_partner {
+        Verdict::Allow
+    } else {
+        checkers::check_all(&service.checkers, &request)
+            .instrument(info_span!("checkers"))
+            .await
+    };
+    match verdict {
+        Verdict::Allow => (),
+        Verdict::Cap(_) if has_coupon => (),
+        Verdict::Cap(cap) => amount = std::cmp::min(amount, cap),
+        Verdict::Deny(reason) => return Err(FaucetError::Blocked(reason)),
+    }
+
+    let budget = service.spend_budget.as_ref().filter(|_| !has_coupon);
+    let amount = if has_coupon {
+        amount
+    } else {
+        capped(amount, service.maximum_amount)
+    };
+    // Started right before funding, so that concurrent requests for the same receiver cannot
+    // both get through
+    if let Some(cooldown) = cooldown {
+        cooldown
+            .start(receiver_address)
+            .map_err(|retry_after| cooling_down(receiver_address, retry_after))?;
+    }
+    let daily_limit = service
+        .daily_limit
+        .as_ref()
+        .filter(|_| !has_coupon && !params.create_only && amount > 0);
+    let amount = match daily_limit {
+        // Top ups may fall short of the minimum amount, and are not refused for it
+        Some(daily_limit) => match daily_limit
+            .reserve(
+                &caller,
+                amount,
+                std::cmp::min(amount, service.minimum_amount),
+            )
+            .await
+        {
+            Ok(amount) => amount,
+            Err(err) => {
+                if let Some(cooldown) = cooldown {
+                    cooldown.cancel(receiver_address);
+                }
+                return Err(match err.downcast::<DailyLimitReached>() {
+                    Ok(reached) => FaucetError::RateLimited {
+                        message: reached.to_string(),
+                        retry_after: reached.retry_after,
+                    },
+                    Err(err) => FaucetError::Funding(err),
+                });
+            }
+        },
+        None => amount,
+    };
+    if let Some(budget) = budget {
+        if let Err(err) = budget.try_spend(amount) {
+            if let Some(cooldown) = cooldown {
+                cooldown.cancel(receiver_address);
+            }
+            if let Some(daily_limit) = daily_limit {
+                daily_limit.refund(&caller, amount).await;
+            }
+            return Err(FaucetError::BudgetExhausted {
+                message: err.to_string(),
+                retry_after: err.retry_after,
+            });
+        }
+    }
+
+    let result = fund(service, params, amount).await;
+
+    // Only coins that were actually submitted count against the budget, the daily limit and
+    // the cooldown. Transactions whose wait failed may still commit, so they count
+    let submitted = match &result {
+        Ok(Response::DryRun(_)) => false,
+        Ok(_) => true,
+        Err(err) => err.is::<WaitFailed>(),
+    };
+    if !submitted {
+        if let Some(budget) = budget {
+            budget.refund(amount);
+        }
+        if let Some(daily_limit) = daily_limit {
+            daily_limit.refund(&caller, amount).await;
+        }
+        if let Some(cooldown) = cooldown {
+            cooldown.cancel(receiver_address);
+        }
+    }
+
+    if let (Some(sybil), Some(receiver)) = (&service.sybil, receiver) {
+        if matches!(
+            result,
+            Ok(Response::Funded(_)) | Ok(Response::SubmittedTxns(_))
+        ) {
+            sybil.record(client_ip, receiver);
+        }
+    }
+
+    result
+        .map(|response| (amount, response))
+        .map_err(FaucetError::Funding)
+}
+
+/// Waiting on the funding transactions failed once they were all submitted, so they may still
+/// commit
+#[derive(Debug, Error)]
+#[error(transparent)]
+struct WaitFailed(anyhow::Error);
+
+fn cooling_down(receiver: AccountAddress, retry_after: Duration) -> FaucetError {
+    FaucetError::RateLimited {
+        message: format!(
+            "{} was funded recently, try again in {} seconds",
+            receiver,
+            retry_after.as_secs().max(1)
+        ),
+        retry_after,
     }
 }
 
 #[derive(Debug)]
 pub enum Response {
     SubmittedTxns(Vec<SignedTransaction>),
-    SubmittedTxnsHashes(Vec<HashValue>),
+    Funded(FundResponse),
+    /// Signed but never submitted, see [`MintParams::dry_run`]
+    DryRun(Vec<SignedTransaction>),
 }
 
 impl std::fmt::Display for Response {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Response::SubmittedTxns(value) => {
+            Response::SubmittedTxns(value) | Response::DryRun(value) => {
                 write!(f, "{}", hex::encode(bcs::to_bytes(&value).unwrap()))
             }
-            Response::SubmittedTxnsHashes(value) => {
+            Response::Funded(value) => {
                 write!(f, "{}", serde_json::to_string(&value).unwrap())
             }
         }
     }
 }
 
-#[derive(Deserialize, Debug)]
+/// How far a request's transactions get before it is answered
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitFor {
+    /// Accepted by the node
+    Submitted,
+    /// Committed, with their outcome and the receiver's resulting balance reported
+    Executed,
+    /// Committed, and the ledger moved the faucet's finality lag of versions past them
+    Finalized,
+}
+
+impl std::str::FromStr for WaitFor {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "submitted" => Ok(WaitFor::Submitted),
+            "executed" => Ok(WaitFor::Executed),
+            "finalized" => Ok(WaitFor::Finalized),
+            _ => Err("must be submitted, executed or finalized"),
+        }
+    }
+}
+
+/// How often the ledger version is polled while waiting for finality
+const FINALITY_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Polls of the ledger version before giving up on finality
+const FINALITY_POLLS: usize = 60;
+
+#[derive(Deserialize, Debug, Default)]
 pub struct MintParams {
-    pub amount: u64,
+    /// In base units, or in whole coins like `1.5APT`. Defaults to the service's default amount
+    pub amount: Option<Amount>,
+    /// Fund the receiver up to this balance instead of by `amount`, nothing if it already
+    /// holds as much
+    pub top_up_to: Option<Amount>,
     pub auth_key: Option<String>,
     pub address: Option<String>,
-    pub pub_key: Option<Ed25519PublicKey>,
+    /// Hex encoded ed25519 public key, funding the address derived from it
+    pub pub_key: Option<String>,
+    /// Aptos name like `alice.apt`, funding the address it points to, see [`crate::ans`]
+    pub name: Option<String>,
     pub return_txns: Option<bool>,
+    /// Build and sign the transactions without submitting them
+    pub dry_run: Option<bool>,
+    /// Wait for the transactions to commit, and report their outcome and the receiver's
+    /// resulting balance
+    pub wait: Option<bool>,
+    /// Takes precedence over `wait`, which is `executed` when set and `submitted` otherwise
+    pub wait_for: Option<WaitFor>,
+    /// Nonce from `GET /challenge`, required when the faucet uses challenges
+    pub nonce: Option<String>,
+    /// Hex encoded signature of `nonce` by `pub_key`
+    pub signature: Option<String>,
+    /// Code from `POST /admin/coupons`, minting the coupon's amount
+    pub coupon: Option<String>,
+    /// Chain the request is meant for, by name or id. Requests for another chain than the
+    /// faucet's are refused
+    pub chain_id: Option<String>,
+    /// Only create the receiver's account, without minting anything, see [`crate::accounts`]
+    #[serde(skip)]
+    pub create_only: bool,
+    /// When the client gives up on the request, from its [`mint_queue::DEADLINE_HEADER`]
+    #[serde(skip)]
+    pub deadline: Option<Instant>,
 }
 
 impl std::fmt::Display for MintParams {
@@ -78,113 +713,335 @@ impl std::fmt::Display for MintParams {
 }
 
 impl MintParams {
+    fn wait_for(&self) -> WaitFor {
+        match (self.wait_for, self.wait) {
+            (Some(wait_for), _) => wait_for,
+            (None, Some(true)) => WaitFor::Executed,
+            (None, _) => WaitFor::Submitted,
+        }
+    }
+
+    /// The amount, or the target balance when topping up
+    fn requested(&self) -> Option<&Amount> {
+        self.top_up_to.as_ref().or_else(|| self.amount.as_ref())
+    }
+
+    /// The field [`MintParams::requested`] is from
+    fn requested_field(&self) -> &'static str {
+        match self.top_up_to {
+            Some(_) => "top_up_to",
+            None => "amount",
+        }
+    }
+
+    /// The receiver, none if no receiver field is set. Fails on any malformed receiver field
+    fn checked_receiver(&self) -> Result<Option<AccountAddress>, FieldError> {
+        receiver::receiver(
+            self.address.as_deref(),
+            self.auth_key.as_deref(),
+            self.pub_key.as_deref(),
+        )
+    }
+
+    /// The receiver, none if it is not given or malformed
     fn receiver(&self) -> Option<AccountAddress> {
-        if let Some(auth_key) = self.auth_key.as_ref() {
-            return match AccountAddress::from_hex_literal(auth_key) {
-                Ok(auth_key) => Some(auth_key),
-                Err(_) => match AccountAddress::from_hex(auth_key) {
-                    Ok(auth_key) => Some(auth_key),
-                    Err(_) => None,
-                },
-            };
-        }
-        if let Some(address) = self.address.as_ref() {
-            return match AccountAddress::from_hex_literal(address) {
-                Ok(address) => Some(address),
-                Err(_) => match AccountAddress::from_hex(address) {
-                    Ok(address) => Some(address),
-                    Err(_) => None,
-                },
-            };
+        self.checked_receiver().ok().flatten()
+    }
+}
+
+/// The query string of `/mint`, every value taken as given so that malformed ones are refused
+/// field by field, rather than failing the whole query
+#[derive(Deserialize, Debug, Default)]
+pub struct MintQuery {
+    pub amount: Option<String>,
+    pub top_up_to: Option<String>,
+    pub auth_key: Option<String>,
+    pub address: Option<String>,
+    pub pub_key: Option<String>,
+    pub name: Option<String>,
+    pub return_txns: Option<String>,
+    pub dry_run: Option<String>,
+    pub wait: Option<String>,
+    pub wait_for: Option<String>,
+    pub nonce: Option<String>,
+    pub signature: Option<String>,
+    pub coupon: Option<String>,
+    pub chain_id: Option<String>,
+}
+
+impl MintQuery {
+    /// The params the query stands for, or every field that is malformed or conflicts with
+    /// another
+    pub fn parse(self) -> Result<MintParams, FaucetError> {
+        let mut errors = Vec::new();
+        let amount = parse_field(&mut errors, "amount", self.amount, str::parse::<Amount>);
+        let top_up_to = parse_field(
+            &mut errors,
+            "top_up_to",
+            self.top_up_to,
+            str::parse::<Amount>,
+        );
+        if amount.is_some() && top_up_to.is_some() {
+            errors.push(InvalidField::new("top_up_to", TOP_UP_WITH_AMOUNT));
+        }
+        for (field, value) in [("address", &self.address), ("auth_key", &self.auth_key)] {
+            if let Some(value) = value {
+                if let Err(err) = receiver::parse_address(field, value) {
+                    errors.push(err.into());
+                }
+            }
+        }
+        if let Some(Err(err)) = self.pub_key.as_deref().map(receiver::parse_pub_key) {
+            errors.push(err.into());
+        }
+        if let Some(Err(err)) = self.name.as_deref().map(str::parse::<Name>) {
+            errors.push(err);
+        }
+        if self.name.is_some()
+            && (self.address.is_some() || self.auth_key.is_some() || self.pub_key.is_some())
+        {
+            errors.push(InvalidField::new("name", NAME_WITH_RECEIVER));
+        }
+        let return_txns = parse_field(&mut errors, "return_txns", self.return_txns, parse_bool);
+        let dry_run = parse_field(&mut errors, "dry_run", self.dry_run, parse_bool);
+        let wait = parse_field(&mut errors, "wait", self.wait, parse_bool);
+        let wait_for = parse_field(
+            &mut errors,
+            "wait_for",
+            self.wait_for,
+            str::parse::<WaitFor>,
+        );
+
+        if !errors.is_empty() {
+            return Err(FaucetError::InvalidFields(errors));
         }
-        if let Some(pub_key) = self.pub_key.as_ref() {
-            return Some(AuthenticationKey::ed25519(pub_key).derived_address());
+        Ok(MintParams {
+            amount,
+            top_up_to,
+            auth_key: self.auth_key,
+            address: self.address,
+            pub_key: self.pub_key,
+            name: self.name,
+            return_txns,
+            dry_run,
+            wait,
+            wait_for,
+            nonce: self.nonce,
+            signature: self.signature,
+            coupon: self.coupon,
+            chain_id: self.chain_id,
+            ..MintParams::default()
+        })
+    }
+}
+
+/// `value` parsed, or none after noting why it is malformed in `errors`
+fn parse_field<T, E: fmt::Display>(
+    errors: &mut Vec<InvalidField>,
+    field: &str,
+    value: Option<String>,
+    parse: impl FnOnce(&str) -> Result<T, E>,
+) -> Option<T> {
+    match parse(value.as_deref()?) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            errors.push(InvalidField::new(field, err));
+            None
         }
-        None
     }
 }
 
-pub async fn process(service: &Service, params: MintParams) -> Result<Response> {
-    let maybe_maximum_amount = service.maximum_amount.unwrap_or(params.amount);
-    let amount = std::cmp::min(params.amount, maybe_maximum_amount);
+fn parse_bool(value: &str) -> Result<bool, &'static str> {
+    value.parse().map_err(|_| "must be true or false")
+}
 
-    let receiver_address = params.receiver().ok_or_else(|| {
-        anyhow::format_err!("You must provide 'address' (preferred), 'pub_key', or 'auth_key'")
-    })?;
+/// `amount`, capped at `maximum_amount`
+fn capped(amount: u64, maximum_amount: Option<u64>) -> u64 {
+    std::cmp::min(amount, maximum_amount.unwrap_or(amount))
+}
+
+/// `amount` in base units, or the default amount if there is none. The coin's decimals are
+/// fetched from chain for amounts in whole coins, which are refused as the request's `field`
+/// if they do not convert
+async fn base_units(
+    service: &Service,
+    field: &str,
+    amount: Option<&Amount>,
+) -> Result<u64, FaucetError> {
+    let amount = match (amount, service.default_amount) {
+        (Some(Amount::Base(amount)), _) => return Ok(*amount),
+        (Some(amount), _) => amount,
+        (None, Some(default_amount)) => return Ok(default_amount),
+        (None, None) => return Err(FaucetError::InvalidParams(MISSING_AMOUNT.to_string())),
+    };
+    let decimals = service
+        .coin_decimals
+        .get_or_try_init(|| amount::coin_decimals(&service.client))
+        .await
+        .map_err(FaucetError::Node)?;
+    amount
+        .base_units(*decimals)
+        .map_err(|err| InvalidField::new(field, err).into())
+}
+
+pub async fn process(service: &Service, params: MintParams) -> Result<Response, FaucetError> {
+    let amount = capped(
+        base_units(service, "amount", params.amount.as_ref()).await?,
+        service.maximum_amount,
+    );
+    fund(service, params, amount)
+        .await
+        .map_err(FaucetError::Funding)
+}
+
+/// Creates the receiver if needed and mints exactly `amount` to it
+pub(crate) async fn fund(service: &Service, params: MintParams, amount: u64) -> Result<Response> {
+    let receiver_address = params
+        .receiver()
+        .ok_or_else(|| anyhow::format_err!(MISSING_RECEIVER))?;
+    // Every funding transaction goes through here, which makes sure standbys never submit one
+    if !service.is_leader() {
+        anyhow::bail!("Standbys do not submit transactions");
+    }
 
-    let (mut faucet_seq, mut receiver_seq) = sequences(service, receiver_address).await?;
+    // The funder is replaced on redelegation, so the sequence numbers fetched for one funder must
+    // never be applied to another
+    let faucet_address = service.faucet_account.lock().await.address();
+    let (mut faucet_seq, mut receiver_seq) = sequences(service, faucet_address, receiver_address)
+        .instrument(info_span!("sequences"))
+        .await?;
     let our_faucet_seq = {
         let mut faucet_account = service.faucet_account.lock().await;
 
         // If the onchain sequence_number is greater than what we have, update our
         // sequence_numbers
-        if faucet_seq > faucet_account.sequence_number() {
+        if faucet_account.address() == faucet_address
+            && faucet_seq > faucet_account.sequence_number()
+        {
             *faucet_account.sequence_number_mut() = faucet_seq;
         }
         faucet_account.sequence_number()
     };
 
     // We shouldn't have too many outstanding txns
+    let max_in_flight = service.max_in_flight_transactions;
     for _ in 0..60 {
-        if our_faucet_seq < faucet_seq + 50 {
+        if our_faucet_seq < faucet_seq + max_in_flight {
             break;
         }
         warn!(
+            request_id = current_request_id(),
             "We have too many outstanding transactions: {}. Sleeping to let the system catchup.",
             (our_faucet_seq - faucet_seq)
         );
 
         tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-        let (lhs, rhs) = sequences(service, receiver_address).await?;
+        let (lhs, rhs) = sequences(service, faucet_address, receiver_address).await?;
         faucet_seq = lhs;
         receiver_seq = rhs;
     }
 
     // After 30 seconds, we still have not caught up, we are likely unhealthy
-    if our_faucet_seq >= faucet_seq + 50 {
-        error!("We are unhealthy, transactions have likely expired.");
+    if our_faucet_seq >= faucet_seq + max_in_flight {
+        error!(
+            request_id = current_request_id(),
+            "We are unhealthy, transactions have likely expired."
+        );
         let mut faucet_account = service.faucet_account.lock().await;
-        if faucet_account.sequence_number() >= faucet_seq + 50 {
-            info!("Resetting the sequence number counter.");
+        if faucet_account.address() == faucet_address
+            && faucet_account.sequence_number() >= faucet_seq + max_in_flight
+        {
+            info!(
+                request_id = current_request_id(),
+                "Resetting the sequence number counter."
+            );
             *faucet_account.sequence_number_mut() = faucet_seq;
         } else {
-            info!("Someone else reset the sequence number counter ahead of us.");
+            info!(
+                request_id = current_request_id(),
+                "Someone else reset the sequence number counter ahead of us."
+            );
         }
     }
 
+    let dry_run = service.dry_run || params.dry_run.unwrap_or(false);
     let mut txns = vec![];
 
-    {
+    let starting_seq = {
         let mut faucet_account = service.faucet_account.lock().await;
+        // Replicas sharing the funder sign with the sequence numbers they leased. Dry runs hand
+        // theirs back, so they sign with whatever comes next
+        if let (Some(leases), false) = (&service.sequence_leases, dry_run) {
+            let count = receiver_seq.is_none() as u64 + (amount != 0) as u64;
+            *faucet_account.sequence_number_mut() = leases
+                .take(count, faucet_seq)
+                .instrument(info_span!("lease"))
+                .await?;
+        }
+        let _build = info_span!("build").entered();
+        let starting_seq = faucet_account.sequence_number();
 
         if receiver_seq.is_none() {
-            let builder =
-                service
-                    .transaction_factory
-                    .payload(aptos_stdlib::encode_account_create_account(
-                        receiver_address,
-                    ));
+            let builder = service
+                .transaction_factory
+                .payload(service.funding.create_account(receiver_address));
 
             let txn = faucet_account.sign_with_transaction_builder(builder);
             txns.push(txn)
         }
 
         if amount != 0 {
-            txns.push(
-                faucet_account.sign_with_transaction_builder(service.transaction_factory.payload(
-                    aptos_stdlib::encode_test_coin_mint(receiver_address, amount),
-                )),
-            );
+            let builder = service
+                .transaction_factory
+                .payload(service.funding.fund(receiver_address, amount));
+            txns.push(faucet_account.sign_with_transaction_builder(builder));
         }
+
+        // Nothing will be submitted, so hand the sequence numbers back for real requests
+        if dry_run {
+            *faucet_account.sequence_number_mut() = starting_seq;
+        }
+        starting_seq
+    };
+
+    if dry_run {
+        return Ok(Response::DryRun(txns));
     }
 
-    let requests = txns.iter().map(|txn| service.client.submit(txn));
-    let mut responses = futures::future::join_all(requests).await;
+    let requests = txns
+        .iter()
+        .map(|txn| counters::node_request("submit", service.client.submit(txn)));
+    let mut responses = futures::future::join_all(requests)
+        .instrument(info_span!("submit"))
+        .await;
+    for (txn, response) in txns.iter().zip(&responses) {
+        if response.is_ok() {
+            service.outstanding.record(faucet_address, txn);
+        }
+    }
 
     // If there was an issue submitting a transaction we should just reset our sequence_numbers
     // to what was on chain
     if responses.iter().any(Result::is_err) {
-        *service.faucet_account.lock().await.sequence_number_mut() = faucet_seq;
+        // The cached sequence number may be what got the transactions rejected
+        if let Some(cache) = &service.funder_cache {
+            cache.invalidate().await;
+        }
+        match &service.sequence_leases {
+            // The others' transactions may hold any sequence number from the on-chain one, so only
+            // ours are handed back. Those of partly submitted requests are gaps, see `gaps`
+            Some(leases) => {
+                if responses.iter().all(Result::is_err) {
+                    leases.give_back(starting_seq, txns.len() as u64);
+                }
+            }
+            None => {
+                let mut faucet_account = service.faucet_account.lock().await;
+                if faucet_account.address() == faucet_address {
+                    *faucet_account.sequence_number_mut() = faucet_seq;
+                }
+            }
+        }
     }
 
     while !responses.is_empty() {
@@ -195,30 +1052,149 @@ pub async fn process(service: &Service, params: MintParams) -> Result<Response>
     if params.return_txns.unwrap_or(false) {
         Ok(Response::SubmittedTxns(txns))
     } else {
-        let hashes = txns
+        let txn_hashes = txns
             .iter()
             .map(|txn| txn.clone().committed_hash())
             .collect();
-        Ok(Response::SubmittedTxnsHashes(hashes))
+        let mut response = FundResponse {
+            destination: receiver_address,
+            amount,
+            txn_hashes,
+            balance: None,
+            txns: Vec::new(),
+        };
+        let wait_for = params.wait_for();
+        if wait_for != WaitFor::Submitted {
+            let (committed_txns, versions): (Vec<_>, Vec<_>) =
+                futures::future::try_join_all(txns.iter().map(|txn| committed(service, txn)))
+                    .instrument(info_span!("wait"))
+                    .await
+                    .map_err(WaitFailed)?
+                    .into_iter()
+                    .unzip();
+            response.txns = committed_txns;
+            if wait_for == WaitFor::Finalized {
+                if let Some(version) = versions.into_iter().max() {
+                    finalized(service, version)
+                        .instrument(info_span!("finality"))
+                        .await
+                        .map_err(WaitFailed)?;
+                }
+            }
+            // The account was created since it was looked up. The mint does not depend on the
+            // creation succeeding, so the failed creation is left out instead of failing the
+            // request
+            let creation_failed = response.txns.first().map_or(false, |txn| {
+                !txn.success && account_already_exists(&txn.vm_status)
+            });
+            if receiver_seq.is_none() && creation_failed {
+                if service.log_sampler.sample(module_path!()) {
+                    info!(
+                        request_id = current_request_id(),
+                        "[faucet]: {} already existed, skipping its creation", receiver_address
+                    );
+                }
+                response.txn_hashes.remove(0);
+                response.txns.remove(0);
+            }
+            response.balance = balance(service, receiver_address).await.ok();
+        }
+        Ok(Response::Funded(response))
     }
 }
 
-async fn sequences(service: &Service, receiver: AccountAddress) -> Result<(u64, Option<u64>)> {
-    let faucet_address = service.faucet_account.lock().await.address();
-    let f_request = service.client.get_account(faucet_address);
-    let r_request = service.client.get_account(receiver);
-    let mut responses = futures::future::join_all([f_request, r_request]).await;
+/// Waits for `txn` to commit, reporting transactions that failed to execute rather than
+/// erroring. Returns the version it committed at along with its outcome
+pub(crate) async fn committed(
+    service: &Service,
+    txn: &SignedTransaction,
+) -> Result<(CommittedTxn, u64)> {
+    let hash = txn.clone().committed_hash();
+    let wait = service.client.wait_for_signed_transaction(txn);
+    let transaction = match counters::node_request("wait", wait).await {
+        Ok(transaction) => transaction.into_inner(),
+        Err(err) => {
+            let lookup = service.client.get_transaction(hash);
+            match counters::node_request("get_transaction", lookup).await {
+                Ok(transaction) if !transaction.inner().is_pending() => transaction.into_inner(),
+                _ => return Err(err),
+            }
+        }
+    };
+    let info = transaction.transaction_info()?;
+    let committed = CommittedTxn {
+        hash,
+        gas_used: info.gas_used.into(),
+        success: info.success,
+        vm_status: info.vm_status.clone(),
+    };
+    Ok((committed, info.version.into()))
+}
 
-    let receiver_seq_num = responses
-        .remove(1)
-        .as_ref()
+/// Waits for the ledger to be the service's finality lag of versions past `version`
+async fn finalized(service: &Service, version: u64) -> Result<()> {
+    let target = version.saturating_add(service.finality_version_lag);
+    for _ in 0..FINALITY_POLLS {
+        let ledger = service.client.get_ledger_information();
+        let ledger_version = counters::node_request("get_ledger_information", ledger)
+            .await?
+            .into_inner()
+            .version;
+        if ledger_version >= target {
+            return Ok(());
+        }
+        tokio::time::sleep(FINALITY_POLL_INTERVAL).await;
+    }
+    Err(anyhow::format_err!(
+        "the ledger did not reach version {} to finalize the transactions",
+        target
+    ))
+}
+
+/// Balance of `address`, 0 if the account does not exist yet
+async fn balance(service: &Service, address: AccountAddress) -> Result<u64> {
+    match counters::node_request(
+        "get_account_balance",
+        service.client.get_account_balance(address),
+    )
+    .await
+    {
+        Ok(balance) => Ok(balance.into_inner().get()),
+        Err(err)
+            if err.downcast_ref::<RestError>().map_or(false, |err| {
+                err.code == StatusCode::NOT_FOUND.as_u16() as u32
+            }) =>
+        {
+            Ok(0)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+async fn sequences(
+    service: &Service,
+    faucet_address: AccountAddress,
+    receiver: AccountAddress,
+) -> Result<(u64, Option<u64>)> {
+    let f_request = async {
+        match &service.funder_cache {
+            Some(cache) => cache.sequence_number(&service.client, faucet_address).await,
+            None => {
+                counters::node_request("get_account", service.client.get_account(faucet_address))
+                    .await
+                    .map(|account| account.inner().sequence_number)
+                    .map_err(Into::into)
+            }
+        }
+    };
+    let r_request = counters::node_request("get_account", service.client.get_account(receiver));
+    let (faucet_response, receiver_response) = futures::future::join(f_request, r_request).await;
+
+    let receiver_seq_num = receiver_response
         .ok()
         .map(|account| account.inner().sequence_number);
-    let faucet_seq_num = responses
-        .remove(0)
-        .map_err(|_| anyhow::format_err!("faucet account {} not found", faucet_address))?
-        .inner()
-        .sequence_number;
+    let faucet_seq_num = faucet_response
+        .map_err(|_| anyhow::format_err!("faucet account {} not found", faucet_address))?;
 
     Ok((faucet_seq_num, receiver_seq_num))
 }
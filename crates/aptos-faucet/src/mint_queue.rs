@@ -0,0 +1,324 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Bounds how many mint requests are processed at once. Requests beyond the bound wait in one
+//! of two lanes, and a freed slot always goes to the authenticated lane first, so that partners'
+//! CI pipelines are not starved when anonymous traffic spikes.
+//!
+//! Waiting requests are dropped once their client has certainly given up on them, rather than
+//! using up sequence numbers on transactions nobody waits for: clients can announce how long they
+//! wait with the [`DEADLINE_HEADER`], and the queue can bound how long any request waits.
+//!
+//! [`MintQueue::stats`] tells how congested the queue is, for `GET /admin/queue`.
+
+use crate::counters;
+use aptos_infallible::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::oneshot;
+use warp::{Filter, Rejection};
+
+/// How far back [`QueueStats`] count finished and dropped requests
+pub const DRAIN_WINDOW: Duration = Duration::from_secs(60);
+
+/// Milliseconds the client waits for a response, counted from when the faucet receives the
+/// request
+pub const DEADLINE_HEADER: &str = "x-request-deadline-ms";
+
+/// When the client sending the request gives up on it, per its [`DEADLINE_HEADER`]
+pub fn deadline() -> impl Filter<Extract = (Option<Instant>,), Error = Rejection> + Clone {
+    warp::header::optional::<u64>(DEADLINE_HEADER)
+        .map(|ms: Option<u64>| ms.map(|ms| Instant::now() + Duration::from_millis(ms)))
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Lane {
+    /// Requests signed by a partner, see [`crate::partner`]
+    Authenticated,
+    Anonymous,
+}
+
+impl Lane {
+    fn index(self) -> usize {
+        match self {
+            Lane::Authenticated => 0,
+            Lane::Anonymous => 1,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Lane::Authenticated => "authenticated",
+            Lane::Anonymous => "anonymous",
+        }
+    }
+}
+
+struct State {
+    /// Slots not held by any request
+    available: usize,
+    /// Requests waiting for a slot, by lane index
+    waiting: [VecDeque<oneshot::Sender<Slot>>; 2],
+    /// When requests released their slot, over the last [`DRAIN_WINDOW`]
+    released: VecDeque<Instant>,
+    /// When requests were dropped without a slot, over the last [`DRAIN_WINDOW`]
+    dropped: VecDeque<Instant>,
+}
+
+impl State {
+    fn forget_before_window(&mut self) {
+        let now = Instant::now();
+        for times in [&mut self.released, &mut self.dropped] {
+            while matches!(times.front(), Some(at) if now.duration_since(*at) > DRAIN_WINDOW) {
+                times.pop_front();
+            }
+        }
+    }
+}
+
+/// How congested the queue is, returned by `GET /admin/queue`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct QueueStats {
+    /// Requests waiting in the authenticated lane
+    pub queued_authenticated: usize,
+    /// Requests waiting in the anonymous lane
+    pub queued_anonymous: usize,
+    /// Requests holding a slot
+    pub processing: usize,
+    pub max_concurrent: usize,
+    /// Share of the slots held, from 0 to 1
+    pub utilization: f64,
+    /// Requests that released their slot per second, over the last [`DRAIN_WINDOW`]
+    pub drain_rate: f64,
+    /// Requests dropped without a slot over the last [`DRAIN_WINDOW`], as their client had given
+    /// up
+    pub dropped: usize,
+}
+
+pub struct MintQueue {
+    state: Mutex<State>,
+    max_concurrent: usize,
+    /// Longest time a request waits for a slot, unbounded when empty
+    max_wait: Option<Duration>,
+}
+
+impl MintQueue {
+    /// A queue letting `max_concurrent` requests through at once, and dropping requests that
+    /// waited for `max_wait`
+    pub fn new(max_concurrent: usize, max_wait: Option<Duration>) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(State {
+                available: max_concurrent,
+                waiting: [VecDeque::new(), VecDeque::new()],
+                released: VecDeque::new(),
+                dropped: VecDeque::new(),
+            }),
+            max_concurrent,
+            max_wait,
+        })
+    }
+
+    /// Waits for a slot, which is released when dropped. Returns `None` if `deadline`, or the
+    /// queue's maximum wait, passes first
+    pub async fn acquire(self: &Arc<Self>, lane: Lane, deadline: Option<Instant>) -> Option<Slot> {
+        let max_wait = self.max_wait.map(|max_wait| Instant::now() + max_wait);
+        let deadline = match (deadline, max_wait) {
+            (Some(deadline), Some(max_wait)) => Some(deadline.min(max_wait)),
+            (deadline, max_wait) => deadline.or(max_wait),
+        };
+        if matches!(deadline, Some(deadline) if deadline <= Instant::now()) {
+            self.state.lock().dropped.push_back(Instant::now());
+            return None;
+        }
+
+        let receiver = {
+            let mut state = self.state.lock();
+            if state.available > 0 {
+                state.available -= 1;
+                return Some(Slot {
+                    queue: Some(self.clone()),
+                });
+            }
+            let (sender, receiver) = oneshot::channel();
+            state.waiting[lane.index()].push_back(sender);
+            set_queued_gauge(&state, lane);
+            receiver
+        };
+        let slot = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), receiver)
+                .await
+                .ok(),
+            None => Some(receiver.await),
+        };
+        match slot {
+            Some(slot) => Some(slot.expect("waiters are handed a slot before being dropped")),
+            None => {
+                // A slot handed over as the deadline passed went to the next request when the
+                // receiver was dropped, only the expired waiter is left to forget
+                let mut state = self.state.lock();
+                state.waiting[lane.index()].retain(|waiter| !waiter.is_closed());
+                state.dropped.push_back(Instant::now());
+                set_queued_gauge(&state, lane);
+                None
+            }
+        }
+    }
+
+    /// Requests waiting in `lane`
+    pub fn queued(&self, lane: Lane) -> usize {
+        self.state.lock().waiting[lane.index()].len()
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let mut state = self.state.lock();
+        state.forget_before_window();
+        let processing = self.max_concurrent - state.available;
+        QueueStats {
+            queued_authenticated: state.waiting[Lane::Authenticated.index()].len(),
+            queued_anonymous: state.waiting[Lane::Anonymous.index()].len(),
+            processing,
+            max_concurrent: self.max_concurrent,
+            utilization: processing as f64 / self.max_concurrent as f64,
+            drain_rate: state.released.len() as f64 / DRAIN_WINDOW.as_secs_f64(),
+            dropped: state.dropped.len(),
+        }
+    }
+
+    /// Hands the slot to the next waiting request, or makes it available
+    fn release(self: &Arc<Self>) {
+        let mut state = self.state.lock();
+        state.released.push_back(Instant::now());
+        state.forget_before_window();
+        for lane in [Lane::Authenticated, Lane::Anonymous] {
+            while let Some(waiter) = state.waiting[lane.index()].pop_front() {
+                let slot = Slot {
+                    queue: Some(self.clone()),
+                };
+                match waiter.send(slot) {
+                    Ok(()) => {
+                        set_queued_gauge(&state, lane);
+                        return;
+                    }
+                    // The request was dropped while waiting, so the slot goes to the next one
+                    Err(mut slot) => slot.queue = None,
+                }
+            }
+            set_queued_gauge(&state, lane);
+        }
+        state.available += 1;
+    }
+}
+
+fn set_queued_gauge(state: &State, lane: Lane) {
+    counters::QUEUED_REQUESTS
+        .with_label_values(&[lane.label()])
+        .set(state.waiting[lane.index()].len() as i64);
+}
+
+/// Permission to process a mint request
+pub struct Slot {
+    queue: Option<Arc<MintQueue>>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            queue.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn authenticated_requests_go_first() {
+        let queue = MintQueue::new(1, None);
+        let slot = queue.acquire(Lane::Anonymous, None).await.unwrap();
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut waiters = Vec::new();
+        for lane in [Lane::Anonymous, Lane::Authenticated] {
+            let (queue, order) = (queue.clone(), order.clone());
+            waiters.push(tokio::spawn(async move {
+                let _slot = queue.acquire(lane, None).await.unwrap();
+                order.lock().push(lane);
+            }));
+            while queue.queued(lane) == 0 {
+                tokio::task::yield_now().await;
+            }
+        }
+        // A request dropped while waiting does not swallow the slot
+        let abandoned = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.acquire(Lane::Authenticated, None).await }
+        });
+        while queue.queued(Lane::Authenticated) < 2 {
+            tokio::task::yield_now().await;
+        }
+        abandoned.abort();
+        assert!(abandoned.await.is_err());
+
+        drop(slot);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*order.lock(), vec![Lane::Authenticated, Lane::Anonymous]);
+        assert_eq!(queue.queued(Lane::Authenticated), 0);
+        assert_eq!(queue.state.lock().available, 1);
+    }
+
+    #[tokio::test]
+    async fn expired_requests_are_dropped() {
+        let queue = MintQueue::new(1, Some(Duration::from_secs(3600)));
+        // The client gave up before the request reached the queue
+        assert!(queue
+            .acquire(Lane::Anonymous, Some(Instant::now()))
+            .await
+            .is_none());
+
+        let slot = queue.acquire(Lane::Anonymous, None).await.unwrap();
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert!(queue
+            .acquire(Lane::Anonymous, Some(deadline))
+            .await
+            .is_none());
+        assert_eq!(queue.queued(Lane::Anonymous), 0);
+
+        drop(slot);
+        assert_eq!(queue.state.lock().available, 1);
+
+        // The queue's own bound applies to requests without a deadline
+        let queue = MintQueue::new(1, Some(Duration::from_millis(10)));
+        let _slot = queue.acquire(Lane::Authenticated, None).await.unwrap();
+        assert!(queue.acquire(Lane::Authenticated, None).await.is_none());
+        assert_eq!(queue.queued(Lane::Authenticated), 0);
+    }
+
+    #[tokio::test]
+    async fn stats_tell_how_congested_the_queue_is() {
+        let queue = MintQueue::new(2, Some(Duration::from_millis(10)));
+        let first = queue.acquire(Lane::Anonymous, None).await.unwrap();
+        let _second = queue.acquire(Lane::Anonymous, None).await.unwrap();
+        assert!(queue.acquire(Lane::Anonymous, None).await.is_none());
+        drop(first);
+
+        assert_eq!(
+            queue.stats(),
+            QueueStats {
+                queued_authenticated: 0,
+                queued_anonymous: 0,
+                processing: 1,
+                max_concurrent: 2,
+                utilization: 0.5,
+                drain_rate: 1.0 / 60.0,
+                dropped: 1,
+            }
+        );
+    }
+}
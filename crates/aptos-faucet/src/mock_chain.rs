@@ -0,0 +1,663 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! An in-memory stand-in for a fullnode, implementing just enough of the REST API for the faucet:
+//! ledger info, accounts, the TestCoin balance resource, transaction submission and lookup, the
+//! genesis transaction, and the registry of an Aptos Names Service contract.
+//!
+//! Submitted transactions are executed in sequence number order per sender, and only the script
+//! functions the faucet issues are understood. This backs `aptos-faucet run --mock-chain`, so
+//! frontends can integrate against the faucet without running a node.
+
+use crate::ans::Name;
+use anyhow::Result;
+use aptos_crypto::hash::HashValue;
+use aptos_infallible::Mutex;
+use aptos_rest_client::aptos_api_types::{
+    AccountData, DirectWriteSet, LedgerInfo, PendingTransaction, Response,
+    Transaction as TransactionData, TransactionInfo, TransactionPayload as TransactionPayloadData,
+    WriteSet, WriteSetPayload,
+};
+use aptos_sdk::{
+    transaction_builder::aptos_stdlib::ScriptFunctionCall,
+    types::{
+        account_address::AccountAddress,
+        chain_id::ChainId,
+        transaction::{authenticator::AuthenticationKey, SignedTransaction},
+    },
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::{Infallible, TryFrom},
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use warp::{
+    body::BodyDeserializeError,
+    cors::CorsForbidden,
+    http::{header, HeaderValue, StatusCode},
+    reject::{LengthRequired, MethodNotAllowed, PayloadTooLarge, UnsupportedMediaType},
+    reply, Filter, Rejection, Reply,
+};
+
+/// Balance given to the faucet account when the faucet runs against a mock chain
+pub const MOCK_FAUCET_BALANCE: u64 = u64::MAX / 2;
+
+/// Decimals the mock chain reports for TestCoin
+pub const MOCK_COIN_DECIMALS: u8 = 8;
+
+/// VM status of failed transactions
+const MOVE_ABORT: &str = "Move abort";
+
+/// Handle of the table names are registered in, whichever address the ANS contract is at
+const NAME_REGISTRY_HANDLE: u128 = 1;
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct MockAccount {
+    pub authentication_key: AuthenticationKey,
+    pub balance: u64,
+    pub sequence_number: u64,
+}
+
+impl MockAccount {
+    pub fn new(balance: u64) -> Self {
+        Self {
+            authentication_key: AuthenticationKey::new([1; 32]),
+            balance,
+            sequence_number: 0,
+        }
+    }
+}
+
+/// A name registered with the Aptos Names Service
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MockName {
+    pub target_address: Option<AccountAddress>,
+    pub expiration_time_sec: u64,
+}
+
+/// Failures a [`MockChain`] can be told to inject, so callers can deterministically test how
+/// they handle a misbehaving node. The default injects nothing.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct InjectedFailures {
+    /// Answer every request with this status, as if the node were down
+    pub unavailable: Option<StatusCode>,
+    /// Reject transaction submissions with this status
+    pub reject_submissions: Option<StatusCode>,
+    /// Answer balance lookups with this status
+    pub reject_balance_reads: Option<StatusCode>,
+    /// Answer transaction lookups with this status, failing whoever waits on them
+    pub reject_transaction_reads: Option<StatusCode>,
+    /// Accept submitted transactions but never execute them
+    pub drop_transactions: bool,
+    /// Execute submitted transactions, but mark every one as failed
+    pub fail_transactions: bool,
+    /// Delay every response by this long
+    pub latency: Option<Duration>,
+    /// Report a ledger timestamp this far behind the wall clock, as if the node were syncing
+    pub ledger_lag: Option<Duration>,
+    /// Answer lookups of these accounts with a `404`, as if the node had not caught up with
+    /// their creation yet
+    pub hidden_accounts: Vec<AccountAddress>,
+}
+
+struct ExecutedTransaction {
+    txn: SignedTransaction,
+    version: u64,
+    /// Why the transaction failed, none if it succeeded
+    abort: Option<&'static str>,
+}
+
+#[derive(Default)]
+struct Ledger {
+    accounts: HashMap<AccountAddress, MockAccount>,
+    /// Transactions waiting on a lower sequence number from the same sender
+    pending: HashMap<AccountAddress, BTreeMap<u64, SignedTransaction>>,
+    executed: HashMap<HashValue, ExecutedTransaction>,
+    names: HashMap<Name, MockName>,
+    /// Hash of the transaction at version 0
+    genesis: HashValue,
+    version: u64,
+    failures: InjectedFailures,
+}
+
+impl Ledger {
+    /// Executes the sender's pending transactions for as long as there is no sequence number gap
+    fn execute_ready(&mut self, sender: AccountAddress) {
+        loop {
+            let sequence_number = match self.accounts.get(&sender) {
+                Some(account) => account.sequence_number,
+                None => return,
+            };
+            let txn = match self
+                .pending
+                .get_mut(&sender)
+                .and_then(|pending| pending.remove(&sequence_number))
+            {
+                Some(txn) => txn,
+                None => return,
+            };
+            self.execute(txn);
+        }
+    }
+
+    fn execute(&mut self, txn: SignedTransaction) {
+        let outcome = if self.failures.fail_transactions {
+            Err(MOVE_ABORT)
+        } else {
+            match ScriptFunctionCall::decode(txn.payload()) {
+                Some(ScriptFunctionCall::AccountCreateAccount {
+                    auth_key: address, ..
+                }) => {
+                    if self.accounts.contains_key(&address) {
+                        Err("Move abort: RESOURCE_ALREADY_EXISTS")
+                    } else {
+                        // Fresh accounts are controlled by the key their address derives from
+                        let account = MockAccount {
+                            authentication_key: AuthenticationKey::new(address.into_bytes()),
+                            ..MockAccount::new(0)
+                        };
+                        self.accounts.insert(address, account);
+                        Ok(())
+                    }
+                }
+                Some(ScriptFunctionCall::TestCoinMint {
+                    dst_addr, amount, ..
+                }) => match self.accounts.get_mut(&dst_addr) {
+                    Some(account) => {
+                        account.balance = account.balance.saturating_add(amount);
+                        Ok(())
+                    }
+                    None => Err(MOVE_ABORT),
+                },
+                Some(ScriptFunctionCall::CoinTransfer { to, amount, .. }) => {
+                    if self.transfer(txn.sender(), to, amount) {
+                        Ok(())
+                    } else {
+                        Err(MOVE_ABORT)
+                    }
+                }
+                Some(ScriptFunctionCall::AccountRotateAuthenticationKey { new_auth_key }) => {
+                    match (
+                        AuthenticationKey::try_from(new_auth_key),
+                        self.accounts.get_mut(&txn.sender()),
+                    ) {
+                        (Ok(auth_key), Some(sender)) => {
+                            sender.authentication_key = auth_key;
+                            Ok(())
+                        }
+                        _ => Err(MOVE_ABORT),
+                    }
+                }
+                Some(ScriptFunctionCall::TestCoinDelegateMintCapability { .. })
+                | Some(ScriptFunctionCall::TestCoinClaimMintCapability { .. }) => Ok(()),
+                _ => Err(MOVE_ABORT),
+            }
+        };
+
+        if let Some(sender) = self.accounts.get_mut(&txn.sender()) {
+            sender.sequence_number += 1;
+        }
+        self.version += 1;
+        self.executed.insert(
+            txn.clone().committed_hash(),
+            ExecutedTransaction {
+                txn,
+                version: self.version,
+                abort: outcome.err(),
+            },
+        );
+    }
+
+    fn transfer(&mut self, from: AccountAddress, to: AccountAddress, amount: u64) -> bool {
+        if !self.accounts.contains_key(&to) {
+            return false;
+        }
+        match self.accounts.get_mut(&from) {
+            Some(sender) if sender.balance >= amount => sender.balance -= amount,
+            _ => return false,
+        }
+        self.accounts.get_mut(&to).unwrap().balance += amount;
+        true
+    }
+}
+
+/// A shareable handle to an in-memory ledger, see the module documentation.
+#[derive(Clone)]
+pub struct MockChain {
+    chain_id: ChainId,
+    ledger: Arc<Mutex<Ledger>>,
+}
+
+impl MockChain {
+    pub fn new(chain_id: ChainId) -> Self {
+        Self {
+            chain_id,
+            ledger: Arc::new(Mutex::new(Ledger::default())),
+        }
+    }
+
+    pub fn create_account(&self, address: AccountAddress, account: MockAccount) {
+        self.ledger.lock().accounts.insert(address, account);
+    }
+
+    pub fn remove_account(&self, address: AccountAddress) -> Option<MockAccount> {
+        self.ledger.lock().accounts.remove(&address)
+    }
+
+    pub fn account(&self, address: AccountAddress) -> Option<MockAccount> {
+        self.ledger.lock().accounts.get(&address).cloned()
+    }
+
+    pub fn register_name(&self, name: Name, record: MockName) {
+        self.ledger.lock().names.insert(name, record);
+    }
+
+    /// Wipes the ledger and restarts it from a new genesis, as devnets are every so often. The
+    /// injected failures are kept
+    pub fn reset(&self) {
+        let mut ledger = self.ledger.lock();
+        *ledger = Ledger {
+            genesis: HashValue::random(),
+            failures: ledger.failures.clone(),
+            ..Ledger::default()
+        };
+    }
+
+    /// Replaces the failures injected into subsequent requests
+    pub fn set_failures(&self, failures: InjectedFailures) {
+        self.ledger.lock().failures = failures;
+    }
+
+    pub fn failures(&self) -> InjectedFailures {
+        self.ledger.lock().failures.clone()
+    }
+
+    /// Serves the mock REST API on `address` in the background, returning the bound address
+    pub fn start(&self, address: impl Into<SocketAddr>) -> Result<SocketAddr> {
+        let (address, server) = warp::serve(self.routes()).try_bind_ephemeral(address)?;
+        tokio::task::spawn(server);
+        Ok(address)
+    }
+
+    pub fn routes(&self) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+        let chain = self.clone();
+        let chain = warp::any().map(move || chain.clone());
+
+        let failures = chain.clone().and_then(inject_failures).untuple_one();
+        let ledger_info = warp::path::end()
+            .and(warp::get())
+            .and(chain.clone())
+            .and_then(handle_get_ledger_info);
+        let account = warp::path!("accounts" / String)
+            .and(warp::get())
+            .and(chain.clone())
+            .and_then(handle_get_account);
+        let resource = warp::path!("accounts" / String / "resource" / String)
+            .and(warp::get())
+            .and(chain.clone())
+            .and_then(handle_get_resource);
+        let table_item = warp::path!("tables" / String / "item")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(chain.clone())
+            .and_then(handle_get_table_item);
+        let get_transaction = warp::path!("transactions" / String)
+            .and(warp::get())
+            .and(chain.clone())
+            .and_then(handle_get_transaction);
+        let submit_transaction = warp::path!("transactions")
+            .and(warp::post())
+            .and(warp::body::bytes())
+            .and(chain)
+            .and_then(handle_submit_transaction);
+
+        failures
+            .and(
+                ledger_info
+                    .or(account)
+                    .or(resource)
+                    .or(table_item)
+                    .or(get_transaction)
+                    .or(submit_transaction),
+            )
+            .with(
+                warp::cors()
+                    .allow_any_origin()
+                    .allow_methods(vec!["POST", "GET"])
+                    .allow_headers(vec![header::CONTENT_TYPE]),
+            )
+            .recover(handle_rejection)
+    }
+
+    fn ledger_info(&self) -> LedgerInfo {
+        let version = self.ledger.lock().version;
+        let lag = self.failures().ledger_lag.unwrap_or_default();
+        let timestamp_usecs = (SystemTime::now() - lag)
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_micros() as u64;
+        LedgerInfo {
+            chain_id: self.chain_id.id(),
+            epoch: 1,
+            ledger_version: version.into(),
+            ledger_timestamp: timestamp_usecs.into(),
+        }
+    }
+
+    fn response<T: Serialize>(&self, body: &T) -> reply::Response {
+        Response::new(self.ledger_info(), body)
+            .unwrap()
+            .into_response()
+    }
+}
+
+async fn inject_failures(chain: MockChain) -> Result<(), Rejection> {
+    let failures = chain.failures();
+    if let Some(latency) = failures.latency {
+        tokio::time::sleep(latency).await;
+    }
+    match failures.unavailable {
+        Some(code) => Err(warp::reject::custom(Error::new(
+            code,
+            "injected failure: node unavailable".to_owned(),
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn parse_address(address: &str) -> Option<AccountAddress> {
+    AccountAddress::try_from(address.to_owned())
+        .or_else(|_| AccountAddress::from_hex_literal(address))
+        .or_else(|_| AccountAddress::from_hex(address))
+        .ok()
+}
+
+async fn handle_get_ledger_info(chain: MockChain) -> Result<impl Reply, Rejection> {
+    Ok(chain.response(&chain.ledger_info()))
+}
+
+async fn handle_get_account(address: String, chain: MockChain) -> Result<impl Reply, Rejection> {
+    let hidden_accounts = chain.failures().hidden_accounts;
+    let account = parse_address(&address)
+        .filter(|address| !hidden_accounts.contains(address))
+        .and_then(|address| chain.account(address))
+        .ok_or_else(warp::reject)?;
+
+    let auth_vec: Vec<u8> = account.authentication_key.as_ref().into();
+    let account_data = AccountData {
+        authentication_key: auth_vec.into(),
+        sequence_number: account.sequence_number.into(),
+    };
+    Ok(chain.response(&account_data))
+}
+
+async fn handle_get_resource(
+    address: String,
+    resource_type: String,
+    chain: MockChain,
+) -> Result<impl Reply, Rejection> {
+    // Only the TestCoin balance and decimals are modelled
+    if resource_type.contains("CoinInfo") {
+        let resource = serde_json::json!({
+            "type": "0x1::Coin::CoinInfo<0x1::TestCoin::TestCoin>",
+            "data": {
+                "name": "Test Coin",
+                "symbol": "TC",
+                "decimals": MOCK_COIN_DECIMALS.to_string(),
+            },
+        });
+        return Ok(chain.response(&resource));
+    }
+    if resource_type.ends_with("::domains::NameRegistryV1") {
+        let resource = serde_json::json!({
+            "type": resource_type,
+            "data": { "registry": { "handle": NAME_REGISTRY_HANDLE.to_string() } },
+        });
+        return Ok(chain.response(&resource));
+    }
+    if !resource_type.contains("CoinStore") {
+        return Err(warp::reject());
+    }
+    if let Some(code) = chain.failures().reject_balance_reads {
+        return Err(warp::reject::custom(Error::new(
+            code,
+            "injected failure: balance read rejected".to_owned(),
+        )));
+    }
+    let account = parse_address(&address)
+        .and_then(|address| chain.account(address))
+        .ok_or_else(warp::reject)?;
+
+    let resource = serde_json::json!({
+        "type": "0x1::Coin::CoinStore<0x1::TestCoin::TestCoin>",
+        "data": { "coin": { "value": account.balance.to_string() } },
+    });
+    Ok(chain.response(&resource))
+}
+
+async fn handle_get_table_item(
+    handle: String,
+    request: serde_json::Value,
+    chain: MockChain,
+) -> Result<impl Reply, Rejection> {
+    // Only the name registry is modelled
+    if handle != NAME_REGISTRY_HANDLE.to_string() {
+        return Err(warp::reject());
+    }
+    let key = &request["key"];
+    let name = Name {
+        subdomain: key["subdomain_name"]["vec"]
+            .get(0)
+            .and_then(|subdomain| subdomain.as_str())
+            .map(str::to_owned),
+        domain: key["domain_name"]
+            .as_str()
+            .ok_or_else(warp::reject)?
+            .to_owned(),
+    };
+    let record = chain
+        .ledger
+        .lock()
+        .names
+        .get(&name)
+        .cloned()
+        .ok_or_else(warp::reject)?;
+
+    let target_address: Vec<_> = record
+        .target_address
+        .iter()
+        .map(|address| address.to_hex_literal())
+        .collect();
+    let item = serde_json::json!({
+        "property_version": "0",
+        "expiration_time_sec": record.expiration_time_sec.to_string(),
+        "target_address": { "vec": target_address },
+    });
+    Ok(chain.response(&item))
+}
+
+async fn handle_get_transaction(
+    version_or_hash: String,
+    chain: MockChain,
+) -> Result<impl Reply, Rejection> {
+    if let Some(code) = chain.failures().reject_transaction_reads {
+        return Err(warp::reject::custom(Error::new(
+            code,
+            "injected failure: transaction read rejected".to_owned(),
+        )));
+    }
+    let hash = match version_or_hash.parse::<u64>() {
+        Ok(0) => {
+            let genesis = genesis(chain.ledger.lock().genesis);
+            return Ok(chain.response(&genesis));
+        }
+        Ok(version) => chain
+            .ledger
+            .lock()
+            .executed
+            .iter()
+            .find(|(_, executed)| executed.version == version)
+            .map(|(hash, _)| *hash)
+            .ok_or_else(warp::reject)?,
+        Err(_) => HashValue::from_hex(version_or_hash.trim_start_matches("0x"))
+            .map_err(|_| warp::reject())?,
+    };
+
+    let serializable_txn: TransactionData = {
+        let ledger = chain.ledger.lock();
+        let executed = ledger.executed.get(&hash).ok_or_else(warp::reject)?;
+        let info = TransactionInfo {
+            version: executed.version.into(),
+            hash: hash.into(),
+            state_root_hash: HashValue::zero().into(),
+            event_root_hash: HashValue::zero().into(),
+            gas_used: 0.into(),
+            success: executed.abort.is_none(),
+            vm_status: executed
+                .abort
+                .unwrap_or("Executed successfully")
+                .to_string(),
+            accumulator_root_hash: HashValue::zero().into(),
+            changes: vec![],
+        };
+        (&executed.txn, info, dummy_payload(), Vec::new(), 0).into()
+    };
+
+    Ok(chain.response(&serializable_txn))
+}
+
+async fn handle_submit_transaction(
+    txn: bytes::Bytes,
+    chain: MockChain,
+) -> Result<impl Reply, Rejection> {
+    let txn: SignedTransaction = bcs::from_bytes(&txn).map_err(|err| {
+        warp::reject::custom(Error::new(StatusCode::BAD_REQUEST, err.to_string()))
+    })?;
+    if txn.chain_id() != chain.chain_id {
+        return Err(warp::reject::custom(Error::new(
+            StatusCode::BAD_REQUEST,
+            format!("invalid chain id {}", txn.chain_id()),
+        )));
+    }
+    if let Some(code) = chain.failures().reject_submissions {
+        return Err(warp::reject::custom(Error::new(
+            code,
+            "injected failure: submission rejected".to_owned(),
+        )));
+    }
+
+    let pending_txn = PendingTransaction {
+        hash: txn.clone().committed_hash().into(),
+        request: (&txn, dummy_payload()).into(),
+    };
+
+    {
+        let mut ledger = chain.ledger.lock();
+        let sender = txn.sender();
+        ledger
+            .pending
+            .entry(sender)
+            .or_default()
+            .insert(txn.sequence_number(), txn);
+        if !ledger.failures.drop_transactions {
+            ledger.execute_ready(sender);
+        }
+    }
+
+    Ok(reply::with_status(
+        chain.response(&pending_txn),
+        StatusCode::ACCEPTED,
+    ))
+}
+
+/// The genesis transaction with the hash `hash`. Its payload type is not exported, so it is built
+/// as JSON
+fn genesis(hash: HashValue) -> serde_json::Value {
+    let info = TransactionInfo {
+        version: 0.into(),
+        hash: hash.into(),
+        state_root_hash: HashValue::zero().into(),
+        event_root_hash: HashValue::zero().into(),
+        gas_used: 0.into(),
+        success: true,
+        vm_status: "Executed successfully".to_string(),
+        accumulator_root_hash: HashValue::zero().into(),
+        changes: vec![],
+    };
+    let mut genesis = serde_json::to_value(&info).unwrap();
+    genesis["type"] = "genesis_transaction".into();
+    genesis["payload"] = serde_json::to_value(&dummy_payload()).unwrap();
+    genesis["events"] = serde_json::json!([]);
+    genesis
+}
+
+fn dummy_payload() -> TransactionPayloadData {
+    TransactionPayloadData::WriteSetPayload(WriteSetPayload {
+        write_set: WriteSet::DirectWriteSet(DirectWriteSet {
+            changes: Vec::new(),
+            events: Vec::new(),
+        }),
+    })
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct Error {
+    pub code: u16,
+    pub message: String,
+}
+
+impl Error {
+    fn new(code: StatusCode, message: String) -> Error {
+        Error {
+            code: code.as_u16(),
+            message,
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.code).unwrap()
+    }
+}
+
+impl warp::reject::Reject for Error {}
+
+async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    let code;
+    let body;
+
+    if err.is_not_found() {
+        code = StatusCode::NOT_FOUND;
+        body = reply::json(&Error::new(code, "Not Found".to_owned()));
+    } else if let Some(error) = err.find::<Error>() {
+        code = error.status_code();
+        body = reply::json(error);
+    } else if let Some(cause) = err.find::<CorsForbidden>() {
+        code = StatusCode::FORBIDDEN;
+        body = reply::json(&Error::new(code, cause.to_string()));
+    } else if let Some(cause) = err.find::<BodyDeserializeError>() {
+        code = StatusCode::BAD_REQUEST;
+        body = reply::json(&Error::new(code, cause.to_string()));
+    } else if let Some(cause) = err.find::<LengthRequired>() {
+        code = StatusCode::LENGTH_REQUIRED;
+        body = reply::json(&Error::new(code, cause.to_string()));
+    } else if let Some(cause) = err.find::<PayloadTooLarge>() {
+        code = StatusCode::PAYLOAD_TOO_LARGE;
+        body = reply::json(&Error::new(code, cause.to_string()));
+    } else if let Some(cause) = err.find::<UnsupportedMediaType>() {
+        code = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+        body = reply::json(&Error::new(code, cause.to_string()));
+    } else if let Some(cause) = err.find::<MethodNotAllowed>() {
+        code = StatusCode::METHOD_NOT_ALLOWED;
+        body = reply::json(&Error::new(code, cause.to_string()));
+    } else {
+        code = StatusCode::INTERNAL_SERVER_ERROR;
+        body = reply::json(&Error::new(code, format!("unexpected error: {:?}", err)));
+    }
+    let mut rep = reply::with_status(body, code).into_response();
+    rep.headers_mut()
+        .insert("access-control-allow-origin", HeaderValue::from_static("*"));
+    Ok(rep)
+}
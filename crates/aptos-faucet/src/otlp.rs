@@ -0,0 +1,366 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Exports the faucet's tracing spans to an OpenTelemetry collector over OTLP/HTTP with JSON
+//! encoding. Export is configured with the standard environment variables:
+//!
+//! * `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`, or `OTEL_EXPORTER_OTLP_ENDPOINT` with `/v1/traces`
+//!   appended. Export is disabled if neither is set.
+//! * `OTEL_EXPORTER_OTLP_HEADERS`, as comma separated `key=value` pairs
+//! * `OTEL_SERVICE_NAME`, defaulting to `aptos-faucet`
+//!
+//! The spans of a request sent with a `traceparent` header are part of the caller's trace, see
+//! [`crate::request_log`].
+//!
+//! With the `tokio-console` feature, the same subscriber also serves the runtime's tasks to
+//! tokio-console.
+
+use crate::request_log;
+use anyhow::Result;
+use aptos_infallible::Mutex;
+use aptos_logger::{tracing_adapter::TracingToAptosDataLayer, warn};
+use rand::RngCore;
+use serde_json::json;
+use std::{
+    env, fmt, mem,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer, Registry};
+
+// tokio only records its tasks for tokio-console when built with the unstable cfg, and the
+// console would otherwise connect to show nothing
+#[cfg(all(feature = "tokio-console", not(tokio_unstable)))]
+compile_error!("the tokio-console feature needs RUSTFLAGS=\"--cfg tokio_unstable\"");
+
+/// How often finished spans are sent to the collector
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Finished spans kept while the collector is unreachable, newer spans are dropped
+const MAX_BUFFERED_SPANS: usize = 10_000;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OtlpConfig {
+    /// URL spans are posted to
+    pub endpoint: String,
+    pub headers: Vec<(String, String)>,
+    pub service_name: String,
+}
+
+impl OtlpConfig {
+    /// Reads the `OTEL_*` environment variables, `None` if no endpoint is set
+    pub fn from_env() -> Option<Self> {
+        let endpoint = env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT")
+            .ok()
+            .or_else(|| {
+                let base = env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok()?;
+                Some(format!("{}/v1/traces", base.trim_end_matches('/')))
+            })?;
+        let headers = env::var("OTEL_EXPORTER_OTLP_HEADERS")
+            .map(|headers| parse_headers(&headers))
+            .unwrap_or_default();
+        let service_name =
+            env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "aptos-faucet".to_string());
+        Some(Self {
+            endpoint,
+            headers,
+            service_name,
+        })
+    }
+}
+
+fn parse_headers(headers: &str) -> Vec<(String, String)> {
+    headers
+        .split(',')
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            Some((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Installs a tracing subscriber exporting spans over OTLP, if the environment configures an
+/// endpoint, and serving tokio-console with the `tokio-console` feature. Must be called before
+/// the logger is initialized, as the logger otherwise installs its own subscriber. Returns
+/// whether export was enabled
+pub fn init_from_env() -> Result<bool> {
+    let config = OtlpConfig::from_env();
+    let exporting = config.is_some();
+    if !exporting && !cfg!(feature = "tokio-console") {
+        return Ok(false);
+    }
+    let subscriber = Registry::default()
+        .with(TracingToAptosDataLayer)
+        .with(config.map(OtlpLayer::start).transpose()?);
+    // Listens on TOKIO_CONSOLE_BIND, 127.0.0.1:6669 by default
+    #[cfg(feature = "tokio-console")]
+    let subscriber = subscriber.with(console_subscriber::spawn());
+    tracing::subscriber::set_global_default(subscriber)?;
+    Ok(exporting)
+}
+
+/// A span that is still open
+struct SpanRecord {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    name: &'static str,
+    start: SystemTime,
+    attributes: SpanAttributes,
+}
+
+#[derive(Default)]
+struct SpanAttributes(Vec<(&'static str, String)>);
+
+impl Visit for SpanAttributes {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name(), value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.push((field.name(), format!("{:?}", value)));
+    }
+}
+
+/// Collects the faucet's spans as they close, and exports them from a background thread
+pub struct OtlpLayer {
+    finished: Arc<Mutex<Vec<serde_json::Value>>>,
+}
+
+impl OtlpLayer {
+    pub fn start(config: OtlpConfig) -> Result<Self> {
+        let finished = Arc::new(Mutex::new(Vec::new()));
+        let exported = finished.clone();
+        std::thread::Builder::new()
+            .name("otlp-export".to_string())
+            .spawn(move || export(config, exported))?;
+        Ok(Self { finished })
+    }
+}
+
+fn export(config: OtlpConfig, finished: Arc<Mutex<Vec<serde_json::Value>>>) {
+    // The blocking client runs its own runtime, so it is built on this thread rather than from
+    // within the caller's runtime
+    let client = match reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+    {
+        Ok(client) => client,
+        Err(err) => {
+            warn!("[faucet]: unable to start span export: {}", err);
+            return;
+        }
+    };
+
+    loop {
+        std::thread::sleep(EXPORT_INTERVAL);
+        let spans = mem::take(&mut *finished.lock());
+        if spans.is_empty() {
+            continue;
+        }
+        let body = export_request(&config.service_name, spans).to_string();
+        let mut request = client
+            .post(&config.endpoint)
+            .header("content-type", "application/json")
+            .body(body);
+        for (name, value) in &config.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        if let Err(err) = request
+            .send()
+            .and_then(|response| response.error_for_status())
+        {
+            warn!(
+                "[faucet]: unable to export spans to {}: {}",
+                config.endpoint, err
+            );
+        }
+    }
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        // Only the faucet's own spans are exported, not those of its dependencies
+        if !attrs.metadata().target().starts_with("aptos_faucet") {
+            return;
+        }
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+
+        let parent = span.parent().and_then(|parent| {
+            let extensions = parent.extensions();
+            let record = extensions.get::<SpanRecord>()?;
+            Some((record.trace_id, record.span_id))
+        });
+        // Root spans continue the trace of the request being handled, if it came with one
+        let parent = parent.or_else(|| {
+            let caller = request_log::current_trace_parent()?;
+            Some((caller.trace_id, caller.parent_id))
+        });
+        let mut rng = rand::thread_rng();
+        let trace_id = parent.map(|(trace_id, _)| trace_id).unwrap_or_else(|| {
+            let mut trace_id = [0; 16];
+            rng.fill_bytes(&mut trace_id);
+            trace_id
+        });
+        let mut span_id = [0; 8];
+        rng.fill_bytes(&mut span_id);
+
+        let mut attributes = SpanAttributes::default();
+        attrs.record(&mut attributes);
+        span.extensions_mut().insert(SpanRecord {
+            trace_id,
+            span_id,
+            parent_span_id: parent.map(|(_, span_id)| span_id),
+            name: attrs.metadata().name(),
+            start: SystemTime::now(),
+            attributes,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(record) = span.extensions_mut().get_mut::<SpanRecord>() {
+                values.record(&mut record.attributes);
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => return,
+        };
+        let record = match span.extensions_mut().remove::<SpanRecord>() {
+            Some(record) => record,
+            None => return,
+        };
+
+        let mut finished = self.finished.lock();
+        if finished.len() < MAX_BUFFERED_SPANS {
+            finished.push(span_json(&record, SystemTime::now()));
+        }
+    }
+}
+
+fn span_json(record: &SpanRecord, end: SystemTime) -> serde_json::Value {
+    let attributes: Vec<_> = record
+        .attributes
+        .0
+        .iter()
+        .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+        .collect();
+    json!({
+        "traceId": hex::encode(record.trace_id),
+        "spanId": hex::encode(record.span_id),
+        "parentSpanId": record.parent_span_id.map(hex::encode).unwrap_or_default(),
+        "name": record.name,
+        // SPAN_KIND_INTERNAL
+        "kind": 1,
+        "startTimeUnixNano": unix_nanos(record.start).to_string(),
+        "endTimeUnixNano": unix_nanos(end).to_string(),
+        "attributes": attributes,
+    })
+}
+
+fn export_request(service_name: &str, spans: Vec<serde_json::Value>) -> serde_json::Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    { "key": "service.name", "value": { "stringValue": service_name } },
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "aptos-faucet" },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request_log::{with_request_id, RequestContext};
+
+    #[test]
+    fn spans_are_nested_under_their_parent() {
+        let layer = OtlpLayer {
+            finished: Arc::new(Mutex::new(Vec::new())),
+        };
+        let finished = layer.finished.clone();
+        let subscriber = Registry::default().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let parent = tracing::info_span!("mint", request_id = "abc");
+            let _entered = parent.enter();
+            tracing::info_span!("submit").in_scope(|| ());
+        });
+
+        let finished = finished.lock();
+        assert_eq!(finished.len(), 2);
+        let (submit, mint) = (&finished[0], &finished[1]);
+        assert_eq!(submit["name"], "submit");
+        assert_eq!(mint["name"], "mint");
+        assert_eq!(submit["traceId"], mint["traceId"]);
+        assert_eq!(submit["parentSpanId"], mint["spanId"]);
+        assert_eq!(mint["parentSpanId"], "");
+        assert_eq!(mint["attributes"][0]["value"]["stringValue"], "abc");
+    }
+
+    #[tokio::test]
+    async fn root_spans_continue_the_callers_trace() {
+        let layer = OtlpLayer {
+            finished: Arc::new(Mutex::new(Vec::new())),
+        };
+        let finished = layer.finished.clone();
+        let subscriber = Registry::default().with(layer);
+        let context = RequestContext {
+            id: "abc".to_string(),
+            trace_parent: "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+                .parse()
+                .ok(),
+        };
+
+        with_request_id(context, async move {
+            tracing::subscriber::with_default(subscriber, || {
+                tracing::info_span!("mint").in_scope(|| ());
+            });
+            Ok(Box::new(warp::reply()) as Box<dyn warp::Reply>)
+        })
+        .await
+        .unwrap();
+
+        let finished = finished.lock();
+        assert_eq!(finished[0]["traceId"], "0af7651916cd43dd8448eb211c80319c");
+        assert_eq!(finished[0]["parentSpanId"], "b7ad6b7169203331");
+    }
+
+    #[test]
+    fn headers_parse() {
+        assert_eq!(
+            parse_headers("x-api-key=secret, tenant = faucet,invalid"),
+            vec![
+                ("x-api-key".to_string(), "secret".to_string()),
+                ("tenant".to_string(), "faucet".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,320 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Mint requests signed by server-to-server partners with a shared secret, so that no key that
+//! could leak into browser code is ever sent. A signed request carries three headers:
+//!
+//! * `x-faucet-partner`: the partner's name
+//! * `x-faucet-timestamp`: the unix time in seconds the request was signed at
+//! * `x-faucet-signature`: the hex encoded HMAC-SHA256, keyed by the partner's secret, of
+//!   `<method>\n<path and query>\n<timestamp>\n<body>`
+//!
+//! Requests whose timestamp is further than the replay window from the faucet's clock are
+//! refused, as are signatures already seen within the window.
+
+use crate::{counters, request_limits, request_log};
+use aptos_infallible::Mutex;
+use bytes::Bytes;
+use hmac::{Hmac, Mac, NewMac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use warp::{filters::path::FullPath, http::Method, Filter, Rejection};
+
+pub const PARTNER_HEADER: &str = "x-faucet-partner";
+pub const TIMESTAMP_HEADER: &str = "x-faucet-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-faucet-signature";
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PartnerConfig {
+    /// Shared secret by partner name
+    #[serde(default)]
+    pub secrets: BTreeMap<String, String>,
+    /// Files holding further partners' shared secrets, by partner name, see [`crate::secrets`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub secret_files: BTreeMap<String, PathBuf>,
+    /// Largest difference allowed between a request's timestamp and the faucet's clock
+    #[serde(default = "default_replay_window_secs")]
+    pub replay_window_secs: u64,
+}
+
+fn default_replay_window_secs() -> u64 {
+    300
+}
+
+/// The parts of a request covered by its signature
+#[derive(Clone, Debug)]
+pub struct SignedRequest {
+    pub partner: String,
+    pub timestamp: String,
+    pub signature: String,
+    pub method: Method,
+    pub path_and_query: String,
+    pub body: Bytes,
+}
+
+/// Extracts the signed parts of requests carrying a signature
+pub(crate) fn signed_request(
+    max_body_bytes: u64,
+) -> impl Filter<Extract = (Option<SignedRequest>,), Error = Rejection> + Clone {
+    signed_request_and_body(max_body_bytes).map(|signed, _| signed)
+}
+
+/// Like [`signed_request`], but also passes on the body for routes that parse it
+pub(crate) fn signed_request_and_body(
+    max_body_bytes: u64,
+) -> impl Filter<Extract = (Option<SignedRequest>, Bytes), Error = Rejection> + Clone {
+    warp::header::optional::<String>(SIGNATURE_HEADER)
+        .and(warp::header::optional::<String>(PARTNER_HEADER))
+        .and(warp::header::optional::<String>(TIMESTAMP_HEADER))
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(request_log::raw_query())
+        .and(request_limits::body(max_body_bytes))
+        .map(
+            |signature: Option<String>,
+             partner: Option<String>,
+             timestamp: Option<String>,
+             method: Method,
+             path: FullPath,
+             query: String,
+             body: Bytes| {
+                let path_and_query = if query.is_empty() {
+                    path.as_str().to_string()
+                } else {
+                    format!("{}?{}", path.as_str(), query)
+                };
+                let signed = signature.map(|signature| SignedRequest {
+                    partner: partner.unwrap_or_default(),
+                    timestamp: timestamp.unwrap_or_default(),
+                    signature,
+                    method,
+                    path_and_query,
+                    body: body.clone(),
+                });
+                (signed, body)
+            },
+        )
+        .untuple_one()
+}
+
+/// Hex encoded signature of a request, as partners compute it
+pub fn sign(
+    secret: &str,
+    method: &Method,
+    path_and_query: &str,
+    timestamp: u64,
+    body: &[u8],
+) -> String {
+    let mac = mac(secret, method, path_and_query, &timestamp.to_string(), body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn mac(
+    secret: &str,
+    method: &Method,
+    path_and_query: &str,
+    timestamp: &str,
+    body: &[u8],
+) -> HmacSha256 {
+    let mut mac = HmacSha256::new_varkey(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(format!("{}\n{}\n{}\n", method, path_and_query, timestamp).as_bytes());
+    mac.update(body);
+    mac
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum PartnerError {
+    UnknownPartner,
+    /// The timestamp is missing, or outside of the replay window
+    InvalidTimestamp,
+    InvalidSignature,
+    /// The signature was already used
+    Replayed,
+}
+
+impl fmt::Display for PartnerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartnerError::UnknownPartner => write!(f, "Unknown '{}'", PARTNER_HEADER),
+            PartnerError::InvalidTimestamp => write!(
+                f,
+                "'{}' is missing or too far from the current time",
+                TIMESTAMP_HEADER
+            ),
+            PartnerError::InvalidSignature => write!(f, "Invalid '{}'", SIGNATURE_HEADER),
+            PartnerError::Replayed => write!(f, "The request was already made"),
+        }
+    }
+}
+
+impl std::error::Error for PartnerError {}
+
+/// How a signed request ended
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    /// With the amount funded, in base units
+    Funded(u64),
+    DryRun,
+    Refused,
+}
+
+/// A partner's signed requests since the faucet started, as returned by `GET /admin/usage`
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PartnerUsage {
+    /// Requests whose signature verified
+    pub requests: u64,
+    /// Requests funded, dry runs excluded
+    pub funded: u64,
+    /// Amount funded, in base units
+    pub amount: u64,
+}
+
+pub struct PartnerAuth {
+    config: PartnerConfig,
+    /// Signatures seen within the replay window, with the unix time they were seen at
+    seen: Mutex<HashMap<String, u64>>,
+    /// By partner name, every configured partner included
+    usage: Mutex<BTreeMap<String, PartnerUsage>>,
+}
+
+impl PartnerAuth {
+    pub fn new(config: PartnerConfig) -> Self {
+        let usage = config
+            .secrets
+            .keys()
+            .map(|partner| (partner.clone(), PartnerUsage::default()))
+            .collect();
+        Self {
+            config,
+            seen: Mutex::new(HashMap::new()),
+            usage: Mutex::new(usage),
+        }
+    }
+
+    /// Counts a request signed by `partner`
+    pub fn record(&self, partner: &str, outcome: Outcome) {
+        let mut usage = self.usage.lock();
+        let usage = usage.entry(partner.to_string()).or_default();
+        usage.requests += 1;
+        let outcome = match outcome {
+            Outcome::Funded(amount) => {
+                usage.funded += 1;
+                usage.amount = usage.amount.saturating_add(amount);
+                counters::PARTNER_AMOUNT
+                    .with_label_values(&[partner])
+                    .inc_by(amount);
+                "funded"
+            }
+            Outcome::DryRun => "dry_run",
+            Outcome::Refused => "refused",
+        };
+        counters::PARTNER_REQUESTS
+            .with_label_values(&[partner, outcome])
+            .inc();
+    }
+
+    /// Usage of every partner, by name
+    pub fn usage(&self) -> BTreeMap<String, PartnerUsage> {
+        self.usage.lock().clone()
+    }
+
+    /// Checks the request's signature, returning the partner that signed it
+    pub fn verify(&self, request: &SignedRequest) -> Result<String, PartnerError> {
+        let secret = self
+            .config
+            .secrets
+            .get(&request.partner)
+            .ok_or(PartnerError::UnknownPartner)?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs();
+        let window = self.config.replay_window_secs;
+        match request.timestamp.parse::<u64>() {
+            Ok(timestamp) if timestamp.max(now) - timestamp.min(now) <= window => (),
+            _ => return Err(PartnerError::InvalidTimestamp),
+        }
+
+        let signature = hex::decode(request.signature.trim_start_matches("0x"))
+            .map_err(|_| PartnerError::InvalidSignature)?;
+        mac(
+            secret,
+            &request.method,
+            &request.path_and_query,
+            &request.timestamp,
+            &request.body,
+        )
+        .verify(&signature)
+        .map_err(|_| PartnerError::InvalidSignature)?;
+
+        // Timestamps are accepted up to a window on either side of now, so signatures are
+        // remembered for twice as long
+        let mut seen = self.seen.lock();
+        seen.retain(|_, seen_at| now.saturating_sub(*seen_at) <= 2 * window);
+        if seen.insert(hex::encode(signature), now).is_some() {
+            return Err(PartnerError::Replayed);
+        }
+        Ok(request.partner.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_are_verified_once() {
+        let auth = PartnerAuth::new(PartnerConfig {
+            secrets: vec![("ci".to_string(), "secret".to_string())]
+                .into_iter()
+                .collect(),
+            secret_files: BTreeMap::new(),
+            replay_window_secs: 300,
+        });
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let request = |timestamp: u64, secret: &str| SignedRequest {
+            partner: "ci".to_string(),
+            timestamp: timestamp.to_string(),
+            signature: sign(secret, &Method::POST, "/mint?amount=1", timestamp, b""),
+            method: Method::POST,
+            path_and_query: "/mint?amount=1".to_string(),
+            body: Bytes::new(),
+        };
+
+        let signed = request(now, "secret");
+        assert_eq!(auth.verify(&signed), Ok("ci".to_string()));
+        assert_eq!(auth.verify(&signed), Err(PartnerError::Replayed));
+
+        assert_eq!(
+            auth.verify(&request(now - 1, "wrong")),
+            Err(PartnerError::InvalidSignature)
+        );
+        assert_eq!(
+            auth.verify(&request(now - 600, "secret")),
+            Err(PartnerError::InvalidTimestamp)
+        );
+        let tampered = SignedRequest {
+            path_and_query: "/mint?amount=1000".to_string(),
+            ..request(now - 2, "secret")
+        };
+        assert_eq!(auth.verify(&tampered), Err(PartnerError::InvalidSignature));
+        let unknown = SignedRequest {
+            partner: "other".to_string(),
+            ..request(now - 3, "secret")
+        };
+        assert_eq!(auth.verify(&unknown), Err(PartnerError::UnknownPartner));
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Startup checks, run by `aptos-faucet run --check` as a deploy gate. `run` itself verifies the
+//! node and optionally waits for it to sync before serving.
+
+use crate::FaucetConfig;
+use aptos_crypto::ed25519::Ed25519PrivateKey;
+use aptos_logger::{info, warn};
+use aptos_rest_client::Client;
+use aptos_sdk::types::{account_address::AccountAddress, chain_id::ChainId};
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use url::Url;
+
+/// How often [`wait_for_sync`] checks on the node
+const SYNC_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A failed startup check. Each variant maps to its own process exit code so that deploy
+/// tooling can tell failures apart.
+#[derive(Debug)]
+pub enum PreflightError {
+    /// The mint key could not be loaded
+    Key(anyhow::Error),
+    /// The server url is invalid or the node did not respond
+    NodeUnreachable(anyhow::Error),
+    /// The node is on a different chain than the one configured
+    ChainIdMismatch { expected: ChainId, actual: u8 },
+    /// The account the faucet sends transactions from does not exist
+    FaucetAccountMissing(AccountAddress),
+    /// The account the faucet sends transactions from cannot pay for gas
+    FaucetAccountUnfunded(AccountAddress),
+}
+
+impl PreflightError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            PreflightError::Key(_) => 2,
+            PreflightError::NodeUnreachable(_) => 3,
+            PreflightError::ChainIdMismatch { .. } => 4,
+            PreflightError::FaucetAccountMissing(_) => 5,
+            PreflightError::FaucetAccountUnfunded(_) => 6,
+        }
+    }
+}
+
+impl fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PreflightError::Key(err) => write!(f, "Unable to load mint key: {}", err),
+            PreflightError::NodeUnreachable(err) => write!(f, "Node is unreachable: {}", err),
+            PreflightError::ChainIdMismatch { expected, actual } => write!(
+                f,
+                "Node is on chain {}, but the faucet is configured for chain {}",
+                actual, expected
+            ),
+            PreflightError::FaucetAccountMissing(address) => {
+                write!(f, "Faucet account {} does not exist", address)
+            }
+            PreflightError::FaucetAccountUnfunded(address) => {
+                write!(f, "Faucet account {} has no coins to pay for gas", address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PreflightError {}
+
+pub fn check_key(config: &FaucetConfig) -> Result<Ed25519PrivateKey, PreflightError> {
+    config.load_mint_key().map_err(PreflightError::Key)
+}
+
+fn client(config: &FaucetConfig) -> Result<Client, PreflightError> {
+    let url = Url::parse(&config.server_url).map_err(|err| {
+        PreflightError::NodeUnreachable(anyhow::format_err!(
+            "Invalid server url {}: {}",
+            config.server_url,
+            err
+        ))
+    })?;
+    Ok(Client::new(url))
+}
+
+/// Checks that the node is reachable and on the configured chain, returning a client for it
+pub async fn check_node(config: &FaucetConfig) -> Result<Client, PreflightError> {
+    let client = client(config)?;
+    let state = client
+        .get_ledger_information()
+        .await
+        .map_err(PreflightError::NodeUnreachable)?
+        .into_inner();
+    if state.chain_id != config.chain_id.id() {
+        return Err(PreflightError::ChainIdMismatch {
+            expected: config.chain_id,
+            actual: state.chain_id,
+        });
+    }
+
+    Ok(client)
+}
+
+/// Waits until the node's latest ledger timestamp is within `max_lag` of the wall clock.
+/// Transactions built against a node that is still syncing expire before it catches up
+pub async fn wait_for_sync(config: &FaucetConfig, max_lag: Duration) -> Result<(), PreflightError> {
+    let client = client(config)?;
+    loop {
+        match client.get_ledger_information().await {
+            Ok(state) => {
+                let ledger_time = UNIX_EPOCH + Duration::from_micros(state.inner().timestamp_usecs);
+                let lag = SystemTime::now()
+                    .duration_since(ledger_time)
+                    .unwrap_or_default();
+                if lag <= max_lag {
+                    return Ok(());
+                }
+                info!(
+                    "[faucet]: waiting for the node to sync, its ledger is {}s behind",
+                    lag.as_secs()
+                );
+            }
+            Err(err) => warn!("[faucet]: waiting for the node to sync: {}", err),
+        }
+        tokio::time::sleep(SYNC_POLL_INTERVAL).await;
+    }
+}
+
+/// Runs every check the faucet depends on at startup: the key loads, the node is reachable and
+/// on the right chain, and the faucet account exists and holds coins.
+pub async fn preflight(config: &FaucetConfig) -> Result<(), PreflightError> {
+    check_key(config)?;
+    let client = check_node(config).await?;
+
+    let faucet_address = config.faucet_address();
+    client
+        .get_account(faucet_address)
+        .await
+        .map_err(|_| PreflightError::FaucetAccountMissing(faucet_address))?;
+    let balance = client
+        .get_account_balance(faucet_address)
+        .await
+        .map(|balance| balance.into_inner().get())
+        .unwrap_or(0);
+    if balance == 0 {
+        return Err(PreflightError::FaucetAccountUnfunded(faucet_address));
+    }
+
+    Ok(())
+}
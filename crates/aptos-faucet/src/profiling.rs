@@ -0,0 +1,78 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! CPU profiles of the running faucet, with the `cpu-profiling` feature, so that hotspots can be
+//! found in production without deploying an instrumented build. `GET /debug/pprof/profile`
+//! samples the stacks of every thread for `seconds` and answers with a flamegraph of them, as an
+//! SVG. It is served to admins only, see [`crate::admin`].
+
+use crate::rejection;
+use serde::Deserialize;
+use std::{convert::Infallible, time::Duration};
+use warp::{http::StatusCode, Reply};
+
+/// How long a profile samples for when the request does not say
+const DEFAULT_PROFILE_SECS: u64 = 30;
+
+/// Longest profile a request may ask for
+const MAX_PROFILE_SECS: u64 = 300;
+
+/// Stack samples per second, off the round numbers so that sampling does not line up with
+/// periodic work
+const SAMPLING_FREQUENCY: i32 = 99;
+
+#[derive(Deserialize, Debug)]
+pub struct ProfileParams {
+    /// How long to sample for, 30 seconds by default
+    pub seconds: Option<u64>,
+}
+
+pub(crate) async fn handle_profile(params: ProfileParams) -> Result<Box<dyn Reply>, Infallible> {
+    let seconds = params.seconds.unwrap_or(DEFAULT_PROFILE_SECS);
+    if seconds == 0 || seconds > MAX_PROFILE_SECS {
+        return Ok(rejection::reply(
+            StatusCode::BAD_REQUEST,
+            "bad_params",
+            format!("'seconds' must be between 1 and {}", MAX_PROFILE_SECS),
+        ));
+    }
+
+    // The thread sleeps while the samples are taken, which is no job for a runtime worker
+    let profile =
+        tokio::task::spawn_blocking(move || flamegraph(Duration::from_secs(seconds))).await;
+    Ok(match profile {
+        Ok(Ok(svg)) => Box::new(warp::reply::with_header(
+            svg,
+            "content-type",
+            "image/svg+xml",
+        )),
+        Ok(Err(pprof::Error::Running)) => rejection::reply(
+            StatusCode::CONFLICT,
+            "profile_in_progress",
+            "Another profile is being taken",
+        ),
+        Ok(Err(err)) => rejection::reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            format!("Unable to profile: {}", err),
+        ),
+        Err(err) => rejection::reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            format!("Unable to profile: {}", err),
+        ),
+    })
+}
+
+/// Samples every thread for `duration`, and renders their stacks as a flamegraph
+fn flamegraph(duration: Duration) -> pprof::Result<Vec<u8>> {
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(SAMPLING_FREQUENCY)
+        // Unwinding from the signal handler through these can deadlock
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()?;
+    std::thread::sleep(duration);
+    let mut svg = Vec::new();
+    guard.report().build()?.flamegraph(&mut svg)?;
+    Ok(svg)
+}
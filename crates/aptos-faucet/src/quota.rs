@@ -0,0 +1,74 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! `GET /quota` reports what the caller may still mint without counting a request, so that
+//! frontends can disable their fund button instead of letting users run into `429`s.
+
+use crate::{
+    budget::BudgetQuota,
+    client_ip,
+    ip_limit::IpQuota,
+    request_log::{request_context, with_request_id},
+    Service,
+};
+use serde::{Deserialize, Serialize};
+use std::{convert::Infallible, net::IpAddr, sync::Arc};
+use warp::{Filter, Rejection, Reply};
+
+pub fn quota_route(
+    service: Arc<Service>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    // GET /quota
+    warp::path!("quota")
+        .and(warp::get())
+        .and(client_ip(service.clone()))
+        .and(warp::any().map(move || service.clone()))
+        .and(request_context())
+        .and_then(|client_ip, service, context| {
+            with_request_id(context, handle(service, client_ip))
+        })
+}
+
+/// Returned by `GET /quota`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Quota {
+    /// Whether a mint request from the caller would currently pass the limits below
+    pub available: bool,
+    pub minimum_amount: u64,
+    pub maximum_amount: Option<u64>,
+    /// Amount each receiver address may be funded per UTC day, across all its requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_maximum_amount: Option<u64>,
+    pub default_amount: Option<u64>,
+    /// The caller's address and subnet limits, empty when there are none
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ip_limits: Vec<IpQuota>,
+    /// The faucet wide minting budget, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spend_budget: Option<BudgetQuota>,
+}
+
+async fn handle(
+    service: Arc<Service>,
+    client_ip: Option<IpAddr>,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let ip_limits = match (&service.ip_limiter, client_ip) {
+        (Some(limiter), Some(client_ip)) => limiter.quota(client_ip),
+        _ => Vec::new(),
+    };
+    let spend_budget = service.spend_budget.as_ref().map(|budget| budget.quota());
+
+    let available = ip_limits.iter().all(|limit| limit.remaining > 0)
+        && spend_budget
+            .as_ref()
+            .map_or(true, |budget| budget.remaining >= service.minimum_amount);
+    Ok(Box::new(warp::reply::json(&Quota {
+        available,
+        minimum_amount: service.minimum_amount,
+        maximum_amount: service.maximum_amount,
+        daily_maximum_amount: service.daily_limit.as_ref().map(|limit| limit.limit()),
+        default_amount: service.default_amount,
+        ip_limits,
+        spend_budget,
+    })))
+}
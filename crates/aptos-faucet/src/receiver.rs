@@ -0,0 +1,239 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Parsing of the fields naming the account to fund: `address`, `auth_key` and `pub_key`. Every
+//! endpoint parses them here, so they accept the same forms everywhere: hex with or without a
+//! `0x` prefix, and for addresses and authentication keys, the short form like `0x1` as well as
+//! all 64 digits. The short form needs the prefix, so that a truncated copy of an address is
+//! refused rather than funding another account. Malformed values are refused with an error
+//! naming the field, rather than being ignored.
+
+use aptos_crypto::ed25519::Ed25519PublicKey;
+use aptos_sdk::types::{
+    account_address::AccountAddress, transaction::authenticator::AuthenticationKey,
+};
+use serde::{Deserialize, Deserializer};
+use std::convert::TryFrom;
+use thiserror::Error;
+
+/// Hex digits of a full length address, authentication key or public key
+const HEX_LENGTH: usize = AccountAddress::LENGTH * 2;
+
+/// What is wrong with a field's value
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub enum Invalid {
+    #[error("is empty")]
+    Empty,
+    #[error("contains {0:?}, which is not a hex digit")]
+    NotHex(char),
+    #[error("is longer than {} hex digits", HEX_LENGTH)]
+    TooLong,
+    #[error("must be {} hex digits, or start with 0x", HEX_LENGTH)]
+    TooShort,
+    #[error("must be {} hex digits", HEX_LENGTH)]
+    WrongLength,
+    #[error("is not a valid ed25519 public key")]
+    InvalidKey,
+}
+
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+#[error("'{field}' {reason}")]
+pub struct FieldError {
+    pub field: &'static str,
+    pub reason: Invalid,
+}
+
+/// The hex digits of `value`, without its `0x` prefix, and whether it had one
+fn hex_digits(field: &'static str, value: &str) -> Result<(&str, bool), FieldError> {
+    let invalid = |reason| FieldError { field, reason };
+    let (digits, prefixed) = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(digits) => (digits, true),
+        None => (value, false),
+    };
+    if digits.is_empty() {
+        return Err(invalid(Invalid::Empty));
+    }
+    if let Some(c) = digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(invalid(Invalid::NotHex(c)));
+    }
+    if digits.len() > HEX_LENGTH {
+        return Err(invalid(Invalid::TooLong));
+    }
+    Ok((digits, prefixed))
+}
+
+/// Parses an address or authentication key given as `field`
+pub fn parse_address(field: &'static str, value: &str) -> Result<AccountAddress, FieldError> {
+    let (digits, prefixed) = hex_digits(field, value)?;
+    if digits.len() < HEX_LENGTH && !prefixed {
+        return Err(FieldError {
+            field,
+            reason: Invalid::TooShort,
+        });
+    }
+    let padded = format!("{:0>width$}", digits, width = HEX_LENGTH);
+    // Cannot fail, the digits were checked above
+    Ok(AccountAddress::from_hex(padded).expect("64 hex digits are an address"))
+}
+
+/// Deserializes an `address` field the way [`parse_address`] parses it
+pub fn deserialize_address<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<AccountAddress, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_address("address", &value).map_err(serde::de::Error::custom)
+}
+
+/// Parses an ed25519 public key given as `pub_key`
+pub fn parse_pub_key(value: &str) -> Result<Ed25519PublicKey, FieldError> {
+    let invalid = |reason| FieldError {
+        field: "pub_key",
+        reason,
+    };
+    let (digits, _) = hex_digits("pub_key", value)?;
+    if digits.len() != HEX_LENGTH {
+        return Err(invalid(Invalid::WrongLength));
+    }
+    let bytes = hex::decode(digits).map_err(|_| invalid(Invalid::WrongLength))?;
+    Ed25519PublicKey::try_from(bytes.as_slice()).map_err(|_| invalid(Invalid::InvalidKey))
+}
+
+/// The account named by whichever of the fields are set, preferring `auth_key`, then `address`,
+/// then `pub_key`. Every field that is set must be valid, none if none is set
+pub fn receiver(
+    address: Option<&str>,
+    auth_key: Option<&str>,
+    pub_key: Option<&str>,
+) -> Result<Option<AccountAddress>, FieldError> {
+    let address = address
+        .map(|address| parse_address("address", address))
+        .transpose()?;
+    let auth_key = auth_key
+        .map(|auth_key| parse_address("auth_key", auth_key))
+        .transpose()?;
+    let pub_key = pub_key.map(parse_pub_key).transpose()?;
+    Ok(auth_key
+        .or(address)
+        .or_else(|| pub_key.map(|pub_key| AuthenticationKey::ed25519(&pub_key).derived_address())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_crypto::{ed25519::Ed25519PrivateKey, PrivateKey, Uniform};
+    use rand::{seq::SliceRandom, Rng};
+
+    #[test]
+    fn addresses_are_accepted_in_every_form() {
+        let one = AccountAddress::from_hex_literal("0x1").unwrap();
+        for value in &[
+            "0x1",
+            "0X01",
+            "0x0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+        ] {
+            assert_eq!(parse_address("address", value), Ok(one), "{}", value);
+        }
+        let long = "459C77A38803BD53F3ADEE52703810E3A74FD7C46952C497E75AFB0A7932586D";
+        assert_eq!(
+            parse_address("auth_key", long).unwrap(),
+            AccountAddress::from_hex(long).unwrap()
+        );
+    }
+
+    #[test]
+    fn garbage_is_refused_naming_the_field() {
+        let refused = |value: &str| parse_address("address", value).unwrap_err().reason;
+        assert_eq!(refused(""), Invalid::Empty);
+        assert_eq!(refused("0x"), Invalid::Empty);
+        assert_eq!(refused("0x1g"), Invalid::NotHex('g'));
+        assert_eq!(refused(" 0x1"), Invalid::NotHex(' '));
+        assert_eq!(refused("0x0x1"), Invalid::NotHex('x'));
+        assert_eq!(refused("abc"), Invalid::TooShort);
+        assert_eq!(refused(&format!("0x{}", "1".repeat(65))), Invalid::TooLong);
+
+        let err = parse_address("auth_key", "invalid-auth-key").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'auth_key' contains 'i', which is not a hex digit"
+        );
+        assert_eq!(
+            parse_pub_key("0x1").unwrap_err().reason,
+            Invalid::WrongLength
+        );
+    }
+
+    #[test]
+    fn receiver_checks_every_field() {
+        let key = Ed25519PrivateKey::generate(&mut rand::rngs::OsRng).public_key();
+        let pub_key = hex::encode(key.to_bytes());
+        let derived = AuthenticationKey::ed25519(&key).derived_address();
+
+        assert_eq!(receiver(None, None, None), Ok(None));
+        assert_eq!(receiver(None, None, Some(&pub_key)), Ok(Some(derived)));
+        assert_eq!(
+            receiver(Some("0x1"), None, Some(&pub_key)),
+            Ok(Some(AccountAddress::from_hex_literal("0x1").unwrap()))
+        );
+        assert_eq!(
+            receiver(Some("0x1"), None, Some("nope")).unwrap_err().field,
+            "pub_key"
+        );
+        assert_eq!(
+            receiver(Some("0x1"), Some("0x"), None).unwrap_err().field,
+            "auth_key"
+        );
+    }
+
+    /// Random mutations of valid values are either refused with a field error, or parse to the
+    /// address their digits spell
+    #[test]
+    fn fuzzed_values_never_parse_to_another_address() {
+        let mut rng = rand::thread_rng();
+        let alphabet: Vec<char> = "0123456789abcdefABCDEFxX -_gz\u{e9}\u{1f600}"
+            .chars()
+            .collect();
+        for _ in 0..10_000 {
+            let mut value: Vec<char> = match rng.gen_range(0..3) {
+                0 => "0x".chars().collect(),
+                1 => Vec::new(),
+                _ => AccountAddress::random().to_hex_literal().chars().collect(),
+            };
+            for _ in 0..rng.gen_range(0..70) {
+                let c = *alphabet.choose(&mut rng).unwrap();
+                match rng.gen_range(0..3) {
+                    0 if !value.is_empty() => {
+                        let index = rng.gen_range(0..value.len());
+                        value[index] = c;
+                    }
+                    1 if !value.is_empty() => {
+                        value.remove(rng.gen_range(0..value.len()));
+                    }
+                    _ => value.push(c),
+                }
+            }
+            let value: String = value.into_iter().collect();
+
+            match parse_address("address", &value) {
+                Ok(address) => {
+                    let digits = value
+                        .strip_prefix("0x")
+                        .or_else(|| value.strip_prefix("0X"))
+                        .unwrap_or(&value)
+                        .to_ascii_lowercase();
+                    let full = hex::encode(address);
+                    let (padding, rest) = full.split_at(HEX_LENGTH - digits.len());
+                    assert!(padding.chars().all(|c| c == '0'), "{}", value);
+                    assert_eq!(rest, digits, "{}", value);
+                }
+                Err(err) => assert_eq!(err.field, "address"),
+            }
+            if let Err(err) = parse_pub_key(&value) {
+                assert_eq!(err.field, "pub_key");
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Just enough of the Redis protocol to run Lua scripts, which is all the leader lease of
+//! [`crate::leader`] and the sequence numbers of [`crate::sequence_leases`] need, each script
+//! being a single atomic step. A connection is opened per script, so there is nothing to reopen
+//! after an error. TLS is not supported.
+
+use anyhow::{bail, Result};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+};
+
+type Connection = BufStream<TcpStream>;
+
+/// A Redis reply, of the kinds scripts get
+#[derive(Debug, Eq, PartialEq)]
+enum Reply {
+    Status(String),
+    Integer(i64),
+}
+
+/// Runs `script` on the Redis server at `address`, returning its integer result
+pub(crate) async fn eval(
+    address: &str,
+    password: Option<&str>,
+    script: &str,
+    keys: &[&str],
+    args: &[&str],
+) -> Result<i64> {
+    let mut connection = BufStream::new(TcpStream::connect(address).await?);
+    if let Some(password) = password {
+        command(&mut connection, &["AUTH", password]).await?;
+    }
+    let key_count = keys.len().to_string();
+    let mut eval = vec!["EVAL", script, key_count.as_str()];
+    eval.extend(keys);
+    eval.extend(args);
+    match command(&mut connection, &eval).await? {
+        Reply::Integer(result) => Ok(result),
+        reply => bail!("unexpected reply {:?}", reply),
+    }
+}
+
+/// Sends a command and reads its reply, failing on error replies
+async fn command(connection: &mut Connection, args: &[&str]) -> Result<Reply> {
+    let mut request = format!("*{}\r\n", args.len());
+    for arg in args {
+        request.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+    connection.write_all(request.as_bytes()).await?;
+    connection.flush().await?;
+
+    let line = read_line(connection).await?;
+    let (kind, rest) = line.split_at(line.len().min(1));
+    match kind {
+        "+" => Ok(Reply::Status(rest.to_string())),
+        "-" => bail!("server error: {}", rest),
+        ":" => Ok(Reply::Integer(rest.parse()?)),
+        _ => bail!("unexpected reply {:?}", line),
+    }
+}
+
+async fn read_line(connection: &mut Connection) -> Result<String> {
+    let mut line = String::new();
+    if connection.read_line(&mut line).await? == 0 {
+        bail!("connection closed by the server");
+    }
+    Ok(line.trim_end().to_string())
+}
+
+/// A Redis server answering the scripts of a connection each with the next of `results`, after
+/// checking that the password is `secret`. Returns its address
+#[cfg(test)]
+pub(crate) async fn fake_server(results: Vec<i64>) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        for result in results {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut socket = BufStream::new(socket);
+            for expected in ["AUTH", "EVAL"] {
+                let count: usize = read_line(&mut socket).await.unwrap()[1..].parse().unwrap();
+                let mut args = Vec::new();
+                for _ in 0..count {
+                    read_line(&mut socket).await.unwrap();
+                    args.push(read_line(&mut socket).await.unwrap());
+                }
+                assert_eq!(args[0], expected);
+                let reply = match expected {
+                    "AUTH" if args[1] == "secret" => "+OK\r\n".to_string(),
+                    "AUTH" => "-WRONGPASS invalid password\r\n".to_string(),
+                    _ => format!(":{}\r\n", result),
+                };
+                socket.write_all(reply.as_bytes()).await.unwrap();
+                socket.flush().await.unwrap();
+                if reply.starts_with('-') {
+                    break;
+                }
+            }
+        }
+    });
+    address
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn scripts_return_integers() {
+        let address = fake_server(vec![7, 8]).await;
+        let run = |password| eval(&address, password, "return 7", &["key"], &["arg"]);
+        assert_eq!(run(Some("secret")).await.unwrap(), 7);
+        let err = run(Some("wrong")).await.unwrap_err();
+        assert_eq!(err.to_string(), "server error: WRONGPASS invalid password");
+    }
+}
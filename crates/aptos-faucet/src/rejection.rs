@@ -0,0 +1,99 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Replies to requests no route accepted, e.g. unknown paths or malformed query strings, with
+//! the same JSON body as the faucet's other errors. Recovering within [`crate::routes`] rather
+//! than leaving it to warp means these replies are logged and get the CORS and security headers
+//! like any other.
+
+use crate::{error::FaucetError, request_limits::RequestLimitError};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use warp::{
+    body::BodyDeserializeError,
+    http::StatusCode,
+    reject::{
+        InvalidHeader, InvalidQuery, LengthRequired, MethodNotAllowed, MissingHeader,
+        PayloadTooLarge, UnsupportedMediaType,
+    },
+    Rejection, Reply,
+};
+
+/// Body of the reply to a rejected request
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RejectionBody {
+    pub error: String,
+    pub message: String,
+}
+
+pub(crate) async fn handle_rejection(rejection: Rejection) -> Result<Box<dyn Reply>, Infallible> {
+    let (status, error, message) = if rejection.is_not_found() {
+        (StatusCode::NOT_FOUND, "not_found", "Not Found".to_string())
+    } else if let Some(cause) = rejection.find::<InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, "invalid_query", cause.to_string())
+    } else if let Some(cause) = rejection.find::<InvalidHeader>() {
+        (StatusCode::BAD_REQUEST, "invalid_header", cause.to_string())
+    } else if let Some(cause) = rejection.find::<MissingHeader>() {
+        (StatusCode::BAD_REQUEST, "invalid_header", cause.to_string())
+    } else if let Some(cause) = rejection.find::<BodyDeserializeError>() {
+        (StatusCode::BAD_REQUEST, "invalid_body", cause.to_string())
+    } else if let Some(cause) = rejection.find::<RequestLimitError>() {
+        (cause.status(), cause.reason(), cause.to_string())
+    } else if let Some(cause) = rejection.find::<LengthRequired>() {
+        (
+            StatusCode::LENGTH_REQUIRED,
+            "length_required",
+            cause.to_string(),
+        )
+    } else if let Some(cause) = rejection.find::<PayloadTooLarge>() {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            cause.to_string(),
+        )
+    } else if let Some(cause) = rejection.find::<UnsupportedMediaType>() {
+        (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
+            cause.to_string(),
+        )
+    } else if let Some(cause) = rejection.find::<MethodNotAllowed>() {
+        (
+            StatusCode::METHOD_NOT_ALLOWED,
+            "method_not_allowed",
+            cause.to_string(),
+        )
+    } else {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            format!("Unexpected error: {:?}", rejection),
+        )
+    };
+
+    Ok(reply(status, error, message))
+}
+
+/// Replies with `status` and a [`RejectionBody`], for handlers refusing a request themselves
+pub(crate) fn reply(status: StatusCode, error: &str, message: impl Into<String>) -> Box<dyn Reply> {
+    Box::new(warp::reply::with_status(
+        warp::reply::json(&RejectionBody {
+            error: error.to_string(),
+            message: message.into(),
+        }),
+        status,
+    ))
+}
+
+/// Replies to a request refused with `err`, telling clients refused by a limit when to try again
+pub(crate) fn faucet_error(err: &FaucetError) -> Box<dyn Reply> {
+    let reply = reply(err.status_code(), err.reason(), err.to_string());
+    match err.retry_after() {
+        Some(retry_after) => Box::new(warp::reply::with_header(
+            reply,
+            "retry-after",
+            retry_after.as_secs().max(1).to_string(),
+        )),
+        None => reply,
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Caps on the size of requests, so that clients cannot tie up memory with huge URIs, headers or
+//! bodies. Oversized URIs and headers are refused before routing. Bodies are only read by the
+//! routes that need them, and refused as soon as they grow past the limit, whether or not they
+//! declare their length. The `/admin` bulk funding list has its own, larger limit.
+
+use crate::request_log;
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{Stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use warp::{
+    filters::path::FullPath,
+    http::{HeaderMap, StatusCode},
+    reject::Reject,
+    Filter, Rejection,
+};
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RequestLimitsConfig {
+    /// Largest request body accepted
+    pub max_body_bytes: u64,
+    /// Largest total size of the request headers, names and values. The HTTP server refuses
+    /// headers beyond about 400 KiB regardless
+    pub max_header_bytes: usize,
+    /// Longest request URI accepted, path and query string
+    pub max_uri_length: usize,
+}
+
+impl Default for RequestLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 64 * 1024,
+            max_header_bytes: 16 * 1024,
+            max_uri_length: 8 * 1024,
+        }
+    }
+}
+
+impl RequestLimitsConfig {
+    pub fn validate(&self) -> Result<()> {
+        for (name, limit) in [
+            ("max_body_bytes", self.max_body_bytes as usize),
+            ("max_header_bytes", self.max_header_bytes),
+            ("max_uri_length", self.max_uri_length),
+        ] {
+            if limit == 0 {
+                bail!("request_limits.{} must be at least 1", name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why a request was refused
+#[derive(Debug, Error)]
+pub enum RequestLimitError {
+    #[error("Request body is larger than {0} bytes")]
+    Body(u64),
+    #[error("Request headers are larger than {0} bytes")]
+    Headers(usize),
+    #[error("Request URI is longer than {0} bytes")]
+    Uri(usize),
+    #[error("Unable to read the request body")]
+    Unreadable,
+}
+
+impl Reject for RequestLimitError {}
+
+impl RequestLimitError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            RequestLimitError::Body(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            RequestLimitError::Headers(_) => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            RequestLimitError::Uri(_) => StatusCode::URI_TOO_LONG,
+            RequestLimitError::Unreadable => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            RequestLimitError::Body(_) => "payload_too_large",
+            RequestLimitError::Headers(_) => "headers_too_large",
+            RequestLimitError::Uri(_) => "uri_too_long",
+            RequestLimitError::Unreadable => "invalid_body",
+        }
+    }
+}
+
+/// Refuses requests whose URI or headers are over the limits
+pub(crate) fn check(
+    limits: RequestLimitsConfig,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path::full()
+        .and(request_log::raw_query())
+        .and(warp::header::headers_cloned())
+        .and_then(move |path: FullPath, query: String, headers: HeaderMap| {
+            let limits = limits.clone();
+            async move {
+                let uri_length = if query.is_empty() {
+                    path.as_str().len()
+                } else {
+                    path.as_str().len() + 1 + query.len()
+                };
+                if uri_length > limits.max_uri_length {
+                    return Err(warp::reject::custom(RequestLimitError::Uri(
+                        limits.max_uri_length,
+                    )));
+                }
+                let header_bytes: usize = headers
+                    .iter()
+                    .map(|(name, value)| name.as_str().len() + value.len())
+                    .sum();
+                if header_bytes > limits.max_header_bytes {
+                    return Err(warp::reject::custom(RequestLimitError::Headers(
+                        limits.max_header_bytes,
+                    )));
+                }
+                Ok(())
+            }
+        })
+        .untuple_one()
+}
+
+/// The request body, refused once it is larger than `max_bytes`
+pub(crate) fn body(max_bytes: u64) -> impl Filter<Extract = (Bytes,), Error = Rejection> + Clone {
+    warp::header::optional::<u64>("content-length")
+        .and_then(move |length: Option<u64>| async move {
+            match length {
+                // Refused without reading a byte of it
+                Some(length) if length > max_bytes => {
+                    Err(warp::reject::custom(RequestLimitError::Body(max_bytes)))
+                }
+                _ => Ok(()),
+            }
+        })
+        .untuple_one()
+        .and(warp::body::stream())
+        .and_then(move |stream| read(stream, max_bytes))
+}
+
+async fn read(
+    stream: impl Stream<Item = Result<impl Buf, warp::Error>>,
+    max_bytes: u64,
+) -> Result<Bytes, Rejection> {
+    futures::pin_mut!(stream);
+    let mut body = BytesMut::new();
+    while let Some(chunk) = stream
+        .try_next()
+        .await
+        .map_err(|_| warp::reject::custom(RequestLimitError::Unreadable))?
+    {
+        if (body.len() + chunk.remaining()) as u64 > max_bytes {
+            return Err(warp::reject::custom(RequestLimitError::Body(max_bytes)));
+        }
+        body.put(chunk);
+    }
+    Ok(body.freeze())
+}
@@ -0,0 +1,299 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Every request is handled under an id, returned in the `x-request-id` response header and
+//! attached to every log line and audit entry written while handling it, so that user reports can
+//! be correlated with the logs. Each request also gets a structured access log line.
+//!
+//! Requests coming through a gateway keep the ids it gave them: the id of a request carrying an
+//! `x-request-id` header is taken from it, and a W3C `traceparent` header makes the faucet's
+//! spans part of the caller's trace. Both are echoed on the response and passed on to the node
+//! with every request sent while handling it.
+
+use crate::{log_sampling::LogSampler, OptFmt};
+use aptos_logger::info;
+use std::{
+    convert::{Infallible, TryInto},
+    fmt,
+    future::Future,
+    net::IpAddr,
+    str::FromStr,
+    sync::Arc,
+    time::Instant,
+};
+use uuid::Uuid;
+use warp::{
+    filters::path::FullPath,
+    http::{header::HeaderValue, HeaderMap, Method},
+    reply::{Reply, Response},
+    Filter,
+};
+
+/// Request and response header carrying the request id
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Request and response header carrying the W3C trace context
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Longest request id taken from a request, requests with longer ones get a new id
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Query parameters whose values are replaced in the access log
+const REDACTED_PARAMS: &[&str] = &["auth_key", "pub_key", "signature", "coupon"];
+
+tokio::task_local! {
+    static REQUEST: RequestContext;
+}
+
+/// The W3C trace context of a request, see <https://www.w3.org/TR/trace-context/>
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TraceParent {
+    pub trace_id: [u8; 16],
+    /// The caller's span the request was sent from
+    pub parent_id: [u8; 8],
+    pub flags: u8,
+}
+
+impl FromStr for TraceParent {
+    type Err = ();
+
+    /// Parses version `00` headers, `00-<trace id>-<parent id>-<flags>` in lowercase hex
+    fn from_str(header: &str) -> Result<Self, ()> {
+        let fields: Vec<_> = header.trim().split('-').collect();
+        let (trace_id, parent_id, flags) = match fields.as_slice() {
+            ["00", trace_id, parent_id, flags] => (trace_id, parent_id, flags),
+            _ => return Err(()),
+        };
+        if [trace_id, parent_id, flags]
+            .iter()
+            .any(|field| field.bytes().any(|byte| byte.is_ascii_uppercase()))
+        {
+            return Err(());
+        }
+        let trace_id: [u8; 16] = hex::decode(trace_id)
+            .map_err(|_| ())?
+            .try_into()
+            .map_err(|_| ())?;
+        let parent_id: [u8; 8] = hex::decode(parent_id)
+            .map_err(|_| ())?
+            .try_into()
+            .map_err(|_| ())?;
+        let flags = match hex::decode(flags).map_err(|_| ())?.as_slice() {
+            [flags] => *flags,
+            _ => return Err(()),
+        };
+        // All zeroes are invalid ids
+        if trace_id == [0; 16] || parent_id == [0; 8] {
+            return Err(());
+        }
+        Ok(Self {
+            trace_id,
+            parent_id,
+            flags,
+        })
+    }
+}
+
+impl fmt::Display for TraceParent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "00-{}-{}-{:02x}",
+            hex::encode(self.trace_id),
+            hex::encode(self.parent_id),
+            self.flags
+        )
+    }
+}
+
+/// Identifies a request, see the module documentation
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RequestContext {
+    pub id: String,
+    pub trace_parent: Option<TraceParent>,
+}
+
+impl RequestContext {
+    /// The context of a request with `headers`, under a new id unless it carries a valid one
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let id = headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .filter(|id| {
+                !id.is_empty()
+                    && id.len() <= MAX_REQUEST_ID_LEN
+                    && id.bytes().all(|byte| byte.is_ascii_graphic())
+            })
+            .map_or_else(|| Uuid::new_v4().to_string(), str::to_string);
+        let trace_parent = headers
+            .get(TRACEPARENT_HEADER)
+            .and_then(|header| header.to_str().ok()?.parse().ok());
+        Self { id, trace_parent }
+    }
+
+    /// The headers passing the context on, to the client or the node
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        // Ids taken from requests are checked to be valid header values
+        if let Ok(id) = HeaderValue::from_str(&self.id) {
+            headers.insert(REQUEST_ID_HEADER, id);
+        }
+        if let Some(trace_parent) = &self.trace_parent {
+            let value = HeaderValue::from_str(&trace_parent.to_string());
+            headers.insert(
+                TRACEPARENT_HEADER,
+                value.expect("hex is a valid header value"),
+            );
+        }
+        headers
+    }
+}
+
+/// The context of the request, see [`RequestContext::from_headers`]
+pub(crate) fn request_context(
+) -> impl Filter<Extract = (RequestContext,), Error = Infallible> + Clone {
+    warp::header::headers_cloned().map(|headers: HeaderMap| RequestContext::from_headers(&headers))
+}
+
+/// Id of the request the current task is handling, if any
+pub fn current_request_id() -> Option<String> {
+    REQUEST.try_with(|request| request.id.clone()).ok()
+}
+
+/// Trace context of the request the current task is handling, if it came with one
+pub fn current_trace_parent() -> Option<TraceParent> {
+    REQUEST
+        .try_with(|request| request.trace_parent)
+        .ok()
+        .flatten()
+}
+
+/// The headers passing the context of the request the current task is handling on to the node,
+/// empty outside of requests
+pub fn outbound_headers() -> HeaderMap {
+    REQUEST
+        .try_with(RequestContext::headers)
+        .unwrap_or_default()
+}
+
+/// Runs `handler` under the request's `context`, and adds the context's headers to its reply
+pub(crate) async fn with_request_id(
+    context: RequestContext,
+    handler: impl Future<Output = Result<Box<dyn Reply>, Infallible>>,
+) -> Result<impl Reply, Infallible> {
+    let headers = context.headers();
+    let mut response = REQUEST.scope(context, handler).await?.into_response();
+    response.headers_mut().extend(headers);
+    Ok(response)
+}
+
+/// The raw query string, empty if the request has none
+pub(crate) fn raw_query() -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
+    warp::query::raw().or(warp::any().map(String::new)).unify()
+}
+
+/// Logs the request along with the reply it got. Successful requests are subject to sampling
+pub(crate) fn access_log(
+    start: Instant,
+    method: Method,
+    path: FullPath,
+    query: String,
+    client_ip: Option<IpAddr>,
+    user_agent: Option<String>,
+    log_sampler: Arc<LogSampler>,
+    reply: impl Reply,
+) -> Response {
+    let response = reply.into_response();
+    let failed = response.status().is_client_error() || response.status().is_server_error();
+    if !failed && !log_sampler.sample(module_path!()) {
+        return response;
+    }
+    let request_id = response
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok());
+    let trace_id = response
+        .headers()
+        .get(TRACEPARENT_HEADER)
+        .and_then(|header| header.to_str().ok()?.parse::<TraceParent>().ok())
+        .map(|trace_parent| hex::encode(trace_parent.trace_id));
+    info!(
+        request_id = request_id,
+        trace_id = trace_id,
+        method = %method,
+        path = redact(path.as_str(), &query),
+        status = response.status().as_u16(),
+        latency_ms = start.elapsed().as_millis() as u64,
+        client_ip = %OptFmt(client_ip),
+        user_agent = user_agent,
+        "[faucet]: request"
+    );
+    response
+}
+
+/// Rebuilds the path and query, with the values of key parameters replaced
+fn redact(path: &str, query: &str) -> String {
+    if query.is_empty() {
+        return path.to_string();
+    }
+    let query: Vec<_> = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if REDACTED_PARAMS.contains(&name) => format!("{}=<redacted>", name),
+            _ => pair.to_string(),
+        })
+        .collect();
+    format!("{}?{}", path, query.join("&"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_parents_parse() {
+        let header = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let trace_parent: TraceParent = header.parse().unwrap();
+        assert_eq!(trace_parent.flags, 1);
+        assert_eq!(trace_parent.to_string(), header);
+
+        for invalid in [
+            "01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            "00-0AF7651916CD43DD8448EB211C80319C-b7ad6b7169203331-01",
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b71692033-01",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331",
+        ] {
+            assert!(invalid.parse::<TraceParent>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn request_ids_are_taken_from_requests() {
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("gateway-123"));
+        assert_eq!(RequestContext::from_headers(&headers).id, "gateway-123");
+
+        // Ids that would not fit on a log line are replaced
+        let long = HeaderValue::from_str(&"a".repeat(MAX_REQUEST_ID_LEN + 1)).unwrap();
+        headers.insert(REQUEST_ID_HEADER, long);
+        assert!(RequestContext::from_headers(&headers)
+            .id
+            .parse::<Uuid>()
+            .is_ok());
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("a b"));
+        assert!(RequestContext::from_headers(&headers)
+            .id
+            .parse::<Uuid>()
+            .is_ok());
+    }
+
+    #[test]
+    fn keys_are_redacted() {
+        assert_eq!(redact("/health", ""), "/health");
+        assert_eq!(
+            redact("/mint", "amount=10&pub_key=abcd&address=0x1&signature=ef"),
+            "/mint?amount=10&pub_key=<redacted>&address=0x1&signature=<redacted>"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Standing orders, funding fixed addresses on a schedule, e.g. to keep long-running demo
+//! accounts and monitoring canaries from running dry. Each order runs once when the faucet
+//! starts, then every `interval_secs`. Orders are funded without any of the limits or checks of
+//! mint requests.
+
+use crate::{
+    counters,
+    mint::{self, MintParams, Response},
+    Service,
+};
+use anyhow::{bail, format_err, Result};
+use aptos_logger::{info, warn};
+use aptos_sdk::types::account_address::AccountAddress;
+use serde::{Deserialize, Serialize};
+use std::{sync::Weak, time::Duration};
+use tokio::time::MissedTickBehavior;
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StandingOrder {
+    pub address: AccountAddress,
+    /// In base units
+    pub amount: u64,
+    pub interval_secs: u64,
+    /// Only fund what `address` is missing to hold `amount`, rather than `amount` every time
+    #[serde(default)]
+    pub top_up: bool,
+}
+
+impl StandingOrder {
+    pub fn validate(&self) -> Result<()> {
+        if self.amount == 0 || self.interval_secs == 0 {
+            bail!(
+                "the standing order for {} must have a positive amount and interval_secs",
+                self.address
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Executes `order` every `interval_secs` while the service leads, until it is dropped
+pub(crate) async fn run(order: StandingOrder, service: Weak<Service>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(order.interval_secs));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let service = match service.upgrade() {
+            Some(service) => service,
+            None => return,
+        };
+        if !service.is_leader() {
+            continue;
+        }
+        match execute(&service, &order).await {
+            Ok(0) => counters::STANDING_ORDERS
+                .with_label_values(&["skipped"])
+                .inc(),
+            Ok(amount) => {
+                counters::STANDING_ORDERS
+                    .with_label_values(&["funded"])
+                    .inc();
+                info!(
+                    "[faucet]: standing order funded {} with {}",
+                    order.address, amount
+                );
+            }
+            Err(err) => {
+                counters::STANDING_ORDERS
+                    .with_label_values(&["failed"])
+                    .inc();
+                warn!(
+                    "[faucet]: standing order for {} failed: {:#}",
+                    order.address, err
+                );
+            }
+        }
+    }
+}
+
+/// Funds `order` once, waiting for the transactions to commit. Returns the amount funded, 0 when
+/// a top up finds the address already holding enough
+pub async fn execute(service: &Service, order: &StandingOrder) -> Result<u64> {
+    let amount = if order.top_up {
+        let balance = service
+            .client
+            .get_account_balance(order.address)
+            .await
+            .map(|balance| balance.into_inner().get())
+            // Usually an account that does not exist yet
+            .unwrap_or(0);
+        order.amount.saturating_sub(balance)
+    } else {
+        order.amount
+    };
+    if amount == 0 {
+        return Ok(0);
+    }
+
+    let params = MintParams {
+        address: Some(order.address.to_hex_literal()),
+        wait: Some(true),
+        ..MintParams::default()
+    };
+    match mint::fund(service, params, amount).await? {
+        Response::Funded(response) if response.txns.iter().all(|txn| txn.success) => Ok(amount),
+        Response::Funded(_) => Err(format_err!("the transactions failed to execute")),
+        Response::SubmittedTxns(_) | Response::DryRun(_) => Ok(amount),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::setup_service;
+
+    #[tokio::test]
+    async fn orders_fund_or_top_up() {
+        let (chain, service) = setup_service(Some(10));
+        let address = AccountAddress::random();
+        let order = StandingOrder {
+            address,
+            amount: 1000,
+            interval_secs: 3600,
+            top_up: false,
+        };
+
+        // The maximum amount of mint requests does not apply
+        assert_eq!(execute(&service, &order).await.unwrap(), 1000);
+        assert_eq!(execute(&service, &order).await.unwrap(), 1000);
+        assert_eq!(chain.account(address).unwrap().balance, 2000);
+
+        let top_up = StandingOrder {
+            amount: 2500,
+            top_up: true,
+            ..order
+        };
+        assert_eq!(execute(&service, &top_up).await.unwrap(), 500);
+        assert_eq!(execute(&service, &top_up).await.unwrap(), 0);
+        assert_eq!(chain.account(address).unwrap().balance, 2500);
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Secrets read from files, like Docker and Kubernetes secret mounts, so that they stay out of
+//! the command line, the environment and the config file. Each secret option has a `_file`
+//! variant naming the file holding the secret, which is read when the faucet starts. A trailing
+//! newline is not part of the secret.
+
+use anyhow::{bail, format_err, Result};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The secret held by the file at `path`
+pub fn read(path: &Path) -> Result<String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format_err!("Unable to read secret {:?}: {}", path, err))?;
+    let secret = contents.trim_end_matches(|c| c == '\n' || c == '\r');
+    if secret.is_empty() {
+        bail!("Secret {:?} is empty", path);
+    }
+    Ok(secret.to_string())
+}
+
+/// Sets `value` to the secret in `file`, if set. `name` is the option, for errors
+pub fn resolve(name: &str, value: &mut Option<String>, file: &mut Option<PathBuf>) -> Result<()> {
+    if let Some(path) = file.take() {
+        if value.is_some() {
+            bail!("Only one of {} and {}_file may be set", name, name);
+        }
+        *value = Some(read(&path)?);
+    }
+    Ok(())
+}
+
+/// Adds the secret in each of `files` to `values`, under the same key. `name` is the option in the
+/// singular, e.g. `kafka.header` for `kafka.headers` and `kafka.header_files`
+pub fn resolve_all(
+    name: &str,
+    values: &mut BTreeMap<String, String>,
+    files: &mut BTreeMap<String, PathBuf>,
+) -> Result<()> {
+    for (key, path) in std::mem::take(files) {
+        if values.contains_key(&key) {
+            bail!("'{}' is set in both {}s and {}_files", key, name, name);
+        }
+        values.insert(key, read(&path)?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_read_from_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        fs::write(&path, "s3cret\n").unwrap();
+
+        let mut value = None;
+        let mut file = Some(path.clone());
+        resolve("admin_token", &mut value, &mut file).unwrap();
+        assert_eq!(value.as_deref(), Some("s3cret"));
+        assert_eq!(file, None);
+
+        let mut file = Some(path.clone());
+        let err = resolve("admin_token", &mut value, &mut file).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Only one of admin_token and admin_token_file may be set"
+        );
+
+        let mut values = BTreeMap::from([("ci".to_string(), "inline".to_string())]);
+        let mut files = BTreeMap::from([("bot".to_string(), path.clone())]);
+        resolve_all("partners.secret", &mut values, &mut files).unwrap();
+        assert_eq!(values["bot"], "s3cret");
+        assert_eq!(values["ci"], "inline");
+
+        fs::write(&path, "\n").unwrap();
+        assert!(read(&path).is_err());
+        assert!(read(&dir.path().join("missing")).is_err());
+    }
+}
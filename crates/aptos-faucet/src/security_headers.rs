@@ -0,0 +1,72 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Standard security headers added to every response, as the faucet is frequently exposed
+//! directly to browsers.
+
+use anyhow::{format_err, Result};
+use serde::{Deserialize, Serialize};
+use warp::http::{
+    header::{
+        HeaderName, CONTENT_SECURITY_POLICY, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY,
+        X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+    },
+    HeaderMap, HeaderValue,
+};
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityHeadersConfig {
+    /// `max-age` sent in `Strict-Transport-Security`, which is only sent when set. Only set it
+    /// when clients reach the faucet over TLS, e.g. through a TLS terminating proxy
+    pub hsts_max_age_secs: Option<u64>,
+    /// Send `X-Content-Type-Options: nosniff`
+    pub nosniff: bool,
+    /// `Referrer-Policy` to send, none when empty
+    pub referrer_policy: Option<String>,
+    /// Forbid embedding responses in frames, with both `X-Frame-Options` and
+    /// `Content-Security-Policy: frame-ancestors`
+    pub deny_framing: bool,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        Self {
+            hsts_max_age_secs: None,
+            nosniff: true,
+            referrer_policy: Some("no-referrer".to_string()),
+            deny_framing: true,
+        }
+    }
+}
+
+impl SecurityHeadersConfig {
+    /// The headers to add to every response
+    pub fn headers(&self) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        let mut insert = |name: HeaderName, value: &str| -> Result<()> {
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| format_err!("Invalid {} header value: {:?}", name, value))?;
+            headers.insert(name, value);
+            Ok(())
+        };
+
+        if let Some(max_age) = self.hsts_max_age_secs {
+            insert(
+                STRICT_TRANSPORT_SECURITY,
+                &format!("max-age={}; includeSubDomains", max_age),
+            )?;
+        }
+        if self.nosniff {
+            insert(X_CONTENT_TYPE_OPTIONS, "nosniff")?;
+        }
+        if let Some(referrer_policy) = &self.referrer_policy {
+            insert(REFERRER_POLICY, referrer_policy)?;
+        }
+        if self.deny_framing {
+            insert(X_FRAME_OPTIONS, "DENY")?;
+            insert(CONTENT_SECURITY_POLICY, "frame-ancestors 'none'")?;
+        }
+        Ok(headers)
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Funds a throwaway account once at startup, end to end, so that a bad mint key or a node
+//! refusing the faucet's transactions shows up in `GET /ready` before users hit the faucet.
+
+use crate::{
+    mint::{self, MintParams, Response},
+    Service,
+};
+use anyhow::{bail, Result};
+use aptos_infallible::Mutex;
+use aptos_logger::{error, info};
+use aptos_sdk::types::account_address::AccountAddress;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Amount minted to the scratch account
+const SELF_TEST_AMOUNT: u64 = 1;
+
+/// How often standbys check whether they lead yet
+const LEADER_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SelfTestStatus {
+    Pending,
+    Passed,
+    Failed { error: String },
+}
+
+pub struct SelfTest {
+    status: Mutex<SelfTestStatus>,
+}
+
+impl SelfTest {
+    pub fn new() -> Self {
+        Self {
+            status: Mutex::new(SelfTestStatus::Pending),
+        }
+    }
+
+    pub fn status(&self) -> SelfTestStatus {
+        self.status.lock().clone()
+    }
+
+    /// Funds a scratch account from `service` and waits for the transactions to commit. Standbys
+    /// wait until they lead
+    pub async fn run(&self, service: &Service) {
+        while !service.is_leader() {
+            tokio::time::sleep(LEADER_POLL_INTERVAL).await;
+        }
+        let status = match fund_scratch_account(service).await {
+            Ok(receiver) => {
+                info!("[faucet]: self-test funded {}", receiver);
+                SelfTestStatus::Passed
+            }
+            Err(err) => {
+                error!("[faucet]: self-test failed: {:#}", err);
+                SelfTestStatus::Failed {
+                    error: format!("{:#}", err),
+                }
+            }
+        };
+        *self.status.lock() = status;
+    }
+}
+
+impl Default for SelfTest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn fund_scratch_account(service: &Service) -> Result<AccountAddress> {
+    let receiver = AccountAddress::random();
+    let response = mint::process(
+        service,
+        MintParams {
+            amount: Some(SELF_TEST_AMOUNT.into()),
+            address: Some(receiver.to_hex_literal()),
+            return_txns: Some(true),
+            ..MintParams::default()
+        },
+    )
+    .await?;
+
+    match response {
+        Response::SubmittedTxns(txns) => {
+            for txn in txns {
+                service.client.wait_for_signed_transaction(&txn).await?;
+            }
+        }
+        // Nothing is submitted in a dry run, so signing is all that can be tested
+        Response::DryRun(_) => (),
+        Response::Funded(_) => bail!("Expected the submitted transactions"),
+    }
+    Ok(receiver)
+}
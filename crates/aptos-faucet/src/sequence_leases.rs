@@ -0,0 +1,226 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Replicas funding from the same account. The account's next free sequence number is kept in
+//! Redis, and each replica leases ranges of `range_size` sequence numbers from it, then signs and
+//! submits with them on its own. Only the allocation goes through Redis, where it is a single
+//! script, see [`crate::redis`].
+//!
+//! Sequence numbers leased but never submitted, e.g. the rest of a range when a replica restarts,
+//! hold up every later transaction of the account. Replicas fill the gaps in their own ranges,
+//! see [`crate::gaps`], but those of a replica that went away remain until the transactions
+//! waiting on them expire, so ranges should be kept small.
+
+use crate::{counters, redis};
+use anyhow::{bail, format_err, Result};
+use aptos_infallible::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, convert::TryFrom, ops::Range, path::PathBuf, time::Duration};
+
+/// Leases `ARGV[2]` sequence numbers, never below the on-chain sequence number `ARGV[1]`.
+/// Returns the first of them
+const LEASE_SCRIPT: &str = "local start = tonumber(redis.call('get', KEYS[1]) or '0') \
+     if start < tonumber(ARGV[1]) then start = tonumber(ARGV[1]) end \
+     redis.call('set', KEYS[1], start + tonumber(ARGV[2])) \
+     return start";
+
+/// Restarts the counter at `KEYS[1]` from `ARGV[1]` after the chain was reset, unless it already
+/// was for the chain `ARGV[2]`, which is kept at `KEYS[2]`. Returns 1 if it restarted it
+const RESET_SCRIPT: &str = "if redis.call('get', KEYS[2]) == ARGV[2] then return 0 end \
+     redis.call('set', KEYS[1], ARGV[1]) \
+     redis.call('set', KEYS[2], ARGV[2]) \
+     return 1";
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SequenceLeaseConfig {
+    /// `host:port` of the Redis server allocating the sequence numbers
+    pub redis_address: String,
+    /// Key of the next free sequence number, the same for every replica of a funder
+    #[serde(default = "default_key")]
+    pub key: String,
+    /// Password of the Redis server, if it requires one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// File holding `password`, see [`crate::secrets`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_file: Option<PathBuf>,
+    /// Sequence numbers leased at once
+    #[serde(default = "default_range_size")]
+    pub range_size: u64,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_key() -> String {
+    "aptos-faucet:sequence_number".to_string()
+}
+
+fn default_range_size() -> u64 {
+    10
+}
+
+fn default_timeout_ms() -> u64 {
+    2_000
+}
+
+impl SequenceLeaseConfig {
+    pub fn new(redis_address: String) -> Self {
+        Self {
+            redis_address,
+            key: default_key(),
+            password: None,
+            password_file: None,
+            range_size: default_range_size(),
+            timeout_ms: default_timeout_ms(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        // A request signs up to two transactions, which are kept in the same range
+        if self.range_size < 2 {
+            bail!("sequence_leases.range_size must be at least 2");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Leased {
+    /// What is left of the current range
+    available: Range<u64>,
+    /// The ranges that may still hold sequence numbers of this replica, ends by starts
+    ranges: BTreeMap<u64, u64>,
+}
+
+pub struct SequenceLeases {
+    config: SequenceLeaseConfig,
+    leased: Mutex<Leased>,
+}
+
+impl SequenceLeases {
+    pub fn new(config: SequenceLeaseConfig) -> Self {
+        Self {
+            config,
+            leased: Mutex::new(Leased::default()),
+        }
+    }
+
+    /// The first of `count` consecutive sequence numbers to sign with, leasing a new range when
+    /// the current one runs out or the on-chain sequence number `onchain_seq` went past it
+    pub async fn take(&self, count: u64, onchain_seq: u64) -> Result<u64> {
+        {
+            let mut leased = self.leased.lock();
+            let available = &mut leased.available;
+            if available.start >= onchain_seq && available.end - available.start >= count {
+                available.start += count;
+                return Ok(available.start - count);
+            }
+        }
+
+        let size = self.config.range_size.max(count);
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let (floor, size_arg) = (onchain_seq.to_string(), size.to_string());
+        let lease = redis::eval(
+            &self.config.redis_address,
+            self.config.password.as_deref(),
+            LEASE_SCRIPT,
+            &[self.config.key.as_str()],
+            &[floor.as_str(), size_arg.as_str()],
+        );
+        let start = tokio::time::timeout(timeout, lease)
+            .await
+            .map_err(|_| format_err!("Leasing sequence numbers timed out after {:?}", timeout))?
+            .map_err(|err| format_err!("Unable to lease sequence numbers: {:#}", err))?;
+        let start = u64::try_from(start)?;
+        counters::SEQUENCE_LEASES.inc();
+
+        let mut leased = self.leased.lock();
+        leased.ranges.retain(|_, end| *end > onchain_seq);
+        leased.ranges.insert(start, start + size);
+        leased.available = start + count..start + size;
+        Ok(start)
+    }
+
+    /// Hands back the `count` sequence numbers from `start`, which were not submitted, unless
+    /// later ones were taken since
+    pub fn give_back(&self, start: u64, count: u64) {
+        let mut leased = self.leased.lock();
+        if count > 0 && leased.available.start == start + count {
+            leased.available.start = start;
+        }
+    }
+
+    /// Forgets the leased ranges after the chain `chain` was reset, and restarts the shared
+    /// counter from the on-chain sequence number `onchain_seq`, once for all replicas
+    pub async fn reset(&self, onchain_seq: u64, chain: &str) -> Result<()> {
+        *self.leased.lock() = Leased::default();
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let (chain_key, floor) = (
+            format!("{}:chain", self.config.key),
+            onchain_seq.to_string(),
+        );
+        let reset = redis::eval(
+            &self.config.redis_address,
+            self.config.password.as_deref(),
+            RESET_SCRIPT,
+            &[self.config.key.as_str(), chain_key.as_str()],
+            &[floor.as_str(), chain],
+        );
+        tokio::time::timeout(timeout, reset)
+            .await
+            .map_err(|_| format_err!("Resetting sequence numbers timed out after {:?}", timeout))?
+            .map_err(|err| format_err!("Unable to reset sequence numbers: {:#}", err))?;
+        Ok(())
+    }
+
+    /// Whether `sequence_number` was leased by this replica
+    pub fn owns(&self, sequence_number: u64) -> bool {
+        let leased = self.leased.lock();
+        leased
+            .ranges
+            .range(..=sequence_number)
+            .next_back()
+            .map_or(false, |(_, end)| sequence_number < *end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::fake_server;
+
+    #[tokio::test]
+    async fn sequence_numbers_are_taken_from_leased_ranges() {
+        let leases = SequenceLeases::new(SequenceLeaseConfig {
+            password: Some("secret".to_string()),
+            ..SequenceLeaseConfig::new(fake_server(vec![5, 40]).await)
+        });
+
+        // Leases 5 to 14
+        assert_eq!(leases.take(2, 0).await.unwrap(), 5);
+        assert_eq!(leases.take(1, 3).await.unwrap(), 7);
+        leases.give_back(7, 1);
+        assert_eq!(leases.take(1, 3).await.unwrap(), 7);
+        assert!(leases.owns(5) && leases.owns(14));
+        assert!(!leases.owns(4) && !leases.owns(15));
+
+        // Other replicas' transactions committed past the range, leasing 40 to 49
+        assert_eq!(leases.take(2, 20).await.unwrap(), 40);
+        assert!(leases.owns(41));
+        assert!(!leases.owns(8));
+    }
+
+    #[tokio::test]
+    async fn resets_forget_leased_ranges() {
+        let leases = SequenceLeases::new(SequenceLeaseConfig {
+            password: Some("secret".to_string()),
+            ..SequenceLeaseConfig::new(fake_server(vec![40, 1, 0]).await)
+        });
+        assert_eq!(leases.take(2, 0).await.unwrap(), 40);
+
+        leases.reset(0, "genesis").await.unwrap();
+        assert!(!leases.owns(40));
+        assert_eq!(leases.take(2, 0).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,346 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Runs the faucet in-process, so that tests and local tooling can embed a real faucet instead
+//! of spawning the binary.
+
+use crate::{
+    audit::AuditLog,
+    checkers::{
+        DecayChecker, GeoChecker, GitHubChecker, HttpReputationProvider, IpListChecker,
+        ReputationChecker,
+    },
+    connection_limits, delegate_mint_account,
+    events::{kafka::KafkaProducer, nats::NatsPublisher, EventPublisher},
+    mock_chain::{MockAccount, MockChain, MOCK_FAUCET_BALANCE},
+    preflight::{self, PreflightError},
+    routes,
+    tls::Tls,
+    Endowment, FaucetConfig, ServiceBuilder,
+};
+use anyhow::{bail, Result};
+use aptos_crypto::{ed25519::Ed25519PrivateKey, PrivateKey, Uniform};
+use aptos_logger::{error, info, warn, Logger};
+use aptos_sdk::types::{transaction::authenticator::AuthenticationKey, LocalAccount};
+use std::{iter, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{net::TcpListener, sync::oneshot, task::JoinHandle};
+
+/// Stops a faucet started by [`run`]. Dropping the handle also stops the faucet.
+pub struct ShutdownHandle {
+    addresses: Vec<SocketAddr>,
+    senders: Vec<oneshot::Sender<()>>,
+    join_handle: JoinHandle<()>,
+}
+
+impl ShutdownHandle {
+    /// Every address the faucet is listening on, starting with the configured `address`
+    pub fn addresses(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+
+    /// Gracefully shuts down the server, waiting for in-flight requests to complete.
+    pub async fn shutdown(self) -> Result<()> {
+        for sender in self.senders {
+            // The server may already be gone, in which case there is nothing left to signal
+            let _ = sender.send(());
+        }
+        self.join_handle.await?;
+        Ok(())
+    }
+}
+
+/// Starts the faucet in the background, returning the address it is listening on and a handle
+/// to stop it. Further addresses the faucet listens on are available from the handle.
+pub async fn run(config: FaucetConfig) -> Result<(SocketAddr, ShutdownHandle)> {
+    run_with_logger(config, None).await
+}
+
+/// Like [`run`], letting `PUT /admin/log_level` change the filter of the process' `logger`
+pub async fn run_with_logger(
+    mut config: FaucetConfig,
+    logger: Option<Arc<Logger>>,
+) -> Result<(SocketAddr, ShutdownHandle)> {
+    config.resolve_secrets()?;
+    let mint_key = if config.mock_chain {
+        start_mock_chain(&mut config)?
+    } else {
+        config.load_mint_key()?
+    };
+
+    info!(
+        "[faucet]: chain id: {}, server url: {} . Limit: {:?}",
+        config.chain_id,
+        config.server_url.as_str(),
+        config.maximum_amount,
+    );
+
+    // Against a node on another chain every transaction would be rejected
+    match preflight::check_node(&config).await {
+        Ok(_) => (),
+        Err(err @ PreflightError::ChainIdMismatch { .. }) => {
+            if !config.allow_chain_id_mismatch {
+                return Err(err.into());
+            }
+            error!(
+                "[faucet]: {}. Starting anyway as allow_chain_id_mismatch is set, every \
+                 transaction will be rejected",
+                err
+            );
+        }
+        Err(err) => warn!("[faucet]: unable to verify the node's chain id: {}", err),
+    }
+
+    if let Some(max_lag_secs) = config.max_sync_lag_secs {
+        preflight::wait_for_sync(&config, Duration::from_secs(max_lag_secs)).await?;
+    }
+
+    let faucet_account = LocalAccount::new(config.faucet_address(), mint_key, 0);
+
+    let delegate = config.delegates();
+    // Every replica would delegate to an account of its own at startup
+    if delegate && config.leader_election.is_some() {
+        bail!("leader_election requires do_not_delegate");
+    }
+    if delegate && config.sharding.is_some() {
+        bail!("sharding requires do_not_delegate");
+    }
+    if delegate && config.sequence_leases.is_some() {
+        bail!("sequence_leases requires do_not_delegate");
+    }
+
+    // Do not use maximum amount on delegation, this allows the new delegated faucet to
+    // mint a lot for themselves!
+    let maximum_amount = if delegate {
+        None
+    } else {
+        config.maximum_amount
+    };
+
+    let mut builder = ServiceBuilder::new(config.server_url)
+        .chain_id(config.chain_id)
+        .funder(faucet_account)
+        .minimum_amount(config.minimum_amount)
+        .dry_run(config.dry_run)
+        .funding_mode(config.funding_mode);
+    if let Some(maximum_amount) = maximum_amount {
+        builder = builder.maximum_amount(maximum_amount);
+    }
+    if let Some(days) = config.delegate_key_rotation_days {
+        builder = builder.delegate_key_rotation(Duration::from_secs(days * 24 * 3600));
+    }
+    if let Some(daily_maximum_amount) = config.daily_maximum_amount {
+        builder = builder.daily_maximum_amount(daily_maximum_amount);
+    }
+    if let Some(default_amount) = config.default_amount {
+        builder = builder.default_amount(default_amount);
+    }
+    if let Some(spend_budget) = config.spend_budget {
+        builder = builder.spend_budget(
+            spend_budget,
+            Duration::from_secs(config.spend_budget_window_secs),
+        );
+    }
+    if let Some(geo) = &config.geo {
+        builder = builder.checker(GeoChecker::load(geo)?);
+    }
+    for ip_list in &config.ip_lists {
+        builder = builder.checker(IpListChecker::start(ip_list.clone()).await?);
+    }
+    if let Some(github) = &config.github {
+        builder = builder.checker(GitHubChecker::new(github.clone())?);
+    }
+    if let Some(reputation) = &config.reputation {
+        let provider = HttpReputationProvider::new(reputation)?;
+        builder = builder.checker(ReputationChecker::new(provider, reputation));
+    }
+    if let Some(decay) = &config.decay {
+        builder = builder.checker(DecayChecker::new(decay.clone()));
+    }
+    if let Some(balance_limit) = &config.balance_limit {
+        builder = builder.balance_limit(balance_limit.clone());
+    }
+    if let Some(sybil) = &config.sybil {
+        builder = builder.sybil_detection(sybil.clone());
+    }
+    if let Some(bans) = &config.bans {
+        builder = builder.bans(bans.clone());
+    }
+    if let Some(leader_election) = &config.leader_election {
+        builder = builder.leader_election(leader_election.clone());
+    }
+    if let Some(sharding) = &config.sharding {
+        builder = builder.sharding(sharding.clone());
+    }
+    if let Some(sequence_leases) = &config.sequence_leases {
+        builder = builder.sequence_leases(sequence_leases.clone());
+    }
+    if let Some(admin_token) = &config.admin_token {
+        builder = builder.admin_token(admin_token.clone());
+    }
+    if let Some(partners) = &config.partners {
+        builder = builder.partners(partners.clone());
+    }
+    if let Some(max_concurrent_mints) = config.max_concurrent_mints {
+        builder = builder.max_concurrent_mints(max_concurrent_mints);
+    }
+    if let Some(max_queue_wait_ms) = config.max_queue_wait_ms {
+        builder = builder.max_queue_wait(Duration::from_millis(max_queue_wait_ms));
+    }
+    builder = builder
+        .max_in_flight_transactions(config.max_in_flight_transactions)
+        .finality_version_lag(config.finality_version_lag)
+        .funder_cache_ttl(Duration::from_millis(config.funder_cache_ttl_ms));
+    if let Some(contract) = config.ans_contract_address {
+        builder = builder.ans_contract(contract);
+    }
+    if let Some(logger) = logger {
+        builder = builder.logger(logger);
+    }
+    if config.stuck_transaction_secs > 0 {
+        builder =
+            builder.stuck_transaction_threshold(Duration::from_secs(config.stuck_transaction_secs));
+    }
+    if let Some(audit_log) = &config.audit_log {
+        builder = builder.audit_log(AuditLog::open(audit_log.clone())?);
+    }
+    if let Some(kafka) = &config.kafka {
+        builder = builder.event_publisher(EventPublisher::start(
+            KafkaProducer::new(kafka.clone())?,
+            kafka.queue_size,
+        ));
+    }
+    if let Some(nats) = &config.nats {
+        builder = builder.event_publisher(EventPublisher::start(
+            NatsPublisher::new(nats.clone()),
+            nats.queue_size,
+        ));
+    }
+    if config.self_test {
+        builder = builder.self_test();
+    }
+    if config.signature_challenge {
+        builder = builder.signature_challenge(Duration::from_secs(config.challenge_ttl_secs));
+    }
+    if let Some(path_prefix) = &config.path_prefix {
+        builder = builder.path_prefix(path_prefix);
+    }
+    if config.ip_rate_limit.is_enabled() {
+        builder = builder.ip_rate_limit(config.ip_rate_limit.clone());
+    }
+    if let Some(cooldown) = &config.cooldown {
+        builder = builder.cooldown(cooldown.clone());
+    }
+    for order in &config.standing_orders {
+        builder = builder.standing_order(order.clone());
+    }
+    if let Some(chaos) = &config.chaos {
+        warn!(
+            "[faucet]: chaos mode is on, {:.0}% of API requests fail on purpose",
+            (chaos.rate_limited + chaos.server_error) * 100.0
+        );
+        builder = builder.chaos(chaos.clone());
+    }
+    let service = Arc::new(
+        builder
+            .trust_x_forwarded_for(config.trust_x_forwarded_for)
+            .security_headers(config.security_headers.clone())
+            .request_limits(config.request_limits.clone())
+            .log_sampling(&config.log_sampling)
+            .build()?,
+    );
+
+    let service = if delegate {
+        let endowment = Endowment {
+            amount: config.delegate_endowment,
+            top_up_below: config.delegate_top_up_below,
+        };
+        delegate_mint_account(
+            service,
+            config.maximum_amount,
+            endowment,
+            config.delegate_state_file.as_deref(),
+        )
+        .await?
+    } else {
+        service
+    };
+
+    service.start_background_tasks();
+
+    let minting_address = service.faucet_account.lock().await.address();
+    let routes = routes(service);
+    let mut addresses = Vec::new();
+    let mut senders = Vec::new();
+    let mut servers = Vec::new();
+    let connection_limits = config.connection_limits.clone();
+    let tls = config.tls.clone().map(Tls::new).transpose()?;
+    if let Some(tls) = &tls {
+        tls.spawn_reloads();
+    }
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    for address in iter::once(config.address).chain(config.additional_addresses) {
+        let (sender, receiver) = oneshot::channel();
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|err| anyhow::format_err!("Unable to listen on {}: {}", address, err))?;
+        let address = listener.local_addr()?;
+        let server = connection_limits::serve(
+            routes.clone(),
+            listener,
+            connection_limits.clone(),
+            tls.clone(),
+            async move {
+                // Either an explicit shutdown or the handle being dropped stops the server
+                let _ = receiver.await;
+            },
+        );
+        info!(
+            "[faucet]: running on: {}://{}. Minting from {}",
+            scheme, address, minting_address
+        );
+        addresses.push(address);
+        senders.push(sender);
+        servers.push(server);
+    }
+
+    let join_handle = tokio::spawn(async move {
+        for result in futures::future::join_all(servers).await {
+            if let Err(err) = result {
+                error!("[faucet]: server failed: {}", err);
+            }
+        }
+    });
+    Ok((
+        addresses[0],
+        ShutdownHandle {
+            addresses,
+            senders,
+            join_handle,
+        },
+    ))
+}
+
+/// Starts an in-memory chain holding a well funded faucet account and points the config at it.
+/// A random mint key is used if none is configured, neither inline nor as an existing file; a
+/// configured key that cannot be read is an error.
+fn start_mock_chain(config: &mut FaucetConfig) -> Result<Ed25519PrivateKey> {
+    let mint_key = if config.mint_key.is_none() && !config.mint_key_file_path.exists() {
+        Ed25519PrivateKey::generate(&mut rand::rngs::OsRng)
+    } else {
+        config.load_mint_key()?
+    };
+
+    let mock_chain = MockChain::new(config.chain_id);
+    mock_chain.create_account(
+        config.faucet_address(),
+        MockAccount {
+            authentication_key: AuthenticationKey::ed25519(&mint_key.public_key()),
+            ..MockAccount::new(MOCK_FAUCET_BALANCE)
+        },
+    );
+    let mock_address = mock_chain.start(([127, 0, 0, 1], 0))?;
+    config.server_url = format!("http://{}/", mock_address);
+    info!("[faucet]: using a mock chain at {}", config.server_url);
+
+    Ok(mint_key)
+}
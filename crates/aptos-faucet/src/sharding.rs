@@ -0,0 +1,98 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Shared-nothing horizontal scaling. Receivers are split into `shards` by a hash of their
+//! address, and each replica only funds the receivers of its own `shard`, from a funder account
+//! of its own. Replicas never sign with the same account, so any number of them run side by side
+//! without coordinating. Mint requests for receivers of another shard are refused with a `421`
+//! naming the shard, and its URL when `shard_urls` are set, so that clients or the load balancer
+//! can send them there.
+
+use anyhow::{bail, Result};
+use aptos_crypto::hash::HashValue;
+use aptos_sdk::types::account_address::AccountAddress;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use url::Url;
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShardingConfig {
+    /// Number of shards receivers are split into, the same for every replica
+    pub shards: u32,
+    /// Shard of this replica, from 0 to `shards - 1`
+    pub shard: u32,
+    /// Base URL of the replica of each shard, in shard order, named in refusals
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shard_urls: Vec<Url>,
+}
+
+impl ShardingConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.shard >= self.shards {
+            bail!("sharding.shard must be below sharding.shards");
+        }
+        if !self.shard_urls.is_empty() && self.shard_urls.len() != self.shards as usize {
+            bail!("sharding.shard_urls must have a URL for each of the sharding.shards");
+        }
+        Ok(())
+    }
+
+    /// Whether this replica funds `receiver`
+    pub fn owns(&self, receiver: AccountAddress) -> bool {
+        shard_of(receiver, self.shards) == self.shard
+    }
+
+    /// Base URL of the replica of `shard`, if known
+    pub fn url(&self, shard: u32) -> Option<&Url> {
+        self.shard_urls.get(shard as usize)
+    }
+}
+
+/// Shard funding `receiver`. Addresses are hashed first, as vanity and framework addresses are
+/// far from evenly spread
+pub fn shard_of(receiver: AccountAddress, shards: u32) -> u32 {
+    let hash = HashValue::sha3_256_of(&receiver.to_vec());
+    let prefix = u64::from_be_bytes(hash.as_ref()[..8].try_into().expect("8 bytes"));
+    (prefix % shards as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn receivers_map_to_a_single_shard() {
+        // Pinned, as replicas of different versions must agree
+        let receiver = AccountAddress::from_hex_literal(
+            "0x459c77a38803bd53f3adee52703810e3a74fd7c46952c497e75afb0a7932586d",
+        )
+        .unwrap();
+        assert_eq!(shard_of(receiver, 4), 2);
+        assert_eq!(shard_of(receiver, 3), 1);
+        assert_eq!(shard_of(AccountAddress::ONE, 4), 3);
+
+        let configs: Vec<_> = (0..4)
+            .map(|shard| ShardingConfig {
+                shards: 4,
+                shard,
+                shard_urls: Vec::new(),
+            })
+            .collect();
+        for _ in 0..100 {
+            let receiver = AccountAddress::random();
+            let owners = configs
+                .iter()
+                .filter(|config| config.owns(receiver))
+                .count();
+            assert_eq!(owners, 1);
+        }
+
+        let invalid = ShardingConfig {
+            shards: 2,
+            shard: 2,
+            shard_urls: Vec::new(),
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
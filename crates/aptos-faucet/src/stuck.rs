@@ -0,0 +1,166 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Looks after the funder's transactions that were submitted a while ago but have not committed.
+//! Each is looked up on the node: those still in mempool are left alone, those the node never
+//! heard of are submitted again, and those that expired are abandoned. Once every transaction
+//! past the on-chain sequence number was abandoned, the funder's local sequence number is reset
+//! to the on-chain one, so new transactions do not wait on sequence numbers that will never
+//! commit.
+
+use crate::{counters, gaps, Service};
+use anyhow::Result;
+use aptos_logger::{info, warn};
+use std::{sync::Weak, time::Duration};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StuckReport {
+    pub resubmitted: usize,
+    pub abandoned: usize,
+}
+
+/// Checks the transactions submitted more than `threshold` ago every
+/// [`gaps::CHECK_INTERVAL`] while the service leads, until it is dropped
+pub(crate) async fn run(service: Weak<Service>, threshold: Duration) {
+    let mut interval = tokio::time::interval(gaps::CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let service = match service.upgrade() {
+            Some(service) => service,
+            None => return,
+        };
+        if !service.is_leader() {
+            continue;
+        }
+        if let Err(err) = check(&service, threshold).await {
+            warn!(
+                "[faucet]: unable to check for stuck transactions: {:#}",
+                err
+            );
+        }
+    }
+}
+
+/// Resubmits or abandons the funder's transactions submitted more than `threshold` ago that
+/// have not committed
+pub async fn check(service: &Service, threshold: Duration) -> Result<StuckReport> {
+    let (address, local_seq) = {
+        let funder = service.faucet_account.lock().await;
+        (funder.address(), funder.sequence_number())
+    };
+    let account =
+        counters::node_request("get_account", service.client.get_account(address)).await?;
+    let onchain_seq = account.inner().sequence_number;
+    let now_secs = account.state().timestamp_usecs / 1_000_000;
+
+    let mut report = StuckReport::default();
+    let stuck = service
+        .outstanding
+        .submitted_before(address, onchain_seq, threshold);
+    for tracked in stuck {
+        let sequence_number = tracked.txn.sequence_number();
+        if tracked.expired(now_secs) {
+            service.outstanding.forget(address, sequence_number);
+            report.abandoned += 1;
+            continue;
+        }
+
+        let hash = tracked.txn.clone().committed_hash();
+        let lookup = service.client.get_transaction(hash);
+        match counters::node_request("get_transaction", lookup).await {
+            // Still in mempool, waiting on an earlier sequence number
+            Ok(txn) if txn.inner().is_pending() => {}
+            Ok(_) => service.outstanding.forget(address, sequence_number),
+            // Usually the node never heard of it
+            Err(_) => {
+                let submit = service.client.submit(&tracked.txn);
+                match counters::node_request("submit", submit).await {
+                    Ok(_) => {
+                        service.outstanding.record(address, &tracked.txn);
+                        report.resubmitted += 1;
+                    }
+                    Err(err) => warn!(
+                        "[faucet]: unable to resubmit sequence number {} of {}: {}",
+                        sequence_number, address, err
+                    ),
+                }
+            }
+        }
+    }
+
+    if report.abandoned > 0 && local_seq > onchain_seq && service.outstanding.is_empty(address) {
+        let mut funder = service.faucet_account.lock().await;
+        // Unless transactions were signed in the meantime, which are yet to be submitted
+        if funder.address() == address && funder.sequence_number() == local_seq {
+            info!(
+                "[faucet]: resetting the sequence number of {} from {} to {}",
+                address, local_seq, onchain_seq
+            );
+            *funder.sequence_number_mut() = onchain_seq;
+        }
+    }
+
+    counters::STUCK_TRANSACTIONS
+        .with_label_values(&["resubmitted"])
+        .inc_by(report.resubmitted as u64);
+    counters::STUCK_TRANSACTIONS
+        .with_label_values(&["abandoned"])
+        .inc_by(report.abandoned as u64);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mint::{self, MintParams},
+        test_utils::{setup_service, setup_service_with, InjectedFailures},
+    };
+    use aptos_sdk::types::account_address::AccountAddress;
+
+    fn params(receiver: AccountAddress) -> MintParams {
+        MintParams {
+            address: Some(receiver.to_hex_literal()),
+            ..MintParams::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn lost_transactions_are_resubmitted() {
+        let (chain, service) = setup_service(None);
+        let receiver = AccountAddress::random();
+        chain.set_failures(InjectedFailures {
+            drop_transactions: true,
+            ..InjectedFailures::default()
+        });
+        mint::fund(&service, params(receiver), 100).await.unwrap();
+        chain.set_failures(InjectedFailures::default());
+
+        let report = check(&service, Duration::ZERO).await.unwrap();
+        assert_eq!(
+            report,
+            StuckReport {
+                resubmitted: 2,
+                abandoned: 0,
+            }
+        );
+        assert_eq!(chain.account(receiver).unwrap().balance, 100);
+    }
+
+    #[tokio::test]
+    async fn expired_transactions_are_abandoned() {
+        let (chain, service) = setup_service_with(|builder| builder.transaction_expiration_secs(0));
+        chain.set_failures(InjectedFailures {
+            drop_transactions: true,
+            ..InjectedFailures::default()
+        });
+        let receiver = AccountAddress::random();
+        mint::fund(&service, params(receiver), 100).await.unwrap();
+        assert_eq!(service.faucet_account.lock().await.sequence_number(), 2);
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let report = check(&service, Duration::ZERO).await.unwrap();
+        assert_eq!(report.abandoned, 2);
+        assert_eq!(service.faucet_account.lock().await.sequence_number(), 0);
+    }
+}
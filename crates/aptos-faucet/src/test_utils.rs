@@ -0,0 +1,58 @@
+// Copyright (c) Aptos
+// SPDX-License-Identifier: Apache-2.0
+
+//! Helpers for testing faucet integrations without a node. A [`MockChain`] stands in for the
+//! fullnode, and [`InjectedFailures`] makes it misbehave on demand, so that callers can
+//! deterministically test how their code handles an unavailable node, rejected submissions or
+//! transactions that never commit.
+
+use crate::{Service, ServiceBuilder};
+use aptos_crypto::{ed25519::Ed25519PrivateKey, PrivateKey, Uniform};
+use aptos_sdk::types::{
+    account_address::AccountAddress, chain_id::ChainId,
+    transaction::authenticator::AuthenticationKey, LocalAccount,
+};
+use std::sync::Arc;
+
+pub use crate::mock_chain::{InjectedFailures, MockAccount, MockChain, MockName};
+
+/// Starts a mock fullnode on which only `account_address` exists, returning it along with its
+/// REST endpoint. Must be called from within a tokio runtime.
+pub fn setup_fullnode(account_address: AccountAddress) -> (MockChain, String) {
+    let chain = MockChain::new(ChainId::test());
+    chain.create_account(account_address, MockAccount::new(0));
+    let address = chain
+        .start(([127, 0, 0, 1], 0))
+        .expect("mock fullnode should bind to an ephemeral port");
+
+    (chain, format!("http://localhost:{}/", address.port()))
+}
+
+/// Starts a mock fullnode and a [`Service`] minting from a freshly generated faucet account on
+/// it. Must be called from within a tokio runtime.
+pub fn setup_service(maximum_amount: Option<u64>) -> (MockChain, Arc<Service>) {
+    setup_service_with(|builder| match maximum_amount {
+        Some(maximum_amount) => builder.maximum_amount(maximum_amount),
+        None => builder,
+    })
+}
+
+/// Like [`setup_service`], letting `configure` adjust the [`ServiceBuilder`] before the service
+/// is built.
+pub fn setup_service_with(
+    configure: impl FnOnce(ServiceBuilder) -> ServiceBuilder,
+) -> (MockChain, Arc<Service>) {
+    let key = Ed25519PrivateKey::generate(&mut rand::rngs::OsRng);
+    let account_address = AuthenticationKey::ed25519(&key.public_key()).derived_address();
+    let faucet_account = LocalAccount::new(account_address, key, 0);
+
+    let (chain, endpoint) = setup_fullnode(account_address);
+
+    let builder = ServiceBuilder::new(endpoint)
+        .chain_id(ChainId::test())
+        .funder(faucet_account);
+    let service = configure(builder)
+        .build()
+        .expect("mock fullnode endpoint should be valid");
+    (chain, Arc::new(service))
+}
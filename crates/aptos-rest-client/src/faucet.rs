@@ -2,12 +2,308 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{error::Error, Client, Result};
+use aptos_api_types::Transaction;
+use aptos_crypto::HashValue;
 use aptos_types::transaction::SignedTransaction;
 use move_deps::move_core_types::account_address::AccountAddress;
-use reqwest::Url;
+use reqwest::{
+    header::{HeaderMap, ACCEPT, RETRY_AFTER},
+    StatusCode, Url,
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, time::Duration};
+
+/// The JSON body returned by the faucet's mint endpoint when `return_txns` is not set.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FundResponse {
+    /// The account that was created and/or funded
+    pub destination: AccountAddress,
+    /// The amount minted, after the faucet's maximum amount has been applied
+    pub amount: u64,
+    /// Hashes of the submitted transactions, in submission order
+    pub txn_hashes: Vec<HashValue>,
+    /// Balance of the destination once the transactions were committed, only set when the
+    /// faucet was asked to `wait` and could read it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance: Option<u64>,
+    /// Outcome of each transaction, in submission order, only set when the faucet was asked to
+    /// `wait`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub txns: Vec<CommittedTxn>,
+}
+
+/// A transaction the faucet waited on, as reported in [`FundResponse::txns`]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CommittedTxn {
+    pub hash: HashValue,
+    pub gas_used: u64,
+    pub success: bool,
+    pub vm_status: String,
+}
+
+/// The JSON body returned by the faucet's account creation endpoint
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CreatedAccount {
+    pub address: AccountAddress,
+    /// Hash of the transaction creating the account
+    pub txn_hash: HashValue,
+    /// Outcome of the transaction, only set when the faucet was asked to `wait`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub txn: Option<CommittedTxn>,
+}
+
+/// Whether `vm_status` is the abort of creating an account that already exists, which happens
+/// when the account is created between the faucet looking it up and the creation executing
+pub fn account_already_exists(vm_status: &str) -> bool {
+    vm_status.contains("RESOURCE_ALREADY_EXISTS") || vm_status.contains("ACCOUNT_ALREADY_EXISTS")
+}
+
+/// What a faucet reports about itself, see [`FaucetClient::health_check`]. Faucets predating the
+/// JSON health report only tell whether they are healthy and the funder's sequence number
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FaucetHealth {
+    /// Whether the faucet reaches its node
+    pub healthy: bool,
+    pub version: Option<String>,
+    pub git_commit: Option<String>,
+    pub chain_id: Option<u8>,
+    pub funder_address: Option<AccountAddress>,
+    /// Of the funder account, missing if the faucet could not reach its node
+    pub sequence_number: Option<u64>,
+    /// Cargo features the faucet was built with, for faucets serving `GET /version`
+    pub build_features: Vec<String>,
+    pub capabilities: FaucetCapabilities,
+}
+
+/// What a faucet may be relied on for. Faucets that do not report their capabilities only
+/// support minting with `return_txns`
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FaucetCapabilities {
+    /// Mint requests without `return_txns` are answered with a [`FundResponse`]
+    pub json_responses: bool,
+    /// Mint requests may ask the faucet to `wait` for their transactions to commit
+    pub wait: bool,
+    /// `POST /accounts` creates accounts without funding them
+    pub accounts: bool,
+    /// `POST /fund` serves the request shape of the newer faucet
+    pub fund: bool,
+    /// `POST /admin/bulk_fund` funds a batch of receivers, for admins
+    pub bulk_fund: bool,
+    /// Mint requests must solve a signature challenge
+    pub challenge: bool,
+}
+
+impl FaucetCapabilities {
+    /// The capabilities named by `names`, ignoring those this client does not know of
+    fn from_names(names: &[String]) -> Self {
+        let has = |name: &str| names.iter().any(|capability| capability == name);
+        Self {
+            json_responses: has("json_responses"),
+            wait: has("wait"),
+            accounts: has("accounts"),
+            fund: has("fund"),
+            bulk_fund: has("bulk_fund"),
+            challenge: has("challenge"),
+        }
+    }
+}
+
+/// The fields of the faucet's JSON `GET /health` the client reads
+#[derive(Deserialize)]
+struct HealthReport {
+    version: String,
+    git_commit: String,
+    chain: HealthChain,
+    funder_address: AccountAddress,
+    sequence_number: Option<u64>,
+    #[serde(default)]
+    capabilities: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct HealthChain {
+    chain_id: u8,
+}
+
+/// The fields of the faucet's `GET /version` the client reads
+#[derive(Deserialize)]
+struct BuildReport {
+    features: Vec<String>,
+}
+
+impl FaucetHealth {
+    /// Reads the faucet's answer to `GET /health`, the JSON report or the plain text sequence
+    /// number of older faucets
+    fn from_response(status: StatusCode, body: &str) -> Self {
+        match serde_json::from_str::<HealthReport>(body) {
+            Ok(report) => Self {
+                healthy: status.is_success(),
+                version: Some(report.version),
+                git_commit: Some(report.git_commit),
+                chain_id: Some(report.chain.chain_id),
+                funder_address: Some(report.funder_address),
+                sequence_number: report.sequence_number,
+                build_features: Vec::new(),
+                capabilities: FaucetCapabilities::from_names(&report.capabilities),
+            },
+            Err(_) => Self {
+                healthy: status.is_success(),
+                sequence_number: body.trim().parse().ok(),
+                ..Self::default()
+            },
+        }
+    }
+
+    /// Adds the build features from the faucet's answer to `GET /version`, which older faucets
+    /// do not serve
+    fn with_build(mut self, status: StatusCode, body: &str) -> Self {
+        if let (true, Ok(build)) = (
+            status.is_success(),
+            serde_json::from_str::<BuildReport>(body),
+        ) {
+            self.build_features = build.features;
+        }
+        self
+    }
+}
+
+/// Why the faucet refused a request. [`FaucetClient`]'s methods fail with it, wrapped in their
+/// `anyhow::Error`, whenever the faucet answered with an error status; failing to reach the
+/// faucet at all is a [`crate::error::Error`] instead.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FaucetClientError {
+    /// Refused by one of the faucet's limits, until `retry_after` when the faucet tells
+    RateLimited {
+        retry_after: Option<Duration>,
+        message: String,
+    },
+    /// The faucet does not accept the account's address, in the field `field`
+    InvalidAddress { field: String, reason: String },
+    /// The faucet, or the node it submits to, cannot take requests for now
+    Unavailable {
+        status: u16,
+        retry_after: Option<Duration>,
+        message: String,
+    },
+    /// Refused for any other reason
+    Refused { status: u16, message: String },
+}
+
+/// The body the faucet refuses requests with
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: String,
+}
+
+/// The fields of the faucet's `400` for malformed fields that tell which ones
+#[derive(Deserialize)]
+struct InvalidParams {
+    message: String,
+    errors: Vec<InvalidField>,
+}
+
+#[derive(Deserialize)]
+struct InvalidField {
+    field: String,
+    reason: String,
+}
+
+/// The fields naming the account to fund
+const ADDRESS_FIELDS: [&str; 3] = ["address", "auth_key", "pub_key"];
+
+impl FaucetClientError {
+    /// Classifies the faucet's answer with the error status `status`, for tools sending the
+    /// requests of [`FaucetClient::mint_url`] and [`FaucetClient::create_account_url`] themselves
+    pub fn from_response(status: StatusCode, headers: &HeaderMap, body: String) -> Self {
+        let retry_after = headers
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok()?.trim().parse().ok())
+            .map(Duration::from_secs);
+        if status == StatusCode::BAD_REQUEST {
+            if let Ok(params) = serde_json::from_str::<InvalidParams>(&body) {
+                return match params
+                    .errors
+                    .into_iter()
+                    .find(|error| ADDRESS_FIELDS.contains(&error.field.as_str()))
+                {
+                    Some(InvalidField { field, reason }) => {
+                        FaucetClientError::InvalidAddress { field, reason }
+                    }
+                    None => FaucetClientError::Refused {
+                        status: status.as_u16(),
+                        message: params.message,
+                    },
+                };
+            }
+        }
+        // Bodies that are not the faucet's, e.g. a proxy's, are kept whole
+        let message = match serde_json::from_str::<ErrorBody>(&body) {
+            Ok(error) => error.message,
+            Err(_) => body,
+        };
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => FaucetClientError::RateLimited {
+                retry_after,
+                message,
+            },
+            StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT => FaucetClientError::Unavailable {
+                status: status.as_u16(),
+                retry_after,
+                message,
+            },
+            _ => FaucetClientError::Refused {
+                status: status.as_u16(),
+                message,
+            },
+        }
+    }
+
+    /// Whether the request may succeed if sent again later
+    pub fn is_retriable(&self) -> bool {
+        matches!(
+            self,
+            FaucetClientError::RateLimited { .. } | FaucetClientError::Unavailable { .. }
+        )
+    }
+
+    /// When the faucet said the request may be sent again
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            FaucetClientError::RateLimited { retry_after, .. }
+            | FaucetClientError::Unavailable { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FaucetClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FaucetClientError::RateLimited { message, .. } => {
+                write!(f, "rate limited by the faucet: {}", message)
+            }
+            FaucetClientError::InvalidAddress { field, reason } => {
+                write!(f, "invalid address: '{}' {}", field, reason)
+            }
+            FaucetClientError::Unavailable {
+                status, message, ..
+            } => write!(f, "faucet unavailable ({}): {}", status, message),
+            FaucetClientError::Refused { status, message } => {
+                write!(f, "refused by the faucet ({}): {}", status, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FaucetClientError {}
 
 pub struct FaucetClient {
     faucet_url: String,
+    /// Sends the requests to the faucet, a default one is built for each request otherwise, as
+    /// blocking clients cannot be built within an async context
+    client: Option<reqwest::blocking::Client>,
     rest_client: Client,
 }
 
@@ -15,53 +311,123 @@ impl FaucetClient {
     pub fn new(faucet_url: String, rest_url: String) -> Self {
         Self {
             faucet_url,
+            client: None,
             rest_client: Client::new(Url::parse(&rest_url).expect("Unable to parse rest url")),
         }
     }
 
-    pub fn create_account(&self, address: AccountAddress) -> Result<()> {
-        let client = reqwest::blocking::Client::new();
+    /// Sends the requests to the faucet with `client`, e.g. one going through a proxy or trusting
+    /// custom TLS roots, rather than one with the default settings. The client is a blocking one
+    /// as the faucet client's methods block, see [`Self::with_rest_client`] for the node requests
+    pub fn with_client(mut self, client: reqwest::blocking::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Sends the requests waiting on the faucet's transactions to the node with `client`
+    pub fn with_rest_client(mut self, client: reqwest::Client) -> Self {
+        self.rest_client = Client::new_with_client(self.rest_client.base_url.clone(), client);
+        self
+    }
+
+    /// The URL of the mint request funding `address` with `amount`, to be sent as a POST. With
+    /// `return_txns`, the faucet answers with the submitted transactions, BCS and hex encoded,
+    /// rather than a [`FundResponse`]. Lets tools queue requests and send them on their own
+    pub fn mint_url(&self, address: AccountAddress, amount: u64, return_txns: bool) -> Result<Url> {
         let mut url = Url::parse(&self.faucet_url).map_err(Error::request)?;
         url.set_path("mint");
-        let query = format!("auth_key={}&amount=0&return_txns=true", address);
+        let mut query = format!("auth_key={}&amount={}", address, amount);
+        if return_txns {
+            query.push_str("&return_txns=true");
+        }
         url.set_query(Some(&query));
+        Ok(url)
+    }
 
-        let response = client.post(url).send().map_err(Error::request)?;
-        let status_code = response.status();
+    /// The URL of the request creating `address` without funding it, to be sent as a POST. The
+    /// faucet answers once the creation committed, with a [`CreatedAccount`]. Faucets older than
+    /// `POST /accounts` answer with a 404, and create accounts through [`Self::mint_url`] with an
+    /// amount of 0
+    pub fn create_account_url(&self, address: AccountAddress) -> Result<Url> {
+        let mut url = Url::parse(&self.faucet_url).map_err(Error::request)?;
+        url.set_path("accounts");
+        let query = format!("auth_key={}&wait=true", address);
+        url.set_query(Some(&query));
+        Ok(url)
+    }
+
+    /// The URL of the faucet's `path`, a GET endpoint without parameters
+    fn get_url(&self, path: &str) -> Result<Url> {
+        let mut url = Url::parse(&self.faucet_url).map_err(Error::request)?;
+        url.set_path(path);
+        Ok(url)
+    }
+
+    /// Asks the faucet whether it is healthy, which build it runs and what it may be relied on
+    /// for, so that clients can adapt to older deployments. Only fails if the faucet could not be
+    /// reached: unhealthy faucets are reported as such
+    pub fn health_check(&self) -> Result<FaucetHealth> {
+        let client = self.blocking_client();
+        let response = client
+            .get(self.get_url("health")?)
+            .header(ACCEPT, "application/json")
+            .send()
+            .map_err(Error::request)?;
+        let status = response.status();
         let body = response.text().map_err(Error::decode)?;
-        if !status_code.is_success() {
-            return Err(anyhow::anyhow!("body: {}", body));
-        }
+        let health = FaucetHealth::from_response(status, &body);
 
-        let bytes = hex::decode(body).map_err(Error::decode)?;
-        let txns: Vec<SignedTransaction> = bcs::from_bytes(&bytes).map_err(Error::decode)?;
+        let response = client
+            .get(self.get_url("version")?)
+            .send()
+            .map_err(Error::request)?;
+        let status = response.status();
+        let body = response.text().map_err(Error::decode)?;
+        Ok(health.with_build(status, &body))
+    }
 
-        tokio::runtime::Runtime::new()
-            .unwrap()
-            .block_on(self.rest_client.wait_for_signed_transaction(&txns[0]))
-            .map_err(Error::unknown)?;
+    /// Creates the account without funding it, waiting for the creation to commit. Accounts that
+    /// already exist are left as is
+    pub fn create_account(&self, address: AccountAddress) -> Result<()> {
+        let body = match self.send(self.create_account_url(address)?) {
+            Ok(body) => body,
+            Err(err) => {
+                return match err.downcast_ref::<FaucetClientError>() {
+                    Some(FaucetClientError::Refused { status: 409, .. }) => Ok(()),
+                    Some(FaucetClientError::Refused { status: 404, .. }) => {
+                        self.create_account_with_mint(address)
+                    }
+                    _ => Err(err),
+                }
+            }
+        };
 
+        let created: CreatedAccount = serde_json::from_str(&body).map_err(Error::decode)?;
+        match created.txn {
+            Some(txn) if !txn.success && !account_already_exists(&txn.vm_status) => Err(
+                anyhow::anyhow!("account creation {} failed: {}", txn.hash, txn.vm_status),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Creates the account by minting nothing into it, for faucets without `POST /accounts`
+    fn create_account_with_mint(&self, address: AccountAddress) -> Result<()> {
+        let txns = self.submit_mint(address, 0)?;
+        // Nothing is submitted for accounts that already exist
+        if let Some(txn) = txns.first() {
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(self.rest_client.wait_for_signed_transaction(txn))
+                .map_err(Error::unknown)?;
+        }
         Ok(())
     }
 
     pub fn fund(&self, address: AccountAddress, amount: u64) -> Result<()> {
-        let client = reqwest::blocking::Client::new();
-        let mut url = Url::parse(&self.faucet_url).map_err(Error::request)?;
-        url.set_path("mint");
-        let query = format!("auth_key={}&amount={}&return_txns=true", address, amount);
-        url.set_query(Some(&query));
-
         // Faucet returns the transaction that creates the account and needs to be waited on before
         // returning.
-        let response = client.post(url).send().map_err(Error::request)?;
-        let status_code = response.status();
-        let body = response.text().map_err(Error::decode)?;
-        if !status_code.is_success() {
-            return Err(Error::status(status_code.as_u16()).into());
-        }
-
-        let bytes = hex::decode(body).map_err(Error::decode)?;
-        let txns: Vec<SignedTransaction> = bcs::from_bytes(&bytes).map_err(Error::decode)?;
+        let txns = self.submit_mint(address, amount)?;
 
         tokio::runtime::Runtime::new()
             .unwrap()
@@ -71,10 +437,69 @@ impl FaucetClient {
         Ok(())
     }
 
+    /// Funds the account without waiting for the transactions to be committed, returning the
+    /// faucet's description of what was submitted.
+    pub fn request_fund(&self, address: AccountAddress, amount: u64) -> Result<FundResponse> {
+        let body = self.send(self.mint_url(address, amount, false)?)?;
+        Ok(serde_json::from_str(&body).map_err(Error::decode)?)
+    }
+
+    /// Funds the account and waits for every transaction issued by the faucet to be committed,
+    /// returning the executed transactions. An account creation failing because the account
+    /// already exists is left out, as the funding still goes through.
+    pub fn fund_and_wait(&self, address: AccountAddress, amount: u64) -> Result<Vec<Transaction>> {
+        let txns = self.submit_mint(address, amount)?;
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut executed = Vec::with_capacity(txns.len());
+        for (i, txn) in txns.iter().enumerate() {
+            // The faucet only submits more than one transaction when it creates the account first
+            let is_creation = i == 0 && txns.len() > 1;
+            match runtime.block_on(self.rest_client.wait_for_signed_transaction(txn)) {
+                Ok(response) => executed.push(response.into_inner()),
+                Err(err) if is_creation && account_already_exists(&err.to_string()) => (),
+                Err(err) => return Err(Error::unknown(err).into()),
+            }
+        }
+
+        Ok(executed)
+    }
+
     pub fn mint(&self, address: AccountAddress, amount: u64) -> Result<()> {
         self.create_account(address)?;
         self.fund(address, amount)?;
 
         Ok(())
     }
+
+    fn submit_mint(&self, address: AccountAddress, amount: u64) -> Result<Vec<SignedTransaction>> {
+        let body = self.send(self.mint_url(address, amount, true)?)?;
+        let bytes = hex::decode(body).map_err(Error::decode)?;
+        let txns: Vec<SignedTransaction> = bcs::from_bytes(&bytes).map_err(Error::decode)?;
+        Ok(txns)
+    }
+
+    /// POSTs to `url`, returning the body of the faucet's answer, or a [`FaucetClientError`] if
+    /// it refused the request
+    fn send(&self, url: Url) -> Result<String> {
+        let response = self
+            .blocking_client()
+            .post(url)
+            .send()
+            .map_err(Error::request)?;
+        let status_code = response.status();
+        let headers = response.headers().clone();
+        let body = response.text().map_err(Error::decode)?;
+        if !status_code.is_success() {
+            return Err(FaucetClientError::from_response(status_code, &headers, body).into());
+        }
+        Ok(body)
+    }
+
+    fn blocking_client(&self) -> reqwest::blocking::Client {
+        match &self.client {
+            Some(client) => client.clone(),
+            None => reqwest::blocking::Client::new(),
+        }
+    }
 }
@@ -9,16 +9,22 @@ use aptos_types::{
     account_address::AccountAddress, account_config::aptos_root_address,
     transaction::SignedTransaction,
 };
-use reqwest::{header::CONTENT_TYPE, Client as ReqwestClient, StatusCode};
+use reqwest::{
+    header::{HeaderMap, CONTENT_TYPE},
+    Client as ReqwestClient, RequestBuilder, StatusCode,
+};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::{json, Value};
 use state::State;
-use std::time::Duration;
+use std::{fmt, sync::Arc, time::Duration};
 use url::Url;
 
 pub mod error;
 pub mod faucet;
-pub use faucet::FaucetClient;
+pub use faucet::{
+    CommittedTxn, CreatedAccount, FaucetCapabilities, FaucetClient, FaucetClientError,
+    FaucetHealth, FundResponse,
+};
 pub mod response;
 pub use response::Response;
 mod state;
@@ -29,10 +35,23 @@ pub mod aptos;
 
 const USER_AGENT: &str = concat!("aptos-client-sdk-rust / ", env!("CARGO_PKG_VERSION"));
 
-#[derive(Clone, Debug)]
+/// Returns headers to add to a request, see [`Client::with_headers`]
+type HeaderSource = Arc<dyn Fn() -> HeaderMap + Send + Sync>;
+
+#[derive(Clone)]
 pub struct Client {
     inner: ReqwestClient,
     base_url: Url,
+    headers: Option<HeaderSource>,
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("inner", &self.inner)
+            .field("base_url", &self.base_url)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Client {
@@ -43,8 +62,39 @@ impl Client {
             .cookie_store(true)
             .build()
             .unwrap();
+        Self::new_with_client(base_url, inner)
+    }
 
-        Self { inner, base_url }
+    /// Sends the requests with `inner`, e.g. a client going through a proxy or trusting custom
+    /// TLS roots, rather than one with the default settings
+    pub fn new_with_client(base_url: Url, inner: ReqwestClient) -> Self {
+        Self {
+            inner,
+            base_url,
+            headers: None,
+        }
+    }
+
+    /// Adds the headers returned by `headers` to every request, which is called as each request
+    /// is sent, e.g. to propagate the trace context of the task sending it
+    pub fn with_headers(mut self, headers: impl Fn() -> HeaderMap + Send + Sync + 'static) -> Self {
+        self.headers = Some(Arc::new(headers));
+        self
+    }
+
+    fn get(&self, url: Url) -> RequestBuilder {
+        self.with_extra_headers(self.inner.get(url))
+    }
+
+    fn post(&self, url: Url) -> RequestBuilder {
+        self.with_extra_headers(self.inner.post(url))
+    }
+
+    fn with_extra_headers(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.headers {
+            Some(headers) => request.headers(headers()),
+            None => request,
+        }
     }
 
     pub async fn get_aptos_version(&self) -> Result<Response<AptosVersion>> {
@@ -76,7 +126,7 @@ impl Client {
             ledger_timestamp: u64,
         }
 
-        let response = self.inner.get(self.base_url.clone()).send().await?;
+        let response = self.get(self.base_url.clone()).send().await?;
 
         let response = self.json::<Response>(response).await?.map(|r| State {
             chain_id: r.chain_id,
@@ -93,7 +143,6 @@ impl Client {
         let url = self.base_url.join("transactions")?;
 
         let response = self
-            .inner
             .post(url)
             .header(CONTENT_TYPE, BCS_CONTENT_TYPE)
             .body(txn_payload)
@@ -178,7 +227,7 @@ impl Client {
     ) -> Result<Response<Vec<Transaction>>> {
         let url = self.base_url.join("transactions")?;
 
-        let mut request = self.inner.get(url);
+        let mut request = self.get(url);
         if let Some(start) = start {
             request = request.query(&[("start", start)])
         }
@@ -216,7 +265,7 @@ impl Client {
             .base_url
             .join(&format!("transactions/{}", version_or_hash))?;
 
-        Ok(self.inner.get(url).send().await?)
+        Ok(self.get(url).send().await?)
     }
 
     pub async fn get_account_transactions(
@@ -229,7 +278,7 @@ impl Client {
             .base_url
             .join(&format!("accounts/{}/transactions", address))?;
 
-        let mut request = self.inner.get(url);
+        let mut request = self.get(url);
         if let Some(start) = start {
             request = request.query(&[("start", start)])
         }
@@ -251,7 +300,7 @@ impl Client {
             .base_url
             .join(&format!("accounts/{}/resources", address))?;
 
-        let response = self.inner.get(url).send().await?;
+        let response = self.get(url).send().await?;
 
         self.json(response).await
     }
@@ -285,7 +334,7 @@ impl Client {
             .base_url
             .join(&format!("accounts/{}/resource/{}", address, resource_type))?;
 
-        let response = self.inner.get(url).send().await?;
+        let response = self.get(url).send().await?;
         self.json(response).await
     }
 
@@ -297,7 +346,7 @@ impl Client {
             .base_url
             .join(&format!("accounts/{}/modules", address))?;
 
-        let response = self.inner.get(url).send().await?;
+        let response = self.get(url).send().await?;
         self.json(response).await
     }
 
@@ -317,13 +366,13 @@ impl Client {
             "key": json!(key),
         });
 
-        let response = self.inner.post(url).json(&data).send().await?;
+        let response = self.post(url).json(&data).send().await?;
         self.json(response).await
     }
 
     pub async fn get_account(&self, address: AccountAddress) -> Result<Response<Account>> {
         let url = self.base_url.join(&format!("accounts/{}", address))?;
-        let response = self.inner.get(url).send().await?;
+        let response = self.get(url).send().await?;
         self.json(response).await
     }
 
@@ -333,7 +382,7 @@ impl Client {
     ) -> Result<(reqwest::Response, State)> {
         if !response.status().is_success() {
             let error_response = response.json::<RestError>().await?;
-            return Err(anyhow::anyhow!("Request failed: {:?}", error_response));
+            return Err(error_response.into());
         }
         let state = State::from_headers(response.headers())?;
 
@@ -352,7 +401,6 @@ impl Client {
     pub async fn health_check(&self, seconds: u64) -> Result<()> {
         let url = self.base_url.join("-/healthy")?;
         let response = self
-            .inner
             .get(url)
             .query(&[("duration_secs", seconds)])
             .send()
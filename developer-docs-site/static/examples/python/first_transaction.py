@@ -168,7 +168,7 @@ class FaucetClient:
         coins into that account."""
         txns = requests.post(f"{self.url}/mint?amount={amount}&address={address}")
         assert txns.status_code == 200, txns.text
-        for txn_hash in txns.json():
+        for txn_hash in txns.json()["txn_hashes"]:
             self.rest_client.wait_for_transaction(txn_hash)
 #<:!:section_6
 
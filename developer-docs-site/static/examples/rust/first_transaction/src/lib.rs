@@ -314,7 +314,10 @@ impl FaucetClient {
                 res.text().unwrap_or("".to_string()),
             );
         }
-        for txn_hash in res.json::<serde_json::Value>().unwrap().as_array().unwrap() {
+        for txn_hash in res.json::<serde_json::Value>().unwrap()["txn_hashes"]
+            .as_array()
+            .unwrap()
+        {
             self.rest_client
                 .wait_for_transaction(txn_hash.as_str().unwrap())
         }
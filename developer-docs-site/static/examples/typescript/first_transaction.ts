@@ -216,7 +216,7 @@ export class FaucetClient {
     if (response.status != 200) {
       assert(response.status == 200, await response.text());
     }
-    const tnxHashes = await response.json() as Array<string>;
+    const { txn_hashes: tnxHashes } = await response.json() as { txn_hashes: Array<string> };
     for (const tnxHash of tnxHashes) {
       await this.restClient.waitForTransaction(tnxHash);
     }
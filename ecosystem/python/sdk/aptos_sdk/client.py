@@ -475,7 +475,10 @@ class FaucetClient:
             f"{self.base_url}/mint?amount={amount}&address={address}"
         )
         assert txns.status_code == 200, txns.text
-        for txn_hash in txns.json():
+        response = txns.json()
+        # Older faucets answer with the list of hashes alone
+        txn_hashes = response if isinstance(response, list) else response["txn_hashes"]
+        for txn_hash in txn_hashes:
             self.rest_client.wait_for_transaction(txn_hash)
 
 
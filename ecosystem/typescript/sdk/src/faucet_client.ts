@@ -4,6 +4,11 @@ import { AptosClient, AptosClientConfig, raiseForStatus } from './aptos_client';
 import { Types } from './types';
 import { HexString, MaybeHexString } from './hex_string';
 
+/** The faucet's answer to a mint request. Older faucets answer with the array of hashes alone */
+interface FundResponse {
+  txn_hashes: Types.HexEncodedBytes[];
+}
+
 export class FaucetClient extends AptosClient {
   faucetUrl: string;
 
@@ -16,10 +21,10 @@ export class FaucetClient extends AptosClient {
    coins into that account */
   async fundAccount(address: MaybeHexString, amount: number): Promise<Types.HexEncodedBytes[]> {
     const url = `${this.faucetUrl}/mint?amount=${amount}&address=${HexString.ensure(address).noPrefix()}`;
-    const response = await axios.post<Array<string>>(url, {}, { validateStatus: () => true });
+    const response = await axios.post<FundResponse | Array<string>>(url, {}, { validateStatus: () => true });
     raiseForStatus(200, response);
 
-    const tnxHashes = response.data;
+    const tnxHashes = Array.isArray(response.data) ? response.data : response.data.txn_hashes;
     const promises = [];
     for (let i = 0; i < tnxHashes.length; i += 1) {
       const tnxHash = tnxHashes[i];